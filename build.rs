@@ -1,4 +1,5 @@
 fn main() {
+    assigned_numbers::generate();
     #[cfg(target_os = "linux")]
     build::build();
     #[cfg(target_os = "windows")]
@@ -41,6 +42,7 @@ mod build {
             methodtype: None,
             genericvariant: true,
             propnewtype: true,
+            connectiontype: dbus_codegen::ConnectionType::Nonblock,
             ..dbus_codegen::GenOpts::default()
         };
 
@@ -101,7 +103,7 @@ mod build {
             &contents,
             &dbus_codegen::GenOpts {
                 command_line: format!(
-                    "--generic-variant --methodtype None --file {} --output {}",
+                    "--generic-variant --methodtype None --client nonblock --file {} --output {}",
                     input_file.display(),
                     output_file.display()
                 ),
@@ -114,3 +116,175 @@ mod build {
         Ok(())
     }
 }
+
+mod assigned_numbers {
+    use serde_yaml::Value;
+    use std::{collections::HashSet, convert::TryFrom, env, fmt::Write, fs, path::Path};
+
+    const INPUT_PATH: &str = "assigned_numbers";
+
+    /// Generates the name tables and constants in `$OUT_DIR/assigned_numbers.rs` from the YAML
+    /// files which the Bluetooth SIG publishes.
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let mut output = String::new();
+
+        let uuid_files = [
+            ("uuids/service_uuids.yaml", "SERVICE", "services", "service"),
+            (
+                "uuids/characteristic_uuids.yaml",
+                "CHARACTERISTIC",
+                "characteristics",
+                "characteristic",
+            ),
+            (
+                "uuids/descriptors.yaml",
+                "DESCRIPTOR",
+                "descriptors",
+                "descriptor",
+            ),
+        ];
+        for (file, table, module, kind) in uuid_files.iter() {
+            let entries: Vec<(u16, String)> = read(file, "uuids")
+                .iter()
+                .map(|entry| (number(entry, "uuid"), name(entry)))
+                .collect();
+            write_table(&mut output, &format!("{}_NAMES", table), &entries);
+            write_constants(&mut output, module, kind, "Uuid", &entries, |value| {
+                format!("uuid_from_u16({:#06x})", value)
+            });
+        }
+
+        let companies: Vec<(u16, String)> = read(
+            "company_identifiers/company_identifiers.yaml",
+            "company_identifiers",
+        )
+        .iter()
+        .map(|entry| (number(entry, "value"), name(entry)))
+        .collect();
+        write_table(&mut output, "COMPANY_NAMES", &companies);
+
+        // An appearance value is a 10 bit category followed by a 6 bit subcategory, where
+        // subcategory 0 is the generic value for the category.
+        let mut values = Vec::new();
+        for category in read("core/appearance_values.yaml", "appearance_values") {
+            let value = number(&category, "category") << 6;
+            let category_name = name(&category);
+            values.push((value, category_name.clone()));
+            let subcategories = category.get("subcategory").and_then(Value::as_sequence);
+            for subcategory in subcategories.into_iter().flatten() {
+                values.push((
+                    value | number(subcategory, "value"),
+                    format!("{}: {}", category_name, name(subcategory)),
+                ));
+            }
+        }
+        write_table(&mut output, "APPEARANCE_NAMES", &values);
+        write_constants(
+            &mut output,
+            "appearance",
+            "appearance",
+            "u16",
+            &values,
+            |value| format!("{:#06x}", value),
+        );
+
+        let output_path = Path::new(&env::var("OUT_DIR").unwrap()).join("assigned_numbers.rs");
+        fs::write(output_path, output).unwrap();
+    }
+
+    /// Reads the list under `key` in one of the YAML files. The files are parsed into plain YAML
+    /// values rather than deserialized, so that the build script doesn't need `serde`, which the
+    /// crate's `serde` feature already depends on under another name.
+    fn read(file: &str, key: &str) -> Vec<Value> {
+        let path = Path::new(INPUT_PATH).join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let contents = fs::read_to_string(&path).unwrap();
+        let document: Value = serde_yaml::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e));
+        match document.get(key) {
+            Some(Value::Sequence(entries)) => entries.clone(),
+            _ => panic!("{} has no list of {}", path.display(), key),
+        }
+    }
+
+    fn number(entry: &Value, key: &str) -> u16 {
+        entry
+            .get(key)
+            .and_then(Value::as_u64)
+            .and_then(|value| u16::try_from(value).ok())
+            .unwrap_or_else(|| panic!("Entry has no 16-bit {}: {:?}", key, entry))
+    }
+
+    fn name(entry: &Value) -> String {
+        entry
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_else(|| panic!("Entry has no name: {:?}", entry))
+            .to_string()
+    }
+
+    /// Writes a table of names sorted by value, so that it can be binary searched.
+    fn write_table(output: &mut String, name: &str, entries: &[(u16, String)]) {
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|(value, _)| *value);
+        entries.dedup_by_key(|(value, _)| *value);
+        writeln!(output, "const {}: &[(u16, &str)] = &[", name).unwrap();
+        for (value, name) in entries {
+            writeln!(output, "    ({:#06x}, {:?}),", value, name).unwrap();
+        }
+        writeln!(output, "];").unwrap();
+    }
+
+    /// Writes a module with a constant for each entry, named after it.
+    fn write_constants(
+        output: &mut String,
+        module: &str,
+        kind: &str,
+        type_name: &str,
+        entries: &[(u16, String)],
+        value: impl Fn(u16) -> String,
+    ) {
+        writeln!(output, "/// Constants for the assigned {} values.", kind).unwrap();
+        writeln!(output, "pub mod {} {{", module).unwrap();
+        writeln!(output, "    #[allow(unused_imports)]").unwrap();
+        writeln!(output, "    use super::*;").unwrap();
+        let mut names = HashSet::new();
+        for (number, name) in entries {
+            let mut identifier = identifier(name);
+            if !names.insert(identifier.clone()) {
+                identifier = format!("{}_{:04X}", identifier, number);
+                names.insert(identifier.clone());
+            }
+            writeln!(output).unwrap();
+            writeln!(output, "    /// `{:#06x}` {}", number, name).unwrap();
+            writeln!(
+                output,
+                "    pub const {}: {} = {};",
+                identifier,
+                type_name,
+                value(*number)
+            )
+            .unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+    }
+
+    /// Turns a name into an upper case identifier, such as "Heart Rate" into `HEART_RATE`.
+    fn identifier(name: &str) -> String {
+        let mut identifier = String::new();
+        for word in name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            if !identifier.is_empty() {
+                identifier.push('_');
+            }
+            identifier.push_str(&word.to_ascii_uppercase());
+        }
+        if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+            identifier.insert(0, '_');
+        }
+        identifier
+    }
+}
@@ -20,10 +20,11 @@ use btleplug::winrtble::{adapter::Adapter, manager::Manager};
 
 #[cfg(target_os = "linux")]
 fn print_adapter_info(adapter: &Adapter) {
+    use futures::executor::block_on;
     println!(
         "connected adapter {:?} is powered: {:?}",
-        adapter.name(),
-        adapter.is_powered()
+        block_on(adapter.name()),
+        block_on(adapter.is_powered())
     );
 }
 
@@ -76,15 +77,18 @@ fn main() {
                             peripheral.is_connected(),
                             peripheral.properties().local_name
                         );
-                        let chars = peripheral.discover_characteristics();
+                        if let Err(err) = peripheral.discover_characteristics() {
+                            eprintln!("Can't discover characteristics: {}", err);
+                        }
                         if peripheral.is_connected() {
                             println!(
-                                "Discover peripheral : \'{:?}\' characteristics...",
+                                "Discover peripheral : \'{:?}\' services...",
                                 peripheral.properties().local_name
                             );
-                            for chars_vector in chars.into_iter() {
-                                for char_item in chars_vector.iter() {
-                                    println!("{:?}", char_item);
+                            for service in peripheral.services() {
+                                println!("{}", service);
+                                for characteristic in service.characteristics {
+                                    println!("  {}", characteristic);
                                 }
                             }
                             println!(
@@ -8,6 +8,7 @@ use btleplug::bluez::{adapter::Adapter, manager::Manager};
 use btleplug::corebluetooth::{adapter::Adapter, manager::Manager};
 #[cfg(target_os = "windows")]
 use btleplug::winrtble::{adapter::Adapter, manager::Manager};
+use futures::executor::block_on_stream;
 
 // adapter retrieval works differently depending on your platform right now.
 // API needs to be aligned.
@@ -24,24 +25,18 @@ pub fn main() {
     // connect to the adapter
     let central = get_central(&manager);
 
-    // Each adapter can only have one event receiver. We fetch it via
-    // event_receiver(), which will return an option. The first time the getter
-    // is called, it will return Some(Receiver<CentralEvent>). After that, it
-    // will only return None.
-    //
-    // While this API is awkward, is is done as not to disrupt the adapter
-    // retrieval system in btleplug v0.x while still allowing us to use event
-    // streams/channels instead of callbacks. In btleplug v1.x, we'll retrieve
-    // channels as part of adapter construction.
-    let event_receiver = central.event_receiver().unwrap();
+    // Each call to events() creates a new subscription, which receives every
+    // event from the adapter from then on. Subscriptions are independent of
+    // each other, so other parts of a program can listen for events too.
+    let events = central.events();
 
     // start scanning for devices
     central.start_scan().unwrap();
 
-    // Print based on whatever the event receiver outputs. Note that the event
-    // receiver blocks, so in a real program, this should be run in its own
-    // thread (not task, as this library does not yet use async channels).
-    while let Ok(event) = event_receiver.recv() {
+    // Print based on whatever the event stream outputs. The stream is async,
+    // so here we turn it into a blocking iterator. In a real program, this
+    // should be run in its own thread, or awaited from a task.
+    for event in block_on_stream(events) {
         match event {
             CentralEvent::DeviceDiscovered(bd_addr) => {
                 println!("DeviceDiscovered: {:?}", bd_addr);
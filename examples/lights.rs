@@ -28,8 +28,8 @@ pub fn main() {
 
     // start scanning for devices
     central.start_scan().unwrap();
-    // instead of waiting, you can use central.event_receiver() to get a channel
-    // to listen for notifications on.
+    // instead of waiting, you can use central.events() to get a stream of
+    // notifications to listen on.
     thread::sleep(Duration::from_secs(2));
 
     // find the device we're interested in
@@ -11,34 +11,88 @@
 // following copyright:
 //
 // Copyright (c) 2014 The Rust Project Developers
-use crate::api::{BDAddr, CentralEvent, Peripheral};
-use dashmap::DashMap;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use crate::api::{
+    event_stream::{EventSender, EventStream},
+    BDAddr, CentralEvent, DeviceState, ExpiryPolicy, Peripheral,
+};
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
 
+/// How often events may trigger a sweep for expired peripherals. Looking peripherals up always
+/// sweeps first, so this only affects how soon expiry is reported.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identifies a piece of advertisement data which a peripheral can send repeatedly.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum AdvertisementKey {
+    ManufacturerData(BDAddr, u16),
+    ServiceData(BDAddr, Uuid),
+    Services(BDAddr),
+}
+
+impl AdvertisementKey {
+    fn from_event(event: &CentralEvent) -> Option<Self> {
+        match *event {
+            CentralEvent::ManufacturerDataAdvertisement {
+                address,
+                manufacturer_id,
+                ..
+            } => Some(AdvertisementKey::ManufacturerData(address, manufacturer_id)),
+            CentralEvent::ServiceDataAdvertisement {
+                address, service, ..
+            } => Some(AdvertisementKey::ServiceData(address, service)),
+            CentralEvent::ServicesAdvertisement { address, .. } => {
+                Some(AdvertisementKey::Services(address))
+            }
+            _ => None,
+        }
+    }
+
+    fn address(&self) -> BDAddr {
+        match *self {
+            AdvertisementKey::ManufacturerData(address, _)
+            | AdvertisementKey::ServiceData(address, _)
+            | AdvertisementKey::Services(address) => address,
+        }
+    }
+}
+
+/// A known peripheral, along with where it is in its lifecycle.
+#[derive(Clone, Debug)]
+struct Entry<PeripheralType> {
+    peripheral: PeripheralType,
+    state: DeviceState,
+    /// When the last event about the peripheral was emitted.
+    last_seen: Instant,
+}
+
+/// Keeps track of the peripherals which an adapter knows about, and delivers its events.
+///
+/// Peripherals stay known when they disconnect, and are only forgotten when they are reported as
+/// lost, or once they expire under the [`ExpiryPolicy`].
 #[derive(Clone, Debug)]
 pub struct AdapterManager<PeripheralType>
 where
     PeripheralType: Peripheral,
 {
-    peripherals: Arc<DashMap<BDAddr, PeripheralType>>,
-
-    // Sender is never handled mutably, but mpsc's Sender is Send only, not Sync,
-    // so we can't just wrap it in an Arc to pass it around as part of the adapter
-    // struct. I also don't want to use crossbeam channels, to avoid type leakage.
-    //
-    // This will be fixed when we go async in 1.0 and can use stream traits. For
-    // now, we deal with the lock timing.
-    event_sender: Arc<Mutex<Sender<CentralEvent>>>,
-
-    // Normally we'd just return the event receiver when an adapter is created.
-    // However, since adapters are cloned and retrieved via lists, this is really
-    // hard without changing the fundamentals of the API (which I want to do at
-    // some point, but not now). Storing an option here means that we'll only ever
-    // have one event receiver (as mpsc isn't clonable, which is what we want on
-    // the receiver side anyways), but means we also don't have to deal with the
-    // adapter API yet.
-    event_receiver: Arc<Mutex<Option<Receiver<CentralEvent>>>>,
+    peripherals: Arc<DashMap<BDAddr, Entry<PeripheralType>>>,
+    expiry_policy: Arc<Mutex<ExpiryPolicy>>,
+    /// When expired peripherals were last swept for.
+    last_sweep: Arc<Mutex<Instant>>,
+
+    event_sender: Arc<EventSender<CentralEvent>>,
+
+    /// Whether advertisement events which repeat the last one of their kind are dropped.
+    filter_duplicates: Arc<AtomicBool>,
+    /// The last advertisement event of each kind from each peripheral.
+    advertisements: Arc<DashMap<AdvertisementKey, CentralEvent>>,
 }
 
 impl<PeripheralType> AdapterManager<PeripheralType>
@@ -47,86 +101,215 @@ where
 {
     pub fn new() -> Self {
         let peripherals = Arc::new(DashMap::new());
-        let (event_sender, event_receiver) = channel();
         AdapterManager {
             peripherals,
-            event_sender: Arc::new(Mutex::new(event_sender)),
-            event_receiver: Arc::new(Mutex::new(Some(event_receiver))),
+            expiry_policy: Arc::new(Mutex::new(ExpiryPolicy::default())),
+            last_sweep: Arc::new(Mutex::new(Instant::now())),
+            event_sender: Arc::new(EventSender::new()),
+            filter_duplicates: Arc::new(AtomicBool::new(true)),
+            advertisements: Arc::new(DashMap::new()),
         }
     }
 
     pub fn emit(&self, event: CentralEvent) {
+        let sweep_due = self.last_sweep.lock().unwrap().elapsed() >= SWEEP_INTERVAL;
+        if sweep_due {
+            self.expire();
+        }
         match event {
-            CentralEvent::DeviceDisconnected(addr) => {
-                self.peripherals.remove(&addr);
-            }
             CentralEvent::DeviceLost(addr) => {
                 self.peripherals.remove(&addr);
+                self.advertisements.retain(|key, _| key.address() != addr);
+            }
+            _ => {
+                if let Some(mut entry) = self.peripherals.get_mut(&event.address()) {
+                    entry.last_seen = Instant::now();
+                    match event {
+                        CentralEvent::DeviceConnected(_) => entry.state = DeviceState::Connected,
+                        CentralEvent::DeviceDisconnected(_) => {
+                            entry.state = DeviceState::Disconnected
+                        }
+                        _ => {}
+                    }
+                }
             }
-            _ => {}
         }
-        // Since we hold a receiver, this will never fail unless we fill the
-        // channel. Whether that's a good idea is another question entirely.
-        self.event_sender.lock().unwrap().send(event).unwrap();
+        if let Some(key) = AdvertisementKey::from_event(&event) {
+            let previous = self.advertisements.insert(key, event.clone());
+            if self.filter_duplicates() && previous.as_ref() == Some(&event) {
+                return;
+            }
+        }
+        self.event_sender.send(event);
+    }
+
+    /// Returns whether advertisement events which repeat the last one of their kind from the same
+    /// peripheral are dropped. This is on by default.
+    pub fn filter_duplicates(&self) -> bool {
+        self.filter_duplicates.load(Ordering::Relaxed)
     }
 
-    pub fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.event_receiver.lock().unwrap().take()
+    /// Sets whether advertisement events which repeat the last one of their kind from the same
+    /// peripheral are dropped.
+    pub fn set_filter_duplicates(&self, enabled: bool) {
+        self.filter_duplicates.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Forgets the advertisements seen so far, so that the next one of each kind is reported even
+    /// if duplicates are being filtered. This should be called when a new scan starts.
+    pub fn reset_duplicates(&self) {
+        self.advertisements.clear();
+    }
+
+    pub fn events(&self, buffer: usize) -> EventStream<CentralEvent> {
+        self.event_sender.subscribe(buffer)
+    }
+
+    pub fn expiry_policy(&self) -> ExpiryPolicy {
+        *self.expiry_policy.lock().unwrap()
+    }
+
+    /// Sets when peripherals which haven't been heard from are forgotten.
+    pub fn set_expiry_policy(&self, policy: ExpiryPolicy) {
+        *self.expiry_policy.lock().unwrap() = policy;
+        self.expire();
+    }
+
+    /// Forgets the peripherals which have expired under the expiry policy, and emits
+    /// [`CentralEvent::DeviceExpired`] for each of them. Connected peripherals never expire.
+    pub fn expire(&self) {
+        *self.last_sweep.lock().unwrap() = Instant::now();
+        let timeout = match self.expiry_policy() {
+            ExpiryPolicy::Never => return,
+            ExpiryPolicy::After(timeout) => timeout,
+        };
+        let mut expired = Vec::new();
+        self.peripherals.retain(|&address, entry| {
+            let keep = entry.state == DeviceState::Connected || entry.last_seen.elapsed() < timeout;
+            if !keep {
+                expired.push(address);
+            }
+            keep
+        });
+        for address in expired {
+            self.advertisements
+                .retain(|key, _| key.address() != address);
+            self.event_sender.send(CentralEvent::DeviceExpired(address));
+        }
     }
 
     pub fn has_peripheral(&self, addr: &BDAddr) -> bool {
+        self.expire();
         self.peripherals.contains_key(addr)
     }
 
-    pub fn add_peripheral(&self, addr: BDAddr, peripheral: PeripheralType) {
-        assert!(
-            !self.peripherals.contains_key(&addr),
-            "Adding a peripheral that's already in the map."
-        );
-        assert_eq!(peripheral.address(), addr, "Device has unexpected address."); // TODO remove addr argument
-        self.peripherals.insert(addr, peripheral);
-    }
-
-    pub fn update_peripheral(&self, addr: BDAddr, peripheral: PeripheralType) {
-        // FIXME: this function is too easy to use incorrectly.
-        // when you get a peripheral from self.peripheral() you can forget to call update_peripheral easily.
-        // and when you do, there is no guarantee that it hasn't been updated by someone else, causing changes
-        // to be lost.
-        // one way to fix it is to make this function work more similarly to (and/or use) DashMap::alter.
-        // also, firing of events (self.emit) should probably be done here, instead of in peripheral impls.
-        assert!(
-            self.peripherals.contains_key(&addr),
-            "Updating a peripheral that's not in the map."
-        );
-        assert_eq!(peripheral.address(), addr, "Device has unexpected address."); // TODO remove addr argument
-                                                                                  // We already have this peripheral in the map, so there's no need to readd it.
-        let current_peripheral = self.peripherals.get_mut(&addr).unwrap();
-        // Update name if it's none.
-        if peripheral.properties().local_name.is_some()
-            && current_peripheral.properties().local_name.is_none()
-        {
-            current_peripheral.properties().local_name = peripheral.properties().local_name;
-        }
-        if !peripheral.properties().manufacturer_data.is_empty()
-            && current_peripheral.properties().manufacturer_data.is_empty()
-        {
-            current_peripheral.properties().manufacturer_data =
-                peripheral.properties().manufacturer_data;
+    /// Returns where the peripheral is in its lifecycle, if it is known.
+    pub fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        self.expire();
+        self.peripherals.get(&address).map(|entry| entry.state)
+    }
+
+    /// Applies `update` to the peripheral with the given address, adding the one which `create`
+    /// makes first if the address isn't known yet. `create` may return `None` to leave the address
+    /// unknown, in which case there is nothing to update. `update` returns whether it changed the
+    /// peripheral's properties, and the manager then emits [`CentralEvent::DeviceDiscovered`] for
+    /// a new peripheral, or [`CentralEvent::DeviceUpdated`] for a changed one.
+    ///
+    /// Creating and updating the peripheral happen while its entry is locked, so they are one step
+    /// to everyone else, and must not use the manager. Any events which `update` has to report go
+    /// in the list it is given, and are emitted after the discovery or update.
+    pub fn alter_peripheral<C, U>(&self, address: BDAddr, create: C, update: U)
+    where
+        C: FnOnce() -> Option<PeripheralType>,
+        U: FnOnce(&PeripheralType, &mut Vec<CentralEvent>) -> bool,
+    {
+        let mut events = Vec::new();
+        let event = match self.peripherals.entry(address) {
+            MapEntry::Occupied(entry) => {
+                if update(&entry.get().peripheral, &mut events) {
+                    Some(CentralEvent::DeviceUpdated(address))
+                } else {
+                    None
+                }
+            }
+            MapEntry::Vacant(entry) => {
+                let peripheral = match create() {
+                    Some(peripheral) => peripheral,
+                    None => return,
+                };
+                assert_eq!(
+                    peripheral.address(),
+                    address,
+                    "Device has unexpected address."
+                );
+                update(&peripheral, &mut events);
+                entry.insert(Entry {
+                    peripheral,
+                    state: DeviceState::Discovered,
+                    last_seen: Instant::now(),
+                });
+                Some(CentralEvent::DeviceDiscovered(address))
+            }
+        };
+        for event in event.into_iter().chain(events) {
+            self.emit(event);
         }
-        // Update RSSI
-        current_peripheral.properties().tx_power_level = peripheral.properties().tx_power_level;
     }
 
     pub fn peripherals(&self) -> Vec<PeripheralType> {
+        self.expire();
         self.peripherals
             .iter()
-            .map(|val| val.value().clone())
+            .map(|entry| entry.peripheral.clone())
             .collect()
     }
 
     pub fn peripheral(&self, address: BDAddr) -> Option<PeripheralType> {
+        self.expire();
         self.peripherals
             .get(&address)
-            .map(|val| val.value().clone())
+            .map(|entry| entry.peripheral.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::peripheral::Peripheral as MockPeripheral;
+    use futures::{executor::block_on, stream::StreamExt};
+
+    fn advertisement(address: BDAddr, data: &[u8]) -> CentralEvent {
+        CentralEvent::ManufacturerDataAdvertisement {
+            address,
+            manufacturer_id: 0x004c,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn duplicate_advertisements_are_filtered() {
+        let manager = AdapterManager::<MockPeripheral>::new();
+        let mut events = manager.events(16);
+        let address = BDAddr::default();
+
+        manager.emit(advertisement(address, &[1]));
+        manager.emit(advertisement(address, &[1]));
+        manager.emit(advertisement(address, &[2]));
+        manager.emit(CentralEvent::DeviceLost(address));
+        manager.emit(advertisement(address, &[2]));
+        manager.set_filter_duplicates(false);
+        manager.emit(advertisement(address, &[2]));
+
+        let events: Vec<_> = block_on(events.by_ref().take(5).collect());
+        assert_eq!(
+            events,
+            vec![
+                advertisement(address, &[1]),
+                advertisement(address, &[2]),
+                CentralEvent::DeviceLost(address),
+                advertisement(address, &[2]),
+                advertisement(address, &[2]),
+            ]
+        );
     }
 }
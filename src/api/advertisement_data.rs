@@ -0,0 +1,591 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Parsing and serialization of raw advertising data.
+//!
+//! Advertisements, scan responses and classic extended inquiry responses all carry a sequence of
+//! AD structures, each made up of a length octet, an AD type octet and the data. This module
+//! decodes such a sequence into an [`AdvertisementData`], which keeps the distinctions that
+//! platform APIs tend to drop (such as whether a UUID was sent in its 16-bit, 32-bit or 128-bit
+//! form), and encodes it back.
+
+use super::bleuuid::{uuid_from_u16, uuid_from_u32};
+use crate::Error;
+use bitflags::bitflags;
+use std::{collections::HashMap, convert::TryInto, time::Duration};
+use thiserror::Error;
+use uuid::Uuid;
+
+const FLAGS: u8 = 0x01;
+const INCOMPLETE_SERVICES_16: u8 = 0x02;
+const COMPLETE_SERVICES_16: u8 = 0x03;
+const INCOMPLETE_SERVICES_32: u8 = 0x04;
+const COMPLETE_SERVICES_32: u8 = 0x05;
+const INCOMPLETE_SERVICES_128: u8 = 0x06;
+const COMPLETE_SERVICES_128: u8 = 0x07;
+const SHORTENED_LOCAL_NAME: u8 = 0x08;
+const COMPLETE_LOCAL_NAME: u8 = 0x09;
+const TX_POWER_LEVEL: u8 = 0x0A;
+const SOLICITED_SERVICES_16: u8 = 0x14;
+const SOLICITED_SERVICES_128: u8 = 0x15;
+const SERVICE_DATA_16: u8 = 0x16;
+const APPEARANCE: u8 = 0x19;
+const ADVERTISING_INTERVAL: u8 = 0x1A;
+const SOLICITED_SERVICES_32: u8 = 0x1F;
+const SERVICE_DATA_32: u8 = 0x20;
+const SERVICE_DATA_128: u8 = 0x21;
+const URI: u8 = 0x24;
+const LE_SUPPORTED_FEATURES: u8 = 0x27;
+const ADVERTISING_INTERVAL_LONG: u8 = 0x2F;
+const MANUFACTURER_DATA: u8 = 0xFF;
+
+/// The URI schemes which have been assigned a code point, so that a URI can be advertised without
+/// spelling out its scheme. Code point 0x01 stands for a URI which is sent as is.
+const URI_SCHEMES: &[(char, &str)] = &[
+    ('\u{01}', ""),
+    ('\u{02}', "aaa:"),
+    ('\u{03}', "aaas:"),
+    ('\u{04}', "about:"),
+    ('\u{05}', "acap:"),
+    ('\u{06}', "acct:"),
+    ('\u{07}', "cap:"),
+    ('\u{08}', "cid:"),
+    ('\u{09}', "coap:"),
+    ('\u{0A}', "coaps:"),
+    ('\u{0B}', "crid:"),
+    ('\u{0C}', "data:"),
+    ('\u{0D}', "dav:"),
+    ('\u{0E}', "dict:"),
+    ('\u{0F}', "dns:"),
+    ('\u{10}', "file:"),
+    ('\u{11}', "ftp:"),
+    ('\u{12}', "geo:"),
+    ('\u{13}', "go:"),
+    ('\u{14}', "gopher:"),
+    ('\u{15}', "h323:"),
+    ('\u{16}', "http:"),
+    ('\u{17}', "https:"),
+];
+
+/// The most data which fits in a single AD structure, after the length and AD type octets.
+const MAX_DATA_LENGTH: usize = 254;
+
+/// The length of one unit of the advertising interval.
+const ADVERTISING_INTERVAL_UNIT: Duration = Duration::from_micros(625);
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisementDataError {
+    #[error("AD structure at offset {offset} runs past the end of the data")]
+    Truncated { offset: usize },
+    #[error("Malformed AD structure of type {ad_type:#04x}")]
+    Malformed { ad_type: u8 },
+    #[error("AD structure of type {ad_type:#04x} doesn't fit in 255 bytes")]
+    TooLong { ad_type: u8 },
+}
+
+impl From<AdvertisementDataError> for Error {
+    fn from(e: AdvertisementDataError) -> Self {
+        Error::Other(format!("AdvertisementDataError: {}", e))
+    }
+}
+
+bitflags! {
+    /// The flags which a device advertises to say how it can be discovered and which transports
+    /// it supports.
+    pub struct AdvertisingFlags: u8 {
+        const LE_LIMITED_DISCOVERABLE = 0x01;
+        const LE_GENERAL_DISCOVERABLE = 0x02;
+        const BR_EDR_NOT_SUPPORTED = 0x04;
+        const SIMULTANEOUS_LE_BR_EDR_CONTROLLER = 0x08;
+        const SIMULTANEOUS_LE_BR_EDR_HOST = 0x10;
+    }
+}
+
+/// The name which a device advertises for itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LocalName {
+    /// The start of the name, as the whole name didn't fit in the advertisement.
+    Shortened(String),
+    /// The whole name.
+    Complete(String),
+}
+
+impl LocalName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            LocalName::Shortened(name) | LocalName::Complete(name) => name,
+        }
+    }
+}
+
+/// A list of service UUIDs of one size.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ServiceList<T> {
+    pub uuids: Vec<T>,
+    /// Whether these are all the services of this size which the device offers, rather than only
+    /// some of them.
+    pub complete: bool,
+}
+
+/// The typed contents of a sequence of AD structures.
+///
+/// Parsing and then serializing some data gives back the same AD structures, but they may come out
+/// in a different order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AdvertisementData {
+    pub flags: Option<AdvertisingFlags>,
+    pub local_name: Option<LocalName>,
+    /// The services sent as 16-bit UUIDs.
+    pub services_16: Option<ServiceList<u16>>,
+    /// The services sent as 32-bit UUIDs.
+    pub services_32: Option<ServiceList<u32>>,
+    /// The services sent as full 128-bit UUIDs.
+    pub services_128: Option<ServiceList<Uuid>>,
+    /// The services which the device would like a central to offer, sent as 16-bit UUIDs.
+    pub solicited_services_16: Vec<u16>,
+    /// The services which the device would like a central to offer, sent as 32-bit UUIDs.
+    pub solicited_services_32: Vec<u32>,
+    /// The services which the device would like a central to offer, sent as 128-bit UUIDs.
+    pub solicited_services_128: Vec<Uuid>,
+    pub service_data_16: Vec<(u16, Vec<u8>)>,
+    pub service_data_32: Vec<(u32, Vec<u8>)>,
+    pub service_data_128: Vec<(Uuid, Vec<u8>)>,
+    /// Data specific to the device manufacturer, keyed by company identifier.
+    pub manufacturer_data: Vec<(u16, Vec<u8>)>,
+    /// The transmission power level, in dBm.
+    pub tx_power_level: Option<i8>,
+    /// The external appearance of the device, as a value from the assigned numbers.
+    pub appearance: Option<u16>,
+    /// The advertising interval, in units of 0.625 ms.
+    pub advertising_interval: Option<u32>,
+    pub uri: Option<String>,
+    /// The Link Layer features which the device supports, as a bit mask.
+    pub le_supported_features: Option<u64>,
+    /// The AD structures of any other type, as pairs of AD type and data.
+    pub other: Vec<(u8, Vec<u8>)>,
+}
+
+impl AdvertisementData {
+    /// Parses a sequence of AD structures. Parsing stops at the first structure with a length of
+    /// zero, as the rest of the data is padding.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AdvertisementDataError> {
+        let mut data = AdvertisementData::default();
+        let mut offset = 0;
+        while let Some(&length) = bytes.get(offset) {
+            if length == 0 {
+                break;
+            }
+            let structure = bytes
+                .get(offset + 1..offset + 1 + length as usize)
+                .ok_or(AdvertisementDataError::Truncated { offset })?;
+            data.decode(structure[0], &structure[1..])?;
+            offset += 1 + length as usize;
+        }
+        Ok(data)
+    }
+
+    fn decode(&mut self, ad_type: u8, data: &[u8]) -> Result<(), AdvertisementDataError> {
+        let malformed = AdvertisementDataError::Malformed { ad_type };
+        match ad_type {
+            FLAGS => {
+                let flags = data.first().ok_or(malformed)?;
+                self.flags = Some(AdvertisingFlags::from_bits_truncate(*flags));
+            }
+            INCOMPLETE_SERVICES_16 | COMPLETE_SERVICES_16 => {
+                let list = self.services_16.get_or_insert_with(ServiceList::default);
+                list.uuids
+                    .extend(chunks(data, u16::from_le_bytes).ok_or(malformed)?);
+                list.complete |= ad_type == COMPLETE_SERVICES_16;
+            }
+            INCOMPLETE_SERVICES_32 | COMPLETE_SERVICES_32 => {
+                let list = self.services_32.get_or_insert_with(ServiceList::default);
+                list.uuids
+                    .extend(chunks(data, u32::from_le_bytes).ok_or(malformed)?);
+                list.complete |= ad_type == COMPLETE_SERVICES_32;
+            }
+            INCOMPLETE_SERVICES_128 | COMPLETE_SERVICES_128 => {
+                let list = self.services_128.get_or_insert_with(ServiceList::default);
+                list.uuids
+                    .extend(chunks(data, uuid_from_le_bytes).ok_or(malformed)?);
+                list.complete |= ad_type == COMPLETE_SERVICES_128;
+            }
+            SHORTENED_LOCAL_NAME | COMPLETE_LOCAL_NAME => {
+                let name = String::from_utf8(data.to_vec()).map_err(|_| malformed)?;
+                self.local_name = Some(if ad_type == COMPLETE_LOCAL_NAME {
+                    LocalName::Complete(name)
+                } else {
+                    LocalName::Shortened(name)
+                });
+            }
+            TX_POWER_LEVEL => match data {
+                [level] => self.tx_power_level = Some(*level as i8),
+                _ => return Err(malformed),
+            },
+            SOLICITED_SERVICES_16 => self
+                .solicited_services_16
+                .extend(chunks(data, u16::from_le_bytes).ok_or(malformed)?),
+            SOLICITED_SERVICES_32 => self
+                .solicited_services_32
+                .extend(chunks(data, u32::from_le_bytes).ok_or(malformed)?),
+            SOLICITED_SERVICES_128 => self
+                .solicited_services_128
+                .extend(chunks(data, uuid_from_le_bytes).ok_or(malformed)?),
+            SERVICE_DATA_16 => {
+                let (uuid, value) = split(data, u16::from_le_bytes).ok_or(malformed)?;
+                self.service_data_16.push((uuid, value));
+            }
+            SERVICE_DATA_32 => {
+                let (uuid, value) = split(data, u32::from_le_bytes).ok_or(malformed)?;
+                self.service_data_32.push((uuid, value));
+            }
+            SERVICE_DATA_128 => {
+                let (uuid, value) = split(data, uuid_from_le_bytes).ok_or(malformed)?;
+                self.service_data_128.push((uuid, value));
+            }
+            MANUFACTURER_DATA => {
+                let (company, value) = split(data, u16::from_le_bytes).ok_or(malformed)?;
+                self.manufacturer_data.push((company, value));
+            }
+            APPEARANCE => match data {
+                [low, high] => self.appearance = Some(u16::from_le_bytes([*low, *high])),
+                _ => return Err(malformed),
+            },
+            ADVERTISING_INTERVAL | ADVERTISING_INTERVAL_LONG => {
+                let valid = if ad_type == ADVERTISING_INTERVAL {
+                    data.len() == 2
+                } else {
+                    data.len() == 3 || data.len() == 4
+                };
+                if !valid {
+                    return Err(malformed);
+                }
+                self.advertising_interval = Some(le_uint(data) as u32);
+            }
+            URI => {
+                let uri = std::str::from_utf8(data).map_err(|_| malformed)?;
+                let mut chars = uri.chars();
+                let code = chars.next().ok_or(malformed)?;
+                let (_, scheme) = URI_SCHEMES
+                    .iter()
+                    .find(|(scheme_code, _)| *scheme_code == code)
+                    .ok_or(malformed)?;
+                self.uri = Some(format!("{}{}", scheme, chars.as_str()));
+            }
+            LE_SUPPORTED_FEATURES => {
+                if data.len() > 8 {
+                    return Err(malformed);
+                }
+                self.le_supported_features = Some(le_uint(data));
+            }
+            _ => self.other.push((ad_type, data.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Serializes the data as a sequence of AD structures.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AdvertisementDataError> {
+        let mut bytes = Vec::new();
+        let mut push = |ad_type: u8, data: &[u8]| {
+            if data.len() > MAX_DATA_LENGTH {
+                return Err(AdvertisementDataError::TooLong { ad_type });
+            }
+            bytes.push(data.len() as u8 + 1);
+            bytes.push(ad_type);
+            bytes.extend_from_slice(data);
+            Ok(())
+        };
+
+        if let Some(flags) = self.flags {
+            push(FLAGS, &[flags.bits()])?;
+        }
+        if let Some(list) = &self.services_16 {
+            let ad_type = if list.complete {
+                COMPLETE_SERVICES_16
+            } else {
+                INCOMPLETE_SERVICES_16
+            };
+            push(ad_type, &join(&list.uuids, |uuid| uuid.to_le_bytes()))?;
+        }
+        if let Some(list) = &self.services_32 {
+            let ad_type = if list.complete {
+                COMPLETE_SERVICES_32
+            } else {
+                INCOMPLETE_SERVICES_32
+            };
+            push(ad_type, &join(&list.uuids, |uuid| uuid.to_le_bytes()))?;
+        }
+        if let Some(list) = &self.services_128 {
+            let ad_type = if list.complete {
+                COMPLETE_SERVICES_128
+            } else {
+                INCOMPLETE_SERVICES_128
+            };
+            push(ad_type, &join(&list.uuids, uuid_to_le_bytes))?;
+        }
+        match &self.local_name {
+            Some(LocalName::Shortened(name)) => push(SHORTENED_LOCAL_NAME, name.as_bytes())?,
+            Some(LocalName::Complete(name)) => push(COMPLETE_LOCAL_NAME, name.as_bytes())?,
+            None => {}
+        }
+        if let Some(level) = self.tx_power_level {
+            push(TX_POWER_LEVEL, &[level as u8])?;
+        }
+        if !self.solicited_services_16.is_empty() {
+            let data = join(&self.solicited_services_16, |uuid| uuid.to_le_bytes());
+            push(SOLICITED_SERVICES_16, &data)?;
+        }
+        if !self.solicited_services_32.is_empty() {
+            let data = join(&self.solicited_services_32, |uuid| uuid.to_le_bytes());
+            push(SOLICITED_SERVICES_32, &data)?;
+        }
+        if !self.solicited_services_128.is_empty() {
+            let data = join(&self.solicited_services_128, uuid_to_le_bytes);
+            push(SOLICITED_SERVICES_128, &data)?;
+        }
+        for (uuid, value) in &self.service_data_16 {
+            push(SERVICE_DATA_16, &[&uuid.to_le_bytes()[..], value].concat())?;
+        }
+        for (uuid, value) in &self.service_data_32 {
+            push(SERVICE_DATA_32, &[&uuid.to_le_bytes()[..], value].concat())?;
+        }
+        for (uuid, value) in &self.service_data_128 {
+            push(
+                SERVICE_DATA_128,
+                &[&uuid_to_le_bytes(uuid)[..], value].concat(),
+            )?;
+        }
+        if let Some(appearance) = self.appearance {
+            push(APPEARANCE, &appearance.to_le_bytes())?;
+        }
+        if let Some(interval) = self.advertising_interval {
+            let bytes = interval.to_le_bytes();
+            if interval <= 0xFFFF {
+                push(ADVERTISING_INTERVAL, &bytes[..2])?;
+            } else if interval <= 0xFF_FFFF {
+                push(ADVERTISING_INTERVAL_LONG, &bytes[..3])?;
+            } else {
+                push(ADVERTISING_INTERVAL_LONG, &bytes)?;
+            }
+        }
+        if let Some(uri) = &self.uri {
+            // Use the longest scheme which matches, so that "https:" wins over "http:".
+            let (code, scheme) = URI_SCHEMES
+                .iter()
+                .filter(|(_, scheme)| uri.starts_with(scheme))
+                .max_by_key(|(_, scheme)| scheme.len())
+                .unwrap();
+            let mut data = code.to_string();
+            data.push_str(&uri[scheme.len()..]);
+            push(URI, data.as_bytes())?;
+        }
+        if let Some(features) = self.le_supported_features {
+            // Trailing octets which are all zero may be left out.
+            let bytes = features.to_le_bytes();
+            let length = bytes.iter().rposition(|&b| b != 0).map_or(1, |i| i + 1);
+            push(LE_SUPPORTED_FEATURES, &bytes[..length])?;
+        }
+        for (company, value) in &self.manufacturer_data {
+            push(
+                MANUFACTURER_DATA,
+                &[&company.to_le_bytes()[..], value].concat(),
+            )?;
+        }
+        for (ad_type, data) in &self.other {
+            push(*ad_type, data)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Returns every advertised service as a full 128-bit UUID, whichever size it was sent as.
+    pub fn services(&self) -> Vec<Uuid> {
+        let services_16 = self.services_16.iter().flat_map(|list| &list.uuids);
+        let services_32 = self.services_32.iter().flat_map(|list| &list.uuids);
+        let services_128 = self.services_128.iter().flat_map(|list| &list.uuids);
+        services_16
+            .map(|&uuid| uuid_from_u16(uuid))
+            .chain(services_32.map(|&uuid| uuid_from_u32(uuid)))
+            .chain(services_128.copied())
+            .collect()
+    }
+
+    /// Returns every solicited service as a full 128-bit UUID, whichever size it was sent as.
+    pub fn solicited_services(&self) -> Vec<Uuid> {
+        self.solicited_services_16
+            .iter()
+            .map(|&uuid| uuid_from_u16(uuid))
+            .chain(
+                self.solicited_services_32
+                    .iter()
+                    .map(|&uuid| uuid_from_u32(uuid)),
+            )
+            .chain(self.solicited_services_128.iter().copied())
+            .collect()
+    }
+
+    /// Returns the service data keyed by full 128-bit UUIDs, as in
+    /// [`PeripheralProperties::service_data`](super::PeripheralProperties::service_data).
+    pub fn service_data(&self) -> HashMap<Uuid, Vec<u8>> {
+        let service_data_16 = self
+            .service_data_16
+            .iter()
+            .map(|(uuid, value)| (uuid_from_u16(*uuid), value.clone()));
+        let service_data_32 = self
+            .service_data_32
+            .iter()
+            .map(|(uuid, value)| (uuid_from_u32(*uuid), value.clone()));
+        service_data_16
+            .chain(service_data_32)
+            .chain(self.service_data_128.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the advertising interval as a duration.
+    pub fn advertising_interval_duration(&self) -> Option<Duration> {
+        self.advertising_interval
+            .map(|interval| ADVERTISING_INTERVAL_UNIT * interval)
+    }
+}
+
+/// Splits `data` into values of `N` bytes each, or returns `None` if its length isn't a multiple of
+/// `N`.
+fn chunks<T, const N: usize>(data: &[u8], from_bytes: fn([u8; N]) -> T) -> Option<Vec<T>> {
+    let chunks = data.chunks_exact(N);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    Some(
+        chunks
+            .map(|chunk| from_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+/// Splits the value of `N` bytes off the start of `data`, returning it along with the rest.
+fn split<T, const N: usize>(data: &[u8], from_bytes: fn([u8; N]) -> T) -> Option<(T, Vec<u8>)> {
+    if data.len() < N {
+        return None;
+    }
+    let (head, tail) = data.split_at(N);
+    Some((from_bytes(head.try_into().unwrap()), tail.to_vec()))
+}
+
+fn join<T, const N: usize>(values: &[T], to_bytes: impl Fn(&T) -> [u8; N]) -> Vec<u8> {
+    values.iter().flat_map(to_bytes).collect()
+}
+
+fn uuid_from_le_bytes(bytes: [u8; 16]) -> Uuid {
+    Uuid::from_u128(u128::from_le_bytes(bytes))
+}
+
+fn uuid_to_le_bytes(uuid: &Uuid) -> [u8; 16] {
+    uuid.as_u128().to_le_bytes()
+}
+
+/// Reads an unsigned little-endian integer of up to 8 bytes.
+fn le_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_advertisement() {
+        let bytes = [
+            0x02, 0x01, 0x06, // Flags
+            0x05, 0x03, 0x0F, 0x18, 0x0A, 0x18, // Complete 16-bit services
+            0x06, 0x09, b'T', b'h', b'e', b'r', b'm', // Complete local name
+            0x02, 0x0A, 0xF4, // TX power level
+            0x05, 0xFF, 0x4C, 0x00, 0x01, 0x02, // Manufacturer data
+            0x00, 0x00, 0x00, // Padding
+        ];
+        let data = AdvertisementData::parse(&bytes).unwrap();
+        assert_eq!(
+            data,
+            AdvertisementData {
+                flags: Some(
+                    AdvertisingFlags::LE_GENERAL_DISCOVERABLE
+                        | AdvertisingFlags::BR_EDR_NOT_SUPPORTED
+                ),
+                local_name: Some(LocalName::Complete("Therm".to_string())),
+                services_16: Some(ServiceList {
+                    uuids: vec![0x180F, 0x180A],
+                    complete: true,
+                }),
+                tx_power_level: Some(-12),
+                manufacturer_data: vec![(0x004C, vec![0x01, 0x02])],
+                ..AdvertisementData::default()
+            }
+        );
+        assert_eq!(
+            data.services(),
+            vec![uuid_from_u16(0x180F), uuid_from_u16(0x180A)]
+        );
+        assert_eq!(data.to_bytes().unwrap(), bytes[..bytes.len() - 3]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = AdvertisementData {
+            services_32: Some(ServiceList {
+                uuids: vec![0x12345678],
+                complete: false,
+            }),
+            services_128: Some(ServiceList {
+                uuids: vec![Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e)],
+                complete: true,
+            }),
+            solicited_services_16: vec![0x1812],
+            service_data_16: vec![(0xFEAA, vec![0x10, 0x00])],
+            service_data_128: vec![(Uuid::from_u128(1), vec![])],
+            appearance: Some(0x03C1),
+            advertising_interval: Some(0x01_0000),
+            uri: Some("https://example.com".to_string()),
+            le_supported_features: Some(0x01_0001),
+            other: vec![(0x1C, vec![0x00])],
+            ..AdvertisementData::default()
+        };
+        let bytes = data.to_bytes().unwrap();
+        assert_eq!(AdvertisementData::parse(&bytes).unwrap(), data);
+        assert_eq!(
+            data.advertising_interval_duration(),
+            Some(Duration::from_millis(40960))
+        );
+    }
+
+    #[test]
+    fn uri_schemes() {
+        let data = AdvertisementData::parse(b"\x0c\x24\x17//btleplug").unwrap();
+        assert_eq!(data.uri.as_deref(), Some("https://btleplug"));
+        let data = AdvertisementData::parse(b"\x06\x24\x01urn:").unwrap();
+        assert_eq!(data.uri.as_deref(), Some("urn:"));
+        assert_eq!(data.to_bytes().unwrap(), b"\x06\x24\x01urn:");
+    }
+
+    #[test]
+    fn malformed_data() {
+        assert_eq!(
+            AdvertisementData::parse(&[0x02, 0x01, 0x06, 0x05, 0x03, 0x0F]),
+            Err(AdvertisementDataError::Truncated { offset: 3 })
+        );
+        assert_eq!(
+            AdvertisementData::parse(&[0x04, 0x03, 0x0F, 0x18, 0x0A]),
+            Err(AdvertisementDataError::Malformed { ad_type: 0x03 })
+        );
+        let data = AdvertisementData {
+            manufacturer_data: vec![(0x004C, vec![0; 253])],
+            ..AdvertisementData::default()
+        };
+        assert_eq!(
+            data.to_bytes(),
+            Err(AdvertisementDataError::TooLong { ad_type: 0xFF })
+        );
+    }
+}
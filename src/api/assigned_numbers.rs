@@ -0,0 +1,78 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The numbers which the Bluetooth SIG assigns: the names of standard services, characteristics
+//! and descriptors, company identifiers, and appearance values.
+//!
+//! The tables and constants are generated at build time from the SIG's YAML files in the
+//! `assigned_numbers` directory of the crate, so updating those files is all it takes to pick up
+//! newly assigned numbers. The constants are named after the entries, for example
+//! [`services::HEART_RATE`] or [`appearance::WATCH_SPORTS_WATCH`].
+
+use super::bleuuid::uuid_from_u16;
+use uuid::Uuid;
+
+include!(concat!(env!("OUT_DIR"), "/assigned_numbers.rs"));
+
+fn lookup(table: &'static [(u16, &'static str)], value: u16) -> Option<&'static str> {
+    table
+        .binary_search_by_key(&value, |(number, _)| *number)
+        .ok()
+        .map(|index| table[index].1)
+}
+
+/// Returns the name of the standard service with the given 16-bit UUID.
+pub fn service_name(uuid: u16) -> Option<&'static str> {
+    lookup(SERVICE_NAMES, uuid)
+}
+
+/// Returns the name of the standard characteristic with the given 16-bit UUID.
+pub fn characteristic_name(uuid: u16) -> Option<&'static str> {
+    lookup(CHARACTERISTIC_NAMES, uuid)
+}
+
+/// Returns the name of the standard descriptor with the given 16-bit UUID.
+pub fn descriptor_name(uuid: u16) -> Option<&'static str> {
+    lookup(DESCRIPTOR_NAMES, uuid)
+}
+
+/// Returns the name of the company with the given identifier, as used for the keys of
+/// [`PeripheralProperties::manufacturer_data`](super::PeripheralProperties::manufacturer_data).
+pub fn company_name(company_id: u16) -> Option<&'static str> {
+    lookup(COMPANY_NAMES, company_id)
+}
+
+/// Returns the name of an appearance value, such as "Watch: Sports Watch". A value with a
+/// subcategory which isn't known is given the name of its category.
+pub fn appearance_name(appearance: u16) -> Option<&'static str> {
+    lookup(APPEARANCE_NAMES, appearance).or_else(|| lookup(APPEARANCE_NAMES, appearance & !0x3F))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::bleuuid::BleUuid;
+
+    #[test]
+    fn names() {
+        assert_eq!(service_name(0x180D), Some("Heart Rate"));
+        assert_eq!(characteristic_name(0x2A37), Some("Heart Rate Measurement"));
+        assert_eq!(
+            descriptor_name(0x2902),
+            Some("Client Characteristic Configuration")
+        );
+        assert_eq!(company_name(0x004C), Some("Apple, Inc."));
+        assert_eq!(company_name(0xFFFE), None);
+        assert_eq!(appearance_name(0x00C1), Some("Watch: Sports Watch"));
+        assert_eq!(appearance_name(0x00FF), Some("Watch"));
+
+        assert_eq!(services::HEART_RATE, uuid_from_u16(0x180D));
+        assert_eq!(appearance::WATCH_SPORTS_WATCH, 0x00C1);
+        assert_eq!(services::BATTERY.ble_name(), Some("Battery"));
+        assert_eq!(Uuid::from_u128(1).ble_name(), None);
+    }
+}
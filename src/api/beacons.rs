@@ -0,0 +1,492 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Decoding of the common beacon formats: iBeacon, AltBeacon and Eddystone.
+//!
+//! Beacons send their frames as manufacturer data (iBeacon and AltBeacon) or as service data for
+//! the Eddystone service (Eddystone). A [`Beacon`] can be decoded from the properties of a
+//! peripheral, from an advertisement event, or from the raw data, and
+//! [`BeaconCentral::beacon_events`] turns the events of a central into a stream of beacon frames.
+
+use super::{
+    bleuuid::uuid_from_u16, AsyncCentral, AsyncPeripheral, BDAddr, CentralEvent, EventStream,
+    PeripheralProperties,
+};
+use futures::stream::Stream;
+use std::{
+    convert::TryInto,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// The company identifier of Apple, under which iBeacon frames are sent.
+pub const APPLE_COMPANY_ID: u16 = 0x004C;
+/// The 16-bit UUID of the Eddystone service, under which Eddystone frames are sent.
+pub const EDDYSTONE_SERVICE: u16 = 0xFEAA;
+
+const IBEACON_PREFIX: [u8; 2] = [0x02, 0x15];
+const ALTBEACON_PREFIX: [u8; 2] = [0xBE, 0xAC];
+
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+const EDDYSTONE_EID: u8 = 0x30;
+
+const EDDYSTONE_URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+const EDDYSTONE_URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// An Apple iBeacon frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IBeacon {
+    /// The proximity UUID, which usually identifies the organisation running the beacons.
+    pub uuid: Uuid,
+    pub major: u16,
+    pub minor: u16,
+    /// The signal strength expected at a distance of 1 m, in dBm.
+    pub measured_power: i8,
+}
+
+/// An AltBeacon frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AltBeacon {
+    /// The company identifier of the manufacturer which sent the frame.
+    pub manufacturer_id: u16,
+    /// The beacon identifier. The first 16 bytes usually hold an organisational unit, and the
+    /// remaining 4 bytes a major and minor value as in iBeacon.
+    pub id: [u8; 20],
+    /// The signal strength expected at a distance of 1 m, in dBm.
+    pub reference_rssi: i8,
+    /// A byte reserved for use by the manufacturer.
+    pub manufacturer_reserved: u8,
+}
+
+/// The telemetry sent in an Eddystone TLM frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EddystoneTlm {
+    Unencrypted {
+        /// The battery voltage in mV, if the beacon measures it.
+        battery_voltage: Option<u16>,
+        /// The temperature in °C, if the beacon measures it.
+        temperature: Option<f32>,
+        /// The number of frames which the beacon has sent since it was powered on.
+        advertising_count: u32,
+        /// The time since the beacon was powered on, to a resolution of 0.1 s.
+        uptime: Duration,
+    },
+    /// Telemetry which has been encrypted with the beacon's ephemeral identity key.
+    Encrypted {
+        data: [u8; 12],
+        salt: u16,
+        /// The message integrity check.
+        mic: u16,
+    },
+}
+
+/// An Eddystone frame. `tx_power` is the signal strength at a distance of 0 m, in dBm.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Eddystone {
+    Uid {
+        tx_power: i8,
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+    Url {
+        tx_power: i8,
+        /// The URL, with its scheme and any abbreviations expanded.
+        url: String,
+    },
+    Tlm(EddystoneTlm),
+    /// An ephemeral identifier, which only the beacon's owner can resolve.
+    Eid {
+        tx_power: i8,
+        eid: [u8; 8],
+    },
+}
+
+/// The kinds of beacon frame which can be decoded, for use in a [`BeaconFilter`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BeaconKind {
+    IBeacon,
+    AltBeacon,
+    EddystoneUid,
+    EddystoneUrl,
+    EddystoneTlm,
+    EddystoneEid,
+}
+
+/// A decoded beacon frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Beacon {
+    IBeacon(IBeacon),
+    AltBeacon(AltBeacon),
+    Eddystone(Eddystone),
+}
+
+impl Beacon {
+    /// Decodes a beacon frame from manufacturer data, which doesn't include the company identifier.
+    /// Returns `None` if the data isn't an iBeacon or AltBeacon frame.
+    pub fn from_manufacturer_data(manufacturer_id: u16, data: &[u8]) -> Option<Beacon> {
+        if manufacturer_id == APPLE_COMPANY_ID && data.len() == 23 && data[..2] == IBEACON_PREFIX {
+            return Some(Beacon::IBeacon(IBeacon {
+                uuid: Uuid::from_slice(&data[2..18]).unwrap(),
+                major: u16::from_be_bytes([data[18], data[19]]),
+                minor: u16::from_be_bytes([data[20], data[21]]),
+                measured_power: data[22] as i8,
+            }));
+        }
+        if data.len() == 24 && data[..2] == ALTBEACON_PREFIX {
+            return Some(Beacon::AltBeacon(AltBeacon {
+                manufacturer_id,
+                id: data[2..22].try_into().unwrap(),
+                reference_rssi: data[22] as i8,
+                manufacturer_reserved: data[23],
+            }));
+        }
+        None
+    }
+
+    /// Decodes a beacon frame from service data. Returns `None` if the data isn't an Eddystone
+    /// frame.
+    pub fn from_service_data(service: Uuid, data: &[u8]) -> Option<Beacon> {
+        if service != uuid_from_u16(EDDYSTONE_SERVICE) {
+            return None;
+        }
+        let (&frame_type, frame) = data.split_first()?;
+        let eddystone = match frame_type {
+            // The last two bytes of a UID frame are reserved, and some beacons leave them out.
+            EDDYSTONE_UID if frame.len() == 17 || frame.len() == 19 => Eddystone::Uid {
+                tx_power: frame[0] as i8,
+                namespace: frame[1..11].try_into().unwrap(),
+                instance: frame[11..17].try_into().unwrap(),
+            },
+            EDDYSTONE_URL if frame.len() >= 2 => Eddystone::Url {
+                tx_power: frame[0] as i8,
+                url: expand_url(frame[1], &frame[2..])?,
+            },
+            EDDYSTONE_TLM => Eddystone::Tlm(decode_tlm(frame)?),
+            EDDYSTONE_EID if frame.len() == 9 => Eddystone::Eid {
+                tx_power: frame[0] as i8,
+                eid: frame[1..9].try_into().unwrap(),
+            },
+            _ => return None,
+        };
+        Some(Beacon::Eddystone(eddystone))
+    }
+
+    /// Decodes the beacon frame carried by a `ManufacturerDataAdvertisement` or
+    /// `ServiceDataAdvertisement` event, returning it along with the address of the beacon.
+    pub fn from_event(event: &CentralEvent) -> Option<(BDAddr, Beacon)> {
+        match event {
+            CentralEvent::ManufacturerDataAdvertisement {
+                address,
+                manufacturer_id,
+                data,
+            } => Some((
+                *address,
+                Beacon::from_manufacturer_data(*manufacturer_id, data)?,
+            )),
+            CentralEvent::ServiceDataAdvertisement {
+                address,
+                service,
+                data,
+            } => Some((*address, Beacon::from_service_data(*service, data)?)),
+            _ => None,
+        }
+    }
+
+    /// Decodes every beacon frame in the advertisement data of a peripheral. A beacon which
+    /// interleaves several Eddystone frames only has the last one it sent in its properties.
+    pub fn from_properties(properties: &PeripheralProperties) -> Vec<Beacon> {
+        let manufacturer_beacons = properties
+            .manufacturer_data
+            .iter()
+            .filter_map(|(&id, data)| Beacon::from_manufacturer_data(id, data));
+        let service_beacons = properties
+            .service_data
+            .iter()
+            .filter_map(|(&service, data)| Beacon::from_service_data(service, data));
+        manufacturer_beacons.chain(service_beacons).collect()
+    }
+
+    pub fn kind(&self) -> BeaconKind {
+        match self {
+            Beacon::IBeacon(_) => BeaconKind::IBeacon,
+            Beacon::AltBeacon(_) => BeaconKind::AltBeacon,
+            Beacon::Eddystone(Eddystone::Uid { .. }) => BeaconKind::EddystoneUid,
+            Beacon::Eddystone(Eddystone::Url { .. }) => BeaconKind::EddystoneUrl,
+            Beacon::Eddystone(Eddystone::Tlm(_)) => BeaconKind::EddystoneTlm,
+            Beacon::Eddystone(Eddystone::Eid { .. }) => BeaconKind::EddystoneEid,
+        }
+    }
+}
+
+fn expand_url(scheme: u8, encoded: &[u8]) -> Option<String> {
+    let mut url = EDDYSTONE_URL_SCHEMES.get(scheme as usize)?.to_string();
+    for &byte in encoded {
+        match EDDYSTONE_URL_EXPANSIONS.get(byte as usize) {
+            Some(expansion) => url.push_str(expansion),
+            // Only printable ASCII characters may be sent as they are.
+            None if (0x21..0x7F).contains(&byte) => url.push(byte as char),
+            None => return None,
+        }
+    }
+    Some(url)
+}
+
+fn decode_tlm(frame: &[u8]) -> Option<EddystoneTlm> {
+    match frame {
+        [0x00, tlm @ ..] if tlm.len() == 12 => {
+            let battery_voltage = u16::from_be_bytes([tlm[0], tlm[1]]);
+            // The temperature is a signed 8.8 fixed point value, with 0x8000 meaning unknown.
+            let temperature = i16::from_be_bytes([tlm[2], tlm[3]]);
+            let advertising_count = u32::from_be_bytes(tlm[4..8].try_into().unwrap());
+            let uptime = u32::from_be_bytes(tlm[8..12].try_into().unwrap());
+            Some(EddystoneTlm::Unencrypted {
+                battery_voltage: Some(battery_voltage).filter(|&voltage| voltage != 0),
+                temperature: Some(temperature)
+                    .filter(|&temperature| temperature != i16::MIN)
+                    .map(|temperature| f32::from(temperature) / 256.0),
+                advertising_count,
+                uptime: Duration::from_millis(u64::from(uptime) * 100),
+            })
+        }
+        [0x01, etlm @ ..] if etlm.len() == 16 => Some(EddystoneTlm::Encrypted {
+            data: etlm[..12].try_into().unwrap(),
+            salt: u16::from_be_bytes([etlm[12], etlm[13]]),
+            mic: u16::from_be_bytes([etlm[14], etlm[15]]),
+        }),
+        _ => None,
+    }
+}
+
+/// Restricts which beacon frames a [`BeaconStream`] reports. The default filter lets every frame
+/// through.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BeaconFilter {
+    /// Only report frames of these kinds. An empty list allows every kind.
+    pub kinds: Vec<BeaconKind>,
+    /// Only report iBeacon frames with one of these proximity UUIDs. An empty list allows every
+    /// UUID.
+    pub ibeacon_uuids: Vec<Uuid>,
+    /// Only report Eddystone UID frames with one of these namespaces. An empty list allows every
+    /// namespace.
+    pub eddystone_namespaces: Vec<[u8; 10]>,
+}
+
+impl BeaconFilter {
+    pub fn matches(&self, beacon: &Beacon) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&beacon.kind()) {
+            return false;
+        }
+        match beacon {
+            Beacon::IBeacon(ibeacon) => {
+                self.ibeacon_uuids.is_empty() || self.ibeacon_uuids.contains(&ibeacon.uuid)
+            }
+            Beacon::Eddystone(Eddystone::Uid { namespace, .. }) => {
+                self.eddystone_namespaces.is_empty()
+                    || self.eddystone_namespaces.contains(namespace)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A beacon frame received from the device with the given address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeaconEvent {
+    pub address: BDAddr,
+    pub beacon: Beacon,
+}
+
+/// A stream of the beacon frames which a central receives, created by
+/// [`BeaconCentral::beacon_events`].
+///
+/// Frames are decoded from the central's advertisement events, so they are buffered in the same
+/// way as for an [`EventStream`], and the stream ends when the central's event stream does.
+#[derive(Debug)]
+pub struct BeaconStream {
+    events: EventStream<CentralEvent>,
+    filter: BeaconFilter,
+}
+
+impl BeaconStream {
+    /// Returns the number of events which have been discarded from the underlying event stream
+    /// because its buffer was full.
+    pub fn missed_events(&self) -> usize {
+        self.events.missed_events()
+    }
+}
+
+impl Stream for BeaconStream {
+    type Item = BeaconEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BeaconEvent>> {
+        loop {
+            let event = match Pin::new(&mut self.events).poll_next(cx) {
+                Poll::Ready(Some(event)) => event,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some((address, beacon)) = Beacon::from_event(&event) {
+                if self.filter.matches(&beacon) {
+                    return Poll::Ready(Some(BeaconEvent { address, beacon }));
+                }
+            }
+        }
+    }
+}
+
+/// Adds a stream of decoded beacon frames to every [`AsyncCentral`].
+pub trait BeaconCentral<P: AsyncPeripheral>: AsyncCentral<P> {
+    /// Subscribes to the beacon frames which this central receives and which match `filter`. As
+    /// with [`events`](trait.AsyncCentral.html#method.events), only frames received after the
+    /// subscription are reported, so a scan with duplicate filtering turned off suits beacons
+    /// which change their data, such as Eddystone TLM.
+    fn beacon_events(&self, filter: BeaconFilter) -> BeaconStream {
+        BeaconStream {
+            events: AsyncCentral::events(self),
+            filter,
+        }
+    }
+}
+
+impl<P: AsyncPeripheral, C: AsyncCentral<P>> BeaconCentral<P> for C {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{
+        adapter::Adapter,
+        device::{AdvertisingReport, Device},
+    };
+    use futures::{executor::block_on, stream::StreamExt};
+    use std::collections::HashMap;
+
+    const PROXIMITY_UUID: Uuid = Uuid::from_u128(0xe2c56db5_dffb_48d2_b060_d0f5a71096e0);
+
+    fn ibeacon_data() -> Vec<u8> {
+        let mut data = vec![0x02, 0x15];
+        data.extend_from_slice(PROXIMITY_UUID.as_bytes());
+        data.extend_from_slice(&[0x00, 0x01, 0x00, 0x02, 0xC5]);
+        data
+    }
+
+    #[test]
+    fn decode_manufacturer_data() {
+        assert_eq!(
+            Beacon::from_manufacturer_data(APPLE_COMPANY_ID, &ibeacon_data()),
+            Some(Beacon::IBeacon(IBeacon {
+                uuid: PROXIMITY_UUID,
+                major: 1,
+                minor: 2,
+                measured_power: -59,
+            }))
+        );
+        assert_eq!(
+            Beacon::from_manufacturer_data(0x0118, &ibeacon_data()),
+            None
+        );
+
+        let mut data = vec![0xBE, 0xAC];
+        data.extend((1..=20).collect::<Vec<u8>>());
+        data.extend_from_slice(&[0xBC, 0x00]);
+        let beacon = Beacon::from_manufacturer_data(0x0118, &data).unwrap();
+        assert_eq!(beacon.kind(), BeaconKind::AltBeacon);
+        if let Beacon::AltBeacon(altbeacon) = beacon {
+            assert_eq!(altbeacon.id[19], 20);
+            assert_eq!(altbeacon.reference_rssi, -68);
+        }
+    }
+
+    #[test]
+    fn decode_eddystone() {
+        let eddystone = uuid_from_u16(EDDYSTONE_SERVICE);
+        assert_eq!(
+            Beacon::from_service_data(eddystone, b"\x10\xEB\x00btleplug\x07"),
+            Some(Beacon::Eddystone(Eddystone::Url {
+                tx_power: -21,
+                url: "http://www.btleplug.com".to_string(),
+            }))
+        );
+        assert_eq!(
+            Beacon::from_service_data(eddystone, b"\x10\xEB\x04bad"),
+            None
+        );
+
+        let tlm = [
+            0x20, 0x00, 0x0B, 0xB8, 0x18, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x64,
+        ];
+        assert_eq!(
+            Beacon::from_service_data(eddystone, &tlm),
+            Some(Beacon::Eddystone(Eddystone::Tlm(
+                EddystoneTlm::Unencrypted {
+                    battery_voltage: Some(3000),
+                    temperature: Some(24.5),
+                    advertising_count: 256,
+                    uptime: Duration::from_secs(10),
+                }
+            )))
+        );
+
+        let mut uid = vec![0x00, 0xEE];
+        uid.extend(0..16);
+        let beacon = Beacon::from_service_data(eddystone, &uid).unwrap();
+        assert_eq!(beacon.kind(), BeaconKind::EddystoneUid);
+        assert_eq!(Beacon::from_service_data(uuid_from_u16(0x180F), &uid), None);
+    }
+
+    #[test]
+    fn beacon_events_are_filtered() {
+        let adapter = Adapter::new();
+        let filter = BeaconFilter {
+            kinds: vec![BeaconKind::IBeacon],
+            ..BeaconFilter::default()
+        };
+        let mut beacons = adapter.beacon_events(filter);
+        block_on(adapter.start_scan()).unwrap();
+
+        let eddystone = Device::new("AA:BB:CC:DD:EE:01".parse().unwrap());
+        let mut service_data = HashMap::new();
+        service_data.insert(
+            uuid_from_u16(EDDYSTONE_SERVICE),
+            b"\x30\xEE12345678".to_vec(),
+        );
+        let report = AdvertisingReport {
+            service_data,
+            ..AdvertisingReport::default()
+        };
+        assert!(adapter.advertise(&eddystone, report));
+
+        let ibeacon = Device::new("AA:BB:CC:DD:EE:02".parse().unwrap());
+        let mut manufacturer_data = HashMap::new();
+        manufacturer_data.insert(APPLE_COMPANY_ID, ibeacon_data());
+        let report = AdvertisingReport {
+            manufacturer_data,
+            ..AdvertisingReport::default()
+        };
+        assert!(adapter.advertise(&ibeacon, report));
+
+        let event = block_on(beacons.next()).unwrap();
+        assert_eq!(event.address, ibeacon.address());
+        assert_eq!(event.beacon.kind(), BeaconKind::IBeacon);
+        let properties = AsyncPeripheral::properties(
+            &AsyncCentral::peripheral(&adapter, eddystone.address()).unwrap(),
+        );
+        assert_eq!(
+            Beacon::from_properties(&properties),
+            vec![Beacon::Eddystone(Eddystone::Eid {
+                tx_power: -18,
+                eid: *b"12345678",
+            })]
+        );
+    }
+}
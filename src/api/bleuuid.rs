@@ -1,3 +1,4 @@
+use super::assigned_numbers;
 use uuid::Uuid;
 
 const BLUETOOTH_BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
@@ -28,6 +29,10 @@ pub trait BleUuid {
 
     /// Convert the UUID to a string, using short format if applicable.
     fn to_short_string(&self) -> String;
+
+    /// If the UUID is that of a standard service, characteristic or descriptor then return its
+    /// name from the Bluetooth SIG's assigned numbers, otherwise return `None`.
+    fn ble_name(&self) -> Option<&'static str>;
 }
 
 impl BleUuid for Uuid {
@@ -58,6 +63,13 @@ impl BleUuid for Uuid {
             self.to_string()
         }
     }
+
+    fn ble_name(&self) -> Option<&'static str> {
+        let uuid16 = self.to_ble_u16()?;
+        assigned_numbers::service_name(uuid16)
+            .or_else(|| assigned_numbers::characteristic_name(uuid16))
+            .or_else(|| assigned_numbers::descriptor_name(uuid16))
+    }
 }
 
 #[cfg(test)]
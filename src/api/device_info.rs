@@ -0,0 +1,263 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Decoders for the values which identify what kind of device a peripheral is: its appearance,
+//! its Class of Device, and its modalias.
+
+use super::assigned_numbers::{appearance_name, company_name};
+use crate::Error;
+use bitflags::bitflags;
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// The appearance which a device advertises, made up of a category, such as a watch, and a
+/// subcategory within it, such as a sports watch.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Appearance(pub u16);
+
+impl Appearance {
+    /// Returns the category, which is the top 10 bits of the value.
+    pub fn category(self) -> u16 {
+        self.0 >> 6
+    }
+
+    /// Returns the subcategory within the category, which is the bottom 6 bits of the value. Zero
+    /// is the generic subcategory.
+    pub fn subcategory(self) -> u8 {
+        (self.0 & 0x3F) as u8
+    }
+
+    /// Returns the name of the appearance, such as "Watch: Sports Watch", or the name of its
+    /// category if the subcategory isn't known.
+    pub fn name(self) -> Option<&'static str> {
+        appearance_name(self.0)
+    }
+
+    /// Returns the name of the category, such as "Watch".
+    pub fn category_name(self) -> Option<&'static str> {
+        appearance_name(self.0 & !0x3F)
+    }
+}
+
+bitflags! {
+    /// The services which a classic Bluetooth device says it offers in its Class of Device.
+    pub struct ServiceClasses: u16 {
+        const LIMITED_DISCOVERABLE = 0x0001;
+        const LE_AUDIO = 0x0002;
+        const POSITIONING = 0x0008;
+        const NETWORKING = 0x0010;
+        const RENDERING = 0x0020;
+        const CAPTURING = 0x0040;
+        const OBJECT_TRANSFER = 0x0080;
+        const AUDIO = 0x0100;
+        const TELEPHONY = 0x0200;
+        const INFORMATION = 0x0400;
+    }
+}
+
+/// The kind of device which a classic Bluetooth device says it is in its Class of Device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MajorDeviceClass {
+    Miscellaneous,
+    Computer,
+    Phone,
+    NetworkAccessPoint,
+    AudioVideo,
+    Peripheral,
+    Imaging,
+    Wearable,
+    Toy,
+    Health,
+    Uncategorized,
+    /// A value which is reserved for future use.
+    Reserved(u8),
+}
+
+/// The 24-bit Class of Device of a classic Bluetooth device, which says what kind of device it is
+/// and which services it offers.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ClassOfDevice(pub u32);
+
+impl ClassOfDevice {
+    pub fn service_classes(self) -> ServiceClasses {
+        ServiceClasses::from_bits_truncate((self.0 >> 13) as u16)
+    }
+
+    pub fn major_class(self) -> MajorDeviceClass {
+        match (self.0 >> 8) & 0x1F {
+            0x00 => MajorDeviceClass::Miscellaneous,
+            0x01 => MajorDeviceClass::Computer,
+            0x02 => MajorDeviceClass::Phone,
+            0x03 => MajorDeviceClass::NetworkAccessPoint,
+            0x04 => MajorDeviceClass::AudioVideo,
+            0x05 => MajorDeviceClass::Peripheral,
+            0x06 => MajorDeviceClass::Imaging,
+            0x07 => MajorDeviceClass::Wearable,
+            0x08 => MajorDeviceClass::Toy,
+            0x09 => MajorDeviceClass::Health,
+            0x1F => MajorDeviceClass::Uncategorized,
+            major => MajorDeviceClass::Reserved(major as u8),
+        }
+    }
+
+    /// Returns the minor device class, whose meaning depends on the major device class.
+    pub fn minor_class(self) -> u8 {
+        ((self.0 >> 2) & 0x3F) as u8
+    }
+}
+
+/// Who assigned the vendor ID of a [`Modalias`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ModaliasSource {
+    /// The vendor ID is a company identifier assigned by the Bluetooth SIG.
+    Bluetooth,
+    /// The vendor ID is assigned by the USB Implementers Forum.
+    Usb,
+    Other(String),
+}
+
+/// The vendor, product and version IDs of a device or adapter, parsed from a modalias like
+/// `bluetooth:v004Cp0320d0112`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Modalias {
+    pub source: ModaliasSource,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+impl Modalias {
+    /// Returns the name of the vendor, if its ID was assigned by the Bluetooth SIG.
+    pub fn vendor_name(&self) -> Option<&'static str> {
+        match self.source {
+            ModaliasSource::Bluetooth => company_name(self.vendor),
+            _ => None,
+        }
+    }
+}
+
+type ParseModaliasResult<T> = std::result::Result<T, ParseModaliasError>;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum ParseModaliasError {
+    #[error("Modalias has no source")]
+    MissingSource,
+    #[error("Malformed IDs in modalias")]
+    InvalidIds,
+}
+
+impl From<ParseModaliasError> for Error {
+    fn from(e: ParseModaliasError) -> Self {
+        Error::Other(format!("ParseModaliasError: {}", e))
+    }
+}
+
+impl FromStr for Modalias {
+    type Err = ParseModaliasError;
+
+    fn from_str(s: &str) -> ParseModaliasResult<Self> {
+        let (source, ids) = s.split_once(':').ok_or(ParseModaliasError::MissingSource)?;
+        let source = match source {
+            "" => return Err(ParseModaliasError::MissingSource),
+            "bluetooth" => ModaliasSource::Bluetooth,
+            "usb" => ModaliasSource::Usb,
+            other => ModaliasSource::Other(other.to_string()),
+        };
+        // Kernel modaliases carry more fields after the version, which are left out.
+        let id = |prefix: char, offset: usize| {
+            ids.get(offset..offset + 5)
+                .and_then(|field| field.strip_prefix(prefix))
+                .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+                .ok_or(ParseModaliasError::InvalidIds)
+        };
+        Ok(Modalias {
+            source,
+            vendor: id('v', 0)?,
+            product: id('p', 5)?,
+            version: id('d', 10)?,
+        })
+    }
+}
+
+impl Display for Modalias {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let source = match &self.source {
+            ModaliasSource::Bluetooth => "bluetooth",
+            ModaliasSource::Usb => "usb",
+            ModaliasSource::Other(source) => source,
+        };
+        write!(
+            f,
+            "{}:v{:04X}p{:04X}d{:04X}",
+            source, self.vendor, self.product, self.version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_appearance_and_class() {
+        let appearance = Appearance(0x00C1);
+        assert_eq!(appearance.category(), 3);
+        assert_eq!(appearance.subcategory(), 1);
+        assert_eq!(appearance.name(), Some("Watch: Sports Watch"));
+        assert_eq!(appearance.category_name(), Some("Watch"));
+
+        // A smartphone offering telephony, object transfer and networking.
+        let class = ClassOfDevice(0x52020C);
+        assert_eq!(
+            class.service_classes(),
+            ServiceClasses::TELEPHONY
+                | ServiceClasses::OBJECT_TRANSFER
+                | ServiceClasses::NETWORKING
+        );
+        assert_eq!(class.major_class(), MajorDeviceClass::Phone);
+        assert_eq!(class.minor_class(), 3);
+        assert_eq!(
+            ClassOfDevice(0x1F00).major_class(),
+            MajorDeviceClass::Uncategorized
+        );
+    }
+
+    #[test]
+    fn parse_modalias() {
+        let modalias: Modalias = "bluetooth:v004Cp0320d0112".parse().unwrap();
+        assert_eq!(
+            modalias,
+            Modalias {
+                source: ModaliasSource::Bluetooth,
+                vendor: 0x004C,
+                product: 0x0320,
+                version: 0x0112,
+            }
+        );
+        assert_eq!(modalias.vendor_name(), Some("Apple, Inc."));
+        assert_eq!(modalias.to_string(), "bluetooth:v004Cp0320d0112");
+
+        let modalias: Modalias = "usb:v046DpC52Bd1211dc00dsc00dp00ic03isc01ip01in00"
+            .parse()
+            .unwrap();
+        assert_eq!(modalias.source, ModaliasSource::Usb);
+        assert_eq!(modalias.product, 0xC52B);
+        assert_eq!(modalias.vendor_name(), None);
+
+        assert_eq!(
+            "v004Cp0320d0112".parse::<Modalias>(),
+            Err(ParseModaliasError::MissingSource)
+        );
+        assert_eq!(
+            "bluetooth:v004Cp0320".parse::<Modalias>(),
+            Err(ParseModaliasError::InvalidIds)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::api::ValueNotification;
+use futures::stream::Stream;
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// The number of events buffered for each subscriber by
+/// [`AsyncCentral::events`](trait.AsyncCentral.html#method.events).
+pub const DEFAULT_EVENT_BUFFER: usize = 256;
+
+#[derive(Debug)]
+struct Subscription<T> {
+    queue: VecDeque<T>,
+    buffer: usize,
+    missed: usize,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// A stream of events from a single subscription.
+///
+/// Every subscription has its own buffer, so a slow subscriber never delays event delivery to the
+/// adapter or to other subscribers. If events arrive faster than they are consumed and the buffer
+/// fills up, the *oldest* buffered event is discarded to make room for the newest one. The number
+/// of events discarded so far is available from [`missed_events`](#method.missed_events).
+///
+/// Dropping the stream unsubscribes it. The stream ends once whatever it was created from has gone
+/// away. For an adapter's events, that means once the adapter, its clones, and every peripheral
+/// it has handed out have all been dropped, as the peripherals share the adapter's events.
+#[derive(Debug)]
+pub struct EventStream<T> {
+    subscription: Arc<Mutex<Subscription<T>>>,
+}
+
+impl<T> EventStream<T> {
+    /// Returns the number of events which have been discarded from this stream because its buffer
+    /// was full.
+    pub fn missed_events(&self) -> usize {
+        self.subscription.lock().unwrap().missed
+    }
+
+    fn close(&self) {
+        let mut subscription = self.subscription.lock().unwrap();
+        subscription.closed = true;
+        subscription.queue.clear();
+    }
+}
+
+impl<T> Stream for EventStream<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut subscription = self.subscription.lock().unwrap();
+        if let Some(event) = subscription.queue.pop_front() {
+            Poll::Ready(Some(event))
+        } else if subscription.closed {
+            Poll::Ready(None)
+        } else {
+            subscription.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for EventStream<T> {
+    fn drop(&mut self) {
+        // Let the sender know it can forget about this subscription.
+        self.close();
+    }
+}
+
+/// A stream of value notifications from a single characteristic, created by
+/// [`AsyncPeripheral::notifications`](trait.AsyncPeripheral.html#method.notifications).
+///
+/// Notifications are buffered in the same way as for an [`EventStream`]. Dropping the stream
+/// unregisters it, and once no streams remain for a characteristic, the peripheral is asked to stop
+/// sending notifications for it.
+pub struct NotificationStream {
+    events: EventStream<ValueNotification>,
+    on_drop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl NotificationStream {
+    pub(crate) fn new(
+        events: EventStream<ValueNotification>,
+        on_drop: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        NotificationStream {
+            events,
+            on_drop: Some(Box::new(on_drop)),
+        }
+    }
+
+    /// Returns the number of notifications which have been discarded from this stream because its
+    /// buffer was full.
+    pub fn missed_events(&self) -> usize {
+        self.events.missed_events()
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = ValueNotification;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<ValueNotification>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Debug for NotificationStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("NotificationStream")
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        // The subscription must be closed before `on_drop` runs, so that it isn't counted as a
+        // remaining consumer.
+        self.events.close();
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop();
+        }
+    }
+}
+
+/// Delivers a copy of every event to each live [`EventStream`].
+#[derive(Debug)]
+pub(crate) struct EventSender<T> {
+    subscriptions: Mutex<Vec<Arc<Mutex<Subscription<T>>>>>,
+}
+
+impl<T: Clone> EventSender<T> {
+    pub fn new() -> Self {
+        EventSender {
+            subscriptions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a new subscription, which will receive every event sent from now on. `buffer` is
+    /// the number of events which may be waiting to be consumed before old events are discarded,
+    /// and must be at least 1.
+    pub fn subscribe(&self, buffer: usize) -> EventStream<T> {
+        assert!(
+            buffer > 0,
+            "Event buffer must be able to hold at least one event."
+        );
+        let subscription = Arc::new(Mutex::new(Subscription {
+            queue: VecDeque::new(),
+            buffer,
+            missed: 0,
+            waker: None,
+            closed: false,
+        }));
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .push(subscription.clone());
+        EventStream { subscription }
+    }
+
+    pub fn send(&self, event: T) {
+        self.subscriptions.lock().unwrap().retain(|subscription| {
+            let mut subscription = subscription.lock().unwrap();
+            if subscription.closed {
+                return false;
+            }
+            if subscription.queue.len() >= subscription.buffer {
+                subscription.queue.pop_front();
+                subscription.missed += 1;
+            }
+            subscription.queue.push_back(event.clone());
+            if let Some(waker) = subscription.waker.take() {
+                waker.wake();
+            }
+            true
+        });
+    }
+
+    /// Returns true if any subscriptions are still live.
+    pub fn has_subscribers(&self) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|subscription| !subscription.lock().unwrap().closed);
+        !subscriptions.is_empty()
+    }
+}
+
+impl<T> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        // End all the streams, so consumers aren't left waiting forever.
+        for subscription in self.subscriptions.get_mut().unwrap().drain(..) {
+            let mut subscription = subscription.lock().unwrap();
+            subscription.closed = true;
+            if let Some(waker) = subscription.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, stream::StreamExt};
+
+    #[test]
+    fn every_subscriber_gets_every_event() {
+        let sender = EventSender::new();
+        let mut first = sender.subscribe(4);
+        let mut second = sender.subscribe(4);
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(block_on(first.next()), Some(1));
+        assert_eq!(block_on(first.next()), Some(2));
+        assert_eq!(block_on(second.next()), Some(1));
+        assert_eq!(block_on(second.next()), Some(2));
+    }
+
+    #[test]
+    fn full_buffer_discards_oldest() {
+        let sender = EventSender::new();
+        let mut stream = sender.subscribe(2);
+        for i in 0..5 {
+            sender.send(i);
+        }
+        assert_eq!(stream.missed_events(), 3);
+        assert_eq!(block_on(stream.next()), Some(3));
+        assert_eq!(block_on(stream.next()), Some(4));
+    }
+
+    #[test]
+    fn dropped_subscriber_is_removed() {
+        let sender = EventSender::new();
+        let stream = sender.subscribe(1);
+        let mut other = sender.subscribe(1);
+        drop(stream);
+        sender.send(1);
+        assert_eq!(sender.subscriptions.lock().unwrap().len(), 1);
+        assert_eq!(block_on(other.next()), Some(1));
+    }
+
+    #[test]
+    fn stream_ends_when_sender_is_dropped() {
+        let sender = EventSender::new();
+        let mut stream = sender.subscribe(1);
+        sender.send(1);
+        drop(sender);
+        assert_eq!(block_on(stream.next()), Some(1));
+        assert_eq!(block_on(stream.next()), None);
+    }
+}
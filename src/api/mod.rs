@@ -12,21 +12,31 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 mod adapter_manager;
+pub mod advertisement_data;
+pub mod assigned_numbers;
+pub mod beacons;
 pub mod bleuuid;
+pub mod device_info;
+mod event_stream;
+pub mod sensors;
 
-use crate::{Error, Result};
+use crate::{common::blocking::block_on, Error, Result};
 pub use adapter_manager::AdapterManager;
+use async_trait::async_trait;
 use bitflags::bitflags;
+use device_info::{Appearance, ClassOfDevice, Modalias};
+pub(crate) use event_stream::EventSender;
+pub use event_stream::{EventStream, NotificationStream, DEFAULT_EVENT_BUFFER};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use serde_cr as serde;
-use std::sync::mpsc::Receiver;
 use std::{
     collections::{BTreeSet, HashMap},
     convert::TryFrom,
     fmt::{self, Debug, Display, Formatter},
     str::FromStr,
+    time::Duration,
 };
 use thiserror::Error;
 use uuid::Uuid;
@@ -201,10 +211,16 @@ pub struct Characteristic {
     pub value_handle: u16,
     /// The UUID for this characteristic. This uniquely identifies its behavior.
     pub uuid: Uuid,
+    /// The UUID of the service this characteristic belongs to. Together with `uuid` this tells
+    /// apart characteristics which share a UUID but live in different services.
+    pub service_uuid: Uuid,
     /// The set of properties for this characteristic, which indicate what functionality it
     /// supports. If you attempt an operation that is not supported by the characteristics (for
     /// example setting notify on one without the NOTIFY flag), that operation will fail.
     pub properties: CharPropFlags,
+    /// The descriptors of this characteristic, which hold additional information about it, such as
+    /// a user-readable description or its presentation format.
+    pub descriptors: BTreeSet<Descriptor>,
 }
 
 impl Display for Characteristic {
@@ -217,6 +233,53 @@ impl Display for Characteristic {
     }
 }
 
+/// A Bluetooth GATT descriptor. Descriptors belong to a characteristic, and describe it or control
+/// its behaviour. Like characteristics, they are identified by a UUID which may be standardized
+/// (like 0x2901, the Characteristic User Description; the standard set can be found
+/// [here](https://www.bluetooth.com/specifications/gatt/descriptors)) or specific to a device.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Descriptor {
+    /// The handle of the descriptor. Only valid on Linux, will be 0 on all other platforms.
+    pub handle: u16,
+    /// The UUID for this descriptor.
+    pub uuid: Uuid,
+}
+
+impl Display for Descriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "uuid: {:?}, handle: {}", self.uuid, self.handle)
+    }
+}
+
+/// A Bluetooth GATT service. Services group together the characteristics which implement a
+/// particular feature of a device. Like characteristics, they are identified by a UUID which may
+/// be standardized (the standard set of services can be found
+/// [here](https://www.bluetooth.com/specifications/gatt/services)) or specific to a device.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct Service {
+    /// The UUID for this service.
+    pub uuid: Uuid,
+    /// Whether this is a primary service, rather than a secondary service which is only meant to be
+    /// included by other services.
+    pub primary: bool,
+    /// The UUIDs of the services which this service includes.
+    pub included_services: Vec<Uuid>,
+    /// The characteristics belonging to this service.
+    pub characteristics: BTreeSet<Characteristic>,
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uuid: {:?}, primary: {}, characteristics: {}",
+            self.uuid,
+            self.primary,
+            self.characteristics.len()
+        )
+    }
+}
+
 /// The properties of this peripheral, as determined by the advertising reports we've received for
 /// it.
 #[derive(Debug, Default, Clone)]
@@ -227,8 +290,10 @@ pub struct PeripheralProperties {
     pub address_type: AddressType,
     /// The local name. This is generally a human-readable string that identifies the type of device.
     pub local_name: Option<String>,
-    /// The transmission power level for the device
+    /// The transmission power level which the device advertises, in dBm
     pub tx_power_level: Option<i8>,
+    /// The signal strength of the last advertisement received from the device, in dBm
+    pub rssi: Option<i16>,
     /// Advertisement data specific to the device manufacturer. The keys of this map are
     /// 'manufacturer IDs', while the values are arbitrary data.
     pub manufacturer_data: HashMap<u16, Vec<u8>>,
@@ -241,6 +306,130 @@ pub struct PeripheralProperties {
     pub discovery_count: u32,
     /// True if we've discovered the device before
     pub has_scan_response: bool,
+    /// The name which the user has given the device, or else its local name
+    pub alias: Option<String>,
+    /// The appearance which the device advertises
+    pub appearance: Option<Appearance>,
+    /// The Class of Device of a classic Bluetooth device
+    pub class: Option<ClassOfDevice>,
+    /// The name of an icon for the device, following the freedesktop.org icon naming
+    /// specification, such as `input-mouse`
+    pub icon: Option<String>,
+    /// The vendor, product and version IDs of the device
+    pub modalias: Option<Modalias>,
+    /// True if the device only supports pairing from before Bluetooth 2.1
+    pub legacy_pairing: bool,
+    /// True if the device is paired
+    pub paired: bool,
+    /// True if the device is trusted, so it can connect without the user authorizing it
+    pub trusted: bool,
+    /// True if the system refuses connections from the device
+    pub blocked: bool,
+}
+
+/// A snapshot of the state of an adapter, from
+/// [`AsyncCentral::adapter_info`](trait.AsyncCentral.html#method.adapter_info). The platform may not
+/// report every property, so those it doesn't are left as `None`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AdapterInfo {
+    /// The address of the adapter
+    pub address: Option<BDAddr>,
+    /// The type of the adapter's address (either random or public)
+    pub address_type: Option<AddressType>,
+    /// The name which the system gives the adapter
+    pub name: Option<String>,
+    /// The name which the adapter shows to other devices, if it has been changed from `name`
+    pub alias: Option<String>,
+    /// The Class of Device which the adapter advertises over classic Bluetooth
+    pub class: Option<ClassOfDevice>,
+    /// Whether the adapter is switched on
+    pub powered: Option<bool>,
+    /// Whether other devices can discover the adapter
+    pub discoverable: Option<bool>,
+    /// How long the adapter stays discoverable for once made discoverable, where zero means
+    /// indefinitely
+    pub discoverable_timeout: Option<Duration>,
+    /// Whether other devices can pair with the adapter
+    pub pairable: Option<bool>,
+    /// How long the adapter stays pairable for once made pairable, where zero means indefinitely
+    pub pairable_timeout: Option<Duration>,
+    /// Whether the adapter is discovering devices
+    pub discovering: Option<bool>,
+    /// The services which the adapter's host offers to other devices
+    pub services: Vec<Uuid>,
+    /// The vendor, product and version IDs of the adapter
+    pub modalias: Option<Modalias>,
+}
+
+/// The transport to scan on, for adapters which support both Bluetooth Low Energy and classic
+/// Bluetooth (BR/EDR).
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Transport {
+    /// Scan on every transport which the adapter supports.
+    #[default]
+    Auto,
+    /// Only scan for Bluetooth Low Energy devices.
+    LowEnergy,
+    /// Only scan for classic Bluetooth devices.
+    BrEdr,
+}
+
+/// Restricts which devices a scan reports. The default filter lets every device through.
+///
+/// Not every platform supports every field. Starting a scan with a filter which the platform can't
+/// apply fails with [`Error::NotSupported`].
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScanFilter {
+    /// Only report devices which advertise at least one of these services. An empty list
+    /// matches every device.
+    pub services: Vec<Uuid>,
+    /// Only report devices whose signal strength is at least this many dBm.
+    pub rssi: Option<i16>,
+    /// Only report devices whose pathloss, the difference between the advertised transmission
+    /// power and the received signal strength, is at most this many dB. Can't be combined with
+    /// `rssi`.
+    pub pathloss: Option<u16>,
+    /// The transport to scan on.
+    pub transport: Transport,
+    /// Report every advertisement from a device, even when its data hasn't changed. This is on by
+    /// default.
+    pub duplicate_data: bool,
+    /// Only report devices which are discoverable.
+    pub discoverable: bool,
+}
+
+impl Default for ScanFilter {
+    fn default() -> Self {
+        ScanFilter {
+            services: Vec::new(),
+            rssi: None,
+            pathloss: None,
+            transport: Transport::default(),
+            duplicate_data: true,
+            discoverable: false,
+        }
+    }
+}
+
+impl ScanFilter {
+    /// Returns true if the filter lets every device through.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+            && self.rssi.is_none()
+            && self.pathloss.is_none()
+            && self.transport == Transport::Auto
+            && !self.discoverable
+    }
 }
 
 /// The type of write operation to use.
@@ -256,6 +445,105 @@ pub enum WriteType {
 /// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
 /// struct contains both the current state of the device (its properties, characteristics, etc.)
 /// as well as functions for communication.
+///
+/// All operations which talk to the device return futures. See [`Peripheral`] for a blocking
+/// version of this trait.
+#[async_trait]
+pub trait AsyncPeripheral: Send + Sync + Clone + Debug {
+    /// Returns the address of the peripheral.
+    fn address(&self) -> BDAddr;
+
+    /// Returns the set of properties associated with the peripheral. These may be updated over time
+    /// as additional advertising reports are received.
+    fn properties(&self) -> PeripheralProperties;
+
+    /// The set of characteristics we've discovered for this device. This will be empty until
+    /// `discover_characteristics` is called.
+    fn characteristics(&self) -> BTreeSet<Characteristic>;
+
+    /// The set of services we've discovered for this device, along with their characteristics.
+    /// This will be empty until `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service>;
+
+    /// Returns true iff we are currently connected to the device.
+    fn is_connected(&self) -> bool;
+
+    /// Creates a connection to the device. If the returned future resolves to Ok there has been a
+    /// successful connection. Note that peripherals allow only one connection at a time.
+    /// Operations that attempt to communicate with a device will fail until it is connected.
+    async fn connect(&self) -> Result<()>;
+
+    /// Terminates a connection to the device.
+    async fn disconnect(&self) -> Result<()>;
+
+    /// Pairs with the device, connecting to it first if needed, and bonds with it so that the
+    /// pairing is remembered. Pairing methods which need a passkey or confirmation are handled by
+    /// the platform, or on BlueZ by the registered agent.
+    async fn pair(&self) -> Result<()>;
+
+    /// Removes the pairing with the device, along with any keys exchanged with it.
+    async fn unpair(&self) -> Result<()>;
+
+    /// Returns true if the device is paired.
+    async fn is_paired(&self) -> Result<bool>;
+
+    /// Discovers all characteristics for the device, along with their descriptors.
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
+
+    /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
+    /// the case of a write-with-response) if the device returns an error.
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()>;
+
+    /// Sends a request (read) to the device. Resolves to either an error if the request was not
+    /// accepted or the response from the device.
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+
+    /// Sends a read-by-type request to device for the range of handles covered by the
+    /// characteristic and for the specified declaration UUID. See
+    /// [here](https://www.bluetooth.com/specifications/gatt/declarations) for valid UUIDs.
+    /// Resolves to either an error or the device response.
+    async fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>>;
+
+    /// Reads the value of the specified descriptor from the device.
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Writes some data to the specified descriptor. Returns an error if the write couldn't be sent
+    /// or if the device returns an error.
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
+    /// Enables either notify or indicate (depending on support) for the specified characteristic.
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Disables either notify or indicate (depending on support) for the specified characteristic.
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Registers a handler that will be called when value notification messages are received from
+    /// the device. This method should only be used after a connection has been established. Note
+    /// that the handler will be called in a common thread, so it should not block. In particular,
+    /// it must not wait for another call on the peripheral or adapter to complete, as that thread
+    /// may be the one which delivers the reply.
+    fn on_notification(&self, handler: NotificationHandler);
+
+    /// Returns a stream of value notifications from the specified characteristic, subscribing to
+    /// it if this is the first stream for that characteristic. Dropping the stream unregisters it,
+    /// and the characteristic is unsubscribed from once its last stream has been dropped, unless
+    /// it was also subscribed to with [`subscribe`](#tymethod.subscribe).
+    async fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream>;
+}
+
+/// Peripheral is the device that you would like to communicate with (the "server" of BLE). This
+/// struct contains both the current state of the device (its properties, characteristics, etc.)
+/// as well as functions for communication.
+///
+/// This is a blocking facade over [`AsyncPeripheral`], and is implemented for every type which
+/// implements it. Its blocking methods must not be called from a notification handler, as the
+/// thread which runs the handler may be the one which would deliver the reply. They fail with
+/// [`Error::Other`] there instead of deadlocking; use [`AsyncPeripheral`] from another task.
 pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr;
@@ -268,6 +556,10 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// `discover_characteristics` is called.
     fn characteristics(&self) -> BTreeSet<Characteristic>;
 
+    /// The set of services we've discovered for this device, along with their characteristics.
+    /// This will be empty until `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service>;
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool;
 
@@ -279,7 +571,19 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Terminates a connection to the device. This is a synchronous operation.
     fn disconnect(&self) -> Result<()>;
 
-    /// Discovers all characteristics for the device. This is a synchronous operation.
+    /// Pairs with the device, connecting to it first if needed, and bonds with it so that the
+    /// pairing is remembered. Pairing methods which need a passkey or confirmation are handled by
+    /// the platform, or on BlueZ by the registered agent.
+    fn pair(&self) -> Result<()>;
+
+    /// Removes the pairing with the device, along with any keys exchanged with it.
+    fn unpair(&self) -> Result<()>;
+
+    /// Returns true if the device is paired.
+    fn is_paired(&self) -> Result<bool>;
+
+    /// Discovers all characteristics for the device, along with their descriptors. This is a
+    /// synchronous operation.
     fn discover_characteristics(&self) -> Result<Vec<Characteristic>>;
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
@@ -301,6 +605,13 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     /// Synchronously returns either an error or the device response.
     fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>>;
 
+    /// Reads the value of the specified descriptor from the device. This is a synchronous call.
+    fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+
+    /// Writes some data to the specified descriptor. Returns an error if the write couldn't be sent
+    /// or if the device returns an error. This is a synchronous call.
+    fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
     /// This is a synchronous call.
     fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
@@ -311,8 +622,108 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
 
     /// Registers a handler that will be called when value notification messages are received from
     /// the device. This method should only be used after a connection has been established. Note
-    /// that the handler will be called in a common thread, so it should not block.
+    /// that the handler will be called in a common thread, so it should not block, and must not
+    /// call the blocking methods of this trait or of [`Central`].
     fn on_notification(&self, handler: NotificationHandler);
+
+    /// Returns a stream of value notifications from the specified characteristic, subscribing to
+    /// it if this is the first stream for that characteristic. Dropping the stream unregisters it,
+    /// and the characteristic is unsubscribed from once its last stream has been dropped, unless
+    /// it was also subscribed to with [`subscribe`](#tymethod.subscribe). This is a synchronous
+    /// call.
+    fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream>;
+}
+
+impl<P: AsyncPeripheral> Peripheral for P {
+    fn address(&self) -> BDAddr {
+        AsyncPeripheral::address(self)
+    }
+
+    fn properties(&self) -> PeripheralProperties {
+        AsyncPeripheral::properties(self)
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        AsyncPeripheral::characteristics(self)
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        AsyncPeripheral::services(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        AsyncPeripheral::is_connected(self)
+    }
+
+    fn connect(&self) -> Result<()> {
+        block_on(AsyncPeripheral::connect(self))
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        block_on(AsyncPeripheral::disconnect(self))
+    }
+
+    fn pair(&self) -> Result<()> {
+        block_on(AsyncPeripheral::pair(self))
+    }
+
+    fn unpair(&self) -> Result<()> {
+        block_on(AsyncPeripheral::unpair(self))
+    }
+
+    fn is_paired(&self) -> Result<bool> {
+        block_on(AsyncPeripheral::is_paired(self))
+    }
+
+    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        block_on(AsyncPeripheral::discover_characteristics(self))
+    }
+
+    fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        block_on(AsyncPeripheral::write(
+            self,
+            characteristic,
+            data,
+            write_type,
+        ))
+    }
+
+    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read(self, characteristic))
+    }
+
+    fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read_by_type(self, characteristic, uuid))
+    }
+
+    fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        block_on(AsyncPeripheral::read_descriptor(self, descriptor))
+    }
+
+    fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        block_on(AsyncPeripheral::write_descriptor(self, descriptor, data))
+    }
+
+    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        block_on(AsyncPeripheral::subscribe(self, characteristic))
+    }
+
+    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        block_on(AsyncPeripheral::unsubscribe(self, characteristic))
+    }
+
+    fn on_notification(&self, handler: NotificationHandler) {
+        AsyncPeripheral::on_notification(self, handler)
+    }
+
+    fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream> {
+        block_on(AsyncPeripheral::notifications(self, characteristic))
+    }
 }
 
 #[cfg_attr(
@@ -320,10 +731,13 @@ pub trait Peripheral: Send + Sync + Clone + Debug {
     derive(Serialize, Deserialize),
     serde(crate = "serde_cr")
 )]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CentralEvent {
     DeviceDiscovered(BDAddr),
     DeviceLost(BDAddr),
+    /// Emitted when a device is forgotten because it hasn't been heard from for longer than the
+    /// [`ExpiryPolicy`] allows
+    DeviceExpired(BDAddr),
     DeviceUpdated(BDAddr),
     DeviceConnected(BDAddr),
     DeviceDisconnected(BDAddr),
@@ -344,31 +758,187 @@ pub enum CentralEvent {
         address: BDAddr,
         services: Vec<Uuid>,
     },
+    /// Emitted when the signal strength of a device has been measured again, in dBm
+    RssiUpdate {
+        address: BDAddr,
+        rssi: i16,
+    },
+}
+
+impl CentralEvent {
+    /// Returns the address of the device which the event is about.
+    pub fn address(&self) -> BDAddr {
+        match *self {
+            CentralEvent::DeviceDiscovered(address)
+            | CentralEvent::DeviceLost(address)
+            | CentralEvent::DeviceExpired(address)
+            | CentralEvent::DeviceUpdated(address)
+            | CentralEvent::DeviceConnected(address)
+            | CentralEvent::DeviceDisconnected(address)
+            | CentralEvent::ManufacturerDataAdvertisement { address, .. }
+            | CentralEvent::ServiceDataAdvertisement { address, .. }
+            | CentralEvent::ServicesAdvertisement { address, .. }
+            | CentralEvent::RssiUpdate { address, .. } => address,
+        }
+    }
+}
+
+/// Where a known peripheral is in its lifecycle.
+///
+/// A peripheral starts out discovered, and may then connect and disconnect any number of times. It
+/// stays known, whether connected or not, until the platform reports it as lost, or it expires
+/// under the [`ExpiryPolicy`]. Either one forgets it, and is reported by
+/// [`CentralEvent::DeviceLost`] or [`CentralEvent::DeviceExpired`].
+///
+/// There are no lost or expired states, as a peripheral in them would have to be remembered
+/// forever. Devices which use random addresses change them every few minutes, so the adapter would
+/// pile up addresses which will never be heard from again. A lost or expired peripheral is simply
+/// no longer known, and is discovered afresh if it shows up again.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeviceState {
+    /// The peripheral has been found, and hasn't been connected to since.
+    Discovered,
+    /// The peripheral is connected.
+    Connected,
+    /// The peripheral has been connected to before, but isn't now.
+    Disconnected,
+}
+
+/// Decides when a peripheral which hasn't been heard from is forgotten.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_cr")
+)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExpiryPolicy {
+    /// Peripherals are only forgotten when the platform reports them as lost.
+    #[default]
+    Never,
+    /// Peripherals which aren't connected are also forgotten once nothing has been heard from them
+    /// for this long, as judged by the events they cause.
+    After(Duration),
+}
+
+/// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
+///
+/// Operations which talk to the adapter return futures. See [`Central`] for a blocking version of
+/// this trait.
+#[async_trait]
+pub trait AsyncCentral<P: AsyncPeripheral>: Send + Sync + Clone {
+    /// Subscribes to events from this Central module, buffering up to [`DEFAULT_EVENT_BUFFER`]
+    /// events. Any number of subscriptions may exist at once, and each one receives every event
+    /// which occurs after it was created. See [`Event`](enum.CentralEvent.html) for the full set of
+    /// events returned, and [`EventStream`] for what happens when a subscriber falls behind.
+    fn events(&self) -> EventStream<CentralEvent> {
+        self.events_with_buffer(DEFAULT_EVENT_BUFFER)
+    }
+
+    /// Subscribes to events from this Central module, like [`events`](#method.events), but buffers
+    /// up to `buffer` events for this subscriber before discarding the oldest ones. `buffer` must be
+    /// at least 1.
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent>;
+
+    /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
+    /// although this may depend on your bluetooth adapter. Discovered devices will be announced
+    /// to subscribers of `events` and will be available via `peripherals()`.
+    async fn start_scan(&self) -> Result<()>;
+
+    /// Starts a scan for BLE devices, like [`start_scan`](#method.start_scan), but only reports
+    /// the devices which match `filter`. The filter applies until the next scan is started. Fails
+    /// with [`Error::NotSupported`] if the platform can't apply the filter.
+    async fn start_scan_with_filter(&self, filter: ScanFilter) -> Result<()> {
+        if filter.is_empty() {
+            self.start_scan().await
+        } else {
+            Err(Error::NotSupported("start_scan_with_filter".into()))
+        }
+    }
+
+    /// Control whether to use active or passive scan mode to find BLE devices. Active mode scan
+    /// notifies advertises about the scan, whereas passive scan only receives data from the
+    /// advertiser. Defaults to use active mode. This takes effect when the next scan is started,
+    /// which fails with [`Error::NotSupported`] if the platform can't scan passively.
+    fn active(&self, enabled: bool);
+
+    /// Control whether to filter multiple advertisements by the same peer device. Receving
+    /// can be useful for some applications. E.g. when using scan to collect information from
+    /// beacons that update data frequently. Defaults to filter duplicate advertisements. When
+    /// filtering is disabled, advertisement events are sent for every advertising report, even if
+    /// the data hasn't changed.
+    fn filter_duplicates(&self, enabled: bool);
+
+    /// Stops scanning for BLE devices.
+    async fn stop_scan(&self) -> Result<()>;
+
+    /// Returns the list of [`Peripherals`](trait.AsyncPeripheral.html) that have been discovered so
+    /// far. Note that this list may contain peripherals that are no longer available.
+    fn peripherals(&self) -> Vec<P>;
+
+    /// Returns a particular [`Peripheral`](trait.AsyncPeripheral.html) by its address if it has
+    /// been discovered.
+    fn peripheral(&self, address: BDAddr) -> Option<P>;
+
+    /// Returns where the peripheral with the given address is in its lifecycle, or `None` if it
+    /// isn't known, which includes once it has been lost or has expired.
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState>;
+
+    /// Sets when peripherals which haven't been heard from are forgotten. Defaults to
+    /// [`ExpiryPolicy::Never`].
+    fn expiry_policy(&self, policy: ExpiryPolicy);
+
+    /// Returns the current state of the adapter. Fails with [`Error::NotSupported`] if the platform
+    /// can't report it.
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        Err(Error::NotSupported("adapter_info".into()))
+    }
 }
 
 /// Central is the "client" of BLE. It's able to scan for and establish connections to peripherals.
+///
+/// This is a blocking facade over [`AsyncCentral`], and is implemented for every type which
+/// implements it. Like those of [`Peripheral`], its blocking methods fail with [`Error::Other`]
+/// when called from a notification handler or other callback run by the platform.
 pub trait Central<P: Peripheral>: Send + Sync + Clone {
-    /// Retreive the Event [Receiver] for the event channel. This channel
-    /// receiver will receive notifications when events occur for this Central
-    /// module. As this uses an std::channel which cannot be cloned, after the
-    /// first call (which will contain Some<Receiver<CentralEvent>>), all
-    /// subsequent calls will return None. See [`Event`](enum.CentralEvent.html)
-    /// for the full set of events returned.
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>>;
+    /// Subscribes to events from this Central module, buffering up to [`DEFAULT_EVENT_BUFFER`]
+    /// events. Any number of subscriptions may exist at once, and each one receives every event
+    /// which occurs after it was created. See [`Event`](enum.CentralEvent.html) for the full set of
+    /// events returned, and [`EventStream`] for what happens when a subscriber falls behind.
+    fn events(&self) -> EventStream<CentralEvent> {
+        self.events_with_buffer(DEFAULT_EVENT_BUFFER)
+    }
+
+    /// Subscribes to events from this Central module, like [`events`](#method.events), but buffers
+    /// up to `buffer` events for this subscriber before discarding the oldest ones. `buffer` must be
+    /// at least 1.
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent>;
 
     /// Starts a scan for BLE devices. This scan will generally continue until explicitly stopped,
     /// although this may depend on your bluetooth adapter. Discovered devices will be announced
-    /// to subscribers of `on_event` and will be available via `peripherals()`.
+    /// to subscribers of `events` and will be available via `peripherals()`.
     fn start_scan(&self) -> Result<()>;
 
+    /// Starts a scan for BLE devices, like [`start_scan`](#method.start_scan), but only reports
+    /// the devices which match `filter`. The filter applies until the next scan is started. Fails
+    /// with [`Error::NotSupported`] if the platform can't apply the filter.
+    fn start_scan_with_filter(&self, filter: ScanFilter) -> Result<()>;
+
     /// Control whether to use active or passive scan mode to find BLE devices. Active mode scan
     /// notifies advertises about the scan, whereas passive scan only receives data from the
-    /// advertiser. Defaults to use active mode.
+    /// advertiser. Defaults to use active mode. This takes effect when the next scan is started,
+    /// which fails with [`Error::NotSupported`] if the platform can't scan passively.
     fn active(&self, enabled: bool);
 
     /// Control whether to filter multiple advertisements by the same peer device. Receving
     /// can be useful for some applications. E.g. when using scan to collect information from
-    /// beacons that update data frequently. Defaults to filter duplicate advertisements.
+    /// beacons that update data frequently. Defaults to filter duplicate advertisements. When
+    /// filtering is disabled, advertisement events are sent for every advertising report, even if
+    /// the data hasn't changed.
     fn filter_duplicates(&self, enabled: bool);
 
     /// Stops scanning for BLE devices.
@@ -381,6 +951,64 @@ pub trait Central<P: Peripheral>: Send + Sync + Clone {
     /// Returns a particular [`Peripheral`](trait.Peripheral.html) by its address if it has been
     /// discovered.
     fn peripheral(&self, address: BDAddr) -> Option<P>;
+
+    /// Returns where the peripheral with the given address is in its lifecycle, or `None` if it
+    /// isn't known, which includes once it has been lost or has expired.
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState>;
+
+    /// Sets when peripherals which haven't been heard from are forgotten. Defaults to
+    /// [`ExpiryPolicy::Never`].
+    fn expiry_policy(&self, policy: ExpiryPolicy);
+
+    /// Returns the current state of the adapter. Fails with [`Error::NotSupported`] if the platform
+    /// can't report it.
+    fn adapter_info(&self) -> Result<AdapterInfo>;
+}
+
+impl<P: AsyncPeripheral, C: AsyncCentral<P>> Central<P> for C {
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent> {
+        AsyncCentral::events_with_buffer(self, buffer)
+    }
+
+    fn start_scan(&self) -> Result<()> {
+        block_on(AsyncCentral::start_scan(self))
+    }
+
+    fn start_scan_with_filter(&self, filter: ScanFilter) -> Result<()> {
+        block_on(AsyncCentral::start_scan_with_filter(self, filter))
+    }
+
+    fn active(&self, enabled: bool) {
+        AsyncCentral::active(self, enabled)
+    }
+
+    fn filter_duplicates(&self, enabled: bool) {
+        AsyncCentral::filter_duplicates(self, enabled)
+    }
+
+    fn stop_scan(&self) -> Result<()> {
+        block_on(AsyncCentral::stop_scan(self))
+    }
+
+    fn peripherals(&self) -> Vec<P> {
+        AsyncCentral::peripherals(self)
+    }
+
+    fn peripheral(&self, address: BDAddr) -> Option<P> {
+        AsyncCentral::peripheral(self, address)
+    }
+
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        AsyncCentral::peripheral_state(self, address)
+    }
+
+    fn expiry_policy(&self, policy: ExpiryPolicy) {
+        AsyncCentral::expiry_policy(self, policy)
+    }
+
+    fn adapter_info(&self) -> Result<AdapterInfo> {
+        block_on(AsyncCentral::adapter_info(self))
+    }
 }
 
 #[cfg(test)]
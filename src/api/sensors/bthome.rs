@@ -0,0 +1,330 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The BTHome v2 format, which sensors send as service data for the BTHome service.
+//!
+//! After a device information byte, the data is a sequence of objects, each an object ID followed
+//! by a value whose length is fixed by the ID. Encrypted data is sealed with AES-CCM using the
+//! sensor's bind key.
+
+use super::{
+    le_int, le_uint, BinarySensor, BindKey, Measurement, Quantity, SensorData, SensorError,
+    SensorFormat,
+};
+use crate::api::BDAddr;
+use aes::Aes128;
+use ccm::{
+    aead::{generic_array::GenericArray, AeadInPlace, KeyInit},
+    consts::{U13, U4},
+    Ccm,
+};
+
+/// The 16-bit UUID of the BTHome service.
+pub const SERVICE: u16 = 0xFCD2;
+
+const ENCRYPTED: u8 = 0x01;
+const VERSION_SHIFT: u8 = 5;
+const PACKET_ID: u8 = 0x00;
+const TEXT: u8 = 0x53;
+const RAW: u8 = 0x54;
+
+type Cipher = Ccm<Aes128, U4, U13>;
+
+/// How to decode the value of an object: the quantity it measures, its length in bytes, whether it
+/// is signed, and the factor to scale it by to get the quantity's unit.
+struct Object {
+    id: u8,
+    quantity: Quantity,
+    length: usize,
+    signed: bool,
+    factor: f64,
+}
+
+const fn object(id: u8, quantity: Quantity, length: usize, signed: bool, factor: f64) -> Object {
+    Object {
+        id,
+        quantity,
+        length,
+        signed,
+        factor,
+    }
+}
+
+const fn binary(id: u8, sensor: BinarySensor) -> Object {
+    object(id, Quantity::Binary(sensor), 1, false, 1.0)
+}
+
+const POUND: f64 = 0.453_592_37;
+
+const OBJECTS: &[Object] = &[
+    object(0x01, Quantity::Battery, 1, false, 1.0),
+    object(0x02, Quantity::Temperature, 2, true, 0.01),
+    object(0x03, Quantity::Humidity, 2, false, 0.01),
+    object(0x04, Quantity::Pressure, 3, false, 0.01),
+    object(0x05, Quantity::Illuminance, 3, false, 0.01),
+    object(0x06, Quantity::Mass, 2, false, 0.01),
+    object(0x07, Quantity::Mass, 2, false, 0.01 * POUND),
+    object(0x08, Quantity::Dewpoint, 2, true, 0.01),
+    object(0x09, Quantity::Count, 1, false, 1.0),
+    object(0x0A, Quantity::Energy, 3, false, 0.001),
+    object(0x0B, Quantity::Power, 3, false, 0.01),
+    object(0x0C, Quantity::Voltage, 2, false, 0.001),
+    object(0x0D, Quantity::Pm25, 2, false, 1.0),
+    object(0x0E, Quantity::Pm10, 2, false, 1.0),
+    binary(0x0F, BinarySensor::Generic),
+    binary(0x10, BinarySensor::Power),
+    binary(0x11, BinarySensor::Opening),
+    object(0x12, Quantity::Co2, 2, false, 1.0),
+    object(0x13, Quantity::Tvoc, 2, false, 1.0),
+    object(0x14, Quantity::Moisture, 2, false, 0.01),
+    binary(0x15, BinarySensor::BatteryLow),
+    binary(0x16, BinarySensor::BatteryCharging),
+    binary(0x17, BinarySensor::CarbonMonoxide),
+    binary(0x18, BinarySensor::Cold),
+    binary(0x19, BinarySensor::Connectivity),
+    binary(0x1A, BinarySensor::Door),
+    binary(0x1B, BinarySensor::GarageDoor),
+    binary(0x1C, BinarySensor::Gas),
+    binary(0x1D, BinarySensor::Heat),
+    binary(0x1E, BinarySensor::Light),
+    binary(0x1F, BinarySensor::Lock),
+    binary(0x20, BinarySensor::Moisture),
+    binary(0x21, BinarySensor::Motion),
+    binary(0x22, BinarySensor::Moving),
+    binary(0x23, BinarySensor::Occupancy),
+    binary(0x24, BinarySensor::Plug),
+    binary(0x25, BinarySensor::Presence),
+    binary(0x26, BinarySensor::Problem),
+    binary(0x27, BinarySensor::Running),
+    binary(0x28, BinarySensor::Safety),
+    binary(0x29, BinarySensor::Smoke),
+    binary(0x2A, BinarySensor::Sound),
+    binary(0x2B, BinarySensor::Tamper),
+    binary(0x2C, BinarySensor::Vibration),
+    binary(0x2D, BinarySensor::Window),
+    object(0x2E, Quantity::Humidity, 1, false, 1.0),
+    object(0x2F, Quantity::Moisture, 1, false, 1.0),
+    object(0x3A, Quantity::Button, 1, false, 1.0),
+    object(0x3D, Quantity::Count, 2, false, 1.0),
+    object(0x3E, Quantity::Count, 4, false, 1.0),
+    object(0x3F, Quantity::Rotation, 2, true, 0.1),
+    object(0x40, Quantity::Distance, 2, false, 0.001),
+    object(0x41, Quantity::Distance, 2, false, 0.1),
+    object(0x42, Quantity::Duration, 3, false, 0.001),
+    object(0x43, Quantity::Current, 2, false, 0.001),
+    object(0x44, Quantity::Speed, 2, false, 0.01),
+    object(0x45, Quantity::Temperature, 2, true, 0.1),
+    object(0x46, Quantity::UvIndex, 1, false, 0.1),
+    object(0x47, Quantity::Volume, 2, false, 0.1),
+    object(0x48, Quantity::Volume, 2, false, 0.001),
+    object(0x49, Quantity::VolumeFlowRate, 2, false, 0.001),
+    object(0x4A, Quantity::Voltage, 2, false, 0.1),
+    object(0x4B, Quantity::Gas, 3, false, 0.001),
+    object(0x4C, Quantity::Gas, 4, false, 0.001),
+    object(0x4D, Quantity::Energy, 4, false, 0.001),
+    object(0x4E, Quantity::Volume, 4, false, 0.001),
+    object(0x4F, Quantity::Water, 4, false, 0.001),
+    object(0x50, Quantity::Timestamp, 4, false, 1.0),
+    object(0x51, Quantity::Acceleration, 2, false, 0.001),
+    object(0x52, Quantity::Gyroscope, 2, false, 0.001),
+];
+
+/// The dimmer event, which is two bytes: the direction, then the number of steps.
+const DIMMER: u8 = 0x3C;
+const DIMMER_LEFT: u8 = 0x01;
+
+/// Decodes the BTHome service data advertised by the sensor with the given address, decrypting it
+/// with `bind_key` if it is encrypted.
+///
+/// The length of an object's value depends on its ID, so decoding stops at the first object with
+/// an ID which isn't known, and only the measurements before it are returned.
+pub fn decode(
+    address: BDAddr,
+    data: &[u8],
+    bind_key: Option<&BindKey>,
+) -> Result<SensorData, SensorError> {
+    let (&device_info, payload) = data.split_first().ok_or(SensorError::Malformed)?;
+    let version = device_info >> VERSION_SHIFT;
+    if version != 2 {
+        return Err(SensorError::UnsupportedVersion(version));
+    }
+    let objects = if device_info & ENCRYPTED != 0 {
+        let bind_key = bind_key.ok_or(SensorError::Encrypted)?;
+        decrypt(address, device_info, payload, bind_key)?
+    } else {
+        payload.to_vec()
+    };
+
+    let mut sensor_data = SensorData {
+        format: SensorFormat::BtHome,
+        packet_id: None,
+        measurements: Vec::new(),
+    };
+    let mut rest = &objects[..];
+    while let Some((&id, value)) = rest.split_first() {
+        let length = match id {
+            PACKET_ID => {
+                let packet_id = value.first().ok_or(SensorError::Malformed)?;
+                sensor_data.packet_id = Some(u32::from(*packet_id));
+                1
+            }
+            DIMMER => {
+                let (direction, steps) = match value {
+                    [direction, steps, ..] => (*direction, f64::from(*steps)),
+                    _ => return Err(SensorError::Malformed),
+                };
+                let steps = if direction == DIMMER_LEFT {
+                    -steps
+                } else {
+                    steps
+                };
+                sensor_data
+                    .measurements
+                    .push(Measurement::new(Quantity::Dimmer, steps));
+                2
+            }
+            // Text and raw values start with their length, and aren't measurements.
+            TEXT | RAW => 1 + *value.first().ok_or(SensorError::Malformed)? as usize,
+            _ => match OBJECTS.iter().find(|object| object.id == id) {
+                Some(object) => {
+                    let value = value.get(..object.length).ok_or(SensorError::Malformed)?;
+                    let raw = if object.signed {
+                        f64::from(le_int(value))
+                    } else {
+                        f64::from(le_uint(value))
+                    };
+                    sensor_data
+                        .measurements
+                        .push(Measurement::new(object.quantity, raw * object.factor));
+                    object.length
+                }
+                None => break,
+            },
+        };
+        rest = value.get(length..).ok_or(SensorError::Malformed)?;
+    }
+    Ok(sensor_data)
+}
+
+/// Decrypts the payload of an encrypted advertisement, which is the encrypted objects followed by
+/// a 4 byte counter and a 4 byte message integrity check.
+fn decrypt(
+    address: BDAddr,
+    device_info: u8,
+    payload: &[u8],
+    bind_key: &BindKey,
+) -> Result<Vec<u8>, SensorError> {
+    if payload.len() < 8 {
+        return Err(SensorError::Malformed);
+    }
+    let (ciphertext, rest) = payload.split_at(payload.len() - 8);
+    let (counter, mic) = rest.split_at(4);
+    let nonce = nonce(address, device_info, counter);
+    let mut objects = ciphertext.to_vec();
+    Cipher::new(GenericArray::from_slice(bind_key))
+        .decrypt_in_place_detached(
+            GenericArray::from_slice(&nonce),
+            &[],
+            &mut objects,
+            GenericArray::from_slice(mic),
+        )
+        .map_err(|_| SensorError::DecryptionFailed)?;
+    Ok(objects)
+}
+
+/// The nonce is the address of the sensor in its usual, most significant byte first order, then
+/// the service UUID, the device information byte and the counter.
+fn nonce(address: BDAddr, device_info: u8, counter: &[u8]) -> Vec<u8> {
+    let mut nonce: Vec<u8> = address.address.iter().rev().copied().collect();
+    nonce.extend_from_slice(&SERVICE.to_le_bytes());
+    nonce.push(device_info);
+    nonce.extend_from_slice(counter);
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "54:48:E6:8F:80:A5";
+    const KEY: BindKey = *b"0123456789abcdef";
+
+    fn encrypt(objects: &[u8], counter: [u8; 4]) -> Vec<u8> {
+        let address = ADDRESS.parse().unwrap();
+        let mut ciphertext = objects.to_vec();
+        let mic = Cipher::new(GenericArray::from_slice(&KEY))
+            .encrypt_in_place_detached(
+                GenericArray::from_slice(&nonce(address, 0x41, &counter)),
+                &[],
+                &mut ciphertext,
+            )
+            .unwrap();
+        [&[0x41], &ciphertext[..], &counter, &mic].concat()
+    }
+
+    #[test]
+    fn decode_objects() {
+        let data = [
+            0x40, 0x00, 0x09, 0x01, 0x61, 0x02, 0xCA, 0x09, 0x03, 0xBF, 0x13, 0x21, 0x01, 0x3C,
+            0x01, 0x03, 0x99, 0xFF,
+        ];
+        let sensor_data = decode(ADDRESS.parse().unwrap(), &data, None).unwrap();
+        assert_eq!(sensor_data.packet_id, Some(9));
+        assert_eq!(sensor_data.get(Quantity::Battery), Some(97.0));
+        assert!((sensor_data.get(Quantity::Temperature).unwrap() - 25.06).abs() < 1e-9);
+        assert!((sensor_data.get(Quantity::Humidity).unwrap() - 50.55).abs() < 1e-9);
+        assert_eq!(
+            sensor_data.get(Quantity::Binary(BinarySensor::Motion)),
+            Some(1.0)
+        );
+        assert_eq!(sensor_data.get(Quantity::Dimmer), Some(-3.0));
+        // Decoding stops at the unknown object 0x99.
+        assert_eq!(sensor_data.measurements.len(), 5);
+
+        assert_eq!(
+            decode(ADDRESS.parse().unwrap(), &[0x20, 0x01, 0x64], None),
+            Err(SensorError::UnsupportedVersion(1))
+        );
+        assert_eq!(
+            decode(ADDRESS.parse().unwrap(), &[0x40, 0x02, 0xCA], None),
+            Err(SensorError::Malformed)
+        );
+    }
+
+    #[test]
+    fn decrypt_with_bind_key() {
+        let address = ADDRESS.parse().unwrap();
+        let data = encrypt(&[0x02, 0xCA, 0x09, 0x2E, 0x37], [1, 0, 0, 0]);
+        assert_eq!(decode(address, &data, None), Err(SensorError::Encrypted));
+        assert_eq!(
+            decode(address, &data, Some(b"fedcba9876543210")),
+            Err(SensorError::DecryptionFailed)
+        );
+
+        let sensor_data = decode(address, &data, Some(&KEY)).unwrap();
+        assert!((sensor_data.get(Quantity::Temperature).unwrap() - 25.06).abs() < 1e-9);
+        assert_eq!(sensor_data.get(Quantity::Humidity), Some(55.0));
+    }
+
+    #[test]
+    fn decrypt_the_example_from_the_specification() {
+        // The key and service data of the encrypted example in the BTHome v2 specification, which
+        // was sent by ADDRESS.
+        let key: BindKey = [
+            0x23, 0x1D, 0x39, 0xC1, 0xD7, 0xCC, 0x1A, 0xB1, 0xAE, 0xE2, 0x24, 0xCD, 0x09, 0x6D,
+            0xB9, 0x32,
+        ];
+        let data = [
+            0x41, 0xA4, 0x72, 0x66, 0xC9, 0x5F, 0x73, 0x00, 0x11, 0x22, 0x33, 0x78, 0x23, 0x72,
+            0x14,
+        ];
+        let sensor_data = decode(ADDRESS.parse().unwrap(), &data, Some(&key)).unwrap();
+        assert!((sensor_data.get(Quantity::Temperature).unwrap() - 25.06).abs() < 1e-9);
+        assert!((sensor_data.get(Quantity::Humidity).unwrap() - 50.55).abs() < 1e-9);
+        assert_eq!(sensor_data.measurements.len(), 2);
+    }
+}
@@ -0,0 +1,131 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The Xiaomi MiBeacon format, which Xiaomi sensors send as service data for the Xiaomi service.
+//!
+//! The data starts with a frame control field saying which optional parts follow, then the product
+//! ID and a frame counter. Measurements are carried in an object, made up of a 2 byte object ID, a
+//! length and the value.
+
+use super::{le_int, le_uint, Measurement, Quantity, SensorData, SensorError, SensorFormat};
+
+/// The 16-bit UUID of the Xiaomi service.
+pub const SERVICE: u16 = 0xFE95;
+
+const FRAME_ENCRYPTED: u16 = 0x0008;
+const FRAME_HAS_MAC: u16 = 0x0010;
+const FRAME_HAS_CAPABILITY: u16 = 0x0020;
+const FRAME_HAS_OBJECT: u16 = 0x0040;
+/// A capability byte with this bit set is followed by 2 bytes of I/O capability.
+const CAPABILITY_HAS_IO: u8 = 0x20;
+
+const TEMPERATURE: u16 = 0x1004;
+const HUMIDITY: u16 = 0x1006;
+const ILLUMINANCE: u16 = 0x1007;
+const MOISTURE: u16 = 0x1008;
+const CONDUCTIVITY: u16 = 0x1009;
+const BATTERY: u16 = 0x100A;
+const TEMPERATURE_HUMIDITY: u16 = 0x100D;
+
+/// Decodes MiBeacon service data. Encrypted frames aren't supported, and fail with
+/// [`SensorError::Encrypted`].
+pub fn decode(data: &[u8]) -> Result<SensorData, SensorError> {
+    if data.len() < 5 {
+        return Err(SensorError::Malformed);
+    }
+    let frame_control = u16::from_le_bytes([data[0], data[1]]);
+    let frame_counter = data[4];
+    if frame_control & FRAME_ENCRYPTED != 0 {
+        return Err(SensorError::Encrypted);
+    }
+    let mut offset = 5;
+    if frame_control & FRAME_HAS_MAC != 0 {
+        offset += 6;
+    }
+    if frame_control & FRAME_HAS_CAPABILITY != 0 {
+        let capability = *data.get(offset).ok_or(SensorError::Malformed)?;
+        offset += if capability & CAPABILITY_HAS_IO != 0 {
+            3
+        } else {
+            1
+        };
+    }
+
+    let mut measurements = Vec::new();
+    if frame_control & FRAME_HAS_OBJECT != 0 {
+        let object = data.get(offset..).ok_or(SensorError::Malformed)?;
+        if object.len() < 3 {
+            return Err(SensorError::Malformed);
+        }
+        let id = u16::from_le_bytes([object[0], object[1]]);
+        let value = object
+            .get(3..3 + object[2] as usize)
+            .ok_or(SensorError::Malformed)?;
+        decode_object(id, value, &mut measurements)?;
+    }
+    Ok(SensorData {
+        format: SensorFormat::MiBeacon,
+        packet_id: Some(u32::from(frame_counter)),
+        measurements,
+    })
+}
+
+fn decode_object(
+    id: u16,
+    value: &[u8],
+    measurements: &mut Vec<Measurement>,
+) -> Result<(), SensorError> {
+    let expected_length = match id {
+        TEMPERATURE | HUMIDITY | CONDUCTIVITY => 2,
+        ILLUMINANCE => 3,
+        MOISTURE | BATTERY => 1,
+        TEMPERATURE_HUMIDITY => 4,
+        // Objects which aren't measurements, such as button presses, are left out.
+        _ => return Ok(()),
+    };
+    if value.len() != expected_length {
+        return Err(SensorError::Malformed);
+    }
+    let measurement = match id {
+        TEMPERATURE => Measurement::new(Quantity::Temperature, f64::from(le_int(value)) * 0.1),
+        HUMIDITY => Measurement::new(Quantity::Humidity, f64::from(le_uint(value)) * 0.1),
+        ILLUMINANCE => Measurement::new(Quantity::Illuminance, f64::from(le_uint(value))),
+        MOISTURE => Measurement::new(Quantity::Moisture, f64::from(value[0])),
+        CONDUCTIVITY => Measurement::new(Quantity::Conductivity, f64::from(le_uint(value))),
+        BATTERY => Measurement::new(Quantity::Battery, f64::from(value[0])),
+        _ => {
+            measurements.push(Measurement::new(
+                Quantity::Temperature,
+                f64::from(le_int(&value[..2])) * 0.1,
+            ));
+            Measurement::new(Quantity::Humidity, f64::from(le_uint(&value[2..])) * 0.1)
+        }
+    };
+    measurements.push(measurement);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_temperature_and_humidity() {
+        let data = [
+            0x50, 0x20, 0xAA, 0x01, 0x2C, 0x8D, 0x7F, 0x2C, 0x34, 0x65, 0x4C, 0x0D, 0x10, 0x04,
+            0xDC, 0x00, 0xF1, 0x01,
+        ];
+        let sensor_data = decode(&data).unwrap();
+        assert_eq!(sensor_data.packet_id, Some(0x2C));
+        assert!((sensor_data.get(Quantity::Temperature).unwrap() - 22.0).abs() < 1e-9);
+        assert!((sensor_data.get(Quantity::Humidity).unwrap() - 49.7).abs() < 1e-9);
+
+        let mut encrypted = data;
+        encrypted[0] |= 0x08;
+        assert_eq!(decode(&encrypted), Err(SensorError::Encrypted));
+    }
+}
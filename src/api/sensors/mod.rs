@@ -0,0 +1,305 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Decoding of the measurements which sensors broadcast in their advertisements.
+//!
+//! Many sensors never need a connection: they put their latest readings in the service data or
+//! manufacturer data of every advertisement. This module decodes the common formats, BTHome v2,
+//! Xiaomi MiBeacon and Ruuvi RAWv2, into [`Measurement`]s with units, so sensors can be logged
+//! from the [`PeripheralProperties`] which a scan collects.
+
+pub mod bthome;
+pub mod mibeacon;
+pub mod ruuvi;
+
+use super::{bleuuid::uuid_from_u16, BDAddr, PeripheralProperties};
+use crate::Error;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum SensorError {
+    #[error("Malformed sensor data")]
+    Malformed,
+    #[error("Unsupported version {0} of the sensor data format")]
+    UnsupportedVersion(u8),
+    #[error("The sensor data is encrypted, and there is no key to decrypt it")]
+    Encrypted,
+    #[error("The sensor data couldn't be decrypted with the bind key")]
+    DecryptionFailed,
+}
+
+impl From<SensorError> for Error {
+    fn from(e: SensorError) -> Self {
+        Error::Other(format!("SensorError: {}", e))
+    }
+}
+
+/// The key which a sensor encrypts its advertisements with.
+pub type BindKey = [u8; 16];
+
+/// A binary sensor, which reports whether something is on (1) or off (0).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BinarySensor {
+    Generic,
+    Power,
+    Opening,
+    BatteryLow,
+    BatteryCharging,
+    CarbonMonoxide,
+    Cold,
+    Connectivity,
+    Door,
+    GarageDoor,
+    Gas,
+    Heat,
+    Light,
+    Lock,
+    Moisture,
+    Motion,
+    Moving,
+    Occupancy,
+    Plug,
+    Presence,
+    Problem,
+    Running,
+    Safety,
+    Smoke,
+    Sound,
+    Tamper,
+    Vibration,
+    Window,
+}
+
+/// What a [`Measurement`] measures. Each quantity is always given in the same unit, which
+/// [`unit`](#method.unit) returns.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Quantity {
+    Acceleration,
+    AccelerationX,
+    AccelerationY,
+    AccelerationZ,
+    Battery,
+    Binary(BinarySensor),
+    /// A button press, as an event code: 1 for a press, 2 for a double press, 3 for a triple
+    /// press, 4 for a long press, 5 for a long double press, 6 for a long triple press.
+    Button,
+    Co2,
+    Conductivity,
+    Count,
+    Current,
+    Dewpoint,
+    Distance,
+    /// A dimmer rotation, as a number of steps which is negative for a rotation to the left.
+    Dimmer,
+    Duration,
+    Energy,
+    Gas,
+    Gyroscope,
+    Humidity,
+    Illuminance,
+    Mass,
+    Moisture,
+    Pm10,
+    Pm25,
+    Power,
+    Pressure,
+    Rotation,
+    Speed,
+    Temperature,
+    /// A time, in seconds since the Unix epoch.
+    Timestamp,
+    Tvoc,
+    TxPower,
+    UvIndex,
+    Voltage,
+    Volume,
+    VolumeFlowRate,
+    Water,
+}
+
+impl Quantity {
+    /// Returns the symbol of the unit which the quantity is given in, which is empty for quantities
+    /// without a unit.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Quantity::Acceleration
+            | Quantity::AccelerationX
+            | Quantity::AccelerationY
+            | Quantity::AccelerationZ => "m/s²",
+            Quantity::Battery | Quantity::Humidity | Quantity::Moisture => "%",
+            Quantity::Binary(_)
+            | Quantity::Button
+            | Quantity::Count
+            | Quantity::Dimmer
+            | Quantity::UvIndex => "",
+            Quantity::Co2 => "ppm",
+            Quantity::Conductivity => "µS/cm",
+            Quantity::Current => "A",
+            Quantity::Dewpoint | Quantity::Temperature => "°C",
+            Quantity::Distance => "m",
+            Quantity::Duration | Quantity::Timestamp => "s",
+            Quantity::Energy => "kWh",
+            Quantity::Gas => "m³",
+            Quantity::Gyroscope => "°/s",
+            Quantity::Illuminance => "lx",
+            Quantity::Mass => "kg",
+            Quantity::Pm10 | Quantity::Pm25 | Quantity::Tvoc => "µg/m³",
+            Quantity::Power => "W",
+            Quantity::Pressure => "hPa",
+            Quantity::Rotation => "°",
+            Quantity::Speed => "m/s",
+            Quantity::TxPower => "dBm",
+            Quantity::Voltage => "V",
+            Quantity::Volume | Quantity::Water => "L",
+            Quantity::VolumeFlowRate => "m³/h",
+        }
+    }
+}
+
+/// A single value which a sensor reported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    pub quantity: Quantity,
+    /// The value, in the unit of the quantity.
+    pub value: f64,
+}
+
+impl Measurement {
+    pub fn new(quantity: Quantity, value: f64) -> Self {
+        Measurement { quantity, value }
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?}: {} {}",
+            self.quantity,
+            self.value,
+            self.quantity.unit()
+        )
+    }
+}
+
+/// The format which a sensor broadcast its measurements in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SensorFormat {
+    BtHome,
+    MiBeacon,
+    Ruuvi,
+}
+
+/// The measurements from one advertisement of a sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorData {
+    pub format: SensorFormat,
+    /// A counter which the sensor changes whenever it takes new measurements, which tells a new
+    /// reading apart from the same one being advertised again.
+    pub packet_id: Option<u32>,
+    pub measurements: Vec<Measurement>,
+}
+
+impl SensorData {
+    /// Returns the first measurement of the given quantity.
+    pub fn get(&self, quantity: Quantity) -> Option<f64> {
+        self.measurements
+            .iter()
+            .find(|measurement| measurement.quantity == quantity)
+            .map(|measurement| measurement.value)
+    }
+}
+
+/// Decodes the sensor data in the advertisements of peripherals, decrypting it with the bind keys
+/// which have been given for the sensors which encrypt their advertisements.
+#[derive(Clone, Debug, Default)]
+pub struct SensorDecoder {
+    bind_keys: HashMap<BDAddr, BindKey>,
+}
+
+impl SensorDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the key to decrypt the advertisements of the sensor with the given address.
+    pub fn bind_key(mut self, address: BDAddr, key: BindKey) -> Self {
+        self.bind_keys.insert(address, key);
+        self
+    }
+
+    /// Decodes the sensor data in the properties of a peripheral. Returns `Ok(None)` if they don't
+    /// hold any data in a known format.
+    pub fn decode(
+        &self,
+        properties: &PeripheralProperties,
+    ) -> Result<Option<SensorData>, SensorError> {
+        for (&service, data) in &properties.service_data {
+            if let Some(sensor_data) =
+                self.decode_service_data(properties.address, service, data)?
+            {
+                return Ok(Some(sensor_data));
+            }
+        }
+        for (&manufacturer_id, data) in &properties.manufacturer_data {
+            if let Some(sensor_data) = self.decode_manufacturer_data(manufacturer_id, data)? {
+                return Ok(Some(sensor_data));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes the service data which a sensor with the given address advertised. Returns
+    /// `Ok(None)` if the service isn't one of a known format.
+    pub fn decode_service_data(
+        &self,
+        address: BDAddr,
+        service: uuid::Uuid,
+        data: &[u8],
+    ) -> Result<Option<SensorData>, SensorError> {
+        if service == uuid_from_u16(bthome::SERVICE) {
+            let bind_key = self.bind_keys.get(&address);
+            bthome::decode(address, data, bind_key).map(Some)
+        } else if service == uuid_from_u16(mibeacon::SERVICE) {
+            mibeacon::decode(data).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Decodes the manufacturer data which a sensor advertised. Returns `Ok(None)` if the
+    /// manufacturer isn't one with a known format.
+    pub fn decode_manufacturer_data(
+        &self,
+        manufacturer_id: u16,
+        data: &[u8],
+    ) -> Result<Option<SensorData>, SensorError> {
+        if manufacturer_id == ruuvi::MANUFACTURER_ID {
+            ruuvi::decode(data).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Reads an unsigned little-endian integer of up to 4 bytes.
+fn le_uint(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | u32::from(byte))
+}
+
+/// Reads a signed little-endian integer of up to 4 bytes.
+fn le_int(bytes: &[u8]) -> i32 {
+    let shift = 32 - 8 * bytes.len() as u32;
+    ((le_uint(bytes) << shift) as i32) >> shift
+}
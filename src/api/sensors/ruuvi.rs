@@ -0,0 +1,131 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! The RAWv2 format (data format 5), which RuuviTags send as manufacturer data.
+//!
+//! Every field is big-endian, and a field which the tag can't measure holds the largest value of
+//! an unsigned field, or the smallest value of a signed one.
+
+use super::{Measurement, Quantity, SensorData, SensorError, SensorFormat};
+use std::convert::TryInto;
+
+/// The company identifier of Ruuvi Innovations.
+pub const MANUFACTURER_ID: u16 = 0x0499;
+
+const RAW_V2: u8 = 5;
+const LENGTH: usize = 24;
+const STANDARD_GRAVITY: f64 = 9.806_65;
+
+/// Decodes RAWv2 manufacturer data, which doesn't include the company identifier.
+pub fn decode(data: &[u8]) -> Result<SensorData, SensorError> {
+    let (&format, _) = data.split_first().ok_or(SensorError::Malformed)?;
+    if format != RAW_V2 {
+        return Err(SensorError::UnsupportedVersion(format));
+    }
+    if data.len() != LENGTH {
+        return Err(SensorError::Malformed);
+    }
+    let unsigned = |offset: usize| u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap());
+    let signed = |offset: usize| i16::from_be_bytes(data[offset..offset + 2].try_into().unwrap());
+
+    let mut measurements = Vec::new();
+    let mut push = |quantity, value: Option<f64>| {
+        if let Some(value) = value {
+            measurements.push(Measurement::new(quantity, value));
+        }
+    };
+    let signed_value = |offset| Some(signed(offset)).filter(|&value| value != i16::MIN);
+    let unsigned_value = |offset| Some(unsigned(offset)).filter(|&value| value != u16::MAX);
+
+    push(
+        Quantity::Temperature,
+        signed_value(1).map(|value| f64::from(value) * 0.005),
+    );
+    push(
+        Quantity::Humidity,
+        unsigned_value(3).map(|value| f64::from(value) * 0.0025),
+    );
+    // The pressure is in Pa, less 50000 Pa.
+    push(
+        Quantity::Pressure,
+        unsigned_value(5).map(|value| (f64::from(value) + 50000.0) / 100.0),
+    );
+    // The acceleration is in milli-g.
+    for (quantity, offset) in [
+        (Quantity::AccelerationX, 7),
+        (Quantity::AccelerationY, 9),
+        (Quantity::AccelerationZ, 11),
+    ] {
+        push(
+            quantity,
+            signed_value(offset).map(|value| f64::from(value) / 1000.0 * STANDARD_GRAVITY),
+        );
+    }
+    // The power information packs the battery voltage above 1.6 V in mV into the top 11 bits, and
+    // the transmission power above -40 dBm in steps of 2 dBm into the bottom 5 bits.
+    let power = unsigned(13);
+    let voltage = power >> 5;
+    let tx_power = power & 0x1F;
+    push(
+        Quantity::Voltage,
+        Some(voltage)
+            .filter(|&voltage| voltage != 0x7FF)
+            .map(|voltage| (f64::from(voltage) + 1600.0) / 1000.0),
+    );
+    push(
+        Quantity::TxPower,
+        Some(tx_power)
+            .filter(|&tx_power| tx_power != 0x1F)
+            .map(|tx_power| f64::from(tx_power) * 2.0 - 40.0),
+    );
+    push(
+        Quantity::Count,
+        Some(data[15])
+            .filter(|&count| count != u8::MAX)
+            .map(f64::from),
+    );
+    Ok(SensorData {
+        format: SensorFormat::Ruuvi,
+        packet_id: unsigned_value(16).map(u32::from),
+        measurements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_raw_v2() {
+        // The valid data test vector from the format specification.
+        let data = [
+            0x05, 0x12, 0xFC, 0x53, 0x94, 0xC3, 0x7C, 0x00, 0x04, 0xFF, 0xFC, 0x04, 0x0C, 0xAC,
+            0x36, 0x42, 0x00, 0xCD, 0xCB, 0xB8, 0x33, 0x4C, 0x88, 0x4F,
+        ];
+        let sensor_data = decode(&data).unwrap();
+        let get = |quantity| sensor_data.get(quantity).unwrap();
+        assert!((get(Quantity::Temperature) - 24.3).abs() < 1e-9);
+        assert!((get(Quantity::Humidity) - 53.49).abs() < 1e-9);
+        assert!((get(Quantity::Pressure) - 1000.44).abs() < 1e-9);
+        assert!((get(Quantity::AccelerationZ) - 1.036 * STANDARD_GRAVITY).abs() < 1e-9);
+        assert!((get(Quantity::Voltage) - 2.977).abs() < 1e-9);
+        assert_eq!(get(Quantity::TxPower), 4.0);
+        assert_eq!(get(Quantity::Count), 66.0);
+        assert_eq!(sensor_data.packet_id, Some(205));
+
+        // The invalid data test vector, in which every field is marked as unavailable.
+        let invalid = [
+            0x05, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0xFF,
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        assert!(decode(&invalid).unwrap().measurements.is_empty());
+        assert_eq!(
+            decode(&[0x03, 0x29]),
+            Err(SensorError::UnsupportedVersion(3))
+        );
+    }
+}
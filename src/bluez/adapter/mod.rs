@@ -16,35 +16,54 @@
 mod peripheral;
 
 use super::{
-    bluez_dbus::adapter::OrgBluezAdapter1, bluez_dbus::device::OrgBluezDevice1Properties,
+    bluez_dbus::adapter::{
+        OrgBluezAdapter1, OrgBluezAdapter1Properties, OrgBluezLEAdvertisingManager1,
+        ORG_BLUEZ_ADAPTER1_NAME,
+    },
+    bluez_dbus::device::OrgBluezDevice1Properties,
     bluez_dbus::device::ORG_BLUEZ_DEVICE1_NAME,
     bluez_dbus::gatt_characteristic::OrgBluezGattCharacteristic1Properties,
     bluez_dbus::gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
-    bluez_dbus::gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME, BLUEZ_DEST, DEFAULT_TIMEOUT,
+    bluez_dbus::gatt_descriptor::OrgBluezGattDescriptor1Properties,
+    bluez_dbus::gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+    bluez_dbus::gatt_service::OrgBluezGattService1Properties,
+    bluez_dbus::gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME,
+    connection::Connection,
+    BLUEZ_DEST,
 };
 use crate::{
-    api::{AdapterManager, BDAddr, Central, CentralEvent, CharPropFlags},
-    bluez::adapter::peripheral::Peripheral,
+    api::{
+        device_info::ClassOfDevice, AdapterInfo, AdapterManager, AddressType, AsyncCentral, BDAddr,
+        CentralEvent, CharPropFlags, DeviceState, EventStream, ExpiryPolicy, ScanFilter, Transport,
+    },
+    bluez::{
+        adapter::peripheral::Peripheral,
+        advertisement::{self, Advertisement, AdvertisementRegistration, Include},
+        gatt_server::{GattApplication, LocalService},
+        util::call_error,
+    },
     Error, Result,
 };
-use dashmap::DashMap;
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
 use dbus::{
-    arg::RefArg,
-    blocking::{Proxy, SyncConnection},
+    arg::{PropMap, RefArg, Variant},
     channel::Token,
     message::SignalArgs,
-    Path,
+    nonblock::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Proxy, SyncConnection},
+    Message, Path,
 };
 use displaydoc::Display;
 use log::{debug, error, info, trace, warn};
-use parking_lot::ReentrantMutex;
 use static_assertions::assert_impl_all;
 use std::{
     self,
     iter::Iterator,
     str::FromStr,
-    sync::{mpsc::Receiver, Arc, Condvar, Mutex},
-    thread::{self, JoinHandle},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
     time::Duration,
 };
 use thiserror::Error;
@@ -54,6 +73,22 @@ use uuid::Uuid;
 enum TokenType {
     DeviceDiscovery,
     DeviceLost,
+    PropertiesChanged,
+}
+
+/// The signal handlers of an adapter. The connection may be shared with other adapters, so the
+/// handlers are removed from it when the last clone of the adapter is dropped.
+struct MatchTokens {
+    connection: Arc<Connection>,
+    tokens: DashMap<TokenType, Token>,
+}
+
+impl Drop for MatchTokens {
+    fn drop(&mut self) {
+        for entry in self.tokens.iter() {
+            self.connection.stop_match(*entry.value());
+        }
+    }
 }
 
 type ParseCharPropFlagsResult<T> = std::result::Result<T, ParseCharPropFlagsError>;
@@ -100,141 +135,397 @@ impl FromStr for CharPropFlags {
 
 /// Adapter represents a physical bluetooth interface in your system, for example a bluetooth
 /// dongle.
+///
+/// Once the adapter is removed from the system, for example by unplugging the dongle, its methods
+/// return [`Error::AdapterNotAvailable`]. Plugging it back in doesn't revive the handle, so get a
+/// new one from the [`Manager`](crate::bluez::manager::Manager) when it reports the adapter as
+/// added again.
 #[derive(Clone)]
 pub struct Adapter {
-    connection: Arc<SyncConnection>,
-    listener: Arc<ReentrantMutex<SyncConnection>>,
+    connection: Arc<Connection>,
     path: String,
     manager: AdapterManager<Peripheral>,
-    match_tokens: Arc<DashMap<TokenType, Token>>,
-
-    should_stop: Arc<(Condvar, Mutex<bool>)>,
-    thread_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    match_tokens: Arc<MatchTokens>,
+    active: Arc<AtomicBool>,
+    removed: Arc<AtomicBool>,
+    show_blocked: Arc<AtomicBool>,
+    // Whether the last scan left a discovery filter set, which BlueZ keeps until it is cleared.
+    filtered: Arc<AtomicBool>,
+    // Paths of unknown devices which are being fetched, or were skipped as blocked, so that their
+    // changes don't fetch them over and over.
+    rediscovering: Arc<DashSet<String>>,
 }
 
 assert_impl_all!(SyncConnection: Sync, Send);
 assert_impl_all!(Adapter: Sync, Send);
 
 impl Adapter {
-    pub(crate) fn from_dbus_path(path: &Path) -> Result<Adapter> {
-        let conn = Arc::new(SyncConnection::new_system()?);
-        let proxy = conn.with_proxy(BLUEZ_DEST, path, DEFAULT_TIMEOUT);
-        info!("DevInfo: {:?}", proxy.address()?);
+    pub(crate) async fn from_dbus_path(
+        path: Path<'static>,
+        connection: Arc<Connection>,
+    ) -> Result<Adapter> {
+        let proxy = Proxy::new(BLUEZ_DEST, &path, connection.timeout(), &**connection);
+        let address = proxy
+            .address()
+            .await
+            .map_err(call_error(connection.timeout()))?;
+        info!("DevInfo: {:?}", address);
 
         let adapter = Adapter {
-            connection: conn,
-            listener: Arc::new(ReentrantMutex::new(SyncConnection::new_system()?)),
+            connection: connection.clone(),
             path: path.to_string(),
             manager: AdapterManager::new(),
-            match_tokens: Arc::new(DashMap::new()),
-            should_stop: Arc::new((Condvar::new(), Mutex::new(false))),
-            thread_handle: Arc::new(Mutex::new(None)),
+            match_tokens: Arc::new(MatchTokens {
+                connection,
+                tokens: DashMap::new(),
+            }),
+            active: Arc::new(AtomicBool::new(true)),
+            removed: Arc::new(AtomicBool::new(false)),
+            show_blocked: Arc::new(AtomicBool::new(false)),
+            filtered: Arc::new(AtomicBool::new(false)),
+            rediscovering: Arc::new(DashSet::new()),
         };
 
-        adapter.setup();
+        adapter.setup().await?;
 
         Ok(adapter)
     }
 
-    fn setup(&self) {
-        use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesRemoved as InterfacesRemoved;
+    /// Returns a closure which recovers the adapter, as long as a clone of it is still alive.
+    /// Signal handlers are owned by the connection, so they must not keep the adapter alive
+    /// themselves.
+    fn downgrade(&self) -> impl Fn() -> Option<Adapter> + Send + 'static {
+        let match_tokens: Weak<MatchTokens> = Arc::downgrade(&self.match_tokens);
+        let path = self.path.clone();
+        let manager = self.manager.clone();
+        let active = self.active.clone();
+        let removed = self.removed.clone();
+        let show_blocked = self.show_blocked.clone();
+        let filtered = self.filtered.clone();
+        let rediscovering = self.rediscovering.clone();
+        move || {
+            match_tokens.upgrade().map(|match_tokens| Adapter {
+                connection: match_tokens.connection.clone(),
+                path: path.clone(),
+                manager: manager.clone(),
+                match_tokens,
+                active: active.clone(),
+                removed: removed.clone(),
+                show_blocked: show_blocked.clone(),
+                filtered: filtered.clone(),
+                rediscovering: rediscovering.clone(),
+            })
+        }
+    }
+
+    async fn setup(&self) -> Result<()> {
+        use dbus::nonblock::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesRemoved as InterfacesRemoved;
         let mut lost_rule = InterfacesRemoved::match_rule(None, None);
         lost_rule.path = Some(Path::from("/"));
 
-        // // Enable watching the DBus channel for new messages
-        // conn.channel().set_watch_enabled(true);
-        // let watcher = conn.channel().watch();
-        // // watcher.
-        let listener = self.listener.clone();
-        let should_stop = self.should_stop.clone();
-
-        // Spawn a new thread to process incoming DBus messages.
-        // This thread gets cleaned up in the 'impl Drop for Adapter'
-        *self.thread_handle.lock().unwrap() = Some(thread::spawn(move || {
-            let (cvar, should_stop) = &*should_stop;
-            loop {
-                let (should_stop, _timeout_result) = cvar
-                    .wait_timeout(should_stop.lock().unwrap(), Duration::from_millis(100))
-                    .unwrap();
-                if *should_stop {
-                    break;
+        let adapter = self.downgrade();
+        let token = self
+            .connection
+            .add_match(lost_rule, move |args: InterfacesRemoved, _msg| {
+                trace!("Received 'InterfacesRemoved' signal");
+                let adapter = match adapter() {
+                    Some(adapter) => adapter,
+                    None => return false,
+                };
+                let path = args.object;
+
+                if *path == *adapter.path
+                    && args.interfaces.iter().any(|s| s == ORG_BLUEZ_ADAPTER1_NAME)
+                {
+                    info!("Adapter \"{}\" was removed", adapter.path);
+                    adapter.removed.store(true, Ordering::Relaxed);
+                } else if args.interfaces.iter().any(|s| s == ORG_BLUEZ_DEVICE1_NAME) {
+                    adapter.device_removed(&path).unwrap();
+                } else if args
+                    .interfaces
+                    .iter()
+                    .any(|s| s == ORG_BLUEZ_GATT_SERVICE1_NAME)
+                {
+                    // Ignore Services that get removed, the BTLEPlug API doesn't support that
+                } else if args
+                    .interfaces
+                    .iter()
+                    .any(|s| s == ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME)
+                {
+                    // Ignore Characteristics that get removed, the BTLEPlug API doesn't support that
                 }
 
-                // listener is protected by a mutex. as calling `process()` when any proxied request is awaiting a reply, will cause the reply to be dropped...
-                let lock = listener.lock();
-                // Important! The [parking_log::ReentrantMutex] promotes fairness, that is, eventually giving
-                //  other threads waiting on a lock to get the lock. Where as, Rust's std::sync::Mutex does not.
-                //  See: https://docs.rs/parking_lot/0.11.1/parking_lot/type.Mutex.html#fairness
-                while lock.process(Duration::from_secs(0)).unwrap() {}
-            }
-        }));
+                true
+            })
+            .await?;
+        self.match_tokens
+            .tokens
+            .insert(TokenType::DeviceLost, token);
+
+        // Listen for property changes on everything below this adapter (devices, services,
+        // characteristics, etc.), and hand them to the peripheral they belong to.
+        let mut changed_rule = PropertiesPropertiesChanged::match_rule(None, None);
+        changed_rule.path = Some(Path::from(self.path.clone()));
+        changed_rule.path_is_namespace = true;
+
+        let adapter = self.downgrade();
+        let token = self
+            .connection
+            .add_match(
+                changed_rule,
+                move |args: PropertiesPropertiesChanged, msg| {
+                    let adapter = match adapter() {
+                        Some(adapter) => adapter,
+                        None => return false,
+                    };
+                    let path = match msg.path() {
+                        Some(path) => path,
+                        None => return true,
+                    };
+                    let address = match adapter.address_from_path(&path) {
+                        Some(address) => address,
+                        None => return true,
+                    };
+                    if args.interface_name == ORG_BLUEZ_DEVICE1_NAME {
+                        if !args.invalidated_properties.is_empty() {
+                            warn!(
+                                "TODO: Got some properties to invalidate\n\t{:?}",
+                                args.invalidated_properties
+                            );
+                        }
+                        let changed = OrgBluezDevice1Properties(&args.changed_properties);
+                        if adapter.manager.has_peripheral(&address) {
+                            adapter.update_device(address, changed);
+                        } else if adapter
+                            .match_tokens
+                            .tokens
+                            .contains_key(&TokenType::DeviceDiscovery)
+                        {
+                            // BlueZ still has the devices which have expired or been lost, so
+                            // this discovers them again the next time it hears from them.
+                            if changed.blocked() == Some(false) {
+                                adapter.rediscovering.remove(&*path);
+                            }
+                            adapter.rediscover_device(&path);
+                        }
+                    } else if let Some(peripheral) = adapter.manager.peripheral(address) {
+                        peripheral.properties_changed(args, msg);
+                    }
+                    true
+                },
+            )
+            .await?;
+        self.match_tokens
+            .tokens
+            .insert(TokenType::PropertiesChanged, token);
 
-        let adapter = self.clone();
-        self.match_tokens.insert(
-            TokenType::DeviceLost,
-            self.listener
-                .lock()
-                .add_match(lost_rule, move |args: InterfacesRemoved, _c, _msg| {
-                    trace!("Received 'InterfacesRemoved' signal");
-                    let path = args.object;
+        Ok(())
+    }
 
-                    if args.interfaces.iter().any(|s| s == ORG_BLUEZ_DEVICE1_NAME) {
-                        adapter.remove_device(&path).unwrap();
-                    } else if args
-                        .interfaces
-                        .iter()
-                        .any(|s| s == ORG_BLUEZ_GATT_SERVICE1_NAME)
-                    {
-                        // Ignore Services that get removed, the BTLEPlug API doesn't support that
-                    } else if args
-                        .interfaces
-                        .iter()
-                        .any(|s| s == ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME)
-                    {
-                        // Ignore Characteristics that get removed, the BTLEPlug API doesn't support that
-                    }
+    /// Returns the D-Bus object path of the adapter, such as `/org/bluez/hci0`, which
+    /// [`ManagerEvent`](crate::bluez::manager::ManagerEvent)s identify the adapter by.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
 
-                    return true;
-                })
-                .unwrap(),
-        );
+    /// Fails with [`Error::AdapterNotAvailable`] once BlueZ has removed the adapter.
+    fn check_available(&self) -> Result<()> {
+        if self.removed.load(Ordering::Relaxed) {
+            Err(Error::AdapterNotAvailable)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Converts the error from a method call made through [`proxy`](Self::proxy).
+    fn call_error(&self) -> impl Fn(dbus::Error) -> Error {
+        call_error(self.connection.timeout())
     }
 
-    pub fn proxy(&self) -> Proxy<&SyncConnection> {
-        self.connection
-            .with_proxy(BLUEZ_DEST, &self.path, DEFAULT_TIMEOUT)
+    pub fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        Proxy::new(
+            BLUEZ_DEST,
+            &self.path,
+            self.connection.timeout(),
+            &**self.connection,
+        )
     }
 
     /// Get the adapter's powered state. This also indicates the appropriate connectable state of the adapter.
-    pub fn is_powered(&self) -> Result<bool> {
-        Ok(self.proxy().powered()?)
+    pub async fn is_powered(&self) -> Result<bool> {
+        self.check_available()?;
+        self.proxy().powered().await.map_err(self.call_error())
     }
 
     /// Switch an adapter on or off. This will also set the appropriate connectable state of the adapter.
-    pub fn set_powered(&self, powered: bool) -> Result<()> {
-        Ok(self.proxy().set_powered(powered)?)
+    pub async fn set_powered(&self, powered: bool) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_powered(powered)
+            .await
+            .map_err(self.call_error())
+    }
+
+    pub async fn name(&self) -> Result<String> {
+        self.check_available()?;
+        self.proxy().name().await.map_err(self.call_error())
+    }
+
+    pub async fn address(&self) -> Result<BDAddr> {
+        self.check_available()?;
+        let address = self.proxy().address().await.map_err(self.call_error())?;
+        Ok(address.parse()?)
+    }
+
+    pub async fn discoverable(&self) -> Result<bool> {
+        self.check_available()?;
+        self.proxy().discoverable().await.map_err(self.call_error())
+    }
+
+    pub async fn set_discoverable(&self, enabled: bool) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_discoverable(enabled)
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Sets how long the adapter stays discoverable for once made discoverable. Zero keeps it
+    /// discoverable until it is made undiscoverable again.
+    pub async fn set_discoverable_timeout(&self, timeout: Duration) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_discoverable_timeout(seconds(timeout))
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Sets the name which the adapter shows to other devices. An empty alias goes back to the
+    /// adapter's name.
+    pub async fn set_alias(&self, alias: &str) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_alias(alias.to_string())
+            .await
+            .map_err(self.call_error())
+    }
+
+    pub async fn set_pairable(&self, enabled: bool) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_pairable(enabled)
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Sets how long the adapter stays pairable for once made pairable. Zero keeps it pairable
+    /// until it is made unpairable again.
+    pub async fn set_pairable_timeout(&self, timeout: Duration) -> Result<()> {
+        self.check_available()?;
+        self.proxy()
+            .set_pairable_timeout(seconds(timeout))
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Controls whether devices which have been blocked are reported as peripherals, with
+    /// [`PeripheralProperties::blocked`](crate::api::PeripheralProperties::blocked) set, rather than
+    /// hidden. Defaults to hiding them. This applies to devices found from now on.
+    pub fn show_blocked_devices(&self, enabled: bool) {
+        self.show_blocked.store(enabled, Ordering::Relaxed);
+        self.rediscovering.clear();
+    }
+
+    /// Asks BlueZ to forget the device with the given address, along with its pairing. It is
+    /// reported as lost, and will be found again like a new device if it is still advertising.
+    pub async fn remove_device(&self, address: BDAddr) -> Result<()> {
+        self.check_available()?;
+        let path = format!(
+            "{}/dev_{}",
+            self.path,
+            address.to_string().replace(':', "_")
+        );
+        self.proxy()
+            .remove_device(Path::from(path))
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Returns the names of the discovery filter fields which the running BlueZ supports, such as
+    /// `UUIDs` or `RSSI`. A [`ScanFilter`] which uses any other field can't be used to scan.
+    pub async fn discovery_filters(&self) -> Result<Vec<String>> {
+        self.check_available()?;
+        self.proxy()
+            .get_discovery_filters()
+            .await
+            .map_err(self.call_error())
+    }
+
+    /// Starts advertising `advertisement`, until the returned registration is dropped.
+    pub async fn advertise(
+        &self,
+        advertisement: Advertisement,
+    ) -> Result<AdvertisementRegistration> {
+        self.check_available()?;
+        AdvertisementRegistration::register(
+            self.connection.clone(),
+            Path::from(self.path.clone()),
+            advertisement,
+        )
+        .await
     }
 
-    pub fn name(&self) -> Result<String> {
-        Ok(self.proxy().name()?)
+    /// Returns how many more advertisements the adapter can advertise at once.
+    pub async fn advertising_instances(&self) -> Result<u8> {
+        self.check_available()?;
+        OrgBluezLEAdvertisingManager1::supported_instances(&self.proxy())
+            .await
+            .map_err(self.call_error())
     }
 
-    pub fn address(&self) -> Result<BDAddr> {
-        Ok(self.proxy().address()?.parse()?)
+    /// Returns the data which BlueZ can add to advertisements by itself on this adapter.
+    pub async fn supported_includes(&self) -> Result<Vec<Include>> {
+        self.check_available()?;
+        let names = OrgBluezLEAdvertisingManager1::supported_includes(&self.proxy())
+            .await
+            .map_err(self.call_error())?;
+        Ok(advertisement::includes(&names))
     }
 
-    pub fn discoverable(&self) -> Result<bool> {
-        Ok(self.proxy().discoverable()?)
+    /// Serves `services` to centrals which connect to this adapter, until the returned
+    /// application is dropped.
+    pub async fn register_application(
+        &self,
+        services: Vec<LocalService>,
+    ) -> Result<GattApplication> {
+        self.check_available()?;
+        GattApplication::register(
+            self.connection.clone(),
+            Path::from(self.path.clone()),
+            services,
+        )
+        .await
     }
 
-    pub fn set_discoverable(&self, enabled: bool) -> Result<()> {
-        Ok(self.proxy().set_discoverable(enabled)?)
+    /// Convert "/org/bluez/hciXX/dev_XX_XX_XX_XX_XX_XX/serviceXX" into "XX:XX:XX:XX:XX:XX"
+    fn address_from_path(&self, path: &str) -> Option<BDAddr> {
+        path.strip_prefix(format!("{}/dev_", self.path).as_str())
+            .and_then(|p| p.get(..17))
+            .and_then(|p| p.replace("_", ":").parse::<BDAddr>().ok())
     }
 
-    fn get_existing_peripherals(&self) -> Result<()> {
-        use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManager;
-        let proxy = self.connection.with_proxy(BLUEZ_DEST, "/", DEFAULT_TIMEOUT);
-        let objects = proxy.get_managed_objects()?;
+    async fn get_existing_peripherals(&self) -> Result<()> {
+        use dbus::nonblock::stdintf::org_freedesktop_dbus::ObjectManager;
+        let proxy = Proxy::new(
+            BLUEZ_DEST,
+            "/",
+            self.connection.timeout(),
+            &**self.connection,
+        );
+        let objects = proxy
+            .get_managed_objects()
+            .await
+            .map_err(self.call_error())?;
 
         trace!("Fetching already known peripherals from \"{}\"", self.path);
 
@@ -248,62 +539,54 @@ impl Adapter {
         adapter_objects
             .clone()
             .filter_map(|(p, i)| i.get(ORG_BLUEZ_DEVICE1_NAME).map(|d| (p, d)))
-            .map(|(path, device)| {
-                Ok(self.add_device(path.as_str().unwrap(), OrgBluezDevice1Properties(device))?)
-            })
-            .collect::<Result<()>>()?;
+            .try_for_each(|(path, device)| {
+                self.add_device(path.as_str().unwrap(), OrgBluezDevice1Properties(device))
+            })?;
 
         trace!("Fetching known peripheral services");
         // then, objects that implement org.bluez.GattService1 as they depend on devices being known first
         adapter_objects
             .clone()
-            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_SERVICE1_NAME).map(|a| (p, a)))
-            .map(|(path, attribute)| {
-                Ok(self.add_attribute(
+            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_SERVICE1_NAME).map(|s| (p, s)))
+            .try_for_each(|(path, service)| {
+                self.add_service(
                     path.as_str().unwrap(),
-                    OrgBluezGattCharacteristic1Properties(attribute),
-                )?)
-            })
-            .collect::<Result<()>>()?;
+                    OrgBluezGattService1Properties(service),
+                )
+            })?;
 
         trace!("Fetching known peripheral characteristics");
         // then, objects that implement org.bluez.GattCharacteristic1 as they depend on devices being known first
         adapter_objects
             .clone()
             .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME).map(|a| (p, a)))
-            .map(|(path, attribute)| {
-                Ok(self.add_attribute(
+            .try_for_each(|(path, attribute)| {
+                self.add_attribute(
                     path.as_str().unwrap(),
                     OrgBluezGattCharacteristic1Properties(attribute),
-                )?)
-            })
-            .collect::<Result<()>>()?;
-
-        // TODO: Descriptors are nested behind characteristics, and their UUID may be used more than once.
-        //       btleplug will need to refactor it's API before descriptors may be supported.
-        // trace!("Fetching known peripheral descriptors");
-        // adapter_objects
-        //     .clone()
-        //     .filter_map(|(p, i)| i.get(BLUEZ_INTERFACE_DESCRIPTOR).map(|a| (p, a)))
-        //     .map(|(path, descriptor)| {
-        //         Ok(self.add_attribute(path.as_str().unwrap(), descriptor)?)
-        //     })
-        //     .collect::<Result<()>>()?;
+                )
+            })?;
+
+        trace!("Fetching known peripheral descriptors");
+        // and finally, objects that implement org.bluez.GattDescriptor1
+        adapter_objects
+            .clone()
+            .filter_map(|(p, i)| i.get(ORG_BLUEZ_GATT_DESCRIPTOR1_NAME).map(|d| (p, d)))
+            .try_for_each(|(path, descriptor)| {
+                self.add_descriptor(
+                    path.as_str().unwrap(),
+                    OrgBluezGattDescriptor1Properties(descriptor),
+                )
+            })?;
 
         Ok(())
     }
 
-    fn remove_device(&self, path: &str) -> Result<()> {
-        if let Some(address) = path
-            .strip_prefix(format!("{}/dev_", self.path).as_str())
-            .and_then(|p| p[..17].replace("_", ":").parse::<BDAddr>().ok())
-        {
+    fn device_removed(&self, path: &str) -> Result<()> {
+        self.rediscovering.remove(path);
+        if let Some(address) = self.address_from_path(path) {
             debug!("Removing device \"{:?}\"", address);
-            let listener = self.listener.lock();
-            debug!("Got listener lock");
-            if let Some(peripheral) = self.manager.peripheral(address) {
-                peripheral.stop_listening(&*listener).unwrap()
-            } else {
+            if self.manager.peripheral(address).is_none() {
                 error!("Device \"{:?}\" not found!", address);
             }
 
@@ -315,39 +598,138 @@ impl Adapter {
         Ok(())
     }
 
-    /// Helper function to add a org.bluez.Device1 object to the adapter manager
+    /// Helper function to add a org.bluez.Device1 object to the adapter manager, given all of its
+    /// properties.
     fn add_device(&self, path: &str, device: OrgBluezDevice1Properties) -> Result<()> {
         if let Some(address) = device.address() {
             let address: BDAddr = address.parse()?;
-            // Ignore devices that are blocked, else they'll make this library a bit harder to manage
-            // TODO: Should we allow blocked devices to be "discovered"?
-            if device.blocked().unwrap_or(false) {
-                info!("Skipping blocked device \"{:?}\"", address);
-                return Ok(());
-            }
-            let peripheral = self.manager.peripheral(address).unwrap_or_else(|| {
-                Peripheral::new(self.manager.clone(), self.connection.clone(), path, address)
-            });
-            peripheral.update_properties(device);
-            if !self.manager.has_peripheral(&address) {
-                info!(
-                    "Adding discovered peripheral \"{}\" on \"{}\"",
-                    address, self.path
-                );
-                {
-                    let listener = self.listener.lock();
-                    peripheral.listen(&listener)?;
-                    // TODO: cal peripheral.stop_listening(...) when the peripheral is removed.
+            self.manager.alter_peripheral(
+                address,
+                || {
+                    // Blocked devices are hidden unless asked for, as there's little to be done
+                    // with them. Known devices which become blocked are still updated, so that
+                    // they say so.
+                    if device.blocked().unwrap_or(false)
+                        && !self.show_blocked.load(Ordering::Relaxed)
+                    {
+                        info!("Skipping blocked device \"{:?}\"", address);
+                        return None;
+                    }
+                    info!(
+                        "Adding discovered peripheral \"{}\" on \"{}\"",
+                        address, self.path
+                    );
+                    Some(Peripheral::new(self.connection.clone(), path, address))
+                },
+                |peripheral, events| peripheral.update_properties(device, events),
+            );
+        } else {
+            error!("Could not retrieve 'Address' from DBus 'InterfaceAdded' message with interface '{}'", ORG_BLUEZ_DEVICE1_NAME);
+        }
+
+        Ok(())
+    }
+
+    /// Updates a known device with the properties which BlueZ says have changed.
+    fn update_device(&self, address: BDAddr, changed: OrgBluezDevice1Properties) {
+        self.manager.alter_peripheral(
+            address,
+            || None,
+            |peripheral, events| peripheral.update_properties(changed, events),
+        );
+    }
+
+    /// Adds a device which BlueZ has but which isn't known, such as one which has expired, once
+    /// all of its properties have been fetched. A change signal only has the properties which
+    /// changed, which aren't enough to go on, and may be for a device which is hidden.
+    ///
+    /// A device is only fetched once at a time, and not at all once it has been skipped as blocked,
+    /// until it is unblocked or blocked devices are shown.
+    fn rediscover_device(&self, path: &str) {
+        if !self.rediscovering.insert(path.to_string()) {
+            return;
+        }
+        let message = Message::method_call(
+            &BLUEZ_DEST.into(),
+            &path.into(),
+            &"org.freedesktop.DBus.Properties".into(),
+            &"GetAll".into(),
+        )
+        .append1(ORG_BLUEZ_DEVICE1_NAME);
+        let adapter = self.downgrade();
+        let device_path = path.to_string();
+        let sent = self.connection.call_with_reply(message, move |mut reply| {
+            let adapter = match adapter() {
+                Some(adapter) => adapter,
+                None => return,
+            };
+            match reply
+                .as_result()
+                .and_then(|reply| Ok(reply.read1::<PropMap>()?))
+            {
+                Ok(properties) => {
+                    let device = OrgBluezDevice1Properties(&properties);
+                    if !device.blocked().unwrap_or(false)
+                        || adapter.show_blocked.load(Ordering::Relaxed)
+                    {
+                        adapter.rediscovering.remove(&device_path);
+                    }
+                    if let Err(e) = adapter.add_device(&device_path, device) {
+                        error!("Error adding device {}: {:?}", device_path, e);
+                    }
                 }
-                self.manager.add_peripheral(address, peripheral);
-                self.manager.emit(CentralEvent::DeviceDiscovered(address));
-            } else {
-                info!("Updating peripheral \"{}\"", address);
-                self.manager.update_peripheral(address, peripheral);
-                self.manager.emit(CentralEvent::DeviceUpdated(address));
+                Err(e) => {
+                    adapter.rediscovering.remove(&device_path);
+                    debug!("Could not get the properties of {}: {}", device_path, e);
+                }
+            }
+        });
+        if let Err(e) = sent {
+            self.rediscovering.remove(path);
+            error!("Error rediscovering device {}: {:?}", path, e);
+        }
+    }
+
+    fn add_service(&self, path: &str, service: OrgBluezGattService1Properties) -> Result<()> {
+        if let Some(device_id) = self.address_from_path(path) {
+            if let Some(device) = self.manager.peripheral(device_id) {
+                trace!("Adding service \"{}\" on \"{:?}\"", path, device_id);
+                let uuid: Uuid = service
+                    .uuid()
+                    .ok_or_else(|| Error::Other(format!("Service {} has no UUID", path)))?
+                    .parse()?;
+                device.add_service(
+                    path,
+                    uuid,
+                    service.primary().unwrap_or(true),
+                    service
+                        .includes()
+                        .map_or(&[], |includes| includes.as_slice()),
+                )?;
             }
         } else {
-            error!("Could not retrieve 'Address' from DBus 'InterfaceAdded' message with interface '{}'", ORG_BLUEZ_DEVICE1_NAME);
+            return Err(Error::Other("Invalid DBus path for service".to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn add_descriptor(
+        &self,
+        path: &str,
+        descriptor: OrgBluezGattDescriptor1Properties,
+    ) -> Result<()> {
+        if let Some(device_id) = self.address_from_path(path) {
+            if let Some(device) = self.manager.peripheral(device_id) {
+                trace!("Adding descriptor \"{}\" on \"{:?}\"", path, device_id);
+                let uuid: Uuid = descriptor
+                    .uuid()
+                    .ok_or_else(|| Error::Other(format!("Descriptor {} has no UUID", path)))?
+                    .parse()?;
+                device.add_descriptor(path, uuid)?;
+            }
+        } else {
+            return Err(Error::Other("Invalid DBus path for descriptor".to_string()));
         }
 
         Ok(())
@@ -358,13 +740,13 @@ impl Adapter {
         path: &str,
         characteristic: OrgBluezGattCharacteristic1Properties,
     ) -> Result<()> {
-        // Convert "/org/bluez/hciXX/dev_XX_XX_XX_XX_XX_XX/serviceXX" into "XX:XX:XX:XX:XX:XX"
-        if let Some(device_id) = path.strip_prefix(format!("{}/dev_", self.path).as_str()) {
-            let device_id: BDAddr = device_id[..17].replace("_", ":").parse()?;
-
+        if let Some(device_id) = self.address_from_path(path) {
             if let Some(device) = self.manager.peripheral(device_id) {
                 trace!("Adding characteristic \"{}\" on \"{:?}\"", path, device_id);
-                let uuid: Uuid = characteristic.uuid().unwrap().parse()?;
+                let uuid: Uuid = characteristic
+                    .uuid()
+                    .ok_or_else(|| Error::Other(format!("Characteristic {} has no UUID", path)))?
+                    .parse()?;
                 let flags = if let Some(flags) = characteristic.flags() {
                     flags.iter().map(|s| s.parse::<CharPropFlags>()).fold(
                         Ok(CharPropFlags::new()),
@@ -392,90 +774,177 @@ impl Adapter {
     }
 }
 
-impl Drop for Adapter {
-    /// Cleans up the thread started in Adapter::new()
-    fn drop(&mut self) {
-        let mut thread_handle = self.thread_handle.lock().unwrap();
-        let handle = std::mem::replace(&mut *thread_handle, None);
-        if let Some(handle) = handle {
-            let (cvar, should_stop) = &*self.should_stop;
-            // mimicing a cancelation token though a ConVar<bool>
-            *should_stop.lock().unwrap() = true;
-            cvar.notify_all();
-
-            handle.join().unwrap();
-        }
+/// Converts a timeout to the whole seconds which BlueZ takes, saturating rather than wrapping.
+fn seconds(timeout: Duration) -> u32 {
+    timeout.as_secs().min(u32::MAX.into()) as u32
+}
+
+/// Converts a scan filter to the properties for `SetDiscoveryFilter`, leaving out the fields which
+/// are at their defaults.
+fn discovery_filter(filter: &ScanFilter) -> PropMap {
+    let mut properties = PropMap::new();
+    if !filter.services.is_empty() {
+        let uuids: Vec<String> = filter.services.iter().map(Uuid::to_string).collect();
+        properties.insert("UUIDs".to_string(), Variant(Box::new(uuids)));
+    }
+    if let Some(rssi) = filter.rssi {
+        properties.insert("RSSI".to_string(), Variant(Box::new(rssi)));
     }
+    if let Some(pathloss) = filter.pathloss {
+        properties.insert("Pathloss".to_string(), Variant(Box::new(pathloss)));
+    }
+    let transport = match filter.transport {
+        Transport::Auto => None,
+        Transport::LowEnergy => Some("le"),
+        Transport::BrEdr => Some("bredr"),
+    };
+    if let Some(transport) = transport {
+        properties.insert(
+            "Transport".to_string(),
+            Variant(Box::new(transport.to_string())),
+        );
+    }
+    if !filter.duplicate_data {
+        properties.insert("DuplicateData".to_string(), Variant(Box::new(false)));
+    }
+    if filter.discoverable {
+        properties.insert("Discoverable".to_string(), Variant(Box::new(true)));
+    }
+    properties
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent> {
+        self.manager.events(buffer)
+    }
+
+    fn filter_duplicates(&self, enabled: bool) {
+        self.manager.set_filter_duplicates(enabled);
     }
 
-    fn filter_duplicates(&self, _enabled: bool) {
-        todo!()
-        // self.filter_duplicates
-        // .clone()
-        // .store(enabled, Ordering::Relaxed);
+    async fn start_scan(&self) -> Result<()> {
+        self.start_scan_with_filter(ScanFilter::default()).await
     }
 
-    fn start_scan<'a>(&'a self) -> Result<()> {
-        use dbus::blocking::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded as InterfacesAdded;
+    async fn start_scan_with_filter(&self, filter: ScanFilter) -> Result<()> {
+        use dbus::nonblock::stdintf::org_freedesktop_dbus::ObjectManagerInterfacesAdded as InterfacesAdded;
+
+        self.check_available()?;
+        // BlueZ's discovery is always active. Passive scanning needs an advertisement monitor,
+        // which only works for monitors with patterns, and is still experimental.
+        if !self.active.load(Ordering::Relaxed) {
+            return Err(Error::NotSupported("Passive scanning".into()));
+        }
+        // BlueZ would refuse this too, but only with a generic invalid arguments error.
+        if filter.rssi.is_some() && filter.pathloss.is_some() {
+            return Err(Error::NotSupported(
+                "Combining the rssi and pathloss scan filters".into(),
+            ));
+        }
+
+        let mut properties = discovery_filter(&filter);
+        // Older versions of BlueZ can't say which filters they support, so they are left to refuse
+        // the ones they don't.
+        let supported = match self.proxy().get_discovery_filters().await {
+            Ok(supported) => Some(supported),
+            Err(e) if e.name() == Some("org.freedesktop.DBus.Error.UnknownMethod") => None,
+            Err(e) => return Err(self.call_error()(e)),
+        };
+        if let Some(supported) = &supported {
+            if let Some(name) = properties.keys().find(|name| !supported.contains(name)) {
+                return Err(Error::NotSupported(format!("Discovery filter {}", name)));
+            }
+            // Have BlueZ drop duplicates too where it can, rather than sending each one over
+            // D-Bus only for the adapter manager to drop it.
+            if self.manager.filter_duplicates()
+                && filter.duplicate_data
+                && supported.iter().any(|name| name == "DuplicateData")
+            {
+                properties.insert("DuplicateData".to_string(), Variant(Box::new(false)));
+            }
+        }
+        self.manager.reset_duplicates();
+        // BlueZ keeps the filter from the last scan, so an empty one has to be set to clear it.
+        // Otherwise there is nothing to set.
+        let filtered = !properties.is_empty();
+        if filtered || self.filtered.load(Ordering::Relaxed) {
+            self.proxy()
+                .set_discovery_filter(properties)
+                .await
+                .map_err(self.call_error())?;
+            self.filtered.store(filtered, Ordering::Relaxed);
+        }
 
         // remove the previous token if it's still awkwardly overstaying their welcome...
-        if let Some((_t, token)) = self.match_tokens.remove(&TokenType::DeviceDiscovery) {
+        if let Some((_t, token)) = self.match_tokens.tokens.remove(&TokenType::DeviceDiscovery) {
             warn!("Removing previous match token");
-            self.listener.lock().remove_match(token)?;
+            self.connection.remove_match(token).await?;
         }
 
         // TODO: Should this be invoked earlier? Do we need to rely on the application to called 'start_scan()' before fetching peripherals that may already be known to bluez?
-        self.get_existing_peripherals()?;
+        self.get_existing_peripherals().await?;
 
         trace!("Starting discovery listener");
         {
             let mut discovered_rule = InterfacesAdded::match_rule(None, None);
             discovered_rule.path = Some(Path::from("/"));
 
-            let adapter = self.clone();
-            self.match_tokens.insert(
-                TokenType::DeviceDiscovery,
-                self.listener.lock().add_match(
-                    discovered_rule,
-                    move |args: InterfacesAdded, _c, _msg| {
-                        trace!("Received 'InterfacesAdded' signal");
-                        let path = args.object;
-
-                        if let Some(device) =
-                            OrgBluezDevice1Properties::from_interfaces(&args.interfaces)
-                        {
-                            adapter.add_device(&path, device).unwrap();
-                        } else if let Some(service) =
-                            args.interfaces.get(ORG_BLUEZ_GATT_SERVICE1_NAME)
-                        {
-                            adapter
-                                .add_attribute(
-                                    &path,
-                                    OrgBluezGattCharacteristic1Properties(service),
-                                )
-                                .unwrap();
-                        } else if let Some(characteristic) =
-                            OrgBluezGattCharacteristic1Properties::from_interfaces(&args.interfaces)
-                        {
-                            adapter.add_attribute(&path, characteristic).unwrap();
+            let adapter = self.downgrade();
+            let token = self
+                .connection
+                .add_match(discovered_rule, move |args: InterfacesAdded, _msg| {
+                    trace!("Received 'InterfacesAdded' signal");
+                    let adapter = match adapter() {
+                        Some(adapter) => adapter,
+                        None => return false,
+                    };
+                    let path = args.object;
+                    if !path.starts_with(adapter.path.as_str()) {
+                        return true;
+                    }
+
+                    // This runs on the I/O thread, so a malformed object is only logged, rather
+                    // than taking all of the D-Bus traffic down with it.
+                    if let Some(device) =
+                        OrgBluezDevice1Properties::from_interfaces(&args.interfaces)
+                    {
+                        if let Err(e) = adapter.add_device(&path, device) {
+                            error!("Error adding device {}: {:?}", path, e);
+                        }
+                    } else if let Some(service) =
+                        OrgBluezGattService1Properties::from_interfaces(&args.interfaces)
+                    {
+                        if let Err(e) = adapter.add_service(&path, service) {
+                            error!("Error adding service {}: {:?}", path, e);
+                        }
+                    } else if let Some(characteristic) =
+                        OrgBluezGattCharacteristic1Properties::from_interfaces(&args.interfaces)
+                    {
+                        if let Err(e) = adapter.add_attribute(&path, characteristic) {
+                            error!("Error adding characteristic {}: {:?}", path, e);
+                        }
+                    } else if let Some(descriptor) =
+                        OrgBluezGattDescriptor1Properties::from_interfaces(&args.interfaces)
+                    {
+                        if let Err(e) = adapter.add_descriptor(&path, descriptor) {
+                            error!("Error adding descriptor {}: {:?}", path, e);
                         }
+                    }
 
-                        return true;
-                    },
-                )?,
-            );
+                    true
+                })
+                .await?;
+            self.match_tokens
+                .tokens
+                .insert(TokenType::DeviceDiscovery, token);
         }
 
-        if let Err(error) = self.proxy().start_discovery() {
-            match error.name() {
+        if let Err(error) = self.proxy().start_discovery().await {
+            match self.call_error()(error) {
                 // Don't error if BlueZ has already started scanning.
-                Some("org.bluez.Error.InProgress") => Ok(()),
-                _ => Err(error)?,
+                Error::InProgress(_) => Ok(()),
+                error => Err(error),
             }
         } else {
             debug!("Starting discovery");
@@ -483,17 +952,18 @@ impl Central<Peripheral> for Adapter {
         }
     }
 
-    fn stop_scan(&self) -> Result<()> {
-        if let Some((_t, token)) = self.match_tokens.remove(&TokenType::DeviceDiscovery) {
+    async fn stop_scan(&self) -> Result<()> {
+        self.check_available()?;
+        if let Some((_t, token)) = self.match_tokens.tokens.remove(&TokenType::DeviceDiscovery) {
             trace!("Stopping discovery listener");
-            self.listener.lock().remove_match(token)?;
+            self.connection.remove_match(token).await?;
         }
 
-        if let Err(error) = self.proxy().stop_discovery() {
-            match error.name() {
+        if let Err(error) = self.proxy().stop_discovery().await {
+            match self.call_error()(error) {
                 // Don't error if BlueZ has already stopped scanning.
-                Some("org.bluez.Error.InProgress") => Ok(()),
-                _ => Err(error)?,
+                Error::InProgress(_) => Ok(()),
+                error => Err(error),
             }
         } else {
             debug!("Stopping discovery");
@@ -509,7 +979,598 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
-    fn active(&self, _enabled: bool) {
-        todo!()
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        self.manager.peripheral_state(address)
+    }
+
+    fn expiry_policy(&self, policy: ExpiryPolicy) {
+        self.manager.set_expiry_policy(policy);
+    }
+
+    fn active(&self, enabled: bool) {
+        self.active.store(enabled, Ordering::Relaxed);
+    }
+
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+        self.check_available()?;
+        let properties = self
+            .proxy()
+            .get_all(ORG_BLUEZ_ADAPTER1_NAME)
+            .await
+            .map_err(self.call_error())?;
+        let adapter = OrgBluezAdapter1Properties(&properties);
+        let timeout = |seconds: u32| Duration::from_secs(seconds.into());
+        Ok(AdapterInfo {
+            address: adapter.address().map(|a| a.parse()).transpose()?,
+            address_type: adapter
+                .address_type()
+                .and_then(|address_type| AddressType::from_str(address_type)),
+            name: adapter.name().cloned(),
+            alias: adapter.alias().cloned(),
+            class: adapter.class().map(ClassOfDevice),
+            powered: adapter.powered(),
+            discoverable: adapter.discoverable(),
+            discoverable_timeout: adapter.discoverable_timeout().map(timeout),
+            pairable: adapter.pairable(),
+            pairable_timeout: adapter.pairable_timeout().map(timeout),
+            discovering: adapter.discovering(),
+            services: adapter
+                .uuids()
+                .map(|uuids| uuids.iter().filter_map(|uuid| uuid.parse().ok()).collect())
+                .unwrap_or_default(),
+            modalias: adapter
+                .modalias()
+                .and_then(|modalias| modalias.parse().ok()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluez::bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME;
+    use crate::{
+        api::{device_info::Appearance, AsyncPeripheral},
+        bluez::fake_bluez::{manufacturer_data, strings, wait, wait_for_event, FakeBluez},
+    };
+    use dbus::arg::messageitem::MessageItem;
+
+    const ADDRESS: &str = "11:22:33:44:55:66";
+    const OTHER_ADDRESS: &str = "66:55:44:33:22:11";
+
+    #[test]
+    fn scan_reports_known_and_new_devices() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        bluez.add_device(
+            &hci0,
+            ADDRESS,
+            vec![
+                ("Name", "Known".into()),
+                ("Appearance", 0x00C1u16.into()),
+                ("Icon", "watch".into()),
+                ("Modalias", "bluetooth:v004Cp0320d0112".into()),
+                ("Trusted", true.into()),
+            ],
+        );
+        let adapter = bluez.adapter();
+        let mut events = adapter.events();
+
+        wait(adapter.start_scan()).unwrap();
+        let known: BDAddr = ADDRESS.parse().unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == known),
+        );
+        let properties = adapter.peripheral(known).unwrap().properties();
+        assert_eq!(properties.local_name, Some("Known".to_string()));
+        assert_eq!(properties.alias, Some("11-22-33-44-55-66".to_string()));
+        assert_eq!(properties.appearance, Some(Appearance(0x00C1)));
+        assert_eq!(properties.icon, Some("watch".to_string()));
+        assert_eq!(properties.modalias.unwrap().product, 0x0320);
+        assert!(properties.trusted);
+        assert!(!properties.paired);
+        assert_eq!(
+            bluez.property(&hci0, ORG_BLUEZ_ADAPTER1_NAME, "Discovering"),
+            Some(true.into())
+        );
+
+        let new = bluez.add_device(&hci0, OTHER_ADDRESS, vec![]);
+        let other: BDAddr = OTHER_ADDRESS.parse().unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == other),
+        );
+
+        bluez.remove_object(&new);
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceLost(a) if *a == other),
+        );
+        assert!(adapter.peripheral(other).is_none());
+
+        wait(adapter.stop_scan()).unwrap();
+        assert_eq!(
+            bluez.property(&hci0, ORG_BLUEZ_ADAPTER1_NAME, "Discovering"),
+            Some(false.into())
+        );
+    }
+
+    #[test]
+    fn property_changes_update_peripherals() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+        let address: BDAddr = ADDRESS.parse().unwrap();
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceDiscovered(_))
+        });
+
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "Name",
+            MessageItem::from("Renamed"),
+        );
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceUpdated(a) if *a == address),
+        );
+        assert_eq!(
+            adapter.peripheral(address).unwrap().properties().local_name,
+            Some("Renamed".to_string())
+        );
+
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "ManufacturerData",
+            manufacturer_data(&[(0x004c, &[1, 2, 3])]),
+        );
+        wait_for_event(&mut events, |e| {
+            matches!(
+                e,
+                CentralEvent::ManufacturerDataAdvertisement { manufacturer_id: 0x004c, data, .. }
+                    if data == &[1, 2, 3]
+            )
+        });
+
+        // The RSSI update comes after the TX power has been handled, as BlueZ's signals are
+        // handled in order.
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "TxPower",
+            MessageItem::Int16(4),
+        );
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "RSSI",
+            MessageItem::Int16(-64),
+        );
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::RssiUpdate { rssi: -64, .. })
+        });
+        let properties = adapter.peripheral(address).unwrap().properties();
+        assert_eq!(properties.rssi, Some(-64));
+        assert_eq!(properties.tx_power_level, Some(4));
+    }
+
+    #[test]
+    fn malformed_objects_are_skipped() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceDiscovered(_))
+        });
+
+        let service = bluez.add_service(&device, 0x0010, "not a UUID");
+        let characteristic = bluez.add_characteristic(
+            &service,
+            0x0011,
+            "00002a19-0000-1000-8000-00805f9b34fb",
+            &["read"],
+            &[],
+        );
+        bluez.add_descriptor(&characteristic, 0x0012, "not a UUID either", &[]);
+        // Signals are still handled after the one which couldn't be.
+        bluez.add_device(&hci0, OTHER_ADDRESS, vec![]);
+        let other: BDAddr = OTHER_ADDRESS.parse().unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == other),
+        );
+    }
+
+    #[test]
+    fn expired_devices_are_rediscovered() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![("Name", "Known".into())]);
+        let adapter = bluez.adapter();
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+        let address: BDAddr = ADDRESS.parse().unwrap();
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceDiscovered(_))
+        });
+        assert_eq!(
+            adapter.peripheral_state(address),
+            Some(DeviceState::Discovered)
+        );
+
+        adapter.expiry_policy(ExpiryPolicy::After(Duration::from_millis(10)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(adapter.peripherals().is_empty());
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceExpired(a) if *a == address),
+        );
+
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "RSSI",
+            MessageItem::Int16(-50),
+        );
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == address),
+        );
+        // The change only says what the RSSI is, but the rest isn't lost.
+        let properties = adapter.peripheral(address).unwrap().properties();
+        assert_eq!(properties.rssi, Some(-50));
+        assert_eq!(properties.local_name, Some("Known".to_string()));
+    }
+
+    #[test]
+    fn expired_devices_are_not_rediscovered_without_a_scan() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        let address: BDAddr = ADDRESS.parse().unwrap();
+        wait(adapter.start_scan()).unwrap();
+        wait(adapter.stop_scan()).unwrap();
+        adapter.expiry_policy(ExpiryPolicy::After(Duration::from_millis(10)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(adapter.peripherals().is_empty());
+
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "RSSI",
+            MessageItem::Int16(-50),
+        );
+        // The reply comes after the change has been handled.
+        wait(adapter.adapter_info()).unwrap();
+        assert!(adapter.peripheral(address).is_none());
+    }
+
+    #[test]
+    fn scan_filter_is_set_on_adapter() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        let battery: Uuid = "0000180f-0000-1000-8000-00805f9b34fb".parse().unwrap();
+        let filter = ScanFilter {
+            services: vec![battery],
+            rssi: Some(-70),
+            transport: Transport::LowEnergy,
+            duplicate_data: false,
+            ..ScanFilter::default()
+        };
+        wait(adapter.start_scan_with_filter(filter)).unwrap();
+
+        let set = bluez.discovery_filter(&hci0);
+        assert_eq!(
+            set.keys().collect::<Vec<_>>(),
+            vec!["DuplicateData", "RSSI", "Transport", "UUIDs"]
+        );
+        assert_eq!(set["RSSI"], MessageItem::Int16(-70));
+        assert_eq!(set["Transport"], MessageItem::from("le"));
+        assert_eq!(set["DuplicateData"], MessageItem::from(false));
+        assert_eq!(set["UUIDs"], strings(&[&battery.to_string()]));
+
+        // A plain scan clears the filter, apart from BlueZ's own duplicate filtering.
+        wait(adapter.start_scan()).unwrap();
+        let set = bluez.discovery_filter(&hci0);
+        assert_eq!(set.keys().collect::<Vec<_>>(), vec!["DuplicateData"]);
+    }
+
+    #[test]
+    fn discovery_filters_are_optional() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        let set_filter = |bluez: &FakeBluez| {
+            bluez
+                .calls()
+                .iter()
+                .any(|(_path, member)| member == "SetDiscoveryFilter")
+        };
+        adapter.filter_duplicates(false);
+        wait(adapter.start_scan()).unwrap();
+        assert!(!set_filter(&bluez));
+
+        // Older versions of BlueZ don't know GetDiscoveryFilters, which plain scans get by without.
+        adapter.filter_duplicates(true);
+        bluez.fail_next(
+            "GetDiscoveryFilters",
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            "Unknown method",
+        );
+        wait(adapter.start_scan()).unwrap();
+        assert!(!set_filter(&bluez));
+
+        // A filter is still passed on, for BlueZ to refuse if it can't apply it.
+        bluez.fail_next(
+            "GetDiscoveryFilters",
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            "Unknown method",
+        );
+        let filter = ScanFilter {
+            rssi: Some(-70),
+            ..ScanFilter::default()
+        };
+        wait(adapter.start_scan_with_filter(filter)).unwrap();
+        assert_eq!(
+            bluez.discovery_filter(&hci0)["RSSI"],
+            MessageItem::Int16(-70)
+        );
+    }
+
+    #[test]
+    fn rssi_and_pathloss_filters_are_not_combined() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        let filter = ScanFilter {
+            rssi: Some(-70),
+            pathloss: Some(20),
+            ..ScanFilter::default()
+        };
+        assert!(matches!(
+            wait(adapter.start_scan_with_filter(filter)),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(!bluez.calls().iter().any(|(_path, member)| {
+            member == "GetDiscoveryFilters" || member == "StartDiscovery"
+        }));
+    }
+
+    #[test]
+    fn duplicate_advertisements_are_reported_when_not_filtered() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        adapter.filter_duplicates(false);
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+        assert!(bluez.discovery_filter(&hci0).is_empty());
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceDiscovered(_))
+        });
+
+        let is_advertisement = |e: &CentralEvent| {
+            matches!(
+                e,
+                CentralEvent::ManufacturerDataAdvertisement {
+                    manufacturer_id: 0x004c,
+                    ..
+                }
+            )
+        };
+        for _ in 0..2 {
+            bluez.set_property(
+                &device,
+                ORG_BLUEZ_DEVICE1_NAME,
+                "ManufacturerData",
+                manufacturer_data(&[(0x004c, &[1, 2, 3])]),
+            );
+            wait_for_event(&mut events, is_advertisement);
+        }
+
+        // With duplicates filtered, a repeat is dropped but a change gets through.
+        adapter.filter_duplicates(true);
+        for data in [[1, 2, 3], [1, 2, 3], [4, 5, 6]] {
+            bluez.set_property(
+                &device,
+                ORG_BLUEZ_DEVICE1_NAME,
+                "ManufacturerData",
+                manufacturer_data(&[(0x004c, &data)]),
+            );
+        }
+        let event = wait_for_event(&mut events, is_advertisement);
+        assert!(matches!(
+            event,
+            CentralEvent::ManufacturerDataAdvertisement { data, .. } if data == vec![4, 5, 6]
+        ));
+    }
+
+    #[test]
+    fn passive_scanning_is_not_supported() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        adapter.active(false);
+        assert!(matches!(
+            wait(adapter.start_scan()),
+            Err(Error::NotSupported(_))
+        ));
+        adapter.active(true);
+        wait(adapter.start_scan()).unwrap();
+    }
+
+    #[test]
+    fn unsupported_scan_filter_is_rejected() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        bluez.set_supported_filters(&["UUIDs", "Transport"]);
+        let adapter = bluez.adapter();
+        assert_eq!(
+            wait(adapter.discovery_filters()).unwrap(),
+            vec!["UUIDs", "Transport"]
+        );
+
+        let filter = ScanFilter {
+            pathloss: Some(20),
+            ..ScanFilter::default()
+        };
+        assert!(matches!(
+            wait(adapter.start_scan_with_filter(filter)),
+            Err(Error::NotSupported(_))
+        ));
+        assert!(!bluez
+            .calls()
+            .iter()
+            .any(|(_path, member)| member == "StartDiscovery"));
+    }
+
+    #[test]
+    fn start_scan_tolerates_discovery_in_progress() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        bluez.fail_next(
+            "StartDiscovery",
+            "org.bluez.Error.InProgress",
+            "Operation already in progress",
+        );
+        wait(adapter.start_scan()).unwrap();
+
+        bluez.fail_next(
+            "StartDiscovery",
+            "org.bluez.Error.NotReady",
+            "Resource Not Ready",
+        );
+        assert!(wait(adapter.start_scan()).is_err());
+    }
+
+    #[test]
+    fn adapter_info_and_setters() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+
+        wait(adapter.set_alias("Provisioned")).unwrap();
+        wait(adapter.set_pairable(false)).unwrap();
+        wait(adapter.set_pairable_timeout(Duration::from_secs(60))).unwrap();
+        wait(adapter.set_discoverable_timeout(Duration::from_secs(u64::MAX))).unwrap();
+        assert_eq!(
+            bluez.property(&hci0, ORG_BLUEZ_ADAPTER1_NAME, "DiscoverableTimeout"),
+            Some(u32::MAX.into())
+        );
+
+        let info = wait(adapter.adapter_info()).unwrap();
+        assert_eq!(info.address, Some("00:00:00:00:00:01".parse().unwrap()));
+        assert_eq!(info.address_type, Some(AddressType::Public));
+        assert_eq!(info.name.as_deref(), Some("hci0"));
+        assert_eq!(info.alias.as_deref(), Some("Provisioned"));
+        assert_eq!(info.pairable, Some(false));
+        assert_eq!(info.pairable_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(info.discovering, Some(false));
+        // The fake adapter has no device ID.
+        assert_eq!(info.modalias, None);
+    }
+
+    #[test]
+    fn blocked_devices_are_hidden_unless_asked_for() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        bluez.add_device(&hci0, ADDRESS, vec![("Blocked", true.into())]);
+        bluez.add_device(&hci0, OTHER_ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        let blocked: BDAddr = ADDRESS.parse().unwrap();
+        let other: BDAddr = OTHER_ADDRESS.parse().unwrap();
+
+        wait(adapter.start_scan()).unwrap();
+        assert!(adapter.peripheral(blocked).is_none());
+        assert!(!adapter.peripheral(other).unwrap().properties().blocked);
+
+        adapter.show_blocked_devices(true);
+        wait(adapter.start_scan()).unwrap();
+        assert!(adapter.peripheral(blocked).unwrap().properties().blocked);
+
+        let mut events = adapter.events();
+        wait(adapter.remove_device(other)).unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceLost(a) if *a == other),
+        );
+        assert!(matches!(
+            wait(adapter.remove_device(other)),
+            Err(Error::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn blocked_devices_stay_hidden_when_they_change() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![("Blocked", true.into())]);
+        let other_device = bluez.add_device(&hci0, OTHER_ADDRESS, vec![]);
+        let adapter = bluez.adapter();
+        let blocked: BDAddr = ADDRESS.parse().unwrap();
+        let other: BDAddr = OTHER_ADDRESS.parse().unwrap();
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+
+        bluez.set_property(
+            &device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "RSSI",
+            MessageItem::Int16(-50),
+        );
+        bluez.set_property(
+            &other_device,
+            ORG_BLUEZ_DEVICE1_NAME,
+            "RSSI",
+            MessageItem::Int16(-60),
+        );
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceUpdated(a) if *a == other),
+        );
+        // Both changes have been handled, and so has fetching the blocked device's properties.
+        wait(adapter.adapter_info()).unwrap();
+        assert!(adapter.peripheral(blocked).is_none());
+
+        // Further changes don't fetch the blocked device's properties again.
+        for rssi in [-51, -52, -53] {
+            bluez.set_property(
+                &device,
+                ORG_BLUEZ_DEVICE1_NAME,
+                "RSSI",
+                MessageItem::Int16(rssi),
+            );
+        }
+        wait(adapter.adapter_info()).unwrap();
+        let get_all = |bluez: &FakeBluez| {
+            bluez
+                .calls()
+                .iter()
+                .filter(|(path, member)| *path == device && member == "GetAll")
+                .count()
+        };
+        assert_eq!(get_all(&bluez), 1);
+
+        // Until it is unblocked, which adds it.
+        bluez.set_property(&device, ORG_BLUEZ_DEVICE1_NAME, "Blocked", false.into());
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == blocked),
+        );
+        assert_eq!(get_all(&bluez), 2);
     }
 }
@@ -13,63 +13,136 @@
 
 use crate::{
     api::{
-        AdapterManager, AddressType, BDAddr, CentralEvent, CharPropFlags, Characteristic,
-        NotificationHandler, Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification,
-        WriteType,
+        device_info::{Appearance, ClassOfDevice},
+        AddressType, AsyncPeripheral, BDAddr, CentralEvent, CharPropFlags, Characteristic,
+        Descriptor, NotificationHandler, NotificationStream, PeripheralProperties, Service,
+        ValueNotification, WriteType,
     },
     bluez::{
-        bluez_dbus::device::OrgBluezDevice1, bluez_dbus::device::OrgBluezDevice1Properties,
-        bluez_dbus::gatt_characteristic::OrgBluezGattCharacteristic1, AttributeType, Handle,
-        BLUEZ_DEST, DEFAULT_TIMEOUT,
+        bluez_dbus::adapter::OrgBluezAdapter1, bluez_dbus::device::OrgBluezDevice1,
+        bluez_dbus::device::OrgBluezDevice1Properties,
+        bluez_dbus::gatt_characteristic::OrgBluezGattCharacteristic1, bluez_dbus::gatt_descriptor,
+        connection::Connection, util::call_error, AttributeType, Handle, BLUEZ_DEST,
+    },
+    common::{
+        notifications::NotificationStreams,
+        util::{invoke_handlers, set_if_changed},
     },
-    common::util::invoke_handlers,
     Error, Result,
 };
+use async_trait::async_trait;
 use dbus::{
     arg::{cast, PropMap, RefArg, Variant},
-    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Proxy, SyncConnection},
-    channel::Token,
-    message::{Message, SignalArgs},
+    channel::Sender,
+    message::Message,
+    nonblock::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Proxy, SyncConnection},
     Path,
 };
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    future::{self, Either, TryFutureExt},
+    pin_mut,
+    stream::StreamExt,
+};
+use futures_timer::Delay;
 use log::{debug, error, trace, warn};
 use static_assertions::assert_impl_all;
 use std::{
     collections::{BTreeSet, HashMap},
     fmt::{self, Debug, Display, Formatter},
-    sync::{Arc, Condvar, Mutex},
-    time::Instant,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 enum PeripheralState {
     NotConnected,
     Connected,
     ServicesResolved,
 }
 
+/// Holds the connection state of a peripheral, and wakes up anyone waiting for it to change.
+struct StateWatch {
+    inner: Mutex<(PeripheralState, Vec<UnboundedSender<PeripheralState>>)>,
+}
+
+impl StateWatch {
+    fn new() -> Self {
+        StateWatch {
+            inner: Mutex::new((PeripheralState::NotConnected, Vec::new())),
+        }
+    }
+
+    fn get(&self) -> PeripheralState {
+        self.inner.lock().unwrap().0
+    }
+
+    /// Sets a new state, returning the previous one.
+    fn set(&self, state: PeripheralState) -> PeripheralState {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .1
+            .retain(|waiter| waiter.unbounded_send(state).is_ok());
+        std::mem::replace(&mut inner.0, state)
+    }
+
+    /// Waits until the state satisfies `predicate`, and returns that state.
+    async fn wait_for(&self, predicate: impl Fn(PeripheralState) -> bool) -> PeripheralState {
+        let mut changes = {
+            let mut inner = self.inner.lock().unwrap();
+            if predicate(inner.0) {
+                return inner.0;
+            }
+            let (sender, receiver) = mpsc::unbounded();
+            inner.1.push(sender);
+            receiver
+        };
+        while let Some(state) = changes.next().await {
+            if predicate(state) {
+                return state;
+            }
+        }
+        unreachable!("The state sender is never dropped while we are waiting")
+    }
+}
+
+/// What BlueZ has told us about a GATT service. Its characteristics are found separately.
+struct ServiceAttribute {
+    uuid: Uuid,
+    primary: bool,
+    /// The handles of the included services.
+    includes: Vec<u16>,
+}
+
+/// The D-Bus object of a GATT descriptor, along with its UUID.
+struct DescriptorAttribute {
+    path: String,
+    handle: Handle,
+    uuid: Uuid,
+}
+
 #[derive(Clone)]
 pub struct Peripheral {
-    adapter: AdapterManager<Self>,
-    connection: Arc<SyncConnection>,
+    // The peripheral keeps the connection, and so its I/O thread, alive, as it may well outlive
+    // the adapter which found it.
+    connection: Arc<Connection>,
+    timeout: Duration,
     path: String,
     address: BDAddr,
     properties: Arc<Mutex<PeripheralProperties>>,
     characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    services: Arc<Mutex<BTreeSet<Service>>>,
     attributes_map: Arc<Mutex<HashMap<u16, (String, Handle, Characteristic)>>>,
-    state: Arc<(Mutex<PeripheralState>, Condvar)>,
+    service_map: Arc<Mutex<HashMap<u16, ServiceAttribute>>>,
+    descriptor_map: Arc<Mutex<HashMap<u16, DescriptorAttribute>>>,
+    state: Arc<StateWatch>,
     notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
-    listen_token: Arc<Mutex<Option<Token>>>,
+    notification_streams: Arc<NotificationStreams<u16>>,
 }
 
 impl Peripheral {
-    pub fn new(
-        adapter: AdapterManager<Self>,
-        connection: Arc<SyncConnection>,
-        path: &str,
-        address: BDAddr,
-    ) -> Self {
+    pub(crate) fn new(connection: Arc<Connection>, path: &str, address: BDAddr) -> Self {
         let mut properties = PeripheralProperties::default();
         properties.address = address;
         let properties = Arc::new(Mutex::new(properties));
@@ -77,30 +150,31 @@ impl Peripheral {
         let notification_handlers = Arc::new(Mutex::new(Vec::new()));
 
         Peripheral {
-            adapter: adapter,
+            timeout: connection.timeout(),
             connection: connection,
             path: path.to_string(),
             address: address,
-            state: Arc::new((Mutex::new(PeripheralState::NotConnected), Condvar::new())),
+            state: Arc::new(StateWatch::new()),
             properties: properties,
             attributes_map: Arc::new(Mutex::new(HashMap::new())),
+            service_map: Arc::new(Mutex::new(HashMap::new())),
+            descriptor_map: Arc::new(Mutex::new(HashMap::new())),
             characteristics: characteristics,
+            services: Arc::new(Mutex::new(BTreeSet::new())),
             notification_handlers: notification_handlers,
-            listen_token: Arc::new(Mutex::new(None)),
+            notification_streams: Arc::new(NotificationStreams::new()),
         }
     }
 
-    pub fn properties_changed(
-        &self,
-        args: PropertiesPropertiesChanged,
-        _connection: &SyncConnection,
-        message: &Message,
-    ) -> bool {
+    pub fn properties_changed(&self, args: PropertiesPropertiesChanged, message: &Message) {
         let path = message.path().unwrap().into_static();
         let path = path.as_str().unwrap();
         if path.starts_with(self.path.as_str()) {
             if let Ok(handle) = path.parse::<Handle>() {
-                if args.changed_properties.contains_key("Value") {
+                if handle.typ == AttributeType::Descriptor {
+                    // Descriptor values only change when we read or write them ourselves.
+                    trace!("Ignoring properties changed on descriptor {}", path);
+                } else if args.changed_properties.contains_key("Value") {
                     let notification = ValueNotification {
                         handle: Some(handle.handle),
                         uuid: self
@@ -115,6 +189,8 @@ impl Peripheral {
                             .cloned()
                             .unwrap_or_default(),
                     };
+                    self.notification_streams
+                        .dispatch(&handle.handle, &notification);
                     invoke_handlers(&self.notification_handlers, &notification);
                 } else if args.changed_properties.contains_key("Notifying") {
                     // TODO: Keep track of subscribed and unsubscribed characteristics?
@@ -125,13 +201,12 @@ impl Peripheral {
                     );
                 }
             } else {
-                self.update_properties(OrgBluezDevice1Properties(&args.changed_properties));
-                if !args.invalidated_properties.is_empty() {
-                    warn!(
-                        "TODO: Got some properties to invalidate\n\t{:?}",
-                        args.invalidated_properties
-                    );
-                }
+                // The device's own properties go through the adapter manager.
+                trace!(
+                    "Ignoring properties changed on {} for {}",
+                    path,
+                    args.interface_name
+                );
             }
         } else {
             error!(
@@ -139,33 +214,6 @@ impl Peripheral {
                 path, self.path
             );
         }
-
-        true
-    }
-
-    pub fn listen(&self, listener: &SyncConnection) -> Result<()> {
-        let peripheral = self.clone();
-        let mut rule = PropertiesPropertiesChanged::match_rule(None, None);
-        // For some silly lifetime reasons, we need to assign path separately...
-        rule.path = Some(Path::from(self.path.clone()));
-        // And also, we're interested in properties changed on all sub elements
-        rule.path_is_namespace = true;
-        let token =
-            listener.add_match(rule, move |a, s, m| peripheral.properties_changed(a, s, m))?;
-        *self.listen_token.lock().unwrap() = Some(token);
-
-        Ok(())
-    }
-
-    pub fn stop_listening(&self, listener: &SyncConnection) -> Result<()> {
-        trace!("Stop listening for events");
-        let mut token = self.listen_token.lock().unwrap();
-        if token.is_some() {
-            listener.remove_match(token.unwrap())?;
-            *token = None;
-        }
-
-        Ok(())
     }
 
     pub fn add_attribute(&self, path: &str, uuid: Uuid, properties: CharPropFlags) -> Result<()> {
@@ -180,64 +228,143 @@ impl Peripheral {
         // Create a placeholder attribute to store properties and uuid
         let attribute = Characteristic {
             uuid,
+            // Filled in by build_services, as the service may not have been added yet.
+            service_uuid: Uuid::nil(),
             value_handle: handle.handle,
             properties,
             end_handle: 0,
             start_handle: 0,
+            descriptors: BTreeSet::new(),
         };
 
         path_uuid_map.insert(handle.handle, (path.to_string(), handle, attribute));
         Ok(())
     }
 
-    fn build_characteristic_ranges(&self) -> Result<()> {
-        let handles = self.attributes_map.lock().unwrap();
+    pub fn add_descriptor(&self, path: &str, uuid: Uuid) -> Result<()> {
+        trace!("Adding descriptor {} under {}", uuid, path);
+        let handle: Handle = path.parse()?;
+        self.descriptor_map.lock().unwrap().insert(
+            handle.handle,
+            DescriptorAttribute {
+                path: path.to_string(),
+                handle,
+                uuid,
+            },
+        );
+        Ok(())
+    }
 
-        let mut services = handles
+    pub fn add_service(
+        &self,
+        path: &str,
+        uuid: Uuid,
+        primary: bool,
+        includes: &[dbus::Path],
+    ) -> Result<()> {
+        trace!(
+            "Adding service {} (primary: {}) under {}",
+            uuid,
+            primary,
+            path
+        );
+        let handle: Handle = path.parse()?;
+        let includes = includes
             .iter()
-            .filter(|(_k, (_p, h, _v))| h.typ == AttributeType::Service)
-            .map(|(_h, (_p, k, v))| (k, v))
-            .peekable();
-
-        let mut result = self.characteristics.lock().unwrap();
-        result.clear();
-
-        // TODO: Verify that service attributes are returned as characteristics
-        while let Some((handle, attribute)) = services.next() {
-            let next = services.peek();
-            result.insert(Characteristic {
-                start_handle: handle.handle,
-                end_handle: next.map_or(u16::MAX, |n| n.0.handle - 1),
-                value_handle: handle.handle,
-                properties: attribute.properties,
-                uuid: attribute.uuid.clone(),
-            });
-        }
+            .map(|include| Ok(include.parse::<Handle>()?.handle))
+            .collect::<Result<_>>()?;
+        self.service_map.lock().unwrap().insert(
+            handle.handle,
+            ServiceAttribute {
+                uuid,
+                primary,
+                includes,
+            },
+        );
+        Ok(())
+    }
 
-        let mut characteristics = handles
+    /// Works out the handle range and descriptors of each characteristic, and which service it
+    /// belongs to.
+    fn build_services(&self) {
+        let attributes = self.attributes_map.lock().unwrap();
+        let service_map = self.service_map.lock().unwrap();
+        let descriptor_map = self.descriptor_map.lock().unwrap();
+
+        // A characteristic's handle range ends just before the next service or characteristic.
+        let mut boundaries: Vec<u16> = service_map
+            .keys()
+            .chain(attributes.keys())
+            .cloned()
+            .collect();
+        boundaries.sort_unstable();
+
+        let characteristics: Vec<(u16, Characteristic)> = attributes
+            .values()
+            .filter(|(_p, handle, _c)| handle.typ == AttributeType::Characteristic)
+            .map(|(_p, handle, attribute)| {
+                let end_handle = boundaries
+                    .iter()
+                    .find(|&&boundary| boundary > handle.handle)
+                    .map_or(u16::MAX, |boundary| boundary - 1);
+                let descriptors = descriptor_map
+                    .values()
+                    .filter(|descriptor| descriptor.handle.parent == handle.handle)
+                    .map(|descriptor| Descriptor {
+                        handle: descriptor.handle.handle,
+                        uuid: descriptor.uuid,
+                    })
+                    .collect();
+                let characteristic = Characteristic {
+                    start_handle: handle.handle,
+                    end_handle,
+                    value_handle: handle.handle,
+                    properties: attribute.properties,
+                    uuid: attribute.uuid,
+                    service_uuid: service_map
+                        .get(&handle.parent)
+                        .map_or_else(Uuid::nil, |service| service.uuid),
+                    descriptors,
+                };
+                (handle.parent, characteristic)
+            })
+            .collect();
+
+        let services = service_map
             .iter()
-            .filter(|(_h, (_p, k, _v))| k.typ == AttributeType::Characteristic)
-            .map(|(_h, (_p, k, v))| (k, v))
-            .peekable();
-
-        while let Some((handle, attribute)) = characteristics.next() {
-            let next = characteristics.peek();
-            result.insert(Characteristic {
-                start_handle: handle.handle,
-                end_handle: next.map_or(u16::MAX, |n| n.0.handle - 1),
-                value_handle: handle.handle,
-                properties: attribute.properties,
-                uuid: attribute.uuid.clone(),
-            });
-        }
+            .map(|(&handle, service)| Service {
+                uuid: service.uuid,
+                primary: service.primary,
+                included_services: service
+                    .includes
+                    .iter()
+                    .filter_map(|include| service_map.get(include))
+                    .map(|included| included.uuid)
+                    .collect(),
+                characteristics: characteristics
+                    .iter()
+                    .filter(|(parent, _c)| *parent == handle)
+                    .map(|(_parent, characteristic)| characteristic.clone())
+                    .collect(),
+            })
+            .collect();
 
-        Ok(())
+        *self.characteristics.lock().unwrap() =
+            characteristics.into_iter().map(|(_parent, c)| c).collect();
+        *self.services.lock().unwrap() = services;
     }
 
-    pub fn update_properties(&self, args: OrgBluezDevice1Properties) {
+    /// Updates the properties from those which BlueZ reports, and returns whether any of them
+    /// which [`CentralEvent::DeviceUpdated`] covers changed. The events which the update causes are
+    /// added to `events`.
+    pub fn update_properties(
+        &self,
+        args: OrgBluezDevice1Properties,
+        events: &mut Vec<CentralEvent>,
+    ) -> bool {
         trace!("Updating peripheral properties");
         let mut properties = self.properties.lock().unwrap();
-        let mut emit_updated = false;
+        let mut updated = false;
 
         properties.discovery_count += 1;
 
@@ -246,41 +373,30 @@ impl Peripheral {
                 "Updating \"{}\" connected to \"{:?}\"",
                 self.address, connected
             );
-            let (ref lock, ref cvar) = *self.state;
-            let mut state = lock.lock().unwrap();
             if connected {
-                if *state < PeripheralState::Connected {
-                    self.adapter
-                        .emit(CentralEvent::DeviceConnected(self.address));
-                    *state = PeripheralState::Connected;
-                }
-            } else {
-                if *state >= PeripheralState::Connected {
-                    self.adapter
-                        .emit(CentralEvent::DeviceDisconnected(self.address));
+                if self.state.get() < PeripheralState::Connected {
+                    events.push(CentralEvent::DeviceConnected(self.address));
+                    self.state.set(PeripheralState::Connected);
                 }
-                *state = PeripheralState::NotConnected;
+            } else if self.state.set(PeripheralState::NotConnected) >= PeripheralState::Connected {
+                events.push(CentralEvent::DeviceDisconnected(self.address));
             }
-            cvar.notify_all();
         }
 
         if let Some(name) = args.name() {
             debug!("Updating \"{}\" local name to \"{:?}\"", self.address, name);
-            properties.local_name = Some(name.to_owned());
-            emit_updated = true;
+            updated |= set_if_changed(&mut properties.local_name, Some(name.to_owned()));
         }
 
         if let Some(services_resolved) = args.services_resolved() {
             if services_resolved {
-                // Need to prase and figure out handle ranges for all discovered characteristics.
-                self.build_characteristic_ranges().unwrap();
+                // Need to parse and figure out handle ranges for all discovered characteristics.
+                self.build_services();
             }
             // All services have been discovered, time to inform anyone waiting.
-            let (ref lock, ref cvar) = *self.state;
             if services_resolved {
-                *lock.lock().unwrap() = PeripheralState::ServicesResolved;
+                self.state.set(PeripheralState::ServicesResolved);
             }
-            cvar.notify_all();
         }
 
         if let Some(manufacturer_data) = args.manufacturer_data() {
@@ -292,12 +408,11 @@ impl Peripheral {
                 .into_iter()
                 .filter_map(|(&k, v)| {
                     if let Some(v) = cast::<Vec<u8>>(&v.0) {
-                        self.adapter
-                            .emit(CentralEvent::ManufacturerDataAdvertisement {
-                                address: self.address(),
-                                manufacturer_id: k,
-                                data: v.clone(),
-                            });
+                        events.push(CentralEvent::ManufacturerDataAdvertisement {
+                            address: self.address(),
+                            manufacturer_id: k,
+                            data: v.clone(),
+                        });
                         Some((k, v.to_owned()))
                     } else {
                         warn!("Manufacturer data had wrong type: {:?}", &v.0);
@@ -313,7 +428,7 @@ impl Peripheral {
                 .filter_map(|(service, data)| {
                     let service: Uuid = service.parse().unwrap();
                     if let Some(data) = cast::<Vec<u8>>(&data.0) {
-                        self.adapter.emit(CentralEvent::ServiceDataAdvertisement {
+                        events.push(CentralEvent::ServiceDataAdvertisement {
                             address: self.address(),
                             service,
                             data: data.clone(),
@@ -333,7 +448,7 @@ impl Peripheral {
                 .filter_map(|uuid| uuid.parse().ok())
                 .collect();
 
-            self.adapter.emit(CentralEvent::ServicesAdvertisement {
+            events.push(CentralEvent::ServicesAdvertisement {
                 address: self.address.clone(),
                 services: properties.services.clone(),
             });
@@ -347,32 +462,128 @@ impl Peripheral {
                 self.address, address_type
             );
 
-            properties.address_type = address_type;
-            emit_updated = true;
+            updated |= set_if_changed(&mut properties.address_type, address_type);
         }
 
         if let Some(rssi) = args.rssi() {
-            let rssi = rssi as i8;
             debug!("Updating \"{}\" RSSI \"{:?}\"", self.address, rssi);
-            properties.tx_power_level = Some(rssi);
-            emit_updated = true;
+            events.push(CentralEvent::RssiUpdate {
+                address: self.address,
+                rssi,
+            });
+            updated |= set_if_changed(&mut properties.rssi, Some(rssi));
+        }
+
+        if let Some(alias) = args.alias() {
+            debug!("Updating \"{}\" alias to \"{:?}\"", self.address, alias);
+            updated |= set_if_changed(&mut properties.alias, Some(alias.to_owned()));
         }
 
-        if emit_updated {
-            self.adapter.emit(CentralEvent::DeviceUpdated(self.address));
+        if let Some(appearance) = args.appearance() {
+            debug!(
+                "Updating \"{}\" appearance to \"{:#06x}\"",
+                self.address, appearance
+            );
+            updated |= set_if_changed(&mut properties.appearance, Some(Appearance(appearance)));
         }
+
+        if let Some(class) = args.class() {
+            debug!("Updating \"{}\" class to \"{:#08x}\"", self.address, class);
+            updated |= set_if_changed(&mut properties.class, Some(ClassOfDevice(class)));
+        }
+
+        if let Some(icon) = args.icon() {
+            debug!("Updating \"{}\" icon to \"{:?}\"", self.address, icon);
+            updated |= set_if_changed(&mut properties.icon, Some(icon.to_owned()));
+        }
+
+        if let Some(modalias) = args.modalias() {
+            debug!(
+                "Updating \"{}\" modalias to \"{:?}\"",
+                self.address, modalias
+            );
+            match modalias.parse() {
+                Ok(modalias) => updated |= set_if_changed(&mut properties.modalias, Some(modalias)),
+                Err(e) => warn!("Could not parse modalias {:?}: {}", modalias, e),
+            }
+        }
+
+        if let Some(legacy_pairing) = args.legacy_pairing() {
+            updated |= set_if_changed(&mut properties.legacy_pairing, legacy_pairing);
+        }
+
+        if let Some(paired) = args.paired() {
+            debug!("Updating \"{}\" paired to \"{:?}\"", self.address, paired);
+            updated |= set_if_changed(&mut properties.paired, paired);
+        }
+
+        if let Some(trusted) = args.trusted() {
+            debug!("Updating \"{}\" trusted to \"{:?}\"", self.address, trusted);
+            updated |= set_if_changed(&mut properties.trusted, trusted);
+        }
+
+        if let Some(blocked) = args.blocked() {
+            debug!("Updating \"{}\" blocked to \"{:?}\"", self.address, blocked);
+            updated |= set_if_changed(&mut properties.blocked, blocked);
+        }
+
+        if let Some(tx_power) = args.tx_power() {
+            debug!("Updating \"{}\" TX power \"{:?}\"", self.address, tx_power);
+            updated |= set_if_changed(&mut properties.tx_power_level, Some(tx_power as i8));
+        }
+
+        updated
+    }
+
+    /// Marks the device as trusted, which lets it connect without the user authorizing it, or
+    /// removes the mark.
+    pub async fn set_trusted(&self, trusted: bool) -> Result<()> {
+        self.proxy()
+            .set_trusted(trusted)
+            .await
+            .map_err(call_error(self.timeout))
+    }
+
+    /// Blocks the device, which disconnects it and refuses any further connections from it, or
+    /// unblocks it.
+    pub async fn set_blocked(&self, blocked: bool) -> Result<()> {
+        self.proxy()
+            .set_blocked(blocked)
+            .await
+            .map_err(call_error(self.timeout))
+    }
+
+    pub fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        Proxy::new(BLUEZ_DEST, &self.path, self.timeout, &**self.connection)
     }
 
-    pub fn proxy(&self) -> Proxy<&SyncConnection> {
-        self.connection
-            .with_proxy(BLUEZ_DEST, &self.path, DEFAULT_TIMEOUT)
+    fn descriptor_proxy(
+        &self,
+        descriptor: &Descriptor,
+    ) -> Option<Proxy<'static, Arc<SyncConnection>>> {
+        let map = self.descriptor_map.lock().unwrap();
+        map.get(&descriptor.handle).map(|descriptor| {
+            Proxy::new(
+                BLUEZ_DEST,
+                descriptor.path.clone(),
+                self.timeout,
+                self.connection.shared(),
+            )
+        })
     }
 
-    pub fn proxy_for(&self, characteristic: &Characteristic) -> Option<Proxy<&SyncConnection>> {
+    pub fn proxy_for(
+        &self,
+        characteristic: &Characteristic,
+    ) -> Option<Proxy<'static, Arc<SyncConnection>>> {
         let map = self.attributes_map.lock().unwrap();
         map.get(&characteristic.value_handle).map(|(path, _h, _c)| {
-            self.connection
-                .with_proxy(BLUEZ_DEST, path.clone(), DEFAULT_TIMEOUT)
+            Proxy::new(
+                BLUEZ_DEST,
+                path.clone(),
+                self.timeout,
+                self.connection.shared(),
+            )
         })
     }
 }
@@ -417,7 +628,8 @@ impl Debug for Peripheral {
     }
 }
 
-impl ApiPeripheral for Peripheral {
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     fn address(&self) -> BDAddr {
         self.address.clone()
     }
@@ -432,54 +644,96 @@ impl ApiPeripheral for Peripheral {
         l.clone()
     }
 
+    fn services(&self) -> BTreeSet<Service> {
+        self.services.lock().unwrap().clone()
+    }
+
     fn is_connected(&self) -> bool {
-        *self.state.0.lock().unwrap() >= PeripheralState::Connected
+        self.state.get() >= PeripheralState::Connected
     }
 
-    fn connect(&self) -> Result<()> {
+    async fn connect(&self) -> Result<()> {
         let started = Instant::now();
-        if let Err(error) = self.proxy().connect() {
+        if let Err(error) = self.proxy().connect().await {
             match error.name() {
                 Some("org.bluez.Error.AlreadyConnected") => Ok(()),
                 Some("org.bluez.Error.Failed") => {
+                    let message = error.message().unwrap_or_default();
                     error!(
                         "BlueZ Failed to connect to \"{:?}\": {}",
-                        self.address,
-                        error.message().unwrap()
+                        self.address, message
                     );
-                    Err(Error::NotConnected)
+                    Err(Error::NotConnected(format!(
+                        "org.bluez.Error.Failed: {}",
+                        message
+                    )))
                 }
-                Some("org.freedesktop.DBus.Error.NoReply") => Err(Error::TimedOut(DEFAULT_TIMEOUT)),
-                _ => Err(error)?,
+                _ => Err(call_error(self.timeout)(error)),
             }
         } else {
             Ok(())
         }?;
         // For somereason, BlueZ may return an Okay result before the the device is actually connected...
         // So lets wait for the "connected" property to update to true
-        let (ref lock, ref cvar) = *self.state;
-        let timeout = DEFAULT_TIMEOUT - Instant::now().duration_since(started);
-        // Map the result of the wait_timeout_while(...) to match our Result<()>
-        cvar.wait_timeout_while(lock.lock().unwrap(), timeout, |c| {
-            *c < PeripheralState::Connected
-        })
-        .map_err(|_| Error::TimedOut(DEFAULT_TIMEOUT))
-        .map(|_| ())
+        let timeout = self
+            .timeout
+            .checked_sub(started.elapsed())
+            .unwrap_or_default();
+        let connected = self
+            .state
+            .wait_for(|state| state >= PeripheralState::Connected);
+        pin_mut!(connected);
+        match future::select(connected, Delay::new(timeout)).await {
+            Either::Left(_) => Ok(()),
+            Either::Right(_) => Err(Error::TimedOut(self.timeout)),
+        }
     }
 
-    fn disconnect(&self) -> Result<()> {
-        Ok(self.proxy().disconnect()?)
+    async fn disconnect(&self) -> Result<()> {
+        self.proxy()
+            .disconnect()
+            .await
+            .map_err(call_error(self.timeout))
     }
 
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
-        let (ref lock, ref cvar) = *self.state;
+    async fn pair(&self) -> Result<()> {
+        if let Err(error) = self.proxy().pair().await {
+            match error.name() {
+                // Don't error if the device is already paired.
+                Some("org.bluez.Error.AlreadyExists") => Ok(()),
+                _ => Err(call_error(self.timeout)(error)),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        // BlueZ only forgets the keys of a device by forgetting the device altogether, so it will
+        // be reported as lost.
+        let adapter_path = self.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let adapter = Proxy::new(BLUEZ_DEST, adapter_path, self.timeout, &**self.connection);
+        OrgBluezAdapter1::remove_device(&adapter, Path::from(self.path.clone()))
+            .await
+            .map_err(call_error(self.timeout))
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        self.proxy()
+            .paired()
+            .await
+            .map_err(call_error(self.timeout))
+    }
+
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
         trace!("Waiting for all services to be resolved");
-        let _guard = cvar
-            .wait_while(lock.lock().unwrap(), |b| *b == PeripheralState::Connected)
-            .unwrap();
+        let state = self
+            .state
+            .wait_for(|state| state != PeripheralState::Connected)
+            .await;
 
-        if *_guard == PeripheralState::NotConnected {
-            return Err(Error::NotConnected);
+        if state == PeripheralState::NotConnected {
+            return Err(Error::NotConnected("discover_characteristics".into()));
         }
 
         debug!("All services are now resolved!");
@@ -493,7 +747,7 @@ impl ApiPeripheral for Peripheral {
             .collect())
     }
 
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
@@ -510,59 +764,368 @@ impl ApiPeripheral for Peripheral {
                 .to_string(),
             )),
         );
-        Ok(self
+        let proxy = self
             .proxy_for(&characteristic)
-            .map(|p| p.write_value(Vec::from(data), options))
-            .ok_or(Error::NotSupported("write".to_string()))??)
+            .ok_or(Error::NotSupported("write".to_string()))?;
+        proxy
+            .write_value(Vec::from(data), options)
+            .await
+            .map_err(call_error(self.timeout))
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        Ok(self
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let proxy = self
             .proxy_for(&characteristic)
-            .map(|p| p.read_value(HashMap::new()))
-            .ok_or(Error::NotSupported("read".to_string()))??)
+            .ok_or(Error::NotSupported("read".to_string()))?;
+        proxy
+            .read_value(HashMap::new())
+            .await
+            .map_err(call_error(self.timeout))
     }
 
     // Is this looking for a characteristic with a descriptor? or a service with a characteristic?
-    fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
-        if let Some(characteristic) =
-            self.attributes_map
-                .lock()
-                .unwrap()
-                .iter()
-                .find_map(|(_k, (_p, _h, c))| {
-                    if c.uuid == uuid
-                        && c.value_handle >= characteristic.start_handle
-                        && c.value_handle <= characteristic.end_handle
-                    {
-                        Some(c)
-                    } else {
-                        None
-                    }
-                })
-        {
-            self.read(characteristic)
+    async fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
+        let found = self
+            .attributes_map
+            .lock()
+            .unwrap()
+            .iter()
+            .find_map(|(_k, (_p, _h, c))| {
+                if c.uuid == uuid
+                    && c.value_handle >= characteristic.start_handle
+                    && c.value_handle <= characteristic.end_handle
+                {
+                    Some(c.clone())
+                } else {
+                    None
+                }
+            });
+        if let Some(characteristic) = found {
+            self.read(&characteristic).await
         } else {
             Err(Error::NotSupported("read_by_type".to_string()))
         }
     }
 
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        Ok(self
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let proxy = self
+            .descriptor_proxy(descriptor)
+            .ok_or(Error::NotSupported("read_descriptor".to_string()))?;
+        gatt_descriptor::OrgBluezGattDescriptor1::read_value(&proxy, HashMap::new())
+            .await
+            .map_err(call_error(self.timeout))
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let proxy = self
+            .descriptor_proxy(descriptor)
+            .ok_or(Error::NotSupported("write_descriptor".to_string()))?;
+        gatt_descriptor::OrgBluezGattDescriptor1::write_value(
+            &proxy,
+            Vec::from(data),
+            HashMap::new(),
+        )
+        .await
+        .map_err(call_error(self.timeout))
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let proxy = self
             .proxy_for(characteristic)
-            .ok_or(Error::NotSupported("subscribe".to_string()))?
-            .start_notify()?)
+            .ok_or(Error::NotSupported("subscribe".to_string()))?;
+        self.notification_streams
+            .subscribe(characteristic.value_handle, || {
+                proxy.start_notify().map_err(call_error(self.timeout))
+            })
+            .await
     }
 
-    fn unsubscribe(&self, _characteristic: &Characteristic) -> Result<()> {
-        Ok(self
-            .proxy_for(_characteristic)
-            .ok_or(Error::NotSupported("unsubscribe".to_string()))?
-            .stop_notify()?)
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let proxy = self
+            .proxy_for(characteristic)
+            .ok_or(Error::NotSupported("unsubscribe".to_string()))?;
+        self.notification_streams
+            .unsubscribe(characteristic.value_handle, || {
+                proxy.stop_notify().map_err(call_error(self.timeout))
+            })
+            .await
     }
 
     fn on_notification(&self, handler: NotificationHandler) {
         let mut list = self.notification_handlers.lock().unwrap();
         list.push(handler);
     }
+
+    async fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream> {
+        let proxy = self
+            .proxy_for(characteristic)
+            .ok_or(Error::NotSupported("notifications".to_string()))?;
+        let unsubscribe = {
+            let proxy = proxy.clone();
+            move || {
+                // There's nobody left to report a failure to, so don't wait for the reply.
+                let message = Message::method_call(
+                    &proxy.destination,
+                    &proxy.path,
+                    &"org.bluez.GattCharacteristic1".into(),
+                    &"StopNotify".into(),
+                );
+                if proxy.connection.send(message).is_err() {
+                    warn!("Could not stop notifications for {}", proxy.path);
+                }
+            }
+        };
+        self.notification_streams
+            .add(
+                characteristic.value_handle,
+                || proxy.start_notify().map_err(call_error(self.timeout)),
+                unsubscribe,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::{AsyncCentral, AsyncPeripheral, BDAddr, CentralEvent, CharPropFlags, WriteType},
+        bluez::{
+            adapter::Adapter,
+            bluez_dbus::device::ORG_BLUEZ_DEVICE1_NAME,
+            bluez_dbus::gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            fake_bluez::{bytes, wait, wait_for_event, FakeBluez},
+        },
+        Error,
+    };
+    use futures::stream::StreamExt;
+
+    const ADDRESS: &str = "11:22:33:44:55:66";
+    const BATTERY_SERVICE: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+    const BATTERY_LEVEL: &str = "00002a19-0000-1000-8000-00805f9b34fb";
+    const USER_DESCRIPTION: &str = "00002901-0000-1000-8000-00805f9b34fb";
+
+    /// A fake BlueZ with a device which has a battery service, along with the path of the
+    /// battery level characteristic.
+    fn battery_device() -> (FakeBluez, String) {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let device = bluez.add_device(&hci0, ADDRESS, vec![]);
+        let service = bluez.add_service(&device, 0x000a, BATTERY_SERVICE);
+        let level = bluez.add_characteristic(
+            &service,
+            0x000b,
+            BATTERY_LEVEL,
+            &["read", "write", "notify"],
+            &[100],
+        );
+        bluez.add_descriptor(&level, 0x000d, USER_DESCRIPTION, b"Battery");
+        (bluez, level)
+    }
+
+    /// Scans for the device, and returns the adapter along with the device's peripheral.
+    fn discover(bluez: &FakeBluez) -> (Adapter, super::Peripheral) {
+        let adapter = bluez.adapter();
+        let mut events = adapter.events();
+        wait(adapter.start_scan()).unwrap();
+        let address: BDAddr = ADDRESS.parse().unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceDiscovered(a) if *a == address),
+        );
+        let peripheral = adapter.peripheral(address).unwrap();
+        (adapter, peripheral)
+    }
+
+    #[test]
+    fn connect_and_use_characteristics() {
+        let (bluez, level_path) = battery_device();
+        let (adapter, peripheral) = discover(&bluez);
+        let mut events = adapter.events();
+
+        wait(peripheral.connect()).unwrap();
+        assert!(peripheral.is_connected());
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceConnected(_))
+        });
+
+        let characteristics = wait(peripheral.discover_characteristics()).unwrap();
+        assert_eq!(characteristics.len(), 1);
+        let level = &characteristics[0];
+        assert_eq!(level.uuid, BATTERY_LEVEL.parse().unwrap());
+        assert_eq!(level.service_uuid, BATTERY_SERVICE.parse().unwrap());
+        assert_eq!(
+            level.properties,
+            CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY
+        );
+        let services = peripheral.services();
+        let service = services.iter().next().unwrap();
+        assert_eq!(service.uuid, BATTERY_SERVICE.parse().unwrap());
+        assert!(service.characteristics.contains(level));
+
+        assert_eq!(wait(peripheral.read(level)).unwrap(), vec![100]);
+        wait(peripheral.write(level, &[42], WriteType::WithResponse)).unwrap();
+        assert_eq!(
+            bluez.property(&level_path, ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, "Value"),
+            Some(bytes(&[42]))
+        );
+
+        let descriptor = level.descriptors.iter().next().unwrap();
+        assert_eq!(descriptor.handle, 0x000d);
+        assert_eq!(
+            wait(peripheral.read_descriptor(descriptor)).unwrap(),
+            b"Battery"
+        );
+
+        wait(peripheral.disconnect()).unwrap();
+        wait_for_event(&mut events, |e| {
+            matches!(e, CentralEvent::DeviceDisconnected(_))
+        });
+        assert!(!peripheral.is_connected());
+    }
+
+    #[test]
+    fn peripherals_outlive_their_adapter() {
+        let (bluez, _level_path) = battery_device();
+        let (adapter, peripheral) = discover(&bluez);
+        wait(peripheral.connect()).unwrap();
+        let characteristics = wait(peripheral.discover_characteristics()).unwrap();
+        drop(adapter);
+
+        assert_eq!(
+            wait(peripheral.read(&characteristics[0])).unwrap(),
+            vec![100]
+        );
+    }
+
+    #[test]
+    fn notification_streams_start_and_stop_notifying() {
+        let (bluez, level_path) = battery_device();
+        let (_adapter, peripheral) = discover(&bluez);
+        wait(peripheral.connect()).unwrap();
+        let characteristics = wait(peripheral.discover_characteristics()).unwrap();
+        let level = &characteristics[0];
+
+        let mut stream = wait(peripheral.notifications(level)).unwrap();
+        assert!(bluez
+            .calls()
+            .contains(&(level_path.clone(), "StartNotify".to_string())));
+        bluez.notify(&level_path, &[99]);
+        let notification = wait(stream.next()).unwrap();
+        assert_eq!(notification.uuid, level.uuid);
+        assert_eq!(notification.value, vec![99]);
+
+        drop(stream);
+        wait(async {
+            while !bluez
+                .calls()
+                .contains(&(level_path.clone(), "StopNotify".to_string()))
+            {
+                futures_timer::Delay::new(std::time::Duration::from_millis(10)).await;
+            }
+        });
+    }
+
+    #[test]
+    fn failed_notifications_are_not_stopped() {
+        let (bluez, level_path) = battery_device();
+        let (_adapter, peripheral) = discover(&bluez);
+        wait(peripheral.connect()).unwrap();
+        let characteristics = wait(peripheral.discover_characteristics()).unwrap();
+        let level = &characteristics[0];
+
+        bluez.fail_next("StartNotify", "org.bluez.Error.Failed", "Not connected");
+        assert!(wait(peripheral.notifications(level)).is_err());
+        // Subscribing explicitly keeps the device notifying after the stream has gone.
+        wait(peripheral.subscribe(level)).unwrap();
+        drop(wait(peripheral.notifications(level)).unwrap());
+        wait(peripheral.unsubscribe(level)).unwrap();
+
+        let calls: Vec<_> = bluez
+            .calls()
+            .into_iter()
+            .filter(|(path, _)| *path == level_path)
+            .map(|(_, method)| method)
+            .collect();
+        assert_eq!(calls, vec!["StartNotify", "StartNotify", "StopNotify"]);
+    }
+
+    #[test]
+    fn blocking_calls_from_handlers_fail() {
+        let (bluez, level_path) = battery_device();
+        let (_adapter, peripheral) = discover(&bluez);
+        wait(peripheral.connect()).unwrap();
+        let characteristics = wait(peripheral.discover_characteristics()).unwrap();
+        let level = characteristics[0].clone();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handler_peripheral = peripheral.clone();
+        let sender = std::sync::Mutex::new(sender);
+        peripheral.on_notification(Box::new(move |_| {
+            let result = crate::api::Peripheral::read(&handler_peripheral, &level);
+            sender.lock().unwrap().send(result).unwrap();
+        }));
+        wait(peripheral.subscribe(&characteristics[0])).unwrap();
+        bluez.notify(&level_path, &[99]);
+        let result = receiver
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert!(matches!(result, Err(Error::Other(_))));
+    }
+
+    #[test]
+    fn failed_connection_is_reported() {
+        let (bluez, _level_path) = battery_device();
+        let (_adapter, peripheral) = discover(&bluez);
+        bluez.fail_next(
+            "Connect",
+            "org.bluez.Error.Failed",
+            "Software caused connection abort",
+        );
+        assert!(matches!(
+            wait(peripheral.connect()),
+            Err(Error::NotConnected(_))
+        ));
+        assert!(!peripheral.is_connected());
+    }
+
+    #[test]
+    fn pair_and_unpair() {
+        let (bluez, _level_path) = battery_device();
+        let (adapter, peripheral) = discover(&bluez);
+        let mut events = adapter.events();
+        assert!(!wait(peripheral.is_paired()).unwrap());
+
+        wait(peripheral.pair()).unwrap();
+        assert!(wait(peripheral.is_paired()).unwrap());
+        // Pairing with a device which is already paired succeeds.
+        wait(peripheral.pair()).unwrap();
+
+        // Unpairing removes the device from BlueZ, so it is lost.
+        wait(peripheral.unpair()).unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceLost(a) if *a == peripheral.address()),
+        );
+    }
+
+    #[test]
+    fn trust_and_block() {
+        let (bluez, _level_path) = battery_device();
+        let (adapter, peripheral) = discover(&bluez);
+        let mut events = adapter.events();
+        let device = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+
+        wait(peripheral.set_trusted(true)).unwrap();
+        assert_eq!(
+            bluez.property(device, ORG_BLUEZ_DEVICE1_NAME, "Trusted"),
+            Some(true.into())
+        );
+
+        wait(peripheral.set_blocked(true)).unwrap();
+        wait_for_event(
+            &mut events,
+            |e| matches!(e, CentralEvent::DeviceUpdated(a) if *a == peripheral.address()),
+        );
+        assert!(peripheral.properties().blocked);
+    }
 }
@@ -0,0 +1,425 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! LE advertisements, which let this computer broadcast data or invite centrals to connect.
+//!
+//! Build an [`Advertisement`] and pass it to
+//! [`Adapter::advertise`](../adapter/struct.Adapter.html#method.advertise). The adapter keeps
+//! advertising until the returned [`AdvertisementRegistration`] is dropped, or until BlueZ stops
+//! it, for example because its timeout ran out. Adapters can only advertise a few advertisements
+//! at once; [`Adapter::advertising_instances`](../adapter/struct.Adapter.html#method.advertising_instances)
+//! says how many more are possible.
+
+use super::{
+    bluez_dbus::adapter::OrgBluezLEAdvertisingManager1,
+    connection::Connection,
+    util::{call_error, insert_property},
+    BLUEZ_DEST,
+};
+use crate::Result;
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    channel::{Sender, Token},
+    nonblock::{Proxy, SyncConnection},
+    Message, MethodErr, Path,
+};
+use log::{debug, error, trace};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use uuid::Uuid;
+
+const ORG_BLUEZ_LEADVERTISEMENT1_NAME: &str = "org.bluez.LEAdvertisement1";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+static NEXT_ADVERTISEMENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Data which BlueZ can add to an advertisement by itself, if the adapter supports it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Include {
+    /// The adapter's transmit power.
+    TxPower,
+    /// The adapter's appearance.
+    Appearance,
+    /// The adapter's name.
+    LocalName,
+}
+
+impl Include {
+    /// Returns the name which BlueZ uses for this in `SupportedIncludes`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Include::TxPower => "tx-power",
+            Include::Appearance => "appearance",
+            Include::LocalName => "local-name",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tx-power" => Some(Include::TxPower),
+            "appearance" => Some(Include::Appearance),
+            "local-name" => Some(Include::LocalName),
+            _ => None,
+        }
+    }
+}
+
+/// The data to advertise, and how. The default advertisement is non-connectable and empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Advertisement {
+    connectable: bool,
+    local_name: Option<String>,
+    services: Vec<Uuid>,
+    manufacturer_data: HashMap<u16, Vec<u8>>,
+    service_data: HashMap<Uuid, Vec<u8>>,
+    appearance: Option<u16>,
+    discoverable: Option<bool>,
+    includes: Vec<Include>,
+    tx_power: Option<i16>,
+    timeout: Option<Duration>,
+}
+
+impl Advertisement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Invites centrals to connect, such as to a GATT server which the application serves.
+    pub fn connectable(mut self) -> Self {
+        self.connectable = true;
+        self
+    }
+
+    pub fn local_name(mut self, name: &str) -> Self {
+        self.local_name = Some(name.to_string());
+        self
+    }
+
+    /// Adds a service UUID to advertise.
+    pub fn service(mut self, uuid: Uuid) -> Self {
+        self.services.push(uuid);
+        self
+    }
+
+    /// Sets the manufacturer data for the given company identifier.
+    pub fn manufacturer_data(mut self, company_id: u16, data: &[u8]) -> Self {
+        self.manufacturer_data.insert(company_id, data.to_vec());
+        self
+    }
+
+    /// Sets the service data for the given service.
+    pub fn service_data(mut self, service: Uuid, data: &[u8]) -> Self {
+        self.service_data.insert(service, data.to_vec());
+        self
+    }
+
+    pub fn appearance(mut self, appearance: u16) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    /// Sets whether the advertisement has the discoverable flag. BlueZ decides by itself when
+    /// this isn't set.
+    pub fn discoverable(mut self, discoverable: bool) -> Self {
+        self.discoverable = Some(discoverable);
+        self
+    }
+
+    /// Asks BlueZ to add some data by itself. Check
+    /// [`Adapter::supported_includes`](../adapter/struct.Adapter.html#method.supported_includes)
+    /// for what the adapter supports.
+    pub fn include(mut self, include: Include) -> Self {
+        if !self.includes.contains(&include) {
+            self.includes.push(include);
+        }
+        self
+    }
+
+    /// Asks for a transmit power, in dBm, which the adapter may not be able to use exactly.
+    pub fn tx_power(mut self, tx_power: i16) -> Self {
+        self.tx_power = Some(tx_power);
+        self
+    }
+
+    /// Stops advertising after the given time, rounded down to whole seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Converts the advertisement to the properties of an `org.bluez.LEAdvertisement1` object.
+    fn properties(&self) -> PropMap {
+        let mut properties = PropMap::new();
+        let kind = if self.connectable {
+            "peripheral"
+        } else {
+            "broadcast"
+        };
+        insert_property(&mut properties, "Type", kind.to_string());
+        if let Some(local_name) = &self.local_name {
+            insert_property(&mut properties, "LocalName", local_name.clone());
+        }
+        if !self.services.is_empty() {
+            let services: Vec<String> = self.services.iter().map(Uuid::to_string).collect();
+            insert_property(&mut properties, "ServiceUUIDs", services);
+        }
+        if !self.manufacturer_data.is_empty() {
+            let data: HashMap<u16, Variant<Box<dyn RefArg>>> = self
+                .manufacturer_data
+                .iter()
+                .map(|(&id, data)| (id, Variant(Box::new(data.clone()) as Box<dyn RefArg>)))
+                .collect();
+            insert_property(&mut properties, "ManufacturerData", data);
+        }
+        if !self.service_data.is_empty() {
+            let data: HashMap<String, Variant<Box<dyn RefArg>>> = self
+                .service_data
+                .iter()
+                .map(|(uuid, data)| {
+                    (
+                        uuid.to_string(),
+                        Variant(Box::new(data.clone()) as Box<dyn RefArg>),
+                    )
+                })
+                .collect();
+            insert_property(&mut properties, "ServiceData", data);
+        }
+        if let Some(appearance) = self.appearance {
+            insert_property(&mut properties, "Appearance", appearance);
+        }
+        if let Some(discoverable) = self.discoverable {
+            insert_property(&mut properties, "Discoverable", discoverable);
+        }
+        if !self.includes.is_empty() {
+            let includes: Vec<String> = self
+                .includes
+                .iter()
+                .map(|include| include.as_str().to_string())
+                .collect();
+            insert_property(&mut properties, "Includes", includes);
+        }
+        if let Some(tx_power) = self.tx_power {
+            insert_property(&mut properties, "TxPower", tx_power);
+        }
+        if let Some(timeout) = self.timeout {
+            let seconds = timeout.as_secs().min(u16::MAX as u64) as u16;
+            insert_property(&mut properties, "Timeout", seconds);
+        }
+        properties
+    }
+}
+
+/// Converts the names in BlueZ's `SupportedIncludes`, leaving out any which aren't known.
+pub(crate) fn includes(names: &[String]) -> Vec<Include> {
+    names
+        .iter()
+        .filter_map(|name| Include::from_name(name))
+        .collect()
+}
+
+/// An advertisement which an adapter is advertising. It is unregistered when this is dropped.
+pub struct AdvertisementRegistration {
+    connection: Arc<Connection>,
+    adapter: Path<'static>,
+    path: Path<'static>,
+    token: Token,
+    released: Arc<AtomicBool>,
+}
+
+impl AdvertisementRegistration {
+    /// Exports `advertisement` on the connection, and registers it with the adapter at `adapter`.
+    pub(crate) async fn register(
+        connection: Arc<Connection>,
+        adapter: Path<'static>,
+        advertisement: Advertisement,
+    ) -> Result<Self> {
+        let path = Path::from(format!(
+            "/org/btleplug/advertisement{}",
+            NEXT_ADVERTISEMENT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let released = Arc::new(AtomicBool::new(false));
+        let token = {
+            let released = released.clone();
+            connection.export(path.clone(), move |call, connection| {
+                let reply = answer(&advertisement, &released, &call)
+                    .unwrap_or_else(|error| error.to_message(&call));
+                if connection.send(reply).is_err() {
+                    error!(
+                        "Could not reply to advertisement request {:?}",
+                        call.member()
+                    );
+                }
+            })
+        };
+        let registration = AdvertisementRegistration {
+            connection,
+            adapter,
+            path,
+            token,
+            released,
+        };
+        let registered = registration
+            .proxy()
+            .register_advertisement(registration.path.clone(), PropMap::new())
+            .await;
+        if let Err(error) = registered {
+            // There is nothing for BlueZ to unregister.
+            registration.released.store(true, Ordering::Relaxed);
+            return Err(call_error(registration.connection.timeout())(error));
+        }
+        debug!(
+            "Registered advertisement {} on {}",
+            registration.path, registration.adapter
+        );
+        Ok(registration)
+    }
+
+    /// Returns whether the adapter is still advertising. BlueZ stops advertising by itself when
+    /// the advertisement's timeout runs out, or when the adapter is powered off.
+    pub fn is_active(&self) -> bool {
+        !self.released.load(Ordering::Relaxed)
+    }
+
+    fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        Proxy::new(
+            BLUEZ_DEST,
+            &self.adapter,
+            self.connection.timeout(),
+            &**self.connection,
+        )
+    }
+}
+
+impl Drop for AdvertisementRegistration {
+    fn drop(&mut self) {
+        self.connection.unexport(self.token);
+        if self.is_active() {
+            // The call is sent as soon as it is made, so the reply can be ignored.
+            drop(self.proxy().unregister_advertisement(self.path.clone()));
+        }
+    }
+}
+
+/// Answers a method call on an advertisement, returning the reply.
+fn answer(
+    advertisement: &Advertisement,
+    released: &AtomicBool,
+    call: &Message,
+) -> std::result::Result<Message, MethodErr> {
+    let interface = call.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+    trace!("Advertisement received {}.{}", interface, member);
+    match (interface.as_str(), member.as_str()) {
+        (ORG_BLUEZ_LEADVERTISEMENT1_NAME, "Release") => {
+            debug!("BlueZ released advertisement {:?}", call.path());
+            released.store(true, Ordering::Relaxed);
+            Ok(call.method_return())
+        }
+        (PROPERTIES, "Get") => {
+            let (requested, name): (&str, &str) = call.read2()?;
+            if requested != ORG_BLUEZ_LEADVERTISEMENT1_NAME {
+                return Err(MethodErr::no_interface(requested));
+            }
+            let value = advertisement
+                .properties()
+                .remove(name)
+                .ok_or_else(|| MethodErr::no_property(name))?;
+            Ok(call.method_return().append1(value))
+        }
+        (PROPERTIES, "GetAll") => {
+            let requested: &str = call.read1()?;
+            if requested != ORG_BLUEZ_LEADVERTISEMENT1_NAME {
+                return Err(MethodErr::no_interface(requested));
+            }
+            Ok(call.method_return().append1(advertisement.properties()))
+        }
+        _ => Err(MethodErr::no_method(&member)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bluez::fake_bluez::{wait, FakeBluez},
+        Error,
+    };
+
+    const BATTERY_SERVICE: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+
+    fn unregistrations(bluez: &FakeBluez) -> usize {
+        bluez
+            .calls()
+            .iter()
+            .filter(|(_, member)| member == "UnregisterAdvertisement")
+            .count()
+    }
+
+    #[test]
+    fn advertise_until_dropped_or_released() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        assert_eq!(wait(adapter.advertising_instances()).unwrap(), 2);
+        assert_eq!(
+            wait(adapter.supported_includes()).unwrap(),
+            vec![Include::TxPower, Include::LocalName]
+        );
+
+        let beacon = Advertisement::new()
+            .manufacturer_data(0x004c, &[0x02, 0x15])
+            .service(BATTERY_SERVICE.parse().unwrap())
+            .include(Include::TxPower)
+            .timeout(Duration::from_secs(30));
+        let beacon = wait(adapter.advertise(beacon)).unwrap();
+        let registered = bluez.advertisements();
+        assert_eq!(registered.len(), 1);
+        let properties = bluez.advertisement_properties(&registered[0]);
+        assert_eq!(properties["Type"].0.as_str(), Some("broadcast"));
+        assert_eq!(properties["Timeout"].0.as_u64(), Some(30));
+        let mut manufacturer_data = properties["ManufacturerData"].0.as_iter().unwrap();
+        assert_eq!(manufacturer_data.next().unwrap().as_u64(), Some(0x004c));
+        let data: Vec<u64> = manufacturer_data
+            .next()
+            .and_then(|variant| variant.as_iter()?.next())
+            .and_then(|bytes| bytes.as_iter())
+            .unwrap()
+            .filter_map(|byte| byte.as_u64())
+            .collect();
+        assert_eq!(data, vec![0x02, 0x15]);
+
+        let connectable = Advertisement::new().connectable().local_name("Test rig");
+        let connectable = wait(adapter.advertise(connectable)).unwrap();
+        let properties = bluez.advertisement_properties(&bluez.advertisements()[1]);
+        assert_eq!(properties["Type"].0.as_str(), Some("peripheral"));
+        assert_eq!(properties["LocalName"].0.as_str(), Some("Test rig"));
+
+        // The adapter has no instances left.
+        assert_eq!(wait(adapter.advertising_instances()).unwrap(), 0);
+        assert!(matches!(
+            wait(adapter.advertise(Advertisement::new())),
+            Err(Error::NotPermitted(_))
+        ));
+
+        drop(beacon);
+        assert_eq!(wait(adapter.advertising_instances()).unwrap(), 1);
+        assert_eq!(unregistrations(&bluez), 1);
+
+        // Advertisements which BlueZ releases aren't unregistered again.
+        bluez.release_advertisement(&bluez.advertisements()[0]);
+        assert!(!connectable.is_active());
+        drop(connectable);
+        assert_eq!(wait(adapter.advertising_instances()).unwrap(), 2);
+        assert_eq!(unregistrations(&bluez), 1);
+    }
+}
@@ -0,0 +1,281 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Authentication agents, which answer BlueZ's questions while pairing.
+//!
+//! Pairing with a device may need the user to enter or confirm a passkey or PIN code. BlueZ asks
+//! for these through an agent: an `org.bluez.Agent1` object which the application exports on its
+//! D-Bus connection. Implement [`Agent`] and register it with
+//! [`Manager::register_agent`](../manager/struct.Manager.html#method.register_agent) to handle
+//! pairing in the application.
+
+use super::{
+    bluez_dbus::manager::OrgBluezAgentManager1,
+    connection::Connection,
+    util::{address_from_device_path, call_error},
+    BLUEZ_DEST,
+};
+use crate::{api::BDAddr, Result};
+use dbus::{
+    channel::{Sender, Token},
+    nonblock::{Proxy, SyncConnection},
+    Message, MethodErr, Path,
+};
+use log::{debug, error, trace};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+use uuid::Uuid;
+
+const ORG_BLUEZ_AGENT1_NAME: &str = "org.bluez.Agent1";
+
+static NEXT_AGENT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The input and output which an agent has available, which decides how BlueZ pairs with a
+/// device.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Capability {
+    /// The agent can show a passkey, but can't take any input.
+    DisplayOnly,
+    /// The agent can show a passkey, and ask the user to confirm it.
+    DisplayYesNo,
+    /// The agent can ask the user to enter a passkey, but can't show one.
+    KeyboardOnly,
+    /// The agent can neither show nor take anything, so pairing is unauthenticated.
+    NoInputNoOutput,
+    /// The agent can show a passkey, and ask the user to enter one.
+    KeyboardDisplay,
+}
+
+impl Capability {
+    fn as_str(self) -> &'static str {
+        match self {
+            Capability::DisplayOnly => "DisplayOnly",
+            Capability::DisplayYesNo => "DisplayYesNo",
+            Capability::KeyboardOnly => "KeyboardOnly",
+            Capability::NoInputNoOutput => "NoInputNoOutput",
+            Capability::KeyboardDisplay => "KeyboardDisplay",
+        }
+    }
+}
+
+/// The reason an agent refuses a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AgentError {
+    /// The user turned the request down.
+    Rejected,
+    /// The user dismissed the request without answering it.
+    Canceled,
+}
+
+impl From<AgentError> for MethodErr {
+    fn from(error: AgentError) -> Self {
+        match error {
+            AgentError::Rejected => ("org.bluez.Error.Rejected", "Rejected").into(),
+            AgentError::Canceled => ("org.bluez.Error.Canceled", "Canceled").into(),
+        }
+    }
+}
+
+/// Answers BlueZ's requests for passkeys, PIN codes, confirmations and authorizations.
+///
+/// Each request is handled on a thread of its own, so the methods may block, for example while
+/// they ask the user. Requests which the agent doesn't override are rejected.
+pub trait Agent: Send + Sync {
+    /// The input and output which this agent has available.
+    fn capability(&self) -> Capability {
+        Capability::KeyboardDisplay
+    }
+
+    /// Returns the PIN code to pair with a device which uses legacy pairing, of up to 16
+    /// characters.
+    fn request_pin_code(&self, _device: BDAddr) -> std::result::Result<String, AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Shows the PIN code which the user has to enter on the device.
+    fn display_pin_code(
+        &self,
+        _device: BDAddr,
+        _pin_code: &str,
+    ) -> std::result::Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Returns the passkey, from 0 to 999999, which the device shows.
+    fn request_passkey(&self, _device: BDAddr) -> std::result::Result<u32, AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Shows the passkey which the user has to type on the device. This is called again as each
+    /// digit is typed, with `entered` being the number typed so far.
+    fn display_passkey(&self, _device: BDAddr, _passkey: u32, _entered: u16) {}
+
+    /// Asks the user to confirm that the device shows the same passkey.
+    fn request_confirmation(
+        &self,
+        _device: BDAddr,
+        _passkey: u32,
+    ) -> std::result::Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Asks the user whether a device which wants to pair without any passkey may do so.
+    fn request_authorization(&self, _device: BDAddr) -> std::result::Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Asks the user whether a device may connect to the given service.
+    fn authorize_service(
+        &self,
+        _device: BDAddr,
+        _service: Uuid,
+    ) -> std::result::Result<(), AgentError> {
+        Err(AgentError::Rejected)
+    }
+
+    /// Called when BlueZ cancels the request which is in progress, because pairing timed out or
+    /// was cancelled.
+    fn cancel(&self) {}
+
+    /// Called when BlueZ stops using the agent, such as when it shuts down.
+    fn release(&self) {}
+}
+
+/// An agent which is registered with BlueZ. The agent is unregistered when this is dropped.
+pub struct AgentRegistration {
+    connection: Arc<Connection>,
+    path: Path<'static>,
+    token: Token,
+}
+
+impl AgentRegistration {
+    /// Exports `agent` on the connection and registers it with BlueZ.
+    pub(crate) async fn register(
+        connection: Arc<Connection>,
+        agent: Arc<dyn Agent>,
+    ) -> Result<Self> {
+        let path = Path::from(format!(
+            "/org/btleplug/agent{}",
+            NEXT_AGENT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let capability = agent.capability();
+        let token = {
+            // Replies are sent from other threads, which need a handle on the connection.
+            let shared = connection.shared();
+            connection.export(path.clone(), move |call, _connection| {
+                handle_call(&agent, call, &shared)
+            })
+        };
+        let registration = AgentRegistration {
+            connection,
+            path,
+            token,
+        };
+        registration
+            .proxy()
+            .register_agent(registration.path.clone(), capability.as_str())
+            .await
+            .map_err(call_error(registration.connection.timeout()))?;
+        debug!("Registered agent {}", registration.path);
+        Ok(registration)
+    }
+
+    /// Makes this the agent which BlueZ uses for requests which no other agent asked for, such
+    /// as a device which wants to pair with us.
+    pub async fn request_default(&self) -> Result<()> {
+        self.proxy()
+            .request_default_agent(self.path.clone())
+            .await
+            .map_err(call_error(self.connection.timeout()))
+    }
+
+    fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        Proxy::new(
+            BLUEZ_DEST,
+            "/org/bluez",
+            self.connection.timeout(),
+            &**self.connection,
+        )
+    }
+}
+
+impl Drop for AgentRegistration {
+    fn drop(&mut self) {
+        self.connection.unexport(self.token);
+        // The call is sent as soon as it is made, so the reply can be ignored.
+        drop(self.proxy().unregister_agent(self.path.clone()));
+    }
+}
+
+/// Handles a method call on an agent, on a thread of its own so that the agent may block.
+fn handle_call(agent: &Arc<dyn Agent>, call: Message, connection: &Arc<SyncConnection>) {
+    let agent = agent.clone();
+    let connection = connection.clone();
+    thread::spawn(move || {
+        let reply = answer(&*agent, &call).unwrap_or_else(|error| error.to_message(&call));
+        if connection.send(reply).is_err() {
+            error!("Could not reply to agent request {:?}", call.member());
+        }
+    });
+}
+
+/// Asks the agent to answer a method call, returning the reply.
+fn answer(agent: &dyn Agent, call: &Message) -> std::result::Result<Message, MethodErr> {
+    let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+    if call.interface().as_deref() != Some(ORG_BLUEZ_AGENT1_NAME) {
+        return Err(MethodErr::no_method(&member));
+    }
+    trace!("Agent received {}", member);
+    let reply = call.method_return();
+    let device = || -> std::result::Result<BDAddr, MethodErr> {
+        let path: Path = call.read1()?;
+        address_from_device_path(&path).ok_or_else(|| MethodErr::invalid_arg(&path))
+    };
+    Ok(match member.as_str() {
+        "Release" => {
+            agent.release();
+            reply
+        }
+        "Cancel" => {
+            agent.cancel();
+            reply
+        }
+        "RequestPinCode" => reply.append1(agent.request_pin_code(device()?)?),
+        "DisplayPinCode" => {
+            let (_, pin_code): (Path, &str) = call.read2()?;
+            agent.display_pin_code(device()?, pin_code)?;
+            reply
+        }
+        "RequestPasskey" => reply.append1(agent.request_passkey(device()?)?),
+        "DisplayPasskey" => {
+            let (_, passkey, entered): (Path, u32, u16) = call.read3()?;
+            agent.display_passkey(device()?, passkey, entered);
+            reply
+        }
+        "RequestConfirmation" => {
+            let (_, passkey): (Path, u32) = call.read2()?;
+            agent.request_confirmation(device()?, passkey)?;
+            reply
+        }
+        "RequestAuthorization" => {
+            agent.request_authorization(device()?)?;
+            reply
+        }
+        "AuthorizeService" => {
+            let (_, uuid): (Path, &str) = call.read2()?;
+            let uuid = uuid.parse().map_err(|_| MethodErr::invalid_arg(uuid))?;
+            agent.authorize_service(device()?, uuid)?;
+            reply
+        }
+        _ => return Err(MethodErr::no_method(&member)),
+    })
+}
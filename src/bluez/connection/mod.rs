@@ -0,0 +1,297 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{common::blocking, Result};
+use dbus::{
+    arg::ReadAll,
+    channel::{BusType, Channel, MatchingReceiver, Token},
+    message::{MatchRule, Message},
+    nonblock::{MethodReply, NonblockReply, Process, Proxy, SyncConnection},
+    Path,
+};
+use futures_timer::Delay;
+use log::{error, trace};
+use std::{
+    future::Future,
+    io::{Read, Write},
+    ops::Deref,
+    os::unix::{io::AsRawFd, net::UnixStream},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// The bus on which to find BlueZ.
+pub(crate) enum Bus {
+    /// The system bus, where BlueZ normally lives.
+    System,
+    /// The bus with the given D-Bus address, such as `unix:path=/run/dbus/system_bus_socket`.
+    Address(String),
+    /// A connection to a bus which the application has already opened.
+    Channel(Channel),
+}
+
+/// A non-blocking D-Bus connection, along with the thread which drives its I/O.
+///
+/// Method calls made through the connection return futures, which are resolved by the I/O thread
+/// once their reply arrives. Signal handlers registered with `add_match` are also run on the I/O
+/// thread, so they must never block on a method call made through the same connection. The
+/// blocking facades refuse to run there, rather than deadlocking.
+///
+/// Dropping the connection stops the I/O thread and unregisters every signal handler added through
+/// it, which releases anything those handlers captured.
+pub(crate) struct Connection {
+    connection: Arc<SyncConnection>,
+    timeout: Duration,
+    match_tokens: Mutex<Vec<Token>>,
+    wake_sender: UnixStream,
+    should_stop: Arc<AtomicBool>,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl Connection {
+    /// Opens a new private connection to the given bus, on which method calls time out after
+    /// `timeout`.
+    pub fn open(bus: Bus, timeout: Duration) -> Result<Self> {
+        let mut channel = match bus {
+            Bus::System => Channel::get_private(BusType::System)?,
+            Bus::Address(address) => Channel::open_private(&address)?,
+            Bus::Channel(channel) => channel,
+        };
+        if channel.unique_name().is_none() {
+            channel.register()?;
+        }
+        Self::from_channel(channel, timeout)
+    }
+
+    fn from_channel(mut channel: Channel, timeout: Duration) -> Result<Self> {
+        channel.set_watch_enabled(true);
+        let mut connection = SyncConnection::from(channel);
+        // Deliver signals to every matching handler, rather than only the first one, so that
+        // several adapters can listen for the same ObjectManager signals.
+        connection.set_signal_match_mode(true);
+
+        let (wake_sender, wake_receiver) = UnixStream::pair()
+            .and_then(|(s, r)| {
+                s.set_nonblocking(true)?;
+                r.set_nonblocking(true)?;
+                Ok((s, r))
+            })
+            .map_err(|e| crate::Error::Other(format!("Could not create wake socket: {}", e)))?;
+        let waker = wake_sender
+            .try_clone()
+            .map_err(|e| crate::Error::Other(format!("Could not create wake socket: {}", e)))?;
+        connection.set_waker(Some(Box::new(move || {
+            // A full socket buffer means a wakeup is already pending, so that isn't an error.
+            let _ = (&waker).write(&[0]);
+            Ok(())
+        })));
+        connection.set_timeout_maker(Some(timeout_maker));
+
+        let connection = Arc::new(connection);
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let thread_handle = {
+            let connection = connection.clone();
+            let should_stop = should_stop.clone();
+            thread::spawn(move || drive(&connection, wake_receiver, &should_stop))
+        };
+
+        Ok(Connection {
+            connection,
+            timeout,
+            match_tokens: Mutex::new(Vec::new()),
+            wake_sender,
+            should_stop,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Returns a shared handle to the underlying connection, which does not keep the I/O thread
+    /// alive.
+    pub fn shared(&self) -> Arc<SyncConnection> {
+        self.connection.clone()
+    }
+
+    /// Returns how long method calls made through this connection wait for a reply.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sends the method call `message`, and calls `handler` with the reply once it arrives. This
+    /// is for handlers which run on the I/O thread and so can't wait for the reply; `handler` runs
+    /// there too.
+    pub fn call_with_reply<F>(&self, message: Message, handler: F) -> Result<()>
+    where
+        F: FnOnce(Message) + Send + 'static,
+    {
+        self.connection
+            .send_with_reply(message, SyncConnection::make_f(|reply, _| handler(reply)))
+            .map(|_token| ())
+            .map_err(|()| crate::Error::Other("Could not send method call".to_string()))
+    }
+
+    /// Asks the bus to deliver messages matching `rule`, and calls `handler` with the parsed
+    /// arguments of each one. The handler is removed when it returns false.
+    pub async fn add_match<A, F>(&self, rule: MatchRule<'static>, mut handler: F) -> Result<Token>
+    where
+        A: ReadAll,
+        F: FnMut(A, &Message) -> bool + Send + 'static,
+    {
+        self.connection.add_match_no_cb(&rule.match_str()).await?;
+        let token = self.connection.start_receive(
+            rule,
+            Box::new(
+                move |message, _connection| match A::read(&mut message.iter_init()) {
+                    Ok(args) => handler(args, &message),
+                    Err(e) => {
+                        error!("Could not parse arguments of {:?}: {}", message, e);
+                        true
+                    }
+                },
+            ),
+        );
+        self.match_tokens.lock().unwrap().push(token);
+        Ok(token)
+    }
+
+    /// Removes a handler which was added with `add_match`.
+    pub async fn remove_match(&self, token: Token) -> Result<()> {
+        self.match_tokens.lock().unwrap().retain(|t| *t != token);
+        Ok(self.connection.remove_match(token).await?)
+    }
+
+    /// Removes a handler which was added with `add_match`, without waiting for the bus to confirm
+    /// that it will stop delivering the messages. This is for use where awaiting isn't possible,
+    /// such as in `Drop`.
+    pub fn stop_match(&self, token: Token) {
+        self.match_tokens.lock().unwrap().retain(|t| *t != token);
+        if let Some((rule, _handler)) = self.connection.stop_receive(token) {
+            // The call is sent as soon as it is made, so the reply can be ignored.
+            let bus = Proxy::new(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                self.timeout,
+                &*self.connection,
+            );
+            let reply: MethodReply<()> =
+                bus.method_call("org.freedesktop.DBus", "RemoveMatch", (rule.match_str(),));
+            drop(reply);
+        }
+    }
+
+    /// Serves the object at `path`, by calling `handler` with each method call made on it. The
+    /// handler is responsible for replying. It runs on the I/O thread, so it must hand anything
+    /// which might take a while over to another thread. Remove it with `unexport`.
+    pub fn export<F>(&self, path: Path<'static>, mut handler: F) -> Token
+    where
+        F: FnMut(Message, &SyncConnection) + Send + Sync + 'static,
+    {
+        let token = self.connection.start_receive(
+            MatchRule::new_method_call().with_path(path),
+            Box::new(move |message, connection| {
+                handler(message, connection);
+                true
+            }),
+        );
+        self.match_tokens.lock().unwrap().push(token);
+        token
+    }
+
+    /// Stops serving an object which was exported with `export`.
+    pub fn unexport(&self, token: Token) {
+        self.match_tokens.lock().unwrap().retain(|t| *t != token);
+        self.connection.stop_receive(token);
+    }
+}
+
+impl Deref for Connection {
+    type Target = SyncConnection;
+
+    fn deref(&self) -> &SyncConnection {
+        &self.connection
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::Relaxed);
+        let _ = (&self.wake_sender).write(&[0]);
+        if let Some(handle) = self.thread_handle.take() {
+            // The last reference may be dropped by a handler running on the I/O thread itself.
+            if handle.thread().id() != thread::current().id() {
+                handle.join().unwrap();
+            }
+        }
+        // The handlers are owned by the connection, but usually hold on to things which hold the
+        // connection, so they have to be removed explicitly to break the cycle.
+        for token in self.match_tokens.lock().unwrap().drain(..) {
+            self.connection.stop_receive(token);
+        }
+    }
+}
+
+fn timeout_maker(deadline: Instant) -> Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>> {
+    Box::pin(Delay::new(
+        deadline.saturating_duration_since(Instant::now()),
+    ))
+}
+
+/// Runs the I/O loop for `connection` until `should_stop` is set. The loop sleeps until either
+/// the bus socket is ready, or the connection's waker has been called because a message is waiting
+/// to be sent.
+fn drive(connection: &SyncConnection, mut wake_receiver: UnixStream, should_stop: &AtomicBool) {
+    trace!("Starting D-Bus I/O thread");
+    // Handlers run here, and a blocking call made from one would wait for this thread forever.
+    blocking::forbid_blocking();
+    let channel: &Channel = connection.as_ref();
+    let mut wake_buffer = [0u8; 64];
+    while !should_stop.load(Ordering::Relaxed) {
+        let watch = channel.watch();
+        let mut fds = [
+            libc::pollfd {
+                fd: watch.fd,
+                events: libc::POLLIN
+                    | if channel.has_messages_to_send() {
+                        libc::POLLOUT
+                    } else {
+                        0
+                    },
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: wake_receiver.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // Wake up now and then regardless, in case a wakeup was lost.
+        let timeout = Duration::from_secs(1).as_millis() as libc::c_int;
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                error!("Polling the D-Bus connection failed: {}", error);
+                break;
+            }
+        }
+        while let Ok(n) = wake_receiver.read(&mut wake_buffer) {
+            if n == 0 {
+                break;
+            }
+        }
+
+        if channel.read_write(Some(Duration::from_secs(0))).is_err() {
+            error!("D-Bus connection was closed");
+            break;
+        }
+        connection.process_all();
+    }
+    trace!("Stopped D-Bus I/O thread");
+}
@@ -0,0 +1,95 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Lets anyone on the bus own any name and talk to anyone else, like a session bus without the
+/// service activation.
+const CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <listen>unix:tmpdir={tmpdir}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow user="*"/>
+    <allow own="*"/>
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+  </policy>
+</busconfig>
+"#;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A private `dbus-daemon`, which is stopped when this is dropped.
+///
+/// The daemon is found on the `PATH`, unless the `DBUS_DAEMON` environment variable gives its
+/// location.
+pub struct TestBus {
+    daemon: Child,
+    address: String,
+    config_path: PathBuf,
+}
+
+impl TestBus {
+    pub fn start() -> Self {
+        let tmpdir = env::temp_dir();
+        let config_path = tmpdir.join(format!(
+            "btleplug-test-bus-{}-{}.conf",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(
+            &config_path,
+            CONFIG.replace("{tmpdir}", &tmpdir.to_string_lossy()),
+        )
+        .expect("Could not write dbus-daemon configuration");
+
+        let program = env::var_os("DBUS_DAEMON").unwrap_or_else(|| "dbus-daemon".into());
+        let mut daemon = Command::new(&program)
+            .arg(format!("--config-file={}", config_path.display()))
+            .arg("--nofork")
+            .arg("--print-address")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Could not start {:?}: {}", program, e));
+
+        // The daemon prints its address once it is ready for connections.
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .expect("Could not read the address of dbus-daemon");
+        let address = address.trim().to_string();
+        assert!(!address.is_empty(), "dbus-daemon exited without an address");
+
+        TestBus {
+            daemon,
+            address,
+            config_path,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Drop for TestBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = fs::remove_file(&self.config_path);
+    }
+}
@@ -0,0 +1,989 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! A fake BlueZ, for testing the backend without Bluetooth hardware.
+//!
+//! [`FakeBluez`] starts a private `dbus-daemon`, owns the `org.bluez` name on it, and serves an
+//! object tree of adapters, devices, services, characteristics and descriptors which the test
+//! builds up and changes as it goes. Changes are announced with the same `ObjectManager` and
+//! `Properties` signals as BlueZ sends, and method calls behave like BlueZ's for a well-behaved
+//! device: `Connect` connects and resolves services straight away, `StartNotify` starts
+//! notifying, and so on. Every method call is recorded, and any of them can be made to fail.
+
+mod bus;
+
+use self::bus::TestBus;
+use super::{
+    bluez_dbus::{
+        adapter::{
+            ORG_BLUEZ_ADAPTER1_NAME, ORG_BLUEZ_GATT_MANAGER1_NAME,
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME,
+        },
+        device::ORG_BLUEZ_DEVICE1_NAME,
+        gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME,
+        manager::ORG_BLUEZ_AGENT_MANAGER1_NAME,
+    },
+    connection::{Bus, Connection},
+    manager::Manager,
+    BLUEZ_DEST, DEFAULT_TIMEOUT,
+};
+use crate::{api::EventStream, bluez::adapter::Adapter};
+use dbus::{
+    arg::{
+        cast,
+        messageitem::{MessageItem, MessageItemArray, MessageItemDict},
+        AppendAll, PropMap, ReadAll, RefArg, Variant,
+    },
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, SignalArgs},
+    nonblock::{
+        stdintf::org_freedesktop_dbus::{
+            ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved,
+            PropertiesPropertiesChanged,
+        },
+        Proxy,
+    },
+    Message, MethodErr, Path,
+};
+use futures::{
+    executor::block_on,
+    future::{self, Either},
+    pin_mut,
+    stream::StreamExt,
+    Future,
+};
+use futures_timer::Delay;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// How long to wait for the backend to react before failing a test.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Properties = BTreeMap<String, MessageItem>;
+
+/// Waits for `future` to finish, panicking if it takes too long.
+pub fn wait<T>(future: impl Future<Output = T>) -> T {
+    pin_mut!(future);
+    match block_on(future::select(future, Delay::new(TIMEOUT))) {
+        Either::Left((output, _)) => output,
+        Either::Right(_) => panic!("Timed out after {:?}", TIMEOUT),
+    }
+}
+
+/// Converts a byte string to a D-Bus `ay`.
+pub fn bytes(value: &[u8]) -> MessageItem {
+    MessageItem::from(value)
+}
+
+/// Converts a list of strings to a D-Bus `as`.
+pub fn strings(values: &[&str]) -> MessageItem {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    MessageItem::from(values.as_slice())
+}
+
+/// Converts manufacturer data to the `a{qv}` which BlueZ uses for it.
+pub fn manufacturer_data(entries: &[(u16, &[u8])]) -> MessageItem {
+    let entries = entries
+        .iter()
+        .map(|&(id, data)| (id.into(), MessageItem::Variant(Box::new(bytes(data)))))
+        .collect();
+    MessageItem::Dict(MessageItemDict::new(entries, "q".into(), "v".into()).unwrap())
+}
+
+/// The state of the fake BlueZ, shared between the test and the method call handler.
+#[derive(Default)]
+struct State {
+    /// The interfaces of each object, and their properties.
+    objects: BTreeMap<String, BTreeMap<String, Properties>>,
+    /// The path and member of every method call received, in order.
+    calls: Vec<(String, String)>,
+    /// Errors to reply to the next calls of each member with, instead of handling them.
+    failures: HashMap<String, VecDeque<MethodErr>>,
+    /// The discovery filter which was last set on each adapter.
+    discovery_filters: HashMap<String, Properties>,
+    /// The discovery filter fields which adapters claim to support, if not all of them.
+    supported_filters: Option<Vec<String>>,
+    /// The registered agent, if any.
+    agent: Option<RegisteredAgent>,
+    /// The registered GATT application, if any.
+    application: Option<RegisteredObject>,
+    /// The registered advertisements.
+    advertisements: Vec<RegisteredObject>,
+    /// The values which GATT applications have notified, by the path of their characteristic.
+    notifications: Vec<(String, Vec<u8>)>,
+}
+
+/// A GATT application or advertisement which a client has registered.
+#[derive(Clone, Debug)]
+pub struct RegisteredObject {
+    /// The unique bus name of the client which registered the object.
+    pub owner: String,
+    pub path: String,
+    /// The adapter which the object was registered with.
+    pub adapter: String,
+}
+
+/// An agent which a client has registered.
+#[derive(Clone, Debug)]
+pub struct RegisteredAgent {
+    /// The unique bus name of the client which registered the agent.
+    pub owner: String,
+    pub path: String,
+    pub capability: String,
+    /// Whether the client asked for this to be the default agent.
+    pub default: bool,
+}
+
+/// A fake `org.bluez` service on a private bus. See the [module documentation](index.html).
+pub struct FakeBluez {
+    // Declared before the bus, so that it is closed before the daemon is stopped.
+    connection: Connection,
+    bus: TestBus,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeBluez {
+    /// Starts a new bus with an empty BlueZ on it.
+    pub fn new() -> Self {
+        let bus = TestBus::start();
+        let connection = Connection::open(Bus::Address(bus.address().to_string()), DEFAULT_TIMEOUT)
+            .expect("Could not connect to the test bus");
+        wait(connection.request_name(BLUEZ_DEST, false, true, true))
+            .expect("Could not own the BlueZ name");
+
+        let state = Arc::new(Mutex::new(State::default()));
+        {
+            let objects = &mut state.lock().unwrap().objects;
+            objects.insert("/".to_string(), BTreeMap::new());
+            let mut bluez = BTreeMap::new();
+            bluez.insert(ORG_BLUEZ_AGENT_MANAGER1_NAME.to_string(), Properties::new());
+            objects.insert("/org/bluez".to_string(), bluez);
+        }
+        {
+            // Keep track of the values which GATT applications notify.
+            let state = state.clone();
+            let mut rule = PropertiesPropertiesChanged::match_rule(None, None);
+            rule.path = Some(Path::from("/org/btleplug"));
+            rule.path_is_namespace = true;
+            wait(
+                connection.add_match(rule, move |args: PropertiesPropertiesChanged, message| {
+                    if let Some(value) = args
+                        .changed_properties
+                        .get("Value")
+                        .and_then(|value| cast::<Vec<u8>>(&value.0))
+                    {
+                        let path = message.path().unwrap().to_string();
+                        state
+                            .lock()
+                            .unwrap()
+                            .notifications
+                            .push((path, value.clone()));
+                    }
+                    true
+                }),
+            )
+            .expect("Could not listen for notifications");
+        }
+        {
+            let state = state.clone();
+            connection.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |message, connection| {
+                    for message in state.lock().unwrap().handle_call(&message) {
+                        let _ = connection.send(message);
+                    }
+                    true
+                }),
+            );
+        }
+
+        FakeBluez {
+            connection,
+            bus,
+            state,
+        }
+    }
+
+    /// Returns the address of the bus which the fake BlueZ is on.
+    pub fn address(&self) -> &str {
+        self.bus.address()
+    }
+
+    /// Returns a new manager connected to the fake BlueZ.
+    pub fn manager(&self) -> Manager {
+        Manager::builder()
+            .bus_address(self.address())
+            .build()
+            .unwrap()
+    }
+
+    /// Returns the first adapter of a new manager connected to the fake BlueZ.
+    pub fn adapter(&self) -> Adapter {
+        wait(self.manager().adapters_async())
+            .unwrap()
+            .into_iter()
+            .next()
+            .expect("The fake BlueZ has no adapters")
+    }
+
+    /// Adds an adapter called `name`, like "hci0", and returns its path.
+    pub fn add_adapter(&self, name: &str, address: &str) -> String {
+        let path = format!("/org/bluez/{}", name);
+        self.add_object(
+            &path,
+            ORG_BLUEZ_ADAPTER1_NAME,
+            vec![
+                ("Address", address.into()),
+                ("AddressType", "public".into()),
+                ("Name", name.into()),
+                ("Alias", name.into()),
+                ("Class", 0u32.into()),
+                ("Powered", true.into()),
+                ("Discoverable", false.into()),
+                ("DiscoverableTimeout", 180u32.into()),
+                ("Pairable", true.into()),
+                ("PairableTimeout", 0u32.into()),
+                ("Discovering", false.into()),
+                ("UUIDs", strings(&[])),
+            ],
+            &[],
+        );
+        let advertising: Properties = vec![
+            ("ActiveInstances", 0u8.into()),
+            ("SupportedInstances", 2u8.into()),
+            ("SupportedIncludes", strings(&["tx-power", "local-name"])),
+            ("SupportedSecondaryChannels", strings(&[])),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        let mut state = self.state.lock().unwrap();
+        let interfaces = state.objects.get_mut(&path).unwrap();
+        interfaces.insert(ORG_BLUEZ_GATT_MANAGER1_NAME.to_string(), Properties::new());
+        interfaces.insert(
+            ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME.to_string(),
+            advertising,
+        );
+        path
+    }
+
+    /// Adds a device with the given address to an adapter, as if it had just been discovered, and
+    /// returns its path. `properties` are added to, or replace, the defaults.
+    pub fn add_device(
+        &self,
+        adapter: &str,
+        address: &str,
+        properties: Vec<(&str, MessageItem)>,
+    ) -> String {
+        let path = format!("{}/dev_{}", adapter, address.replace(':', "_"));
+        self.add_object(
+            &path,
+            ORG_BLUEZ_DEVICE1_NAME,
+            vec![
+                ("Address", address.into()),
+                ("AddressType", "public".into()),
+                ("Alias", address.replace(':', "-").into()),
+                ("Paired", false.into()),
+                ("Trusted", false.into()),
+                ("Blocked", false.into()),
+                ("LegacyPairing", false.into()),
+                ("Connected", false.into()),
+                ("UUIDs", strings(&[])),
+                ("Adapter", Path::from(adapter.to_string()).into()),
+                ("ServicesResolved", false.into()),
+            ],
+            &properties,
+        );
+        path
+    }
+
+    /// Adds a primary service with the given handle to a device, and returns its path.
+    pub fn add_service(&self, device: &str, handle: u16, uuid: &str) -> String {
+        let path = format!("{}/service{:04x}", device, handle);
+        self.add_object(
+            &path,
+            ORG_BLUEZ_GATT_SERVICE1_NAME,
+            vec![
+                ("UUID", uuid.into()),
+                ("Device", Path::from(device.to_string()).into()),
+                ("Primary", true.into()),
+                (
+                    "Includes",
+                    MessageItem::Array(MessageItemArray::new(Vec::new(), "ao".into()).unwrap()),
+                ),
+            ],
+            &[],
+        );
+        path
+    }
+
+    /// Adds a characteristic with the given handle to a service, and returns its path. `flags` are
+    /// BlueZ's names for the characteristic properties, like "read" or "notify".
+    pub fn add_characteristic(
+        &self,
+        service: &str,
+        handle: u16,
+        uuid: &str,
+        flags: &[&str],
+        value: &[u8],
+    ) -> String {
+        let path = format!("{}/char{:04x}", service, handle);
+        self.add_object(
+            &path,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            vec![
+                ("UUID", uuid.into()),
+                ("Service", Path::from(service.to_string()).into()),
+                ("Value", bytes(value)),
+                ("Notifying", false.into()),
+                ("Flags", strings(flags)),
+            ],
+            &[],
+        );
+        path
+    }
+
+    /// Adds a descriptor with the given handle to a characteristic, and returns its path.
+    pub fn add_descriptor(
+        &self,
+        characteristic: &str,
+        handle: u16,
+        uuid: &str,
+        value: &[u8],
+    ) -> String {
+        let path = format!("{}/desc{:04x}", characteristic, handle);
+        self.add_object(
+            &path,
+            ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+            vec![
+                ("UUID", uuid.into()),
+                (
+                    "Characteristic",
+                    Path::from(characteristic.to_string()).into(),
+                ),
+                ("Value", bytes(value)),
+            ],
+            &[],
+        );
+        path
+    }
+
+    /// Removes an object and everything below it, as BlueZ does when a device is lost.
+    pub fn remove_object(&self, path: &str) {
+        let signals = self.state.lock().unwrap().remove_object(path);
+        self.send(signals);
+    }
+
+    /// Returns the value of a property, if the object has it.
+    pub fn property(&self, path: &str, interface: &str, name: &str) -> Option<MessageItem> {
+        self.state
+            .lock()
+            .unwrap()
+            .objects
+            .get(path)?
+            .get(interface)?
+            .get(name)
+            .cloned()
+    }
+
+    /// Changes the value of a property, and announces the change.
+    pub fn set_property(&self, path: &str, interface: &str, name: &str, value: MessageItem) {
+        let signal = self
+            .state
+            .lock()
+            .unwrap()
+            .set_property(path, interface, name, value);
+        self.send(vec![signal]);
+    }
+
+    /// Sends a notification of a new characteristic value, as BlueZ does when the characteristic
+    /// is notifying.
+    pub fn notify(&self, characteristic: &str, value: &[u8]) {
+        self.set_property(
+            characteristic,
+            ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+            "Value",
+            bytes(value),
+        );
+    }
+
+    /// Returns the path and member name of every method call received so far.
+    pub fn calls(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Makes the next call of the method `member` fail with the given D-Bus error.
+    pub fn fail_next(&self, member: &str, error_name: &str, message: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .entry(member.to_string())
+            .or_default()
+            .push_back((error_name.to_string(), message).into());
+    }
+
+    /// Returns the agent which is registered, if any.
+    pub fn agent(&self) -> Option<RegisteredAgent> {
+        self.state.lock().unwrap().agent.clone()
+    }
+
+    /// Makes a request of the registered agent, as BlueZ does while pairing, and waits for the
+    /// reply.
+    pub fn call_agent<A: AppendAll, R: ReadAll + 'static>(
+        &self,
+        member: &str,
+        args: A,
+    ) -> Result<R, dbus::Error> {
+        let agent = self.agent().expect("No agent is registered");
+        self.call_client(&agent.owner, &agent.path, "org.bluez.Agent1", member, args)
+    }
+
+    /// Returns the GATT application which is registered, if any.
+    pub fn application(&self) -> Option<RegisteredObject> {
+        self.state.lock().unwrap().application.clone()
+    }
+
+    /// Asks the registered GATT application for its objects, as BlueZ does when it is registered.
+    pub fn application_objects(&self) -> HashMap<Path<'static>, HashMap<String, PropMap>> {
+        let application = self.application().expect("No application is registered");
+        let (objects,) = self
+            .call_client(
+                &application.owner,
+                &application.path,
+                OBJECT_MANAGER,
+                "GetManagedObjects",
+                (),
+            )
+            .unwrap();
+        objects
+    }
+
+    /// Calls a method of an object of the registered GATT application, as BlueZ does when a
+    /// central uses it, and waits for the reply.
+    pub fn call_application<A: AppendAll, R: ReadAll + 'static>(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: A,
+    ) -> Result<R, dbus::Error> {
+        let application = self.application().expect("No application is registered");
+        self.call_client(&application.owner, path, interface, member, args)
+    }
+
+    /// Returns the advertisements which are registered.
+    pub fn advertisements(&self) -> Vec<RegisteredObject> {
+        self.state.lock().unwrap().advertisements.clone()
+    }
+
+    /// Asks a registered advertisement for its properties, as BlueZ does when it is registered.
+    pub fn advertisement_properties(&self, advertisement: &RegisteredObject) -> PropMap {
+        let (properties,) = self
+            .call_client(
+                &advertisement.owner,
+                &advertisement.path,
+                PROPERTIES,
+                "GetAll",
+                ("org.bluez.LEAdvertisement1",),
+            )
+            .unwrap();
+        properties
+    }
+
+    /// Stops advertising an advertisement and releases it, as BlueZ does when its timeout runs
+    /// out.
+    pub fn release_advertisement(&self, advertisement: &RegisteredObject) {
+        let signals = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .advertisements
+                .retain(|registered| registered.path != advertisement.path);
+            state.change_instances(&advertisement.adapter, -1)
+        };
+        self.send(signals);
+        let () = self
+            .call_client(
+                &advertisement.owner,
+                &advertisement.path,
+                "org.bluez.LEAdvertisement1",
+                "Release",
+                (),
+            )
+            .unwrap();
+    }
+
+    /// Returns the values which GATT applications have notified so far, along with the paths of
+    /// their characteristics.
+    pub fn notifications(&self) -> Vec<(String, Vec<u8>)> {
+        self.state.lock().unwrap().notifications.clone()
+    }
+
+    fn call_client<A: AppendAll, R: ReadAll + 'static>(
+        &self,
+        owner: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        args: A,
+    ) -> Result<R, dbus::Error> {
+        let proxy = Proxy::new(owner, path, TIMEOUT, &*self.connection);
+        wait(proxy.method_call(interface, member, args))
+    }
+
+    /// Returns the discovery filter which was last set on an adapter.
+    pub fn discovery_filter(&self, adapter: &str) -> Properties {
+        self.state
+            .lock()
+            .unwrap()
+            .discovery_filters
+            .get(adapter)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Makes adapters claim to support only the given discovery filter fields, and reject filters
+    /// which use any others.
+    pub fn set_supported_filters(&self, names: &[&str]) {
+        self.state.lock().unwrap().supported_filters =
+            Some(names.iter().map(|name| name.to_string()).collect());
+    }
+
+    fn add_object(
+        &self,
+        path: &str,
+        interface: &str,
+        defaults: Vec<(&str, MessageItem)>,
+        overrides: &[(&str, MessageItem)],
+    ) {
+        let properties: Properties = defaults
+            .into_iter()
+            .chain(overrides.iter().cloned())
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let signal = {
+            let mut state = self.state.lock().unwrap();
+            let interfaces = state.objects.entry(path.to_string()).or_default();
+            interfaces.insert(interface.to_string(), properties);
+            ObjectManagerInterfacesAdded {
+                object: Path::from(path.to_string()),
+                interfaces: interfaces
+                    .iter()
+                    .map(|(interface, properties)| (interface.clone(), prop_map(properties)))
+                    .collect(),
+            }
+            .to_emit_message(&Path::from("/"))
+        };
+        self.send(vec![signal]);
+    }
+
+    fn send(&self, messages: Vec<Message>) {
+        for message in messages {
+            self.connection.send(message).unwrap();
+        }
+    }
+}
+
+impl State {
+    /// Handles a method call, returning the reply along with any signals to send before it.
+    fn handle_call(&mut self, call: &Message) -> Vec<Message> {
+        let path = call.path().map(|p| p.to_string()).unwrap_or_default();
+        let interface = call.interface().map(|i| i.to_string()).unwrap_or_default();
+        let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+        self.calls.push((path.clone(), member.clone()));
+
+        let mut messages = Vec::new();
+        let failure = self.failures.get_mut(&member).and_then(VecDeque::pop_front);
+        let reply = match failure {
+            Some(error) => Err(error),
+            None => self.dispatch(&path, &interface, &member, call, &mut messages),
+        };
+        messages.push(reply.unwrap_or_else(|error| error.to_message(call)));
+        messages
+    }
+
+    fn dispatch(
+        &mut self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        call: &Message,
+        signals: &mut Vec<Message>,
+    ) -> Result<Message, MethodErr> {
+        if !self.objects.contains_key(path) {
+            return Err(MethodErr::no_path(path));
+        }
+        if interface != OBJECT_MANAGER && interface != PROPERTIES {
+            self.properties(path, interface)?;
+        }
+        let reply = call.method_return();
+        Ok(match (interface, member) {
+            (OBJECT_MANAGER, "GetManagedObjects") if path == "/" => {
+                let objects: HashMap<Path, HashMap<String, PropMap>> = self
+                    .objects
+                    .iter()
+                    .filter(|(_path, interfaces)| !interfaces.is_empty())
+                    .map(|(path, interfaces)| {
+                        (
+                            Path::from(path.clone()),
+                            interfaces
+                                .iter()
+                                .map(|(interface, properties)| {
+                                    (interface.clone(), prop_map(properties))
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect();
+                reply.append1(objects)
+            }
+            (PROPERTIES, "Get") => {
+                let (interface, name): (&str, &str) = call.read2()?;
+                let value = self
+                    .properties(path, interface)?
+                    .get(name)
+                    .ok_or_else(|| MethodErr::no_property(name))?;
+                reply.append1(Variant(Box::new(value.clone()) as Box<dyn RefArg>))
+            }
+            (PROPERTIES, "GetAll") => {
+                let interface: &str = call.read1()?;
+                reply.append1(prop_map(self.properties(path, interface)?))
+            }
+            (PROPERTIES, "Set") => {
+                let (interface, name): (&str, &str) = call.read2()?;
+                let mut args = call.iter_init();
+                args.next();
+                args.next();
+                let value: MessageItem = args.get().ok_or_else(MethodErr::no_arg)?;
+                if !self.properties(path, interface)?.contains_key(name) {
+                    return Err(MethodErr::no_property(name));
+                }
+                signals.push(self.set_property(path, interface, name, value.peel().clone()));
+                reply
+            }
+            (ORG_BLUEZ_ADAPTER1_NAME, "StartDiscovery") => {
+                if self.flag(path, interface, "Discovering") {
+                    return Err((
+                        "org.bluez.Error.InProgress",
+                        "Operation already in progress",
+                    )
+                        .into());
+                }
+                signals.push(self.set_property(path, interface, "Discovering", true.into()));
+                reply
+            }
+            (ORG_BLUEZ_ADAPTER1_NAME, "SetDiscoveryFilter") => {
+                let filter = match call.iter_init().get() {
+                    Some(MessageItem::Dict(filter)) => filter,
+                    _ => return Err(MethodErr::no_arg()),
+                };
+                let filter: Properties = filter
+                    .into_vec()
+                    .into_iter()
+                    .filter_map(|(name, value)| match (name, value) {
+                        (MessageItem::Str(name), MessageItem::Variant(value)) => {
+                            Some((name, *value))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let supported = self.supported_filters();
+                if filter.keys().any(|name| !supported.contains(name)) {
+                    return Err((
+                        "org.bluez.Error.InvalidArguments",
+                        "Invalid arguments in method call",
+                    )
+                        .into());
+                }
+                self.discovery_filters.insert(path.to_string(), filter);
+                reply
+            }
+            (ORG_BLUEZ_ADAPTER1_NAME, "GetDiscoveryFilters") => {
+                reply.append1(self.supported_filters())
+            }
+            (ORG_BLUEZ_ADAPTER1_NAME, "StopDiscovery") => {
+                if !self.flag(path, interface, "Discovering") {
+                    return Err(("org.bluez.Error.Failed", "No discovery started").into());
+                }
+                signals.push(self.set_property(path, interface, "Discovering", false.into()));
+                reply
+            }
+            (ORG_BLUEZ_ADAPTER1_NAME, "RemoveDevice") => {
+                let device: Path = call.read1()?;
+                if !self.objects.contains_key(&*device) {
+                    return Err(("org.bluez.Error.DoesNotExist", "Does Not Exist").into());
+                }
+                signals.extend(self.remove_object(&device));
+                reply
+            }
+            (ORG_BLUEZ_GATT_MANAGER1_NAME, "RegisterApplication") => {
+                let application: Path = call.read1()?;
+                if self.application.is_some() {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Exists").into());
+                }
+                self.application = Some(RegisteredObject {
+                    owner: call.sender().map(|s| s.to_string()).unwrap_or_default(),
+                    path: application.to_string(),
+                    adapter: path.to_string(),
+                });
+                reply
+            }
+            (ORG_BLUEZ_GATT_MANAGER1_NAME, "UnregisterApplication") => {
+                let application: Path = call.read1()?;
+                match &self.application {
+                    Some(registered) if registered.path == *application => self.application = None,
+                    _ => return Err(("org.bluez.Error.DoesNotExist", "Does Not Exist").into()),
+                }
+                reply
+            }
+            (ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME, "RegisterAdvertisement") => {
+                let advertisement: Path = call.read1()?;
+                let properties = self.properties(path, interface)?;
+                if properties["SupportedInstances"].inner::<u8>() == Ok(0) {
+                    return Err((
+                        "org.bluez.Error.NotPermitted",
+                        "Maximum advertisements reached",
+                    )
+                        .into());
+                }
+                self.advertisements.push(RegisteredObject {
+                    owner: call.sender().map(|s| s.to_string()).unwrap_or_default(),
+                    path: advertisement.to_string(),
+                    adapter: path.to_string(),
+                });
+                signals.extend(self.change_instances(path, 1));
+                reply
+            }
+            (ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME, "UnregisterAdvertisement") => {
+                let advertisement: Path = call.read1()?;
+                let count = self.advertisements.len();
+                self.advertisements
+                    .retain(|registered| registered.path != *advertisement);
+                if self.advertisements.len() == count {
+                    return Err(("org.bluez.Error.DoesNotExist", "Does Not Exist").into());
+                }
+                signals.extend(self.change_instances(path, -1));
+                reply
+            }
+            (ORG_BLUEZ_AGENT_MANAGER1_NAME, "RegisterAgent") => {
+                let (path, capability): (Path, &str) = call.read2()?;
+                if self.agent.is_some() {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Exists").into());
+                }
+                self.agent = Some(RegisteredAgent {
+                    owner: call.sender().map(|s| s.to_string()).unwrap_or_default(),
+                    path: path.to_string(),
+                    capability: capability.to_string(),
+                    default: false,
+                });
+                reply
+            }
+            (ORG_BLUEZ_AGENT_MANAGER1_NAME, "UnregisterAgent")
+            | (ORG_BLUEZ_AGENT_MANAGER1_NAME, "RequestDefaultAgent") => {
+                let path: Path = call.read1()?;
+                match &mut self.agent {
+                    Some(agent) if agent.path == *path => {
+                        if member == "UnregisterAgent" {
+                            self.agent = None;
+                        } else {
+                            agent.default = true;
+                        }
+                    }
+                    _ => return Err(("org.bluez.Error.DoesNotExist", "Does Not Exist").into()),
+                }
+                reply
+            }
+            (ORG_BLUEZ_DEVICE1_NAME, "Pair") => {
+                if self.flag(path, interface, "Paired") {
+                    return Err(("org.bluez.Error.AlreadyExists", "Already Paired").into());
+                }
+                if !self.flag(path, interface, "Connected") {
+                    signals.push(self.set_property(path, interface, "Connected", true.into()));
+                }
+                signals.push(self.set_property(path, interface, "Paired", true.into()));
+                reply
+            }
+            (ORG_BLUEZ_DEVICE1_NAME, "Connect") => {
+                if self.flag(path, interface, "Connected") {
+                    return Err(("org.bluez.Error.AlreadyConnected", "Already Connected").into());
+                }
+                signals.push(self.set_property(path, interface, "Connected", true.into()));
+                signals.push(self.set_property(path, interface, "ServicesResolved", true.into()));
+                reply
+            }
+            (ORG_BLUEZ_DEVICE1_NAME, "Disconnect") => {
+                if !self.flag(path, interface, "Connected") {
+                    return Err(("org.bluez.Error.NotConnected", "Not Connected").into());
+                }
+                signals.push(self.set_property(path, interface, "ServicesResolved", false.into()));
+                signals.push(self.set_property(path, interface, "Connected", false.into()));
+                reply
+            }
+            (ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, "ReadValue")
+            | (ORG_BLUEZ_GATT_DESCRIPTOR1_NAME, "ReadValue") => {
+                let value = self.properties(path, interface)?["Value"].clone();
+                reply.append1(value)
+            }
+            (ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, "WriteValue")
+            | (ORG_BLUEZ_GATT_DESCRIPTOR1_NAME, "WriteValue") => {
+                // BlueZ only announces values which come from the device, so this is silent.
+                let value: Vec<u8> = call.read1()?;
+                self.properties_mut(path, interface)?
+                    .insert("Value".to_string(), bytes(&value));
+                reply
+            }
+            (ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, "StartNotify") => {
+                signals.push(self.set_property(path, interface, "Notifying", true.into()));
+                reply
+            }
+            (ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME, "StopNotify") => {
+                signals.push(self.set_property(path, interface, "Notifying", false.into()));
+                reply
+            }
+            _ => return Err(MethodErr::no_method(member)),
+        })
+    }
+
+    fn supported_filters(&self) -> Vec<String> {
+        self.supported_filters.clone().unwrap_or_else(|| {
+            [
+                "UUIDs",
+                "RSSI",
+                "Pathloss",
+                "Transport",
+                "DuplicateData",
+                "Discoverable",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect()
+        })
+    }
+
+    fn properties(&self, path: &str, interface: &str) -> Result<&Properties, MethodErr> {
+        self.objects
+            .get(path)
+            .ok_or_else(|| MethodErr::no_path(path))?
+            .get(interface)
+            .ok_or_else(|| MethodErr::no_interface(interface))
+    }
+
+    fn properties_mut(
+        &mut self,
+        path: &str,
+        interface: &str,
+    ) -> Result<&mut Properties, MethodErr> {
+        self.objects
+            .get_mut(path)
+            .ok_or_else(|| MethodErr::no_path(path))?
+            .get_mut(interface)
+            .ok_or_else(|| MethodErr::no_interface(interface))
+    }
+
+    fn flag(&self, path: &str, interface: &str, name: &str) -> bool {
+        self.properties(path, interface)
+            .ok()
+            .and_then(|properties| properties.get(name))
+            .and_then(|value| value.inner::<bool>().ok())
+            .unwrap_or(false)
+    }
+
+    /// Counts advertisements which start or stop on an adapter, returning the signals which
+    /// announce the change.
+    fn change_instances(&mut self, adapter: &str, change: i8) -> Vec<Message> {
+        let interface = ORG_BLUEZ_LEADVERTISING_MANAGER1_NAME;
+        let properties = self.properties(adapter, interface).unwrap();
+        let active = properties["ActiveInstances"].inner::<u8>().unwrap() as i8 + change;
+        let supported = properties["SupportedInstances"].inner::<u8>().unwrap() as i8 - change;
+        vec![
+            self.set_property(adapter, interface, "ActiveInstances", (active as u8).into()),
+            self.set_property(
+                adapter,
+                interface,
+                "SupportedInstances",
+                (supported as u8).into(),
+            ),
+        ]
+    }
+
+    /// Sets a property, returning the signal which announces the change.
+    fn set_property(
+        &mut self,
+        path: &str,
+        interface: &str,
+        name: &str,
+        value: MessageItem,
+    ) -> Message {
+        self.properties_mut(path, interface)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .insert(name.to_string(), value.clone());
+        let mut changed_properties = PropMap::new();
+        changed_properties.insert(name.to_string(), Variant(Box::new(value)));
+        PropertiesPropertiesChanged {
+            interface_name: interface.to_string(),
+            changed_properties,
+            invalidated_properties: Vec::new(),
+        }
+        .to_emit_message(&Path::from(path.to_string()))
+    }
+
+    /// Removes an object and everything below it, returning the signals which announce it.
+    fn remove_object(&mut self, path: &str) -> Vec<Message> {
+        let prefix = format!("{}/", path);
+        let removed: Vec<String> = self
+            .objects
+            .keys()
+            .filter(|p| *p == path || p.starts_with(&prefix))
+            .cloned()
+            .collect();
+        // Children go first, as they do in BlueZ.
+        removed
+            .into_iter()
+            .rev()
+            .map(|path| {
+                let interfaces = self.objects.remove(&path).unwrap();
+                ObjectManagerInterfacesRemoved {
+                    object: Path::from(path),
+                    interfaces: interfaces.into_keys().collect(),
+                }
+                .to_emit_message(&Path::from("/"))
+            })
+            .collect()
+    }
+}
+
+fn prop_map(properties: &Properties) -> PropMap {
+    properties
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.clone(),
+                Variant(Box::new(value.clone()) as Box<dyn RefArg>),
+            )
+        })
+        .collect()
+}
+
+/// Waits for the next event which matches `predicate`, skipping any others.
+pub fn wait_for_event<T>(events: &mut EventStream<T>, predicate: impl Fn(&T) -> bool) -> T {
+    wait(async {
+        while let Some(event) = events.next().await {
+            if predicate(&event) {
+                return event;
+            }
+        }
+        panic!("The event stream ended");
+    })
+}
@@ -0,0 +1,1061 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! GATT servers, which let this computer act as a peripheral.
+//!
+//! Describe the services to serve with [`LocalService`], [`LocalCharacteristic`] and
+//! [`LocalDescriptor`], and publish them with
+//! [`Adapter::register_application`](../adapter/struct.Adapter.html#method.register_application).
+//! BlueZ serves them to every central which connects to the adapter, until the returned
+//! [`GattApplication`] is dropped.
+//!
+//! Each attribute holds a value, which centrals can read and write as allowed by its flags. Give
+//! an attribute a read or write handler to compute values on demand, or to check and act on
+//! writes. The handlers of an application are called one at a time, in the order the requests
+//! arrive, on a thread of the application's own. They may use the manager, but the central waits
+//! for each answer, so they should still return promptly.
+
+use super::{
+    bluez_dbus::{
+        adapter::OrgBluezGattManager1, gatt_characteristic::ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+        gatt_descriptor::ORG_BLUEZ_GATT_DESCRIPTOR1_NAME,
+        gatt_service::ORG_BLUEZ_GATT_SERVICE1_NAME,
+    },
+    connection::Connection,
+    util::{address_from_device_path, call_error, insert_property},
+    BLUEZ_DEST,
+};
+use crate::{
+    api::{BDAddr, CharPropFlags, WriteType},
+    Error, Result,
+};
+use dbus::{
+    arg::{PropMap, RefArg},
+    channel::{Sender, Token},
+    message::SignalArgs,
+    nonblock::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Proxy, SyncConnection},
+    Message, MethodErr, Path,
+};
+use futures::{
+    channel::oneshot,
+    future::{self, Either},
+};
+use futures_timer::Delay;
+use log::{debug, error, trace};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+use uuid::Uuid;
+
+const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+static NEXT_APPLICATION_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Computes the value of an attribute when a central reads it. The handler returns the whole
+/// value, and the part which the central asked for is sent back. It is called on the
+/// application's own thread, not the one which processes D-Bus messages.
+pub type ReadHandler =
+    Box<dyn FnMut(&ReadRequest) -> std::result::Result<Vec<u8>, GattError> + Send>;
+
+/// Checks a value which a central writes to an attribute, before it is stored. It is called on the
+/// application's own thread, not the one which processes D-Bus messages.
+pub type WriteHandler = Box<dyn FnMut(&WriteRequest) -> std::result::Result<(), GattError> + Send>;
+
+/// A central reading the value of an attribute.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReadRequest {
+    /// The central which is reading, if BlueZ says.
+    pub device: Option<BDAddr>,
+    /// The offset into the value at which the read starts.
+    pub offset: u16,
+    /// The MTU of the connection to the central, if BlueZ says.
+    pub mtu: Option<u16>,
+}
+
+/// A central writing to an attribute.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WriteRequest {
+    /// The central which is writing, if BlueZ says.
+    pub device: Option<BDAddr>,
+    /// The offset into the value at which the write starts.
+    pub offset: u16,
+    /// The MTU of the connection to the central, if BlueZ says.
+    pub mtu: Option<u16>,
+    /// Whether the central expects a response.
+    pub write_type: WriteType,
+    pub value: Vec<u8>,
+}
+
+/// The reason a read or write handler refuses a request, which is passed on to the central.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GattError {
+    Failed,
+    InProgress,
+    NotPermitted,
+    NotAuthorized,
+    InvalidOffset,
+    InvalidValueLength,
+    NotSupported,
+}
+
+impl From<GattError> for MethodErr {
+    fn from(error: GattError) -> Self {
+        let name = match error {
+            GattError::Failed => "Failed",
+            GattError::InProgress => "InProgress",
+            GattError::NotPermitted => "NotPermitted",
+            GattError::NotAuthorized => "NotAuthorized",
+            GattError::InvalidOffset => "InvalidOffset",
+            GattError::InvalidValueLength => "InvalidValueLength",
+            GattError::NotSupported => "NotSupported",
+        };
+        (format!("org.bluez.Error.{}", name), name).into()
+    }
+}
+
+/// A service to serve, along with its characteristics.
+pub struct LocalService {
+    uuid: Uuid,
+    primary: bool,
+    characteristics: Vec<LocalCharacteristic>,
+}
+
+impl LocalService {
+    /// Creates a primary service with no characteristics.
+    pub fn new(uuid: Uuid) -> Self {
+        LocalService {
+            uuid,
+            primary: true,
+            characteristics: Vec::new(),
+        }
+    }
+
+    /// Makes this a secondary service.
+    pub fn secondary(mut self) -> Self {
+        self.primary = false;
+        self
+    }
+
+    pub fn characteristic(mut self, characteristic: LocalCharacteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+/// A characteristic to serve, along with its descriptors. BlueZ adds the Client Characteristic
+/// Configuration descriptor of characteristics which notify or indicate by itself.
+pub struct LocalCharacteristic {
+    uuid: Uuid,
+    flags: CharPropFlags,
+    value: Vec<u8>,
+    on_read: Option<ReadHandler>,
+    on_write: Option<WriteHandler>,
+    descriptors: Vec<LocalDescriptor>,
+}
+
+impl LocalCharacteristic {
+    /// Creates a characteristic with the given properties, and an empty value.
+    pub fn new(uuid: Uuid, flags: CharPropFlags) -> Self {
+        LocalCharacteristic {
+            uuid,
+            flags,
+            value: Vec::new(),
+            on_read: None,
+            on_write: None,
+            descriptors: Vec::new(),
+        }
+    }
+
+    /// Sets the initial value.
+    pub fn value(mut self, value: &[u8]) -> Self {
+        self.value = value.to_vec();
+        self
+    }
+
+    pub fn on_read(mut self, handler: ReadHandler) -> Self {
+        self.on_read = Some(handler);
+        self
+    }
+
+    pub fn on_write(mut self, handler: WriteHandler) -> Self {
+        self.on_write = Some(handler);
+        self
+    }
+
+    pub fn descriptor(mut self, descriptor: LocalDescriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+}
+
+/// A descriptor to serve. Descriptors are readable, and only writable if asked for.
+pub struct LocalDescriptor {
+    uuid: Uuid,
+    writable: bool,
+    value: Vec<u8>,
+    on_read: Option<ReadHandler>,
+    on_write: Option<WriteHandler>,
+}
+
+impl LocalDescriptor {
+    /// Creates a read-only descriptor with an empty value.
+    pub fn new(uuid: Uuid) -> Self {
+        LocalDescriptor {
+            uuid,
+            writable: false,
+            value: Vec::new(),
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    /// Lets centrals write to the descriptor.
+    pub fn writable(mut self) -> Self {
+        self.writable = true;
+        self
+    }
+
+    /// Sets the initial value.
+    pub fn value(mut self, value: &[u8]) -> Self {
+        self.value = value.to_vec();
+        self
+    }
+
+    pub fn on_read(mut self, handler: ReadHandler) -> Self {
+        self.on_read = Some(handler);
+        self
+    }
+
+    pub fn on_write(mut self, handler: WriteHandler) -> Self {
+        self.on_write = Some(handler);
+        self
+    }
+}
+
+/// The value of a characteristic or descriptor, and the handlers which serve it.
+struct Attribute {
+    value: Mutex<Vec<u8>>,
+    on_read: Mutex<Option<ReadHandler>>,
+    on_write: Mutex<Option<WriteHandler>>,
+}
+
+impl Attribute {
+    fn new(value: Vec<u8>, on_read: Option<ReadHandler>, on_write: Option<WriteHandler>) -> Self {
+        Attribute {
+            value: Mutex::new(value),
+            on_read: Mutex::new(on_read),
+            on_write: Mutex::new(on_write),
+        }
+    }
+
+    fn read(&self, request: &ReadRequest) -> std::result::Result<Vec<u8>, GattError> {
+        let value = match &mut *self.on_read.lock().unwrap() {
+            Some(handler) => handler(request)?,
+            None => self.value.lock().unwrap().clone(),
+        };
+        value
+            .get(request.offset as usize..)
+            .map(<[u8]>::to_vec)
+            .ok_or(GattError::InvalidOffset)
+    }
+
+    fn write(&self, request: &WriteRequest) -> std::result::Result<(), GattError> {
+        if let Some(handler) = &mut *self.on_write.lock().unwrap() {
+            handler(request)?;
+        }
+        let mut value = self.value.lock().unwrap();
+        let offset = request.offset as usize;
+        if offset > value.len() {
+            return Err(GattError::InvalidOffset);
+        }
+        value.truncate(offset);
+        value.extend_from_slice(&request.value);
+        Ok(())
+    }
+}
+
+/// An object which is exported on D-Bus as part of an application.
+trait Object: Send + Sync {
+    fn path(&self) -> &Path<'static>;
+
+    /// The one BlueZ interface which the object implements.
+    fn interface(&self) -> &'static str;
+
+    fn properties(&self) -> PropMap;
+
+    /// Answers a call of a member of the object's BlueZ interface, adding any signals which it
+    /// causes to `signals`.
+    fn call(
+        &self,
+        member: &str,
+        call: &Message,
+        signals: &mut Vec<Message>,
+    ) -> std::result::Result<Message, MethodErr>;
+}
+
+struct ServiceObject {
+    path: Path<'static>,
+    uuid: Uuid,
+    primary: bool,
+}
+
+impl Object for ServiceObject {
+    fn path(&self) -> &Path<'static> {
+        &self.path
+    }
+
+    fn interface(&self) -> &'static str {
+        ORG_BLUEZ_GATT_SERVICE1_NAME
+    }
+
+    fn properties(&self) -> PropMap {
+        let mut properties = PropMap::new();
+        insert_property(&mut properties, "UUID", self.uuid.to_string());
+        insert_property(&mut properties, "Primary", self.primary);
+        properties
+    }
+
+    fn call(
+        &self,
+        member: &str,
+        _call: &Message,
+        _signals: &mut Vec<Message>,
+    ) -> std::result::Result<Message, MethodErr> {
+        Err(MethodErr::no_method(member))
+    }
+}
+
+struct CharacteristicObject {
+    path: Path<'static>,
+    service: Path<'static>,
+    uuid: Uuid,
+    flags: CharPropFlags,
+    attribute: Attribute,
+    notifying: AtomicBool,
+    /// Senders to wake up tasks which wait for an indication to be confirmed.
+    confirmations: Mutex<Vec<oneshot::Sender<()>>>,
+}
+
+impl CharacteristicObject {
+    /// Returns the signal which announces that the given properties of this characteristic have
+    /// changed.
+    fn changed(&self, properties: PropMap) -> Message {
+        PropertiesPropertiesChanged {
+            interface_name: ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME.to_string(),
+            changed_properties: properties,
+            invalidated_properties: Vec::new(),
+        }
+        .to_emit_message(&self.path)
+    }
+
+    fn set_notifying(&self, notifying: bool, signals: &mut Vec<Message>) {
+        if self.notifying.swap(notifying, Ordering::Relaxed) != notifying {
+            debug!(
+                "Centrals have {} to {}",
+                if notifying {
+                    "subscribed"
+                } else {
+                    "unsubscribed"
+                },
+                self.uuid
+            );
+            let mut properties = PropMap::new();
+            insert_property(&mut properties, "Notifying", notifying);
+            signals.push(self.changed(properties));
+        }
+    }
+}
+
+impl Object for CharacteristicObject {
+    fn path(&self) -> &Path<'static> {
+        &self.path
+    }
+
+    fn interface(&self) -> &'static str {
+        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME
+    }
+
+    fn properties(&self) -> PropMap {
+        let mut properties = PropMap::new();
+        insert_property(&mut properties, "UUID", self.uuid.to_string());
+        insert_property(&mut properties, "Service", self.service.clone());
+        insert_property(&mut properties, "Flags", characteristic_flags(self.flags));
+        insert_property(
+            &mut properties,
+            "Value",
+            self.attribute.value.lock().unwrap().clone(),
+        );
+        if self
+            .flags
+            .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+        {
+            insert_property(
+                &mut properties,
+                "Notifying",
+                self.notifying.load(Ordering::Relaxed),
+            );
+        }
+        properties
+    }
+
+    fn call(
+        &self,
+        member: &str,
+        call: &Message,
+        signals: &mut Vec<Message>,
+    ) -> std::result::Result<Message, MethodErr> {
+        let reply = call.method_return();
+        Ok(match member {
+            "ReadValue" => {
+                if !self.flags.contains(CharPropFlags::READ) {
+                    return Err(GattError::NotPermitted.into());
+                }
+                reply.append1(self.attribute.read(&read_request(call)?)?)
+            }
+            "WriteValue" => {
+                if !self
+                    .flags
+                    .intersects(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE)
+                {
+                    return Err(GattError::NotPermitted.into());
+                }
+                self.attribute.write(&write_request(call)?)?;
+                reply
+            }
+            "StartNotify" | "StopNotify" => {
+                if !self
+                    .flags
+                    .intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE)
+                {
+                    return Err(GattError::NotSupported.into());
+                }
+                self.set_notifying(member == "StartNotify", signals);
+                reply
+            }
+            "Confirm" => {
+                trace!("Indication of {} was confirmed", self.uuid);
+                for sender in self.confirmations.lock().unwrap().drain(..) {
+                    let _ = sender.send(());
+                }
+                reply
+            }
+            _ => return Err(MethodErr::no_method(member)),
+        })
+    }
+}
+
+struct DescriptorObject {
+    path: Path<'static>,
+    characteristic: Path<'static>,
+    uuid: Uuid,
+    writable: bool,
+    attribute: Attribute,
+}
+
+impl Object for DescriptorObject {
+    fn path(&self) -> &Path<'static> {
+        &self.path
+    }
+
+    fn interface(&self) -> &'static str {
+        ORG_BLUEZ_GATT_DESCRIPTOR1_NAME
+    }
+
+    fn properties(&self) -> PropMap {
+        let mut flags = vec!["read".to_string()];
+        if self.writable {
+            flags.push("write".to_string());
+        }
+        let mut properties = PropMap::new();
+        insert_property(&mut properties, "UUID", self.uuid.to_string());
+        insert_property(
+            &mut properties,
+            "Characteristic",
+            self.characteristic.clone(),
+        );
+        insert_property(&mut properties, "Flags", flags);
+        properties
+    }
+
+    fn call(
+        &self,
+        member: &str,
+        call: &Message,
+        _signals: &mut Vec<Message>,
+    ) -> std::result::Result<Message, MethodErr> {
+        let reply = call.method_return();
+        Ok(match member {
+            "ReadValue" => reply.append1(self.attribute.read(&read_request(call)?)?),
+            "WriteValue" => {
+                if !self.writable {
+                    return Err(GattError::NotPermitted.into());
+                }
+                self.attribute.write(&write_request(call)?)?;
+                reply
+            }
+            _ => return Err(MethodErr::no_method(member)),
+        })
+    }
+}
+
+/// A set of services which BlueZ is serving. The services are unregistered when this is dropped.
+pub struct GattApplication {
+    connection: Arc<Connection>,
+    adapter: Path<'static>,
+    path: Path<'static>,
+    characteristics: Vec<Arc<CharacteristicObject>>,
+    tokens: Vec<Token>,
+}
+
+impl GattApplication {
+    /// Exports `services` on the connection, and registers them with the adapter at `adapter`.
+    pub(crate) async fn register(
+        connection: Arc<Connection>,
+        adapter: Path<'static>,
+        services: Vec<LocalService>,
+    ) -> Result<Self> {
+        let path = Path::from(format!(
+            "/org/btleplug/application{}",
+            NEXT_APPLICATION_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut objects: Vec<Arc<dyn Object>> = Vec::new();
+        let mut characteristics = Vec::new();
+        for (i, service) in services.into_iter().enumerate() {
+            let service_path = Path::from(format!("{}/service{}", path, i));
+            objects.push(Arc::new(ServiceObject {
+                path: service_path.clone(),
+                uuid: service.uuid,
+                primary: service.primary,
+            }));
+            for (j, characteristic) in service.characteristics.into_iter().enumerate() {
+                let characteristic_path = Path::from(format!("{}/char{}", service_path, j));
+                let object = Arc::new(CharacteristicObject {
+                    path: characteristic_path.clone(),
+                    service: service_path.clone(),
+                    uuid: characteristic.uuid,
+                    flags: characteristic.flags,
+                    attribute: Attribute::new(
+                        characteristic.value,
+                        characteristic.on_read,
+                        characteristic.on_write,
+                    ),
+                    notifying: AtomicBool::new(false),
+                    confirmations: Mutex::new(Vec::new()),
+                });
+                characteristics.push(object.clone());
+                objects.push(object);
+                for (k, descriptor) in characteristic.descriptors.into_iter().enumerate() {
+                    objects.push(Arc::new(DescriptorObject {
+                        path: Path::from(format!("{}/desc{}", characteristic_path, k)),
+                        characteristic: characteristic_path.clone(),
+                        uuid: descriptor.uuid,
+                        writable: descriptor.writable,
+                        attribute: Attribute::new(
+                            descriptor.value,
+                            descriptor.on_read,
+                            descriptor.on_write,
+                        ),
+                    }));
+                }
+            }
+        }
+
+        let mut tokens = Vec::new();
+        let calls = spawn_worker(connection.shared());
+        for object in &objects {
+            let object = object.clone();
+            let calls = Mutex::new(calls.clone());
+            tokens.push(
+                connection.export(object.path().clone(), move |call, _connection| {
+                    // The worker only stops once the objects are no longer exported.
+                    calls.lock().unwrap().send((object.clone(), call)).unwrap();
+                }),
+            );
+        }
+        let objects = Arc::new(objects);
+        tokens.push(connection.export(path.clone(), move |call, connection| {
+            let reply = managed_objects(&objects, &call).unwrap_or_else(|e| e.to_message(&call));
+            send(connection, reply);
+        }));
+
+        let application = GattApplication {
+            connection,
+            adapter,
+            path,
+            characteristics,
+            tokens,
+        };
+        application
+            .proxy()
+            .register_application(application.path.clone(), PropMap::new())
+            .await
+            .map_err(call_error(application.connection.timeout()))?;
+        debug!(
+            "Registered application {} on {}",
+            application.path, application.adapter
+        );
+        Ok(application)
+    }
+
+    /// Returns the characteristics which at least one central is subscribed to. BlueZ doesn't say
+    /// which centrals are subscribed, only whether any are.
+    pub fn subscriptions(&self) -> Vec<Uuid> {
+        self.characteristics
+            .iter()
+            .filter(|c| c.notifying.load(Ordering::Relaxed))
+            .map(|c| c.uuid)
+            .collect()
+    }
+
+    /// Returns whether any central is subscribed to the given characteristic.
+    pub fn is_subscribed(&self, characteristic: Uuid) -> bool {
+        self.subscriptions().contains(&characteristic)
+    }
+
+    /// Returns the current value of the given characteristic.
+    pub fn value(&self, characteristic: Uuid) -> Result<Vec<u8>> {
+        Ok(self
+            .characteristic(characteristic)?
+            .attribute
+            .value
+            .lock()
+            .unwrap()
+            .clone())
+    }
+
+    /// Sets the value of the given characteristic, and notifies the centrals which are subscribed
+    /// to it.
+    pub fn notify(&self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+        if !characteristic.flags.contains(CharPropFlags::NOTIFY) {
+            return Err(Error::NotSupported(
+                "Notifying a characteristic without the NOTIFY property".into(),
+            ));
+        }
+        self.set_value(&characteristic, value);
+        Ok(())
+    }
+
+    /// Sets the value of the given characteristic, and indicates it to the centrals which are
+    /// subscribed to it. This waits until a central confirms the indication, and fails with
+    /// [`Error::NotConnected`] if no central is subscribed.
+    pub async fn indicate(&self, characteristic: Uuid, value: &[u8]) -> Result<()> {
+        let characteristic = self.characteristic(characteristic)?;
+        if !characteristic.flags.contains(CharPropFlags::INDICATE) {
+            return Err(Error::NotSupported(
+                "Indicating a characteristic without the INDICATE property".into(),
+            ));
+        }
+        if !characteristic.notifying.load(Ordering::Relaxed) {
+            return Err(Error::NotConnected("No central is subscribed".into()));
+        }
+        let (sender, receiver) = oneshot::channel();
+        characteristic.confirmations.lock().unwrap().push(sender);
+        self.set_value(&characteristic, value);
+        let timeout = self.connection.timeout();
+        match future::select(receiver, Delay::new(timeout)).await {
+            Either::Left((Ok(()), _)) => Ok(()),
+            Either::Left((Err(_), _)) => Err(Error::NotConnected(
+                "The central went away before confirming".into(),
+            )),
+            Either::Right(_) => Err(Error::TimedOut(timeout)),
+        }
+    }
+
+    fn characteristic(&self, uuid: Uuid) -> Result<Arc<CharacteristicObject>> {
+        self.characteristics
+            .iter()
+            .find(|c| c.uuid == uuid)
+            .cloned()
+            .ok_or_else(|| Error::Other(format!("No local characteristic {}", uuid)))
+    }
+
+    fn set_value(&self, characteristic: &CharacteristicObject, value: &[u8]) {
+        *characteristic.attribute.value.lock().unwrap() = value.to_vec();
+        let mut properties = PropMap::new();
+        insert_property(&mut properties, "Value", value.to_vec());
+        send(&self.connection, characteristic.changed(properties));
+    }
+
+    fn proxy(&self) -> Proxy<'_, &SyncConnection> {
+        Proxy::new(
+            BLUEZ_DEST,
+            &self.adapter,
+            self.connection.timeout(),
+            &**self.connection,
+        )
+    }
+}
+
+impl Drop for GattApplication {
+    fn drop(&mut self) {
+        for token in self.tokens.drain(..) {
+            self.connection.unexport(token);
+        }
+        // The call is sent as soon as it is made, so the reply can be ignored.
+        drop(self.proxy().unregister_application(self.path.clone()));
+    }
+}
+
+/// Starts the thread which answers the calls on an application's objects, so that the handlers
+/// don't hold up the rest of the connection. The calls are answered in the order they arrive, and
+/// the thread ends once every sender has been dropped.
+fn spawn_worker(connection: Arc<SyncConnection>) -> mpsc::Sender<(Arc<dyn Object>, Message)> {
+    let (sender, receiver) = mpsc::channel::<(Arc<dyn Object>, Message)>();
+    thread::spawn(move || {
+        for (object, call) in receiver {
+            handle_call(&*object, &call, &connection);
+        }
+    });
+    sender
+}
+
+fn handle_call(object: &dyn Object, call: &Message, connection: &SyncConnection) {
+    let mut signals = Vec::new();
+    let reply = answer(object, call, &mut signals).unwrap_or_else(|e| e.to_message(call));
+    send(connection, reply);
+    for signal in signals {
+        send(connection, signal);
+    }
+}
+
+fn answer(
+    object: &dyn Object,
+    call: &Message,
+    signals: &mut Vec<Message>,
+) -> std::result::Result<Message, MethodErr> {
+    let interface = call.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = call.member().map(|m| m.to_string()).unwrap_or_default();
+    trace!("{} received {}.{}", object.path(), interface, member);
+    match interface.as_str() {
+        PROPERTIES => {
+            let requested: &str = call.read1()?;
+            if requested != object.interface() {
+                return Err(MethodErr::no_interface(requested));
+            }
+            let mut properties = object.properties();
+            match member.as_str() {
+                "Get" => {
+                    let (_, name): (&str, &str) = call.read2()?;
+                    let value = properties
+                        .remove(name)
+                        .ok_or_else(|| MethodErr::no_property(name))?;
+                    Ok(call.method_return().append1(value))
+                }
+                "GetAll" => Ok(call.method_return().append1(properties)),
+                _ => Err(MethodErr::no_method(&member)),
+            }
+        }
+        name if name == object.interface() => object.call(&member, call, signals),
+        _ => Err(MethodErr::no_interface(&interface)),
+    }
+}
+
+/// Answers `GetManagedObjects` on the root of an application, which is how BlueZ finds its
+/// services.
+fn managed_objects(
+    objects: &[Arc<dyn Object>],
+    call: &Message,
+) -> std::result::Result<Message, MethodErr> {
+    if call.interface().as_deref() != Some(OBJECT_MANAGER)
+        || call.member().as_deref() != Some("GetManagedObjects")
+    {
+        return Err(MethodErr::no_method(
+            &call.member().map(|m| m.to_string()).unwrap_or_default(),
+        ));
+    }
+    let objects: HashMap<Path, HashMap<String, PropMap>> = objects
+        .iter()
+        .map(|object| {
+            let mut interfaces = HashMap::new();
+            interfaces.insert(object.interface().to_string(), object.properties());
+            (object.path().clone(), interfaces)
+        })
+        .collect();
+    Ok(call.method_return().append1(objects))
+}
+
+fn send(connection: &SyncConnection, message: Message) {
+    if connection.send(message).is_err() {
+        error!("Could not send a message for a GATT application");
+    }
+}
+
+/// Converts characteristic properties to BlueZ's flags.
+fn characteristic_flags(flags: CharPropFlags) -> Vec<String> {
+    [
+        (CharPropFlags::BROADCAST, "broadcast"),
+        (CharPropFlags::READ, "read"),
+        (
+            CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            "write-without-response",
+        ),
+        (CharPropFlags::WRITE, "write"),
+        (CharPropFlags::NOTIFY, "notify"),
+        (CharPropFlags::INDICATE, "indicate"),
+        (
+            CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+            "authenticated-signed-writes",
+        ),
+        (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// Reads the options of a `ReadValue` call.
+fn read_request(call: &Message) -> std::result::Result<ReadRequest, MethodErr> {
+    let options: PropMap = call.read1()?;
+    Ok(ReadRequest {
+        device: device_option(&options),
+        offset: u16_option(&options, "offset").unwrap_or(0),
+        mtu: u16_option(&options, "mtu"),
+    })
+}
+
+/// Reads the value and options of a `WriteValue` call.
+fn write_request(call: &Message) -> std::result::Result<WriteRequest, MethodErr> {
+    let (value, options): (Vec<u8>, PropMap) = call.read2()?;
+    let write_type = match options.get("type").and_then(|t| t.0.as_str()) {
+        Some("command") => WriteType::WithoutResponse,
+        _ => WriteType::WithResponse,
+    };
+    Ok(WriteRequest {
+        device: device_option(&options),
+        offset: u16_option(&options, "offset").unwrap_or(0),
+        mtu: u16_option(&options, "mtu"),
+        write_type,
+        value,
+    })
+}
+
+fn device_option(options: &PropMap) -> Option<BDAddr> {
+    options
+        .get("device")
+        .and_then(|device| device.0.as_str())
+        .and_then(address_from_device_path)
+}
+
+fn u16_option(options: &PropMap, name: &str) -> Option<u16> {
+    options
+        .get(name)
+        .and_then(|value| value.0.as_u64())
+        .map(|value| value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::Central,
+        bluez::fake_bluez::{wait, FakeBluez},
+    };
+    use dbus::arg::Variant;
+    use std::time::Duration;
+
+    const BATTERY_SERVICE: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+    const BATTERY_LEVEL: &str = "00002a19-0000-1000-8000-00805f9b34fb";
+    const USER_DESCRIPTION: &str = "00002901-0000-1000-8000-00805f9b34fb";
+    const CENTRAL: &str = "/org/bluez/hci0/dev_11_22_33_44_55_66";
+
+    fn options(entries: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        entries
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), Variant(value)))
+            .collect()
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        wait(async {
+            while !condition() {
+                Delay::new(Duration::from_millis(10)).await;
+            }
+        });
+    }
+
+    #[test]
+    fn serve_a_battery_service() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        let level: Uuid = BATTERY_LEVEL.parse().unwrap();
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let service = LocalService::new(BATTERY_SERVICE.parse().unwrap()).characteristic(
+            LocalCharacteristic::new(
+                level,
+                CharPropFlags::READ
+                    | CharPropFlags::WRITE
+                    | CharPropFlags::NOTIFY
+                    | CharPropFlags::INDICATE,
+            )
+            .value(&[100, 99, 98])
+            .on_write({
+                let writes = writes.clone();
+                Box::new(move |request: &WriteRequest| {
+                    if request.value.is_empty() {
+                        return Err(GattError::InvalidValueLength);
+                    }
+                    writes.lock().unwrap().push(request.clone());
+                    Ok(())
+                })
+            })
+            .descriptor(LocalDescriptor::new(USER_DESCRIPTION.parse().unwrap()).value(b"Battery")),
+        );
+        let application = wait(adapter.register_application(vec![service])).unwrap();
+        assert_eq!(bluez.application().unwrap().adapter, hci0);
+
+        let objects = bluez.application_objects();
+        assert_eq!(objects.len(), 3);
+        let (level_path, interfaces) = objects
+            .iter()
+            .find(|(_, interfaces)| interfaces.contains_key(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME))
+            .unwrap();
+        let level_path = level_path.to_string();
+        let properties = &interfaces[ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME];
+        let flags: Vec<&str> = properties["Flags"]
+            .0
+            .as_iter()
+            .unwrap()
+            .map(|flag| flag.as_str().unwrap())
+            .collect();
+        assert_eq!(flags, vec!["read", "write", "notify", "indicate"]);
+
+        // Reads and writes are served from the value, through the handlers.
+        let (value,): (Vec<u8>,) = bluez
+            .call_application(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "ReadValue",
+                (options(vec![("offset", Box::new(1u16))]),),
+            )
+            .unwrap();
+        assert_eq!(value, vec![99, 98]);
+        let () = bluez
+            .call_application(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "WriteValue",
+                (
+                    vec![42u8],
+                    options(vec![("device", Box::new(Path::from(CENTRAL)))]),
+                ),
+            )
+            .unwrap();
+        assert_eq!(application.value(level).unwrap(), vec![42]);
+        assert_eq!(
+            writes.lock().unwrap()[0].device,
+            Some("11:22:33:44:55:66".parse().unwrap())
+        );
+        let error = bluez
+            .call_application::<_, ()>(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "WriteValue",
+                (Vec::<u8>::new(), PropMap::new()),
+            )
+            .unwrap_err();
+        assert_eq!(error.name(), Some("org.bluez.Error.InvalidValueLength"));
+
+        // Notifications and indications go out once a central subscribes.
+        assert!(matches!(
+            wait(application.indicate(level, &[1])),
+            Err(Error::NotConnected(_))
+        ));
+        let () = bluez
+            .call_application(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "StartNotify",
+                (),
+            )
+            .unwrap();
+        assert_eq!(application.subscriptions(), vec![level]);
+        application.notify(level, &[50]).unwrap();
+        wait_until(|| {
+            bluez
+                .notifications()
+                .contains(&(level_path.clone(), vec![50]))
+        });
+        std::thread::scope(|scope| {
+            // The central confirms the indication once it arrives.
+            scope.spawn(|| {
+                wait_until(|| {
+                    bluez
+                        .notifications()
+                        .contains(&(level_path.clone(), vec![49]))
+                });
+                let () = bluez
+                    .call_application(
+                        &level_path,
+                        ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                        "Confirm",
+                        (),
+                    )
+                    .unwrap();
+            });
+            wait(application.indicate(level, &[49])).unwrap();
+        });
+        let () = bluez
+            .call_application(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "StopNotify",
+                (),
+            )
+            .unwrap();
+        assert!(!application.is_subscribed(level));
+
+        drop(application);
+        wait_until(|| bluez.application().is_none());
+    }
+
+    #[test]
+    fn handlers_may_use_the_manager() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let adapter = bluez.adapter();
+        let level: Uuid = BATTERY_LEVEL.parse().unwrap();
+        let service = LocalService::new(BATTERY_SERVICE.parse().unwrap()).characteristic(
+            LocalCharacteristic::new(level, CharPropFlags::READ).on_read({
+                let adapter = adapter.clone();
+                // This blocks until the adapter's reply comes through the same connection as the
+                // read.
+                Box::new(move |_request: &ReadRequest| {
+                    let info = Central::adapter_info(&adapter).unwrap();
+                    Ok(info.name.unwrap_or_default().into_bytes())
+                })
+            }),
+        );
+        let application = wait(adapter.register_application(vec![service])).unwrap();
+        let level_path = bluez
+            .application_objects()
+            .into_iter()
+            .find(|(_, interfaces)| interfaces.contains_key(ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME))
+            .unwrap()
+            .0
+            .to_string();
+
+        let (value,): (Vec<u8>,) = bluez
+            .call_application(
+                &level_path,
+                ORG_BLUEZ_GATT_CHARACTERISTIC1_NAME,
+                "ReadValue",
+                (PropMap::new(),),
+            )
+            .unwrap();
+        assert_eq!(value, b"hci0");
+        drop(application);
+    }
+}
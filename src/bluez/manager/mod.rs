@@ -11,16 +11,67 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use super::{bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME, BLUEZ_DEST, DEFAULT_TIMEOUT};
-use crate::{bluez::adapter::Adapter, Result};
-use dbus::blocking::{stdintf::org_freedesktop_dbus::ObjectManager, SyncConnection};
+use super::{
+    bluez_dbus::adapter::ORG_BLUEZ_ADAPTER1_NAME,
+    connection::{Bus, Connection},
+    util::call_error,
+    BLUEZ_DEST, DEFAULT_TIMEOUT,
+};
+use crate::{
+    api::{EventSender, EventStream, DEFAULT_EVENT_BUFFER},
+    bluez::{
+        adapter::Adapter,
+        agent::{Agent, AgentRegistration},
+    },
+    common::blocking::block_on,
+    Result,
+};
+use dbus::{
+    arg::prop_cast,
+    channel::{Channel, Token},
+    message::SignalArgs,
+    nonblock::{
+        stdintf::org_freedesktop_dbus::{
+            ObjectManager, ObjectManagerInterfacesAdded as InterfacesAdded,
+            ObjectManagerInterfacesRemoved as InterfacesRemoved, PropertiesPropertiesChanged,
+        },
+        Proxy,
+    },
+    Path,
+};
+use futures::future::try_join_all;
+use log::trace;
 use static_assertions::assert_impl_all;
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+/// An event about the adapters on the system, from [`Manager::events`]. Adapters are identified by
+/// their D-Bus object path, such as `/org/bluez/hci0`, which is also given by [`Adapter::path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManagerEvent {
+    /// An adapter has been plugged in, or has otherwise become available.
+    AdapterAdded(String),
+    /// An adapter has been unplugged, or has otherwise gone away. Existing handles to it return
+    /// [`Error::AdapterNotAvailable`](crate::Error::AdapterNotAvailable) from now on.
+    AdapterRemoved(String),
+    /// An adapter has been switched on or off, for example by rfkill.
+    AdapterPoweredChanged { adapter: String, powered: bool },
+    /// An adapter has started or stopped discovering devices.
+    AdapterDiscoveringChanged { adapter: String, discovering: bool },
+}
 
 /// This struct is the interface into BlueZ. It can be used to list, manage, and connect to bluetooth
 /// adapters.
+///
+/// A manager opens a single D-Bus connection, which is shared by every adapter and peripheral it
+/// creates. Use [`Manager::builder`] to choose the bus which BlueZ is found on, or to give the
+/// manager a connection which the application has already opened.
 pub struct Manager {
-    dbus_conn: Arc<SyncConnection>,
+    dbus_conn: Arc<Connection>,
+    event_sender: Arc<EventSender<ManagerEvent>>,
+    match_tokens: Vec<Token>,
 }
 assert_impl_all!(Manager: Sync, Send);
 
@@ -28,25 +79,345 @@ impl Manager {
     /// Constructs a new manager to communicate with the BlueZ system. Only one Manager should be
     /// created by your application.
     pub fn new() -> Result<Manager> {
-        Ok(Manager {
-            dbus_conn: Arc::new(SyncConnection::new_system()?),
-        })
+        Self::builder().build()
+    }
+
+    /// Returns a builder for a manager with a different bus or timeout than the defaults.
+    pub fn builder() -> ManagerBuilder {
+        ManagerBuilder::new()
     }
 
     /// Returns the list of adapters available on the system.
     pub fn adapters(&self) -> Result<Vec<Adapter>> {
+        block_on(self.adapters_async())
+    }
+
+    /// Returns the list of adapters available on the system, without blocking.
+    pub async fn adapters_async(&self) -> Result<Vec<Adapter>> {
         // Create a convenience proxy connection that's already namespaced to org.bluez
-        let bluez = self.dbus_conn.with_proxy(BLUEZ_DEST, "/", DEFAULT_TIMEOUT);
+        let bluez = Proxy::new(BLUEZ_DEST, "/", self.dbus_conn.timeout(), &**self.dbus_conn);
 
         // First, use org.freedesktop.DBus.ObjectManager to query org.bluez
         // for adapters
         let adapters = bluez
-            .get_managed_objects()?
+            .get_managed_objects()
+            .await
+            .map_err(call_error(self.dbus_conn.timeout()))?
             .into_iter()
             .filter(|(_k, v)| v.keys().any(|i| i.starts_with(ORG_BLUEZ_ADAPTER1_NAME)))
-            .map(|(path, _v)| Adapter::from_dbus_path(&path))
-            .collect::<Result<Vec<_>>>()?;
+            .map(|(path, _v)| Adapter::from_dbus_path(path, self.dbus_conn.clone()));
+
+        try_join_all(adapters).await
+    }
+
+    /// Subscribes to events about adapters being added, removed, switched on or off, or starting
+    /// or stopping discovery. The stream ends when the manager is dropped.
+    pub fn events(&self) -> EventStream<ManagerEvent> {
+        self.events_with_buffer(DEFAULT_EVENT_BUFFER)
+    }
+
+    /// Subscribes to events like [`events`](#method.events), buffering up to `buffer` events.
+    pub fn events_with_buffer(&self, buffer: usize) -> EventStream<ManagerEvent> {
+        self.event_sender.subscribe(buffer)
+    }
+
+    /// Registers an agent to answer BlueZ's requests when pairing with devices through this
+    /// manager's adapters. The agent stays registered until the returned registration is dropped.
+    pub fn register_agent(&self, agent: Arc<dyn Agent>) -> Result<AgentRegistration> {
+        block_on(self.register_agent_async(agent))
+    }
+
+    /// Registers an agent, like [`register_agent`](#method.register_agent), without blocking.
+    pub async fn register_agent_async(&self, agent: Arc<dyn Agent>) -> Result<AgentRegistration> {
+        AgentRegistration::register(self.dbus_conn.clone(), agent).await
+    }
+
+    /// Listens for adapters coming and going, and for changes to their state, and turns them into
+    /// events. The handlers only hold on to the event sender weakly, so that dropping the manager
+    /// ends the event streams.
+    async fn watch_adapters(&mut self) -> Result<()> {
+        let mut added_rule = InterfacesAdded::match_rule(None, None);
+        added_rule.path = Some(Path::from("/"));
+        let sender = Arc::downgrade(&self.event_sender);
+        let token = self
+            .dbus_conn
+            .add_match(added_rule, move |args: InterfacesAdded, _msg| {
+                if !args.interfaces.contains_key(ORG_BLUEZ_ADAPTER1_NAME) {
+                    return true;
+                }
+                trace!("Adapter \"{}\" was added", args.object);
+                send(&sender, ManagerEvent::AdapterAdded(args.object.to_string()))
+            })
+            .await?;
+        self.match_tokens.push(token);
+
+        let mut removed_rule = InterfacesRemoved::match_rule(None, None);
+        removed_rule.path = Some(Path::from("/"));
+        let sender = Arc::downgrade(&self.event_sender);
+        let token = self
+            .dbus_conn
+            .add_match(removed_rule, move |args: InterfacesRemoved, _msg| {
+                if !args.interfaces.iter().any(|i| i == ORG_BLUEZ_ADAPTER1_NAME) {
+                    return true;
+                }
+                trace!("Adapter \"{}\" was removed", args.object);
+                send(
+                    &sender,
+                    ManagerEvent::AdapterRemoved(args.object.to_string()),
+                )
+            })
+            .await?;
+        self.match_tokens.push(token);
+
+        let mut changed_rule = PropertiesPropertiesChanged::match_rule(None, None);
+        changed_rule.path = Some(Path::from("/org/bluez"));
+        changed_rule.path_is_namespace = true;
+        let sender = Arc::downgrade(&self.event_sender);
+        let token = self
+            .dbus_conn
+            .add_match(
+                changed_rule,
+                move |args: PropertiesPropertiesChanged, msg| {
+                    if args.interface_name != ORG_BLUEZ_ADAPTER1_NAME {
+                        return true;
+                    }
+                    let adapter = match msg.path() {
+                        Some(path) => path.to_string(),
+                        None => return true,
+                    };
+                    let changed = &args.changed_properties;
+                    if let Some(&powered) = prop_cast::<bool>(changed, "Powered") {
+                        let adapter = adapter.clone();
+                        if !send(
+                            &sender,
+                            ManagerEvent::AdapterPoweredChanged { adapter, powered },
+                        ) {
+                            return false;
+                        }
+                    }
+                    if let Some(&discovering) = prop_cast::<bool>(changed, "Discovering") {
+                        let event = ManagerEvent::AdapterDiscoveringChanged {
+                            adapter,
+                            discovering,
+                        };
+                        return send(&sender, event);
+                    }
+                    true
+                },
+            )
+            .await?;
+        self.match_tokens.push(token);
+
+        Ok(())
+    }
+}
+
+impl Drop for Manager {
+    fn drop(&mut self) {
+        // Adapters may keep the connection open after the manager is gone.
+        for token in self.match_tokens.drain(..) {
+            self.dbus_conn.stop_match(token);
+        }
+    }
+}
+
+/// Sends an event if the manager is still alive, returning whether it was.
+fn send(sender: &Weak<EventSender<ManagerEvent>>, event: ManagerEvent) -> bool {
+    match sender.upgrade() {
+        Some(sender) => {
+            sender.send(event);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Configures and creates a [`Manager`].
+///
+/// By default, the manager connects to BlueZ on the system bus, and D-Bus method calls time out
+/// after 30 seconds.
+pub struct ManagerBuilder {
+    bus: Bus,
+    timeout: Duration,
+}
+
+impl ManagerBuilder {
+    /// Creates a builder with the default settings.
+    pub fn new() -> Self {
+        ManagerBuilder {
+            bus: Bus::System,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Connects to the bus with the given D-Bus address, such as
+    /// `unix:path=/run/dbus/system_bus_socket`, instead of the system bus.
+    pub fn bus_address(mut self, address: impl Into<String>) -> Self {
+        self.bus = Bus::Address(address.into());
+        self
+    }
+
+    /// Uses a connection which the application has already opened, instead of opening a new one.
+    /// The manager takes over the connection, and processes all of its messages from then on.
+    /// The connection is registered with the bus first if it hasn't been already.
+    pub fn connection(mut self, channel: Channel) -> Self {
+        self.bus = Bus::Channel(channel);
+        self
+    }
+
+    /// Sets how long to wait for BlueZ to reply to a method call, and for a peripheral to connect,
+    /// before giving up.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Opens the connection and creates the manager.
+    pub fn build(self) -> Result<Manager> {
+        let mut manager = Manager {
+            dbus_conn: Arc::new(Connection::open(self.bus, self.timeout)?),
+            event_sender: Arc::new(EventSender::new()),
+            match_tokens: Vec::new(),
+        };
+        block_on(manager.watch_adapters())?;
+        Ok(manager)
+    }
+}
+
+impl Default for ManagerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::BDAddr,
+        bluez::{
+            agent::{AgentError, Capability},
+            fake_bluez::{wait, wait_for_event, FakeBluez},
+        },
+        Error,
+    };
+    use dbus::Path;
+    use futures::stream::StreamExt;
+
+    #[test]
+    fn adapters_share_an_existing_connection() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        bluez.add_adapter("hci1", "00:00:00:00:00:02");
+        let channel = Channel::open_private(bluez.address()).unwrap();
+        let manager = Manager::builder()
+            .connection(channel)
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        assert_eq!(manager.dbus_conn.timeout(), Duration::from_secs(5));
+
+        let adapters = wait(manager.adapters_async()).unwrap();
+        let mut names: Vec<String> = adapters
+            .iter()
+            .map(|adapter| wait(adapter.name()).unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["hci0", "hci1"]);
+        // The adapters hold on to the manager's connection, and let go of it when dropped.
+        assert!(Arc::strong_count(&manager.dbus_conn) > 1);
+        drop(adapters);
+        assert_eq!(Arc::strong_count(&manager.dbus_conn), 1);
+    }
+
+    #[test]
+    fn events_follow_adapters_coming_and_going() {
+        let bluez = FakeBluez::new();
+        let hci0 = bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let manager = bluez.manager();
+        let mut events = manager.events();
+        let adapter = wait(manager.adapters_async()).unwrap().remove(0);
+        assert_eq!(adapter.path(), hci0);
+
+        bluez.set_property(&hci0, ORG_BLUEZ_ADAPTER1_NAME, "Powered", false.into());
+        assert_eq!(
+            wait_for_event(&mut events, |_| true),
+            ManagerEvent::AdapterPoweredChanged {
+                adapter: hci0.clone(),
+                powered: false
+            }
+        );
+        bluez.set_property(&hci0, ORG_BLUEZ_ADAPTER1_NAME, "Discovering", true.into());
+        assert_eq!(
+            wait_for_event(&mut events, |_| true),
+            ManagerEvent::AdapterDiscoveringChanged {
+                adapter: hci0.clone(),
+                discovering: true
+            }
+        );
+
+        let hci1 = bluez.add_adapter("hci1", "00:00:00:00:00:02");
+        assert_eq!(
+            wait_for_event(&mut events, |_| true),
+            ManagerEvent::AdapterAdded(hci1)
+        );
+
+        bluez.remove_object(&hci0);
+        assert_eq!(
+            wait_for_event(&mut events, |_| true),
+            ManagerEvent::AdapterRemoved(hci0)
+        );
+        // The adapter hears of its removal separately, so it may take a moment to notice.
+        wait(async {
+            while !matches!(adapter.name().await, Err(Error::AdapterNotAvailable)) {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            }
+        });
+
+        drop(manager);
+        assert_eq!(wait(events.next()), None);
+    }
+
+    struct PasskeyAgent;
+
+    impl Agent for PasskeyAgent {
+        fn capability(&self) -> Capability {
+            Capability::KeyboardOnly
+        }
+
+        fn request_passkey(&self, device: BDAddr) -> std::result::Result<u32, AgentError> {
+            assert_eq!(device, "11:22:33:44:55:66".parse().unwrap());
+            Ok(123456)
+        }
+    }
+
+    #[test]
+    fn agents_answer_requests_until_dropped() {
+        let bluez = FakeBluez::new();
+        bluez.add_adapter("hci0", "00:00:00:00:00:01");
+        let manager = bluez.manager();
+        let registration = manager.register_agent(Arc::new(PasskeyAgent)).unwrap();
+        wait(registration.request_default()).unwrap();
+        let agent = bluez.agent().unwrap();
+        assert_eq!(agent.capability, "KeyboardOnly");
+        assert!(agent.default);
+
+        let device = Path::from("/org/bluez/hci0/dev_11_22_33_44_55_66");
+        let (passkey,): (u32,) = bluez
+            .call_agent("RequestPasskey", (device.clone(),))
+            .unwrap();
+        assert_eq!(passkey, 123456);
+        // Requests which the agent doesn't handle are rejected.
+        let error = bluez
+            .call_agent::<_, ()>("RequestConfirmation", (device, 123456u32))
+            .unwrap_err();
+        assert_eq!(error.name(), Some("org.bluez.Error.Rejected"));
 
-        Ok(adapters)
+        drop(registration);
+        wait(async {
+            while bluez.agent().is_some() {
+                futures_timer::Delay::new(Duration::from_millis(10)).await;
+            }
+        });
     }
 }
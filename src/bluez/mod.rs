@@ -12,7 +12,13 @@
 // Copyright (c) 2014 The Rust Project Developers
 
 pub mod adapter;
+pub mod advertisement;
+pub mod agent;
 mod bluez_dbus;
+mod connection;
+#[cfg(test)]
+mod fake_bluez;
+pub mod gatt_server;
 pub mod manager;
 mod util;
 
@@ -59,7 +65,7 @@ impl std::str::FromStr for Handle {
     type Err = crate::Error;
 
     fn from_str(s: &str) -> std::result::Result<Handle, crate::Error> {
-        // serviceXXXX/charYYYY/descriptorZZZZ
+        // serviceXXXX/charYYYY/descZZZZ
         let mut handle = Handle {
             typ: AttributeType::Service,
             parent: 0,
@@ -69,7 +75,9 @@ impl std::str::FromStr for Handle {
             u16::from_str_radix(&s[p..].trim_start_matches(char::is_alphabetic)[..4], 16).unwrap()
         };
 
-        if let Some(descriptor) = s.find("descriptor") {
+        // BlueZ names descriptors "descZZZZ", although its documentation says "descriptorZZZZ", so
+        // accept both.
+        if let Some(descriptor) = s.find("desc") {
             handle.typ = AttributeType::Descriptor;
             handle.handle = get_handle(descriptor);
             handle.parent = get_handle(descriptor - 5);
@@ -109,6 +117,20 @@ mod tests {
         );
     }
     #[test]
+    fn test_parse_short_descriptor_handle() {
+        let handle: Handle = "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char0026/desc0028"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            handle,
+            Handle {
+                typ: AttributeType::Descriptor,
+                handle: 0x28_u16,
+                parent: 0x26_u16
+            }
+        );
+    }
+    #[test]
     fn test_parse_characteristic_handle() {
         let handle: Handle = "/org/bluez/hci0/dev_01_02_03_04_05_06/service0025/char0026"
             .parse()
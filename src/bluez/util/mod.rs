@@ -11,18 +11,157 @@
 //
 // Copyright (c) 2014 The Rust Project Developers
 
-use crate::Error;
+use crate::{api::BDAddr, AttError, Error};
+use dbus::arg::{PropMap, RefArg, Variant};
+use std::time::Duration;
+
+/// How long dbus-rs waits for the bus itself to reply to the calls it makes on our behalf, such as
+/// `AddMatch`.
+const BUS_TIMEOUT: Duration = Duration::from_secs(10);
 
 impl From<dbus::Error> for Error {
     fn from(e: dbus::Error) -> Self {
-        match e.name() {
-            // Some("org.freedesktop.DBus.Error.NoReply") => Error::TimedOut(Duration::new(0,0)), // How to express a TimedOut error without a duration?
-            // TODO: translate other dbus errors into relevant btleplug::Error kind
-            _ => Error::Other(format!(
-                "{}: {}",
-                e.name().unwrap_or("Unknown DBus error"),
-                e.message().unwrap_or("Unknown DBus error.")
-            )),
+        from_dbus(e, BUS_TIMEOUT)
+    }
+}
+
+/// Converts the error from a D-Bus method call made through a proxy with the given timeout, so
+/// that a timed out call says how long it waited.
+pub(crate) fn call_error(timeout: Duration) -> impl Fn(dbus::Error) -> Error {
+    move |e| from_dbus(e, timeout)
+}
+
+fn from_dbus(e: dbus::Error, timeout: Duration) -> Error {
+    let name = e.name().unwrap_or("Unknown DBus error");
+    let message = e.message().unwrap_or("Unknown DBus error.");
+    let detail = format!("{}: {}", name, message);
+    match name {
+        "org.bluez.Error.InProgress" => Error::InProgress(detail),
+        "org.bluez.Error.NotPermitted" => Error::NotPermitted(detail),
+        "org.bluez.Error.NotAuthorized" => Error::NotAuthorized(detail),
+        "org.bluez.Error.InvalidOffset" => Error::InvalidOffset(detail),
+        "org.bluez.Error.NotSupported" => Error::NotSupported(detail),
+        "org.bluez.Error.AuthenticationFailed"
+        | "org.bluez.Error.AuthenticationCanceled"
+        | "org.bluez.Error.AuthenticationRejected"
+        | "org.bluez.Error.AuthenticationTimeout" => Error::AuthenticationFailed(detail),
+        "org.bluez.Error.ConnectionAttemptFailed" => Error::ConnectionAttemptFailed(detail),
+        "org.bluez.Error.NotConnected" => Error::NotConnected(detail),
+        "org.bluez.Error.DoesNotExist" => Error::DeviceNotFound(detail),
+        // The object may be an adapter, device or attribute which has gone away.
+        "org.freedesktop.DBus.Error.UnknownObject" => Error::ObjectNotFound(detail),
+        "org.bluez.Error.NotPaired" => Error::NotPaired(detail),
+        "org.freedesktop.DBus.Error.AccessDenied" => Error::PermissionDenied(detail),
+        "org.freedesktop.DBus.Error.NoReply" | "org.freedesktop.DBus.Error.Timeout" => {
+            Error::TimedOut(timeout)
         }
+        "org.bluez.Error.InvalidValueLength" => {
+            Error::Att(AttError::InvalidAttributeValueLength, detail)
+        }
+        // BlueZ reports the ATT errors which it has no name of its own for as failures, with the
+        // error code in the message.
+        "org.bluez.Error.Failed" => match att_error(message) {
+            Some(att_error) => Error::Att(att_error, detail),
+            None => Error::Other(detail),
+        },
+        _ => Error::Other(detail),
+    }
+}
+
+/// Finds the error code in a message like "Operation failed with ATT error: 0x0e".
+fn att_error(message: &str) -> Option<AttError> {
+    let code = message.split("ATT error: 0x").nth(1)?;
+    u8::from_str_radix(code.get(..2)?, 16)
+        .ok()
+        .map(AttError::from)
+}
+
+/// Converts "/org/bluez/hciXX/dev_XX_XX_XX_XX_XX_XX" into "XX:XX:XX:XX:XX:XX".
+pub(crate) fn address_from_device_path(path: &str) -> Option<BDAddr> {
+    path.rsplit('/')
+        .next()
+        .and_then(|name| name.strip_prefix("dev_"))
+        .and_then(|address| address.replace("_", ":").parse().ok())
+}
+
+/// Adds a property to the properties of an object which we export.
+pub(crate) fn insert_property<T: RefArg + 'static>(properties: &mut PropMap, name: &str, value: T) {
+    properties.insert(name.to_string(), Variant(Box::new(value)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(name: &str, message: &str) -> Error {
+        dbus::Error::new_custom(name, message).into()
+    }
+
+    #[test]
+    fn timeouts_say_how_long_the_call_waited() {
+        let timeout = Duration::from_secs(3);
+        let error = dbus::Error::new_custom(
+            "org.freedesktop.DBus.Error.Timeout",
+            "Timeout waiting for reply",
+        );
+        assert!(matches!(call_error(timeout)(error), Error::TimedOut(t) if t == timeout));
+        assert!(matches!(
+            convert(
+                "org.freedesktop.DBus.Error.NoReply",
+                "Did not receive a reply"
+            ),
+            Error::TimedOut(t) if t == BUS_TIMEOUT
+        ));
+    }
+
+    #[test]
+    fn bluez_errors_are_typed() {
+        assert!(matches!(
+            convert("org.bluez.Error.InProgress", "In Progress"),
+            Error::InProgress(detail) if detail == "org.bluez.Error.InProgress: In Progress"
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.AuthenticationCanceled", "Canceled"),
+            Error::AuthenticationFailed(_)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.NotConnected", "Not Connected"),
+            Error::NotConnected(detail) if detail == "org.bluez.Error.NotConnected: Not Connected"
+        ));
+        assert!(matches!(
+            convert("org.freedesktop.DBus.Error.UnknownObject", "Unknown object"),
+            Error::ObjectNotFound(_)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.NotPaired", "Not Paired"),
+            Error::NotPaired(_)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.Frobnicated", "Frobnicated"),
+            Error::Other(detail) if detail.starts_with("org.bluez.Error.Frobnicated")
+        ));
+    }
+
+    #[test]
+    fn att_errors_are_found_in_failures() {
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.Failed",
+                "Operation failed with ATT error: 0x0f"
+            ),
+            Error::Att(AttError::InsufficientEncryption, _)
+        ));
+        assert!(matches!(
+            convert(
+                "org.bluez.Error.Failed",
+                "Operation failed with ATT error: 0x85"
+            ),
+            Error::Att(AttError::Application(0x85), _)
+        ));
+        assert!(matches!(
+            convert("org.bluez.Error.Failed", "Software caused connection abort"),
+            Error::Other(_)
+        ));
+        assert_eq!(AttError::from(0x0c).code(), 0x0c);
     }
 }
@@ -1 +1,3 @@
+pub mod blocking;
+pub mod notifications;
 pub mod util;
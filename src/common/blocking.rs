@@ -0,0 +1,52 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Running the blocking facades, and keeping them off the threads which must never block.
+
+use crate::{Error, Result};
+use std::{cell::Cell, future::Future};
+
+thread_local! {
+    static BLOCKING_FORBIDDEN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as one which must never block, because it delivers the replies which
+/// a blocking call would be waiting for. Backends call this on the threads which run their
+/// notification handlers and signal callbacks.
+pub fn forbid_blocking() {
+    BLOCKING_FORBIDDEN.with(|forbidden| forbidden.set(true));
+}
+
+/// Runs `future` to completion on the current thread. Fails instead of deadlocking if the thread
+/// is one which must never block, such as when a notification handler calls a blocking method.
+pub fn block_on<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    if BLOCKING_FORBIDDEN.with(Cell::get) {
+        return Err(Error::Other(
+            "Blocking calls can't be made from a notification handler or other callback, as the \
+             thread which runs it would wait for itself"
+                .to_string(),
+        ));
+    }
+    futures::executor::block_on(future)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn blocking_is_refused_where_forbidden() {
+        assert_eq!(block_on(async { Ok(1) }).unwrap(), 1);
+        thread::spawn(|| {
+            forbid_blocking();
+            assert!(matches!(block_on(async { Ok(1) }), Err(Error::Other(_))));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+    api::{EventSender, NotificationStream, ValueNotification, DEFAULT_EVENT_BUFFER},
+    Result,
+};
+use futures::lock::Mutex as AsyncMutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+/// The streams of one characteristic, and whether the device is sending its notifications.
+#[derive(Debug)]
+struct Subscription {
+    sender: EventSender<ValueNotification>,
+    /// Held while subscribing or unsubscribing, so that only one request for the characteristic
+    /// is in flight at a time.
+    lock: Arc<AsyncMutex<()>>,
+    /// Whether the device has confirmed that it is sending notifications.
+    notifying: bool,
+    /// Whether the application subscribed explicitly, in which case the device stays subscribed
+    /// after the last stream has been dropped.
+    explicit: bool,
+}
+
+/// Keeps track of the notification streams for each characteristic of a peripheral, and of
+/// whether each characteristic has been subscribed to. `K` is whatever the backend uses to tell
+/// characteristics apart in the notifications it receives.
+#[derive(Debug)]
+pub struct NotificationStreams<K> {
+    subscriptions: Mutex<HashMap<K, Subscription>>,
+}
+
+impl<K: Clone + Eq + Hash + Send + 'static> NotificationStreams<K> {
+    pub fn new() -> Self {
+        NotificationStreams {
+            subscriptions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Creates a new stream for the characteristic `key`, calling `subscribe` first if the device
+    /// isn't sending its notifications yet. Callers which arrive while that is still pending wait
+    /// for it, so no stream is returned before the device has confirmed the subscription. When the
+    /// last stream for the characteristic is dropped, `unsubscribe` will be called, unless the
+    /// application has also subscribed explicitly.
+    pub async fn add<S, F>(
+        self: &Arc<Self>,
+        key: K,
+        subscribe: S,
+        unsubscribe: impl FnOnce() + Send + 'static,
+    ) -> Result<NotificationStream>
+    where
+        S: FnOnce() -> F,
+        F: Future<Output = Result<()>>,
+    {
+        // Registering the stream straight away keeps the characteristic from being forgotten while
+        // this waits its turn.
+        let (events, lock) = {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            let subscription = self.entry(&mut subscriptions, &key);
+            (
+                subscription.sender.subscribe(DEFAULT_EVENT_BUFFER),
+                subscription.lock.clone(),
+            )
+        };
+        {
+            let _guard = lock.lock().await;
+            if !self.notifying(&key) {
+                if let Err(e) = subscribe().await {
+                    // The device never started notifying, so there's nothing to stop.
+                    drop(events);
+                    self.remove_if_unused(&key);
+                    return Err(e);
+                }
+                self.set(&key, |subscription| subscription.notifying = true);
+            }
+        }
+        let streams = self.clone();
+        Ok(NotificationStream::new(events, move || {
+            if streams.remove_if_unused(&key) {
+                unsubscribe();
+            }
+        }))
+    }
+
+    /// Subscribes to the characteristic `key` on behalf of the application, calling `subscribe`
+    /// if the device isn't sending its notifications yet. The device stays subscribed until
+    /// [`unsubscribe`](#method.unsubscribe) is called, even if streams come and go meanwhile.
+    pub async fn subscribe<F>(&self, key: K, subscribe: impl FnOnce() -> F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let lock = self
+            .entry(&mut self.subscriptions.lock().unwrap(), &key)
+            .lock
+            .clone();
+        let _guard = lock.lock().await;
+        if !self.notifying(&key) {
+            if let Err(e) = subscribe().await {
+                self.remove_if_unused(&key);
+                return Err(e);
+            }
+        }
+        self.set(&key, |subscription| {
+            subscription.notifying = true;
+            subscription.explicit = true;
+        });
+        Ok(())
+    }
+
+    /// Withdraws the application's own subscription to the characteristic `key`. `unsubscribe` is
+    /// only called if there are no streams left for the characteristic, as they still need the
+    /// notifications.
+    pub async fn unsubscribe<F>(&self, key: K, unsubscribe: impl FnOnce() -> F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let lock = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .map(|subscription| {
+                subscription.explicit = false;
+                subscription.lock.clone()
+            });
+        let lock = match lock {
+            Some(lock) => lock,
+            // Nothing is known to be subscribed, but the device may know better.
+            None => return unsubscribe().await,
+        };
+        let _guard = lock.lock().await;
+        if self.remove_if_unused(&key) {
+            unsubscribe().await?;
+        }
+        Ok(())
+    }
+
+    /// Sends `notification` to every stream for the characteristic `key`.
+    pub fn dispatch(&self, key: &K, notification: &ValueNotification) {
+        if let Some(subscription) = self.subscriptions.lock().unwrap().get(key) {
+            subscription.sender.send(notification.clone());
+        }
+    }
+
+    fn entry<'a>(
+        &self,
+        subscriptions: &'a mut HashMap<K, Subscription>,
+        key: &K,
+    ) -> &'a mut Subscription {
+        subscriptions
+            .entry(key.clone())
+            .or_insert_with(|| Subscription {
+                sender: EventSender::new(),
+                lock: Arc::new(AsyncMutex::new(())),
+                notifying: false,
+                explicit: false,
+            })
+    }
+
+    fn notifying(&self, key: &K) -> bool {
+        matches!(
+            self.subscriptions.lock().unwrap().get(key),
+            Some(subscription) if subscription.notifying
+        )
+    }
+
+    fn set(&self, key: &K, change: impl FnOnce(&mut Subscription)) {
+        if let Some(subscription) = self.subscriptions.lock().unwrap().get_mut(key) {
+            change(subscription);
+        }
+    }
+
+    /// Forgets about the characteristic `key` if it has no streams left and the application hasn't
+    /// subscribed to it explicitly. Returns whether it did, and the device was notifying, in which
+    /// case the caller should unsubscribe.
+    fn remove_if_unused(&self, key: &K) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        match subscriptions.get(key) {
+            Some(subscription)
+                if !subscription.explicit && !subscription.sender.has_subscribers() =>
+            {
+                subscriptions.remove(key).unwrap().notifying
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use futures::{executor::block_on, future, stream::StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use uuid::Uuid;
+
+    fn notification(value: u8) -> ValueNotification {
+        ValueNotification {
+            uuid: Uuid::nil(),
+            handle: None,
+            value: vec![value],
+        }
+    }
+
+    /// Counts the calls of the closures it makes.
+    #[derive(Default)]
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Counter {
+        fn unsubscribe(&self) -> impl FnOnce() + Send + 'static {
+            let count = self.0.clone();
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn subscribe(&self) -> impl FnOnce() -> future::Ready<Result<()>> {
+            let count = self.0.clone();
+            move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                future::ready(Ok(()))
+            }
+        }
+
+        fn get(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn unsubscribes_when_last_stream_is_dropped() {
+        let streams = Arc::new(NotificationStreams::new());
+        let (subscribed, unsubscribed) = (Counter::default(), Counter::default());
+
+        let first =
+            block_on(streams.add(1, subscribed.subscribe(), unsubscribed.unsubscribe())).unwrap();
+        let mut second =
+            block_on(streams.add(1, subscribed.subscribe(), unsubscribed.unsubscribe())).unwrap();
+        assert_eq!(subscribed.get(), 1);
+
+        drop(first);
+        assert_eq!(unsubscribed.get(), 0);
+        streams.dispatch(&1, &notification(42));
+        streams.dispatch(&2, &notification(43));
+        assert_eq!(block_on(second.next()), Some(notification(42)));
+
+        drop(second);
+        assert_eq!(unsubscribed.get(), 1);
+        let _third =
+            block_on(streams.add(1, subscribed.subscribe(), unsubscribed.unsubscribe())).unwrap();
+        assert_eq!(subscribed.get(), 2);
+    }
+
+    #[test]
+    fn failed_subscription_is_not_unsubscribed() {
+        let streams = Arc::new(NotificationStreams::new());
+        let unsubscribed = Counter::default();
+        let result = block_on(streams.add(
+            1,
+            || future::ready(Err(Error::NotConnected("start_notify".into()))),
+            unsubscribed.unsubscribe(),
+        ));
+        assert!(matches!(result, Err(Error::NotConnected(_))));
+        assert_eq!(unsubscribed.get(), 0);
+        assert!(streams.subscriptions.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn explicit_subscription_outlives_streams() {
+        let streams = Arc::new(NotificationStreams::new());
+        let (subscribed, unsubscribed) = (Counter::default(), Counter::default());
+        block_on(streams.subscribe(1, subscribed.subscribe())).unwrap();
+        let stream =
+            block_on(streams.add(1, subscribed.subscribe(), unsubscribed.unsubscribe())).unwrap();
+        assert_eq!(subscribed.get(), 1);
+
+        drop(stream);
+        assert_eq!(unsubscribed.get(), 0);
+        block_on(streams.unsubscribe(1, || {
+            unsubscribed.unsubscribe()();
+            future::ready(Ok(()))
+        }))
+        .unwrap();
+        assert_eq!(unsubscribed.get(), 1);
+    }
+}
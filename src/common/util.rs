@@ -36,3 +36,13 @@ pub fn invoke_handlers<F: FnMut(ValueNotification) + ?Sized>(
         (*handlers_guard).push(h)
     });
 }
+
+/// Sets `property` to `value`, and returns whether that changed it.
+pub fn set_if_changed<T: PartialEq>(property: &mut T, value: T) -> bool {
+    if *property == value {
+        false
+    } else {
+        *property = value;
+        true
+    }
+}
@@ -1,14 +1,17 @@
 use super::internal::{run_corebluetooth_thread, CoreBluetoothEvent, CoreBluetoothMessage};
 use super::peripheral::Peripheral;
-use crate::api::{AdapterManager, BDAddr, Central, CentralEvent};
+use crate::api::{
+    AdapterManager, AsyncCentral, BDAddr, CentralEvent, DeviceState, EventStream, ExpiryPolicy,
+    DEFAULT_EVENT_BUFFER,
+};
 use crate::Result;
 use async_std::task;
+use async_trait::async_trait;
 use futures::channel::mpsc::{self, Sender};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 use log::info;
 use std::convert::TryInto;
-use std::sync::mpsc::Receiver;
 
 #[derive(Clone, Debug)]
 pub struct Adapter {
@@ -43,17 +46,21 @@ impl Adapter {
                         // TODO Gotta change uuid into a BDAddr for now. Expand
                         // library identifier type. :(
                         let id = uuid_to_bdaddr(&uuid.to_string());
-                        manager_clone.add_peripheral(
+                        // The name is all there is to a discovery, and the new peripheral
+                        // already has it.
+                        manager_clone.alter_peripheral(
                             id,
-                            Peripheral::new(
-                                uuid,
-                                name,
-                                manager_clone.clone(),
-                                event_receiver,
-                                adapter_sender_clone.clone(),
-                            ),
+                            || {
+                                Some(Peripheral::new(
+                                    uuid,
+                                    name,
+                                    manager_clone.clone(),
+                                    event_receiver,
+                                    adapter_sender_clone.clone(),
+                                ))
+                            },
+                            |_, _| false,
                         );
-                        manager_clone.emit(CentralEvent::DeviceDiscovered(id));
                     }
                     /*
                     CoreBluetoothEvent::DeviceUpdated(uuid, name) => {
@@ -61,7 +68,7 @@ impl Adapter {
                         emit(CentralEvent::DeviceUpdated(id));
                     },
                     */
-                    CoreBluetoothEvent::DeviceLost(uuid) => {
+                    CoreBluetoothEvent::DeviceDisconnected(uuid) => {
                         let id = uuid_to_bdaddr(&uuid.to_string());
                         manager_clone.emit(CentralEvent::DeviceDisconnected(id));
                     }
@@ -70,6 +77,25 @@ impl Adapter {
             }
         });
 
+        // CoreBluetooth never reports peripherals as lost, so they are only forgotten when they
+        // expire. The CoreBluetooth thread has to forget them too, or they would never be
+        // discovered again.
+        let mut events = manager.events(DEFAULT_EVENT_BUFFER);
+        let mut expiry_sender = adapter_sender.clone();
+        task::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let CentralEvent::DeviceExpired(id) = event {
+                    if expiry_sender
+                        .send(CoreBluetoothMessage::ForgetDevice(id))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+
         Adapter {
             manager,
             sender: adapter_sender,
@@ -81,27 +107,25 @@ impl Adapter {
     }
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent> {
+        self.manager.events(buffer)
     }
 
-    fn start_scan(&self) -> Result<()> {
+    async fn start_scan(&self) -> Result<()> {
         info!("Starting CoreBluetooth Scan");
-        task::block_on(async {
-            let mut sender = self.sender.clone();
-            sender.send(CoreBluetoothMessage::StartScanning).await?;
-            Ok(())
-        })
+        self.manager.reset_duplicates();
+        let mut sender = self.sender.clone();
+        sender.send(CoreBluetoothMessage::StartScanning).await?;
+        Ok(())
     }
 
-    fn stop_scan(&self) -> Result<()> {
+    async fn stop_scan(&self) -> Result<()> {
         info!("Stopping CoreBluetooth Scan");
-        task::block_on(async {
-            let mut sender = self.sender.clone();
-            sender.send(CoreBluetoothMessage::StopScanning).await?;
-            Ok(())
-        })
+        let mut sender = self.sender.clone();
+        sender.send(CoreBluetoothMessage::StopScanning).await?;
+        Ok(())
     }
 
     fn peripherals(&self) -> Vec<Peripheral> {
@@ -112,7 +136,17 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        self.manager.peripheral_state(address)
+    }
+
+    fn expiry_policy(&self, policy: ExpiryPolicy) {
+        self.manager.set_expiry_policy(policy);
+    }
+
     fn active(&self, _enabled: bool) {}
 
-    fn filter_duplicates(&self, _enabled: bool) {}
+    fn filter_duplicates(&self, enabled: bool) {
+        self.manager.set_filter_duplicates(enabled);
+    }
 }
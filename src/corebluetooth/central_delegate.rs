@@ -50,14 +50,15 @@ pub enum CentralDelegateEvent {
     ServiceData(Uuid, HashMap<Uuid, Vec<u8>>),
     Services(Uuid, Vec<Uuid>),
     // DiscoveredIncludedServices(Uuid, HashMap<Uuid, StrongPtr>),
-    // Peripheral UUID, HashMap Characteristic Uuid to StrongPtr
-    DiscoveredCharacteristics(Uuid, HashMap<Uuid, StrongPtr>),
+    // Peripheral UUID, Service UUID, HashMap Characteristic Uuid to StrongPtr
+    DiscoveredCharacteristics(Uuid, Uuid, HashMap<Uuid, StrongPtr>),
     ConnectedDevice(Uuid),
     DisconnectedDevice(Uuid),
-    CharacteristicSubscribed(Uuid, Uuid),
-    CharacteristicUnsubscribed(Uuid, Uuid),
-    CharacteristicNotified(Uuid, Uuid, Vec<u8>),
-    CharacteristicWritten(Uuid, Uuid),
+    // Peripheral UUID, Service UUID, Characteristic UUID
+    CharacteristicSubscribed(Uuid, Uuid, Uuid),
+    CharacteristicUnsubscribed(Uuid, Uuid, Uuid),
+    CharacteristicNotified(Uuid, Uuid, Uuid, Vec<u8>),
+    CharacteristicWritten(Uuid, Uuid, Uuid),
     // TODO Deal with descriptors at some point, but not a huge worry at the moment.
     // DiscoveredDescriptors(String, )
 }
@@ -75,9 +76,14 @@ impl Debug for CentralDelegateEvent {
                 .field(uuid)
                 .field(&services.keys().collect::<Vec<_>>())
                 .finish(),
-            CentralDelegateEvent::DiscoveredCharacteristics(uuid, characteristics) => f
+            CentralDelegateEvent::DiscoveredCharacteristics(
+                uuid,
+                service_uuid,
+                characteristics,
+            ) => f
                 .debug_tuple("DiscoveredCharacteristics")
                 .field(uuid)
+                .field(service_uuid)
                 .field(&characteristics.keys().collect::<Vec<_>>())
                 .finish(),
             CentralDelegateEvent::ConnectedDevice(uuid) => {
@@ -86,26 +92,30 @@ impl Debug for CentralDelegateEvent {
             CentralDelegateEvent::DisconnectedDevice(uuid) => {
                 f.debug_tuple("DisconnectedDevice").field(uuid).finish()
             }
-            CentralDelegateEvent::CharacteristicSubscribed(uuid1, uuid2) => f
+            CentralDelegateEvent::CharacteristicSubscribed(uuid1, uuid2, uuid3) => f
                 .debug_tuple("CharacteristicSubscribed")
                 .field(uuid1)
                 .field(uuid2)
+                .field(uuid3)
                 .finish(),
-            CentralDelegateEvent::CharacteristicUnsubscribed(uuid1, uuid2) => f
+            CentralDelegateEvent::CharacteristicUnsubscribed(uuid1, uuid2, uuid3) => f
                 .debug_tuple("CharacteristicUnsubscribed")
                 .field(uuid1)
                 .field(uuid2)
+                .field(uuid3)
                 .finish(),
-            CentralDelegateEvent::CharacteristicNotified(uuid1, uuid2, vec) => f
+            CentralDelegateEvent::CharacteristicNotified(uuid1, uuid2, uuid3, vec) => f
                 .debug_tuple("CharacteristicNotified")
                 .field(uuid1)
                 .field(uuid2)
+                .field(uuid3)
                 .field(vec)
                 .finish(),
-            CentralDelegateEvent::CharacteristicWritten(uuid1, uuid2) => f
+            CentralDelegateEvent::CharacteristicWritten(uuid1, uuid2, uuid3) => f
                 .debug_tuple("CharacteristicWritten")
                 .field(uuid1)
                 .field(uuid2)
+                .field(uuid3)
                 .finish(),
             CentralDelegateEvent::ManufacturerData(uuid, manufacturer_id, manufacturer_data) => f
                 .debug_tuple("ManufacturerData")
@@ -268,6 +278,10 @@ pub mod CentralDelegate {
         delegate
     }
 
+    fn characteristic_service_uuid(characteristic: *mut Object) -> Uuid {
+        cbuuid_to_uuid(cb::attribute_uuid(cb::characteristic_service(characteristic)))
+    }
+
     fn get_characteristic_value(characteristic: *mut Object) -> Vec<u8> {
         trace!("Getting data!");
         let value = cb::characteristic_value(characteristic);
@@ -522,9 +536,10 @@ pub mod CentralDelegate {
             }
             let puuid_nsstring = ns::uuid_uuidstring(cb::peer_identifier(peripheral));
             let puuid = Uuid::from_str(&NSStringUtils::string_to_string(puuid_nsstring)).unwrap();
+            let service_uuid = cbuuid_to_uuid(cb::attribute_uuid(service));
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::DiscoveredCharacteristics(puuid, char_map),
+                CentralDelegateEvent::DiscoveredCharacteristics(puuid, service_uuid, char_map),
             );
         }
     }
@@ -546,10 +561,16 @@ pub mod CentralDelegate {
             let v = get_characteristic_value(characteristic);
             let puuid_nsstring = ns::uuid_uuidstring(cb::peer_identifier(peripheral));
             let puuid = Uuid::from_str(&NSStringUtils::string_to_string(puuid_nsstring)).unwrap();
+            let service_uuid = characteristic_service_uuid(characteristic);
             let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(characteristic));
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::CharacteristicNotified(puuid, characteristic_uuid, v),
+                CentralDelegateEvent::CharacteristicNotified(
+                    puuid,
+                    service_uuid,
+                    characteristic_uuid,
+                    v,
+                ),
             );
             // Notify BluetoothGATTCharacteristic::read_value that read was successful.
         }
@@ -571,10 +592,15 @@ pub mod CentralDelegate {
         if error == nil {
             let puuid_nsstring = ns::uuid_uuidstring(cb::peer_identifier(peripheral));
             let puuid = Uuid::from_str(&NSStringUtils::string_to_string(puuid_nsstring)).unwrap();
+            let service_uuid = characteristic_service_uuid(characteristic);
             let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(characteristic));
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::CharacteristicWritten(puuid, characteristic_uuid),
+                CentralDelegateEvent::CharacteristicWritten(
+                    puuid,
+                    service_uuid,
+                    characteristic_uuid,
+                ),
             );
         }
     }
@@ -590,16 +616,25 @@ pub mod CentralDelegate {
         // TODO check for error here
         let puuid_nsstring = ns::uuid_uuidstring(cb::peer_identifier(peripheral));
         let puuid = Uuid::from_str(&NSStringUtils::string_to_string(puuid_nsstring)).unwrap();
+        let service_uuid = characteristic_service_uuid(characteristic);
         let characteristic_uuid = cbuuid_to_uuid(cb::attribute_uuid(characteristic));
         if cb::characteristic_isnotifying(characteristic) == objc::runtime::YES {
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::CharacteristicSubscribed(puuid, characteristic_uuid),
+                CentralDelegateEvent::CharacteristicSubscribed(
+                    puuid,
+                    service_uuid,
+                    characteristic_uuid,
+                ),
             );
         } else {
             send_delegate_event(
                 delegate,
-                CentralDelegateEvent::CharacteristicUnsubscribed(puuid, characteristic_uuid),
+                CentralDelegateEvent::CharacteristicUnsubscribed(
+                    puuid,
+                    service_uuid,
+                    characteristic_uuid,
+                ),
             );
         }
     }
@@ -440,6 +440,7 @@ pub mod cb {
 
     // CBPeripheralState = NSInteger from CBPeripheral.h
 
+    pub const PERIPHERALSTATE_DISCONNECTED: c_int = 0; // CBPeripheralStateDisconnected
     pub const PERIPHERALSTATE_CONNECTED: c_int = 2; // CBPeripheralStateConnected
 
     // CBAttribute
@@ -492,6 +493,13 @@ pub mod cb {
         }
     }
 
+    pub fn characteristic_service(cbcharacteristic: *mut Object) -> *mut Object /* CBService* */ {
+        unsafe {
+            let service: *mut Object = msg_send![cbcharacteristic, service];
+            service
+        }
+    }
+
     pub fn characteristic_properties(cbcharacteristic: *mut Object) -> c_uint {
         unsafe {
             let properties: c_uint = msg_send![cbcharacteristic, properties];
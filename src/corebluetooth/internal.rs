@@ -9,12 +9,13 @@
 // multiple), see https://forums.developer.apple.com/thread/20810
 
 use super::{
+    adapter::uuid_to_bdaddr,
     central_delegate::{CentralDelegate, CentralDelegateEvent},
     framework::{cb, ns},
     future::{BtlePlugFuture, BtlePlugFutureStateShared},
     utils::{CoreBluetoothUtils, NSStringUtils},
 };
-use crate::api::{CharPropFlags, Characteristic, WriteType};
+use crate::api::{BDAddr, CharPropFlags, Characteristic, Service, WriteType};
 use async_std::task;
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::select;
@@ -107,7 +108,7 @@ impl CBCharacteristic {
 #[derive(Clone, Debug)]
 pub enum CoreBluetoothReply {
     ReadResult(Vec<u8>),
-    Connected(BTreeSet<Characteristic>),
+    Connected(BTreeSet<Service>),
     Disconnected,
     Ok,
     Err(String),
@@ -116,7 +117,8 @@ pub enum CoreBluetoothReply {
 #[derive(Debug)]
 pub enum CBPeripheralEvent {
     Disconnected,
-    Notification(Uuid, Vec<u8>),
+    // service uuid, characteristic uuid, value
+    Notification(Uuid, Uuid, Vec<u8>),
     ManufacturerData(u16, Vec<u8>),
     ServiceData(HashMap<Uuid, Vec<u8>>),
     Services(Vec<Uuid>),
@@ -128,7 +130,11 @@ pub type CoreBluetoothReplyFuture = BtlePlugFuture<CoreBluetoothReply>;
 struct CBPeripheral {
     pub peripheral: StrongPtr,
     services: HashMap<Uuid, StrongPtr>,
-    pub characteristics: HashMap<Uuid, CBCharacteristic>,
+    // Service UUID to the UUIDs of its characteristics
+    service_characteristics: HashMap<Uuid, BTreeSet<Uuid>>,
+    // Keyed by service UUID and characteristic UUID, as several services may have a
+    // characteristic with the same UUID.
+    pub characteristics: HashMap<(Uuid, Uuid), CBCharacteristic>,
     pub event_sender: Sender<CBPeripheralEvent>,
     pub connected_future_state: Option<CoreBluetoothReplyStateShared>,
     pub disconnected_future_state: Option<CoreBluetoothReplyStateShared>,
@@ -158,6 +164,7 @@ impl CBPeripheral {
         Self {
             peripheral,
             services: HashMap::new(),
+            service_characteristics: HashMap::new(),
             characteristics: HashMap::new(),
             event_sender,
             connected_future_state: None,
@@ -167,14 +174,31 @@ impl CBPeripheral {
         }
     }
 
+    /// Forgets what was found while connected, so that connecting again discovers it afresh.
+    pub fn clear_connection(&mut self) {
+        self.services.clear();
+        self.service_characteristics.clear();
+        self.characteristics.clear();
+        self.connected_state_sent = false;
+        self.characteristic_update_count = 0;
+    }
+
     pub fn set_services(&mut self, services: HashMap<Uuid, StrongPtr>) {
         self.services = services;
     }
 
-    pub fn set_characteristics(&mut self, characteristics: HashMap<Uuid, StrongPtr>) {
+    pub fn set_characteristics(
+        &mut self,
+        service_uuid: Uuid,
+        characteristics: HashMap<Uuid, StrongPtr>,
+    ) {
+        self.service_characteristics
+            .entry(service_uuid)
+            .or_default()
+            .extend(characteristics.keys());
         for (c_uuid, c_obj) in characteristics {
             self.characteristics
-                .insert(c_uuid, CBCharacteristic::new(c_obj));
+                .insert((service_uuid, c_uuid), CBCharacteristic::new(c_obj));
         }
         // It's time for QUESTIONABLE ASSUMPTIONS.
         //
@@ -183,31 +207,45 @@ impl CBPeripheral {
         // set_characteristics should be called once for every entry in the
         // service map. Once that's done, we're filled out enough and can send
         // back a Connected reply to the waiting future with all of the
-        // characteristic info in it.
+        // service and characteristic info in it.
         self.characteristic_update_count += 1;
         if self.characteristic_update_count == (self.services.len() as u32) {
             if self.connected_future_state.is_none() {
                 panic!("We should still have a future at this point!");
             }
-            let mut char_set = BTreeSet::new();
-            for (&uuid, c) in &self.characteristics {
-                let char = Characteristic {
-                    // We can't get handles on macOS, just set them to 0.
-                    start_handle: 0,
-                    end_handle: 0,
-                    value_handle: 0,
-                    uuid,
-                    properties: c.properties,
-                };
-                trace!("{:?}", char.uuid);
-                char_set.insert(char);
+            let mut service_set = BTreeSet::new();
+            for (&service_uuid, char_uuids) in &self.service_characteristics {
+                let mut char_set = BTreeSet::new();
+                for uuid in char_uuids {
+                    let c = &self.characteristics[&(service_uuid, *uuid)];
+                    let char = Characteristic {
+                        // We can't get handles on macOS, just set them to 0.
+                        start_handle: 0,
+                        end_handle: 0,
+                        value_handle: 0,
+                        uuid: *uuid,
+                        service_uuid,
+                        properties: c.properties,
+                        descriptors: BTreeSet::new(),
+                    };
+                    trace!("{:?}", char.uuid);
+                    char_set.insert(char);
+                }
+                service_set.insert(Service {
+                    uuid: service_uuid,
+                    // Included services are only found through their including service, so
+                    // anything not in the service map isn't primary.
+                    primary: self.services.contains_key(&service_uuid),
+                    included_services: Vec::new(),
+                    characteristics: char_set,
+                });
             }
             self.connected_future_state
                 .take()
                 .unwrap()
                 .lock()
                 .unwrap()
-                .set_reply(CoreBluetoothReply::Connected(char_set));
+                .set_reply(CoreBluetoothReply::Connected(service_set));
             self.connected_state_sent = true;
         }
     }
@@ -248,20 +286,23 @@ pub enum CoreBluetoothMessage {
     StopScanning,
     ConnectDevice(Uuid, CoreBluetoothReplyStateShared),
     DisconnectDevice(Uuid, CoreBluetoothReplyStateShared),
-    // device uuid, characteristic uuid, future
-    ReadValue(Uuid, Uuid, CoreBluetoothReplyStateShared),
-    // device uuid, characteristic uuid, data, kind, future
+    // device uuid, service uuid, characteristic uuid, future
+    ReadValue(Uuid, Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, service uuid, characteristic uuid, data, kind, future
     WriteValue(
+        Uuid,
         Uuid,
         Uuid,
         Vec<u8>,
         WriteType,
         CoreBluetoothReplyStateShared,
     ),
-    // device uuid, characteristic uuid, future
-    Subscribe(Uuid, Uuid, CoreBluetoothReplyStateShared),
-    // device uuid, characteristic uuid, future
-    Unsubscribe(Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, service uuid, characteristic uuid, future
+    Subscribe(Uuid, Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // device uuid, service uuid, characteristic uuid, future
+    Unsubscribe(Uuid, Uuid, Uuid, CoreBluetoothReplyStateShared),
+    // address of a peripheral which has expired
+    ForgetDevice(BDAddr),
 }
 
 #[derive(Debug)]
@@ -271,7 +312,7 @@ pub enum CoreBluetoothEvent {
     DeviceDiscovered(Uuid, Option<String>, Receiver<CBPeripheralEvent>),
     DeviceUpdated(Uuid, String),
     // identifier
-    DeviceLost(Uuid),
+    DeviceDisconnected(Uuid),
 }
 
 impl CoreBluetoothInternal {
@@ -395,6 +436,7 @@ impl CoreBluetoothInternal {
     fn on_discovered_characteristics(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         char_map: HashMap<Uuid, StrongPtr>,
     ) {
         info!("Found chars!");
@@ -405,7 +447,7 @@ impl CoreBluetoothInternal {
             if p.connected_state_sent {
                 error!("Characteristic discovery message after connection!");
             } else {
-                p.set_characteristics(char_map);
+                p.set_characteristics(service_uuid, char_map);
             }
         }
     }
@@ -416,19 +458,32 @@ impl CoreBluetoothInternal {
     }
 
     async fn on_peripheral_disconnect(&mut self, peripheral_uuid: Uuid) {
+        // The peripheral is kept, as CoreBluetooth can connect to it again without discovering it
+        // again.
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
             if let Some(fut) = p.disconnected_future_state.take() {
                 fut.lock().unwrap().set_reply(CoreBluetoothReply::Disconnected);
             }
+            p.clear_connection();
         }
-        self.peripherals.remove(&peripheral_uuid);
-        self.dispatch_event(CoreBluetoothEvent::DeviceLost(peripheral_uuid))
+        self.dispatch_event(CoreBluetoothEvent::DeviceDisconnected(peripheral_uuid))
             .await;
     }
 
-    fn on_characteristic_subscribed(&mut self, peripheral_uuid: Uuid, characteristic_uuid: Uuid) {
+    fn forget_peripheral(&mut self, address: BDAddr) {
+        // Dropping the event sender also stops the task of any Peripheral still held for it.
+        self.peripherals
+            .retain(|uuid, _| uuid_to_bdaddr(&uuid.to_string()) != address);
+    }
+
+    fn on_characteristic_subscribed(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 trace!("Got subscribed event!");
                 let state = c.subscribe_future_state.pop_back().unwrap();
                 state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
@@ -436,9 +491,14 @@ impl CoreBluetoothInternal {
         }
     }
 
-    fn on_characteristic_unsubscribed(&mut self, peripheral_uuid: Uuid, characteristic_uuid: Uuid) {
+    fn on_characteristic_unsubscribed(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 trace!("Got unsubscribed event!");
                 let state = c.unsubscribe_future_state.pop_back().unwrap();
                 state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
@@ -449,11 +509,12 @@ impl CoreBluetoothInternal {
     async fn on_characteristic_read(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         characteristic_uuid: Uuid,
         data: Vec<u8>,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 trace!("Got read event!");
 
                 let mut data_clone = Vec::new();
@@ -473,7 +534,11 @@ impl CoreBluetoothInternal {
                 } else {
                     if let Err(e) = p
                         .event_sender
-                        .send(CBPeripheralEvent::Notification(characteristic_uuid, data))
+                        .send(CBPeripheralEvent::Notification(
+                            service_uuid,
+                            characteristic_uuid,
+                            data,
+                        ))
                         .await
                     {
                         error!("Error sending notification event: {}", e);
@@ -483,9 +548,14 @@ impl CoreBluetoothInternal {
         }
     }
 
-    fn on_characteristic_written(&mut self, peripheral_uuid: Uuid, characteristic_uuid: Uuid) {
+    fn on_characteristic_written(
+        &mut self,
+        peripheral_uuid: Uuid,
+        service_uuid: Uuid,
+        characteristic_uuid: Uuid,
+    ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 trace!("Got written event!");
                 let state = c.write_future_state.pop_back().unwrap();
                 state.lock().unwrap().set_reply(CoreBluetoothReply::Ok);
@@ -504,7 +574,11 @@ impl CoreBluetoothInternal {
 
     fn disconnect_peripheral(&mut self, peripheral_uuid: Uuid, fut: CoreBluetoothReplyStateShared) {
         trace!("Trying to disconnect peripheral!");
-        if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
+        let peripheral = self
+            .peripherals
+            .get_mut(&peripheral_uuid)
+            .filter(|p| cb::peripheral_state(*p.peripheral) != cb::PERIPHERALSTATE_DISCONNECTED);
+        if let Some(p) = peripheral {
             trace!("Disconnecting peripheral!");
             p.disconnected_future_state = Some(fut);
             cb::centralmanager_cancelperipheralconnection(*self.manager, *p.peripheral);
@@ -517,13 +591,14 @@ impl CoreBluetoothInternal {
     fn write_value(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         characteristic_uuid: Uuid,
         data: Vec<u8>,
         kind: WriteType,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 info!("Writing value! With kind {:?}", kind);
                 cb::peripheral_writevalue_forcharacteristic(
                     *p.peripheral,
@@ -542,11 +617,12 @@ impl CoreBluetoothInternal {
     fn read_value(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         characteristic_uuid: Uuid,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 info!("Reading value!");
                 cb::peripheral_readvalue_forcharacteristic(*p.peripheral, *c.characteristic);
                 c.read_future_state.push_front(fut);
@@ -557,11 +633,12 @@ impl CoreBluetoothInternal {
     fn subscribe(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         characteristic_uuid: Uuid,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 info!("Setting subscribe!");
                 cb::peripheral_setnotifyvalue_forcharacteristic(
                     *p.peripheral,
@@ -576,11 +653,12 @@ impl CoreBluetoothInternal {
     fn unsubscribe(
         &mut self,
         peripheral_uuid: Uuid,
+        service_uuid: Uuid,
         characteristic_uuid: Uuid,
         fut: CoreBluetoothReplyStateShared,
     ) {
         if let Some(p) = self.peripherals.get_mut(&peripheral_uuid) {
-            if let Some(c) = p.characteristics.get_mut(&characteristic_uuid) {
+            if let Some(c) = p.characteristics.get_mut(&(service_uuid, characteristic_uuid)) {
                 info!("Setting subscribe!");
                 cb::peripheral_setnotifyvalue_forcharacteristic(
                     *p.peripheral,
@@ -611,9 +689,11 @@ impl CoreBluetoothInternal {
                     CentralDelegateEvent::DiscoveredServices(peripheral_id, service_map) => {
                         self.on_discovered_services(peripheral_id, service_map)
                     }
-                    CentralDelegateEvent::DiscoveredCharacteristics(peripheral_id, char_map) => {
-                        self.on_discovered_characteristics(peripheral_id, char_map)
-                    }
+                    CentralDelegateEvent::DiscoveredCharacteristics(
+                        peripheral_id,
+                        service_id,
+                        char_map,
+                    ) => self.on_discovered_characteristics(peripheral_id, service_id, char_map),
                     CentralDelegateEvent::ConnectedDevice(peripheral_id) => {
                         self.on_peripheral_connect(peripheral_id)
                     }
@@ -622,21 +702,25 @@ impl CoreBluetoothInternal {
                     }
                     CentralDelegateEvent::CharacteristicSubscribed(
                         peripheral_id,
+                        service_id,
                         characteristic_id,
-                    ) => self.on_characteristic_subscribed(peripheral_id, characteristic_id),
+                    ) => self.on_characteristic_subscribed(peripheral_id, service_id, characteristic_id),
                     CentralDelegateEvent::CharacteristicUnsubscribed(
                         peripheral_id,
+                        service_id,
                         characteristic_id,
-                    ) => self.on_characteristic_unsubscribed(peripheral_id, characteristic_id),
+                    ) => self.on_characteristic_unsubscribed(peripheral_id, service_id, characteristic_id),
                     CentralDelegateEvent::CharacteristicNotified(
                         peripheral_id,
+                        service_id,
                         characteristic_id,
                         data,
-                    ) => self.on_characteristic_read(peripheral_id, characteristic_id, data).await,
+                    ) => self.on_characteristic_read(peripheral_id, service_id, characteristic_id, data).await,
                     CentralDelegateEvent::CharacteristicWritten(
                         peripheral_id,
+                        service_id,
                         characteristic_id,
-                    ) => self.on_characteristic_written(peripheral_id, characteristic_id),
+                    ) => self.on_characteristic_written(peripheral_id, service_id, characteristic_id),
                     CentralDelegateEvent::ManufacturerData(peripheral_id, manufacturer_id, manufacturer_data) => {
                         self.on_manufacturer_data(peripheral_id, manufacturer_id, manufacturer_data).await
                     },
@@ -661,22 +745,24 @@ impl CoreBluetoothInternal {
                         info!("got disconnectdevice msg!");
                         self.disconnect_peripheral(peripheral_uuid, fut);
                     }
-                    CoreBluetoothMessage::ReadValue(peripheral_uuid, char_uuid, fut) => {
-                        self.read_value(peripheral_uuid, char_uuid, fut)
+                    CoreBluetoothMessage::ReadValue(peripheral_uuid, service_uuid, char_uuid, fut) => {
+                        self.read_value(peripheral_uuid, service_uuid, char_uuid, fut)
                     }
                     CoreBluetoothMessage::WriteValue(
                         peripheral_uuid,
+                        service_uuid,
                         char_uuid,
                         data,
                         kind,
                         fut,
-                    ) => self.write_value(peripheral_uuid, char_uuid, data, kind, fut),
-                    CoreBluetoothMessage::Subscribe(peripheral_uuid, char_uuid, fut) => {
-                        self.subscribe(peripheral_uuid, char_uuid, fut)
+                    ) => self.write_value(peripheral_uuid, service_uuid, char_uuid, data, kind, fut),
+                    CoreBluetoothMessage::Subscribe(peripheral_uuid, service_uuid, char_uuid, fut) => {
+                        self.subscribe(peripheral_uuid, service_uuid, char_uuid, fut)
                     }
-                    CoreBluetoothMessage::Unsubscribe(peripheral_uuid, char_uuid, fut) => {
-                        self.unsubscribe(peripheral_uuid, char_uuid, fut)
+                    CoreBluetoothMessage::Unsubscribe(peripheral_uuid, service_uuid, char_uuid, fut) => {
+                        self.unsubscribe(peripheral_uuid, service_uuid, char_uuid, fut)
                     }
+                    CoreBluetoothMessage::ForgetDevice(address) => self.forget_peripheral(address),
                 };
             }
         }
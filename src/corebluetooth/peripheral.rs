@@ -13,13 +13,15 @@ use super::{
 };
 use crate::{
     api::{
-        AdapterManager, AddressType, BDAddr, CentralEvent, Characteristic, NotificationHandler,
-        Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification, WriteType,
+        AdapterManager, AddressType, AsyncPeripheral, BDAddr, CentralEvent, Characteristic,
+        Descriptor, NotificationHandler, NotificationStream, PeripheralProperties, Service,
+        ValueNotification, WriteType,
     },
-    common::util,
+    common::{notifications::NotificationStreams, util},
     Error, Result,
 };
 use async_std::task;
+use async_trait::async_trait;
 use futures::channel::mpsc::{Receiver, SendError, Sender};
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
@@ -35,9 +37,12 @@ use uuid::Uuid;
 #[derive(Clone)]
 pub struct Peripheral {
     notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    // Keyed by service UUID and characteristic UUID.
+    notification_streams: Arc<NotificationStreams<(Uuid, Uuid)>>,
     manager: AdapterManager<Self>,
     uuid: Uuid,
     characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    services: Arc<Mutex<BTreeSet<Service>>>,
     pub(crate) properties: Arc<Mutex<PeripheralProperties>>,
     message_sender: Sender<CoreBluetoothMessage>,
     // We're not actually holding a peripheral object here, that's held out in
@@ -62,29 +67,40 @@ impl Peripheral {
             address_type: AddressType::Random,
             local_name: local_name,
             tx_power_level: None,
+            rssi: None,
             manufacturer_data: HashMap::new(),
             service_data: HashMap::new(),
             services: Vec::new(),
             discovery_count: 1,
             has_scan_response: true,
+            alias: None,
+            appearance: None,
+            class: None,
+            icon: None,
+            modalias: None,
+            legacy_pairing: false,
+            paired: false,
+            trusted: false,
+            blocked: false,
         }));
         let notification_handlers = Arc::new(Mutex::new(Vec::<NotificationHandler>::new()));
         let nh_clone = notification_handlers.clone();
+        let notification_streams = Arc::new(NotificationStreams::new());
+        let ns_clone = notification_streams.clone();
         let p_clone = properties.clone();
         let m_clone = manager.clone();
         task::spawn(async move {
             let mut event_receiver = event_receiver;
             loop {
                 match event_receiver.next().await {
-                    Some(CBPeripheralEvent::Notification(uuid, data)) => {
-                        util::invoke_handlers(
-                            &nh_clone,
-                            &ValueNotification {
-                                uuid,
-                                handle: None,
-                                value: data,
-                            },
-                        );
+                    Some(CBPeripheralEvent::Notification(service_uuid, uuid, data)) => {
+                        let notification = ValueNotification {
+                            uuid,
+                            handle: None,
+                            value: data,
+                        };
+                        ns_clone.dispatch(&(service_uuid, uuid), &notification);
+                        util::invoke_handlers(&nh_clone, &notification);
                     }
                     Some(CBPeripheralEvent::ManufacturerData(manufacturer_id, data)) => {
                         let mut properties = p_clone.lock().unwrap();
@@ -130,7 +146,9 @@ impl Peripheral {
             properties,
             manager,
             characteristics: Arc::new(Mutex::new(BTreeSet::new())),
+            services: Arc::new(Mutex::new(BTreeSet::new())),
             notification_handlers,
+            notification_streams,
             uuid,
             message_sender,
         }
@@ -140,6 +158,45 @@ impl Peripheral {
         debug!("emitted {:?}", event);
         self.manager.emit(event)
     }
+
+    /// Asks CoreBluetooth to start notifying, regardless of who else wants the notifications.
+    async fn start_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        info!("Trying to subscribe!");
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::Subscribe(
+                self.uuid,
+                characteristic.service_uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => info!("subscribed!"),
+            _ => panic!("Didn't subscribe!"),
+        }
+        Ok(())
+    }
+
+    async fn stop_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        info!("Trying to unsubscribe!");
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::Unsubscribe(
+                self.uuid,
+                characteristic.service_uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            _ => panic!("Didn't unsubscribe!"),
+        }
+        Ok(())
+    }
 }
 
 impl Display for Peripheral {
@@ -163,7 +220,8 @@ impl Debug for Peripheral {
     }
 }
 
-impl ApiPeripheral for Peripheral {
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr {
         self.properties.lock().unwrap().address
@@ -181,66 +239,84 @@ impl ApiPeripheral for Peripheral {
         self.characteristics.lock().unwrap().clone()
     }
 
+    /// The set of services we've discovered for this device. This will be empty until
+    /// `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service> {
+        self.services.lock().unwrap().clone()
+    }
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool {
         false
     }
 
-    /// Creates a connection to the device. This is a synchronous operation; if this method returns
-    /// Ok there has been successful connection. Note that peripherals allow only one connection at
-    /// a time. Operations that attempt to communicate with a device will fail until it is connected.
-    fn connect(&self) -> Result<()> {
+    /// Creates a connection to the device. If the returned future resolves to Ok there has been a
+    /// successful connection. Note that peripherals allow only one connection at a time.
+    /// Operations that attempt to communicate with a device will fail until it is connected.
+    async fn connect(&self) -> Result<()> {
         info!("Trying device connect!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::ConnectDevice(
-                    self.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Connected(chars) => {
-                    *(self.characteristics.lock().unwrap()) = chars;
-                    self.emit(CentralEvent::DeviceConnected(
-                        self.properties.lock().unwrap().address,
-                    ));
-                }
-                _ => panic!("Shouldn't get anything but connected!"),
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::ConnectDevice(
+                self.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Connected(services) => {
+                *(self.characteristics.lock().unwrap()) = services
+                    .iter()
+                    .flat_map(|service| service.characteristics.iter().cloned())
+                    .collect();
+                *(self.services.lock().unwrap()) = services;
+                self.emit(CentralEvent::DeviceConnected(
+                    self.properties.lock().unwrap().address,
+                ));
             }
-            info!("Device connected!");
-            Ok(())
-        })
+            _ => panic!("Shouldn't get anything but connected!"),
+        }
+        info!("Device connected!");
+        Ok(())
     }
 
-    /// Terminates a connection to the device. This is a synchronous operation.
-    fn disconnect(&self) -> Result<()> {
+    /// Terminates a connection to the device.
+    async fn disconnect(&self) -> Result<()> {
         info!("Trying device disconnect!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::DisconnectDevice(
-                    self.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Disconnected => {
-                    self.emit(CentralEvent::DeviceDisconnected(
-                        self.properties.lock().unwrap().address,
-                    ));
-                }
-                _ => panic!("Shouldn't get anything but disconnected!"),
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::DisconnectDevice(
+                self.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Disconnected => {
+                self.emit(CentralEvent::DeviceDisconnected(
+                    self.properties.lock().unwrap().address,
+                ));
             }
-            info!("Device disconnected!");
-            Ok(())
-        })
+            _ => panic!("Shouldn't get anything but disconnected!"),
+        }
+        info!("Device disconnected!");
+        Ok(())
+    }
+
+    async fn pair(&self) -> Result<()> {
+        Err(Error::NotSupported("pair".into()))
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        Err(Error::NotSupported("unpair".into()))
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        Err(Error::NotSupported("is_paired".into()))
     }
 
-    /// Discovers all characteristics for the device. This is a synchronous operation.
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+    /// Discovers all characteristics for the device.
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
         let chrs = self.characteristics.lock().unwrap().clone();
         let v = Vec::from_iter(chrs.into_iter());
         Ok(v)
@@ -248,82 +324,63 @@ impl ApiPeripheral for Peripheral {
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
     /// the case of a write-with-response) if the device returns an error.
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::WriteValue(
-                    self.uuid,
-                    characteristic.uuid,
-                    Vec::from(data),
-                    write_type,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => {}
-                reply => panic!("Unexpected reply: {:?}", reply),
-            }
-            Ok(())
-        })
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::WriteValue(
+                self.uuid,
+                characteristic.service_uuid,
+                characteristic.uuid,
+                Vec::from(data),
+                write_type,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::Ok => {}
+            reply => panic!("Unexpected reply: {:?}", reply),
+        }
+        Ok(())
     }
 
     /// Sends a read-by-type request to device for the range of handles covered by the
     /// characteristic and for the specified declaration UUID. See
     /// [here](https://www.bluetooth.com/specifications/gatt/declarations) for valid UUIDs.
-    /// Synchronously returns either an error or the device response.
-    fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+    /// Resolves to either an error or the device response.
+    async fn read_by_type(&self, _characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
         Err(Error::NotSupported("read_by_type".into()))
     }
 
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        Err(Error::NotSupported("read_descriptor".into()))
+    }
+
+    async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+        Err(Error::NotSupported("write_descriptor".into()))
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        info!("Trying to subscribe!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::Subscribe(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => info!("subscribed!"),
-                _ => panic!("Didn't subscribe!"),
-            }
-            Ok(())
-        })
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .subscribe((characteristic.service_uuid, characteristic.uuid), || {
+                self.start_notify(characteristic)
+            })
+            .await
     }
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        info!("Trying to unsubscribe!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::Unsubscribe(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::Ok => {}
-                _ => panic!("Didn't unsubscribe!"),
-            }
-            Ok(())
-        })
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .unsubscribe((characteristic.service_uuid, characteristic.uuid), || {
+                self.stop_notify(characteristic)
+            })
+            .await
     }
 
     /// Registers a handler that will be called when value notification messages are received from
@@ -334,25 +391,43 @@ impl ApiPeripheral for Peripheral {
         list.push(handler);
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+    /// Returns a stream of value notifications from the specified characteristic, subscribing to
+    /// it if this is the first stream for that characteristic.
+    async fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream> {
+        let unsubscribe = {
+            let peripheral = self.clone();
+            let characteristic = characteristic.clone();
+            move || {
+                task::spawn(async move { peripheral.stop_notify(&characteristic).await });
+            }
+        };
+        self.notification_streams
+            .add(
+                (characteristic.service_uuid, characteristic.uuid),
+                || self.start_notify(characteristic),
+                unsubscribe,
+            )
+            .await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
         info!("Trying read!");
-        task::block_on(async {
-            let mut message_sender = self.message_sender.clone();
-            let fut = CoreBluetoothReplyFuture::default();
-            message_sender
-                .send(CoreBluetoothMessage::ReadValue(
-                    self.uuid,
-                    characteristic.uuid,
-                    fut.get_state_clone(),
-                ))
-                .await?;
-            match fut.await {
-                CoreBluetoothReply::ReadResult(chars) => Ok(chars),
-                _ => {
-                    panic!("Shouldn't get anything but read result!");
-                }
+        let mut message_sender = self.message_sender.clone();
+        let fut = CoreBluetoothReplyFuture::default();
+        message_sender
+            .send(CoreBluetoothMessage::ReadValue(
+                self.uuid,
+                characteristic.service_uuid,
+                characteristic.uuid,
+                fut.get_state_clone(),
+            ))
+            .await?;
+        match fut.await {
+            CoreBluetoothReply::ReadResult(chars) => Ok(chars),
+            _ => {
+                panic!("Shouldn't get anything but read result!");
             }
-        })
+        }
     }
 }
 
@@ -46,7 +46,7 @@
 //!
 //!     // start scanning for devices
 //!     central.start_scan().unwrap();
-//!     // instead of waiting, you can use central.event_receiver() to fetch a channel and
+//!     // instead of waiting, you can use central.events() to fetch a stream and
 //!     // be notified of new devices
 //!     thread::sleep(Duration::from_secs(2));
 //!
@@ -90,19 +90,27 @@ pub mod bluez;
 mod common;
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 pub mod corebluetooth;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 #[cfg(target_os = "windows")]
 pub mod winrtble;
 
 #[derive(Debug, thiserror::Error, Clone)]
 pub enum Error {
-    #[error("Permission denied")]
-    PermissionDenied,
+    // The variants with a description keep the backend's own description of the error, such as
+    // the name and message of a D-Bus error, for diagnostics. Where the backend has none, they
+    // say what failed instead, such as the operation or the device.
+    #[error("Permission denied: {}", _0)]
+    PermissionDenied(String),
 
-    #[error("Device not found")]
-    DeviceNotFound,
+    #[error("Device not found: {}", _0)]
+    DeviceNotFound(String),
 
-    #[error("Not connected")]
-    NotConnected,
+    #[error("Not connected: {}", _0)]
+    NotConnected(String),
+
+    #[error("The adapter has been removed")]
+    AdapterNotAvailable,
 
     #[error("The operation is not supported: {}", _0)]
     NotSupported(String),
@@ -110,9 +118,126 @@ pub enum Error {
     #[error("Timed out after {:?}", _0)]
     TimedOut(Duration),
 
+    #[error("The object does not exist: {}", _0)]
+    ObjectNotFound(String),
+
+    #[error("The device is not paired: {}", _0)]
+    NotPaired(String),
+
+    #[error("The operation is already in progress: {}", _0)]
+    InProgress(String),
+
+    #[error("The operation is not permitted: {}", _0)]
+    NotPermitted(String),
+
+    #[error("Not authorized: {}", _0)]
+    NotAuthorized(String),
+
+    #[error("Invalid offset: {}", _0)]
+    InvalidOffset(String),
+
+    #[error("Authentication failed: {}", _0)]
+    AuthenticationFailed(String),
+
+    #[error("Connection attempt failed: {}", _0)]
+    ConnectionAttemptFailed(String),
+
+    #[error("The device responded with {:?}: {}", _0, _1)]
+    Att(AttError, String),
+
     #[error("{}", _0)]
     Other(String),
 }
 
+/// An error code of the Attribute Protocol, which a device responds with when it refuses a GATT
+/// request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttError {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    AttributeNotFound,
+    AttributeNotLong,
+    InsufficientEncryptionKeySize,
+    InvalidAttributeValueLength,
+    Unlikely,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+    DatabaseOutOfSync,
+    ValueNotAllowed,
+    /// An error defined by the application, from 0x80 to 0x9F.
+    Application(u8),
+    /// An error defined by a profile or service specification, from 0xE0 to 0xFF.
+    CommonProfile(u8),
+    /// A reserved error code.
+    Reserved(u8),
+}
+
+impl AttError {
+    /// Returns the error code which is sent over the air.
+    pub fn code(self) -> u8 {
+        match self {
+            AttError::InvalidHandle => 0x01,
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InvalidPdu => 0x04,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::RequestNotSupported => 0x06,
+            AttError::InvalidOffset => 0x07,
+            AttError::InsufficientAuthorization => 0x08,
+            AttError::PrepareQueueFull => 0x09,
+            AttError::AttributeNotFound => 0x0A,
+            AttError::AttributeNotLong => 0x0B,
+            AttError::InsufficientEncryptionKeySize => 0x0C,
+            AttError::InvalidAttributeValueLength => 0x0D,
+            AttError::Unlikely => 0x0E,
+            AttError::InsufficientEncryption => 0x0F,
+            AttError::UnsupportedGroupType => 0x10,
+            AttError::InsufficientResources => 0x11,
+            AttError::DatabaseOutOfSync => 0x12,
+            AttError::ValueNotAllowed => 0x13,
+            AttError::Application(code)
+            | AttError::CommonProfile(code)
+            | AttError::Reserved(code) => code,
+        }
+    }
+}
+
+impl From<u8> for AttError {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => AttError::InvalidHandle,
+            0x02 => AttError::ReadNotPermitted,
+            0x03 => AttError::WriteNotPermitted,
+            0x04 => AttError::InvalidPdu,
+            0x05 => AttError::InsufficientAuthentication,
+            0x06 => AttError::RequestNotSupported,
+            0x07 => AttError::InvalidOffset,
+            0x08 => AttError::InsufficientAuthorization,
+            0x09 => AttError::PrepareQueueFull,
+            0x0A => AttError::AttributeNotFound,
+            0x0B => AttError::AttributeNotLong,
+            0x0C => AttError::InsufficientEncryptionKeySize,
+            0x0D => AttError::InvalidAttributeValueLength,
+            0x0E => AttError::Unlikely,
+            0x0F => AttError::InsufficientEncryption,
+            0x10 => AttError::UnsupportedGroupType,
+            0x11 => AttError::InsufficientResources,
+            0x12 => AttError::DatabaseOutOfSync,
+            0x13 => AttError::ValueNotAllowed,
+            0x80..=0x9F => AttError::Application(code),
+            0xE0..=0xFF => AttError::CommonProfile(code),
+            _ => AttError::Reserved(code),
+        }
+    }
+}
+
 // BtlePlug Result type
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,516 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+    device::{AdvertisingReport, Device},
+    peripheral::Peripheral,
+    Failures, Operation,
+};
+use crate::{
+    api::{
+        AdapterInfo, AdapterManager, AsyncCentral, BDAddr, CentralEvent, DeviceState, EventStream,
+        ExpiryPolicy, ScanFilter, Transport,
+    },
+    Error, Result,
+};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// An adapter in the mock backend. Devices are only seen by the adapter when the test makes them
+/// [`advertise`](#method.advertise).
+#[derive(Clone, Debug)]
+pub struct Adapter {
+    manager: AdapterManager<Peripheral>,
+    scanning: Arc<AtomicBool>,
+    active: Arc<AtomicBool>,
+    filter_duplicates: Arc<AtomicBool>,
+    filter: Arc<Mutex<ScanFilter>>,
+    last_reports: Arc<Mutex<HashMap<BDAddr, AdvertisingReport>>>,
+    failures: Arc<Failures>,
+}
+
+impl Adapter {
+    pub fn new() -> Self {
+        Adapter {
+            manager: AdapterManager::new(),
+            scanning: Arc::new(AtomicBool::new(false)),
+            active: Arc::new(AtomicBool::new(true)),
+            filter_duplicates: Arc::new(AtomicBool::new(true)),
+            filter: Arc::new(Mutex::new(ScanFilter::default())),
+            last_reports: Arc::new(Mutex::new(HashMap::new())),
+            failures: Arc::new(Failures::default()),
+        }
+    }
+
+    /// Returns true if a scan is in progress.
+    pub fn is_scanning(&self) -> bool {
+        self.scanning.load(Ordering::Relaxed)
+    }
+
+    /// Delivers an advertisement from `device`, as if it had been received over the air. The
+    /// report is dropped, as it would be by a real adapter, if no scan is in progress, if it is a
+    /// scan response and the scan is passive, if it doesn't advertise any of the services which
+    /// the scan is filtered on, or if it is identical to the last report from the device and
+    /// duplicates are being filtered. Returns whether the report was delivered.
+    pub fn advertise(&self, device: &Device, report: AdvertisingReport) -> bool {
+        if !self.is_scanning() || (report.scan_response && !self.active.load(Ordering::Relaxed)) {
+            return false;
+        }
+        let filter = self.filter.lock().unwrap().clone();
+        if !filter.services.is_empty()
+            && !filter
+                .services
+                .iter()
+                .any(|service| report.services.contains(service))
+        {
+            return false;
+        }
+        if let Some(threshold) = filter.rssi {
+            if !matches!(report.rssi, Some(rssi) if rssi >= threshold) {
+                return false;
+            }
+        }
+        if let Some(threshold) = filter.pathloss {
+            // The path loss can only be worked out for devices which advertise their TX power.
+            let pathloss = report
+                .tx_power_level
+                .zip(report.rssi)
+                .map(|(tx_power, rssi)| i32::from(tx_power) - i32::from(rssi));
+            if !matches!(pathloss, Some(pathloss) if pathloss <= i32::from(threshold)) {
+                return false;
+            }
+        }
+        let address = device.address();
+        {
+            let mut last_reports = self.last_reports.lock().unwrap();
+            // A device which has expired is new again, whatever it last sent.
+            if (self.filter_duplicates.load(Ordering::Relaxed) || !filter.duplicate_data)
+                && last_reports.get(&address) == Some(&report)
+                && self.manager.has_peripheral(&address)
+            {
+                return false;
+            }
+            last_reports.insert(address, report.clone());
+        }
+
+        self.manager.alter_peripheral(
+            address,
+            || Some(Peripheral::new(self.manager.clone(), device.clone())),
+            |peripheral, events| peripheral.update_properties(&report, events),
+        );
+        true
+    }
+
+    /// Reports that the device with the given address is no longer in range.
+    pub fn lose_device(&self, address: BDAddr) {
+        self.last_reports.lock().unwrap().remove(&address);
+        if self.manager.has_peripheral(&address) {
+            self.manager.emit(CentralEvent::DeviceLost(address));
+        }
+    }
+
+    /// Makes the next call of `operation` on this adapter fail with `error`. Each call to this
+    /// method fails one more call of the operation.
+    pub fn fail_next(&self, operation: Operation, error: Error) {
+        self.failures.push(operation, error);
+    }
+}
+
+impl Default for Adapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent> {
+        self.manager.events(buffer)
+    }
+
+    async fn start_scan(&self) -> Result<()> {
+        self.start_scan_with_filter(ScanFilter::default()).await
+    }
+
+    async fn start_scan_with_filter(&self, filter: ScanFilter) -> Result<()> {
+        // Only the filters which can be checked against an advertising report are supported.
+        if filter.discoverable {
+            return Err(Error::NotSupported("start_scan_with_filter".into()));
+        }
+        if filter.transport == Transport::BrEdr {
+            return Err(Error::NotSupported("BR/EDR scanning".into()));
+        }
+        self.failures.check(Operation::StartScan)?;
+        *self.filter.lock().unwrap() = filter;
+        // Each scan reports every device again, even when duplicates are filtered.
+        self.last_reports.lock().unwrap().clear();
+        self.manager.reset_duplicates();
+        self.scanning.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn active(&self, enabled: bool) {
+        self.active.store(enabled, Ordering::Relaxed);
+    }
+
+    fn filter_duplicates(&self, enabled: bool) {
+        self.filter_duplicates.store(enabled, Ordering::Relaxed);
+        self.manager.set_filter_duplicates(enabled);
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.failures.check(Operation::StopScan)?;
+        self.scanning.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn peripherals(&self) -> Vec<Peripheral> {
+        self.manager.peripherals()
+    }
+
+    fn peripheral(&self, address: BDAddr) -> Option<Peripheral> {
+        self.manager.peripheral(address)
+    }
+
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        self.manager.peripheral_state(address)
+    }
+
+    fn expiry_policy(&self, policy: ExpiryPolicy) {
+        self.manager.set_expiry_policy(policy);
+    }
+
+    async fn adapter_info(&self) -> Result<AdapterInfo> {
+        // The mock adapter is always on, and has nothing else to report.
+        Ok(AdapterInfo {
+            powered: Some(true),
+            discovering: Some(self.is_scanning()),
+            ..AdapterInfo::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        bleuuid::uuid_from_u16, AsyncPeripheral, CharPropFlags, ValueNotification, WriteType,
+    };
+    use futures::{executor::block_on, stream::StreamExt};
+    use std::{thread, time::Duration};
+
+    const BATTERY_SERVICE: u16 = 0x180F;
+    const BATTERY_LEVEL: u16 = 0x2A19;
+    const USER_DESCRIPTION: u16 = 0x2901;
+
+    fn battery_device() -> Device {
+        let device = Device::new("AA:BB:CC:DD:EE:FF".parse().unwrap());
+        device.add_service(uuid_from_u16(BATTERY_SERVICE), true);
+        device.add_characteristic(
+            uuid_from_u16(BATTERY_LEVEL),
+            CharPropFlags::READ | CharPropFlags::WRITE | CharPropFlags::NOTIFY,
+            &[100],
+        );
+        device.add_descriptor(uuid_from_u16(USER_DESCRIPTION), b"Battery");
+        device
+    }
+
+    fn report(local_name: &str) -> AdvertisingReport {
+        AdvertisingReport {
+            local_name: Some(local_name.to_string()),
+            ..AdvertisingReport::default()
+        }
+    }
+
+    /// Starts a scan on a new adapter and has it discover `device`.
+    fn discover(device: &Device) -> (Adapter, Peripheral) {
+        let adapter = Adapter::new();
+        block_on(adapter.start_scan()).unwrap();
+        assert!(adapter.advertise(device, report("Battery")));
+        let peripheral = AsyncCentral::peripheral(&adapter, device.address()).unwrap();
+        (adapter, peripheral)
+    }
+
+    #[test]
+    fn advertisements_are_reported_while_scanning() {
+        let device = battery_device();
+        let adapter = Adapter::new();
+        let mut events = adapter.events();
+        assert!(!adapter.advertise(&device, report("Battery")));
+        block_on(adapter.start_scan()).unwrap();
+        assert!(adapter.advertise(&device, report("Battery")));
+        assert!(!adapter.advertise(&device, report("Battery")));
+        assert!(adapter.advertise(&device, report("Renamed")));
+        adapter.lose_device(device.address());
+
+        let address = device.address();
+        let events: Vec<_> = block_on(events.by_ref().take(3).collect());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                CentralEvent::DeviceDiscovered(a),
+                CentralEvent::DeviceUpdated(b),
+                CentralEvent::DeviceLost(c),
+            ] if *a == address && *b == address && *c == address
+        ));
+        assert!(AsyncCentral::peripherals(&adapter).is_empty());
+    }
+
+    #[test]
+    fn repeated_advertisements_are_not_updates() {
+        let device = battery_device();
+        let address = device.address();
+        let adapter = Adapter::new();
+        let mut events = adapter.events();
+        AsyncCentral::filter_duplicates(&adapter, false);
+        block_on(adapter.start_scan()).unwrap();
+        let report = AdvertisingReport {
+            rssi: Some(-40),
+            ..report("Battery")
+        };
+        assert!(adapter.advertise(&device, report.clone()));
+        assert!(adapter.advertise(&device, report));
+        adapter.lose_device(address);
+
+        // The discovery comes before what the advertisement said, and nothing changed the second
+        // time.
+        let events: Vec<_> = block_on(events.by_ref().take(4).collect());
+        assert_eq!(
+            events,
+            vec![
+                CentralEvent::DeviceDiscovered(address),
+                CentralEvent::RssiUpdate { address, rssi: -40 },
+                CentralEvent::RssiUpdate { address, rssi: -40 },
+                CentralEvent::DeviceLost(address),
+            ]
+        );
+    }
+
+    #[test]
+    fn concurrent_advertisements_all_apply() {
+        let device = battery_device();
+        let adapter = Adapter::new();
+        let mut events = adapter.events();
+        block_on(adapter.start_scan()).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let (adapter, device) = (adapter.clone(), device.clone());
+                thread::spawn(move || adapter.advertise(&device, report(&format!("Battery {}", i))))
+            })
+            .collect();
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+
+        let events: Vec<_> = block_on(events.by_ref().take(8).collect());
+        let discovered = events
+            .iter()
+            .filter(|event| matches!(event, CentralEvent::DeviceDiscovered(_)))
+            .count();
+        assert_eq!(discovered, 1);
+        let peripheral = adapter.peripheral(device.address()).unwrap();
+        assert_eq!(peripheral.properties().discovery_count, 8);
+    }
+
+    #[test]
+    fn scan_filter_drops_other_services() {
+        let device = battery_device();
+        let adapter = Adapter::new();
+        let filter = ScanFilter {
+            services: vec![uuid_from_u16(BATTERY_SERVICE)],
+            ..ScanFilter::default()
+        };
+        block_on(adapter.start_scan_with_filter(filter)).unwrap();
+        assert!(!adapter.advertise(&device, report("Battery")));
+        let battery = AdvertisingReport {
+            services: vec![uuid_from_u16(BATTERY_SERVICE)],
+            ..report("Battery")
+        };
+        assert!(adapter.advertise(&device, battery));
+
+        let filter = ScanFilter {
+            rssi: Some(-70),
+            ..ScanFilter::default()
+        };
+        block_on(adapter.start_scan_with_filter(filter)).unwrap();
+        let weak = AdvertisingReport {
+            rssi: Some(-80),
+            ..report("Battery")
+        };
+        assert!(!adapter.advertise(&device, weak));
+        let strong = AdvertisingReport {
+            rssi: Some(-60),
+            ..report("Battery")
+        };
+        assert!(adapter.advertise(&device, strong));
+        let peripheral = adapter.peripheral(device.address()).unwrap();
+        assert_eq!(peripheral.properties().rssi, Some(-60));
+
+        let filter = ScanFilter {
+            discoverable: true,
+            ..ScanFilter::default()
+        };
+        assert!(matches!(
+            block_on(adapter.start_scan_with_filter(filter)),
+            Err(Error::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn gatt_operations() {
+        let device = battery_device();
+        let (_adapter, peripheral) = discover(&device);
+        block_on(peripheral.connect()).unwrap();
+        assert!(device.is_connected());
+
+        let characteristics = block_on(peripheral.discover_characteristics()).unwrap();
+        assert_eq!(characteristics.len(), 1);
+        let level = &characteristics[0];
+        assert_eq!(
+            (level.start_handle, level.value_handle, level.end_handle),
+            (2, 3, 4)
+        );
+        let descriptor = level.descriptors.iter().next().unwrap();
+        assert_eq!(descriptor.handle, 4);
+        assert_eq!(
+            block_on(peripheral.read_descriptor(descriptor)).unwrap(),
+            b"Battery"
+        );
+
+        assert_eq!(block_on(peripheral.read(level)).unwrap(), vec![100]);
+        block_on(peripheral.write(level, &[50], WriteType::WithResponse)).unwrap();
+        assert_eq!(device.value(level.value_handle), Some(vec![50]));
+        assert!(matches!(
+            block_on(peripheral.write(level, &[50], WriteType::WithoutResponse)),
+            Err(Error::PermissionDenied(_))
+        ));
+    }
+
+    #[test]
+    fn notifications_follow_subscriptions() {
+        let device = battery_device();
+        let (_adapter, peripheral) = discover(&device);
+        block_on(peripheral.connect()).unwrap();
+        let characteristics = block_on(peripheral.discover_characteristics()).unwrap();
+        let level = &characteristics[0];
+
+        assert!(!device.notify(level.value_handle, &[99]));
+        let mut stream = block_on(peripheral.notifications(level)).unwrap();
+        assert!(device.notify(level.value_handle, &[98]));
+        assert_eq!(
+            block_on(stream.next()),
+            Some(ValueNotification {
+                uuid: level.uuid,
+                handle: Some(level.value_handle),
+                value: vec![98],
+            })
+        );
+        drop(stream);
+        assert!(!device.is_subscribed(level.value_handle));
+    }
+
+    #[test]
+    fn injected_errors_fail_once() {
+        let device = battery_device();
+        let (adapter, peripheral) = discover(&device);
+        let mut events = adapter.events();
+        device.fail_next(Operation::Connect, Error::TimedOut(Default::default()));
+        assert!(matches!(
+            block_on(peripheral.connect()),
+            Err(Error::TimedOut(_))
+        ));
+        assert!(!peripheral.is_connected());
+        block_on(peripheral.connect()).unwrap();
+        let characteristics = block_on(peripheral.discover_characteristics()).unwrap();
+
+        device.disconnect();
+        assert!(!peripheral.is_connected());
+        assert!(matches!(
+            block_on(peripheral.read(&characteristics[0])),
+            Err(Error::NotConnected(_))
+        ));
+        let events: Vec<_> = block_on(events.by_ref().take(2).collect());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                CentralEvent::DeviceConnected(_),
+                CentralEvent::DeviceDisconnected(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn peripherals_are_kept_until_they_expire() {
+        let device = battery_device();
+        let (adapter, peripheral) = discover(&device);
+        let address = device.address();
+        let mut events = adapter.events();
+        assert_eq!(
+            adapter.peripheral_state(address),
+            Some(DeviceState::Discovered)
+        );
+        block_on(peripheral.connect()).unwrap();
+        assert_eq!(
+            adapter.peripheral_state(address),
+            Some(DeviceState::Connected)
+        );
+
+        // Connected peripherals don't expire.
+        adapter.expiry_policy(ExpiryPolicy::After(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(AsyncCentral::peripherals(&adapter).len(), 1);
+
+        device.disconnect();
+        assert_eq!(
+            adapter.peripheral_state(address),
+            Some(DeviceState::Disconnected)
+        );
+        assert!(AsyncCentral::peripheral(&adapter, address).is_some());
+        thread::sleep(Duration::from_millis(20));
+        assert!(AsyncCentral::peripherals(&adapter).is_empty());
+        assert_eq!(adapter.peripheral_state(address), None);
+
+        let lifecycle: Vec<_> = block_on(events.by_ref().take(3).collect());
+        assert_eq!(
+            lifecycle,
+            vec![
+                CentralEvent::DeviceConnected(address),
+                CentralEvent::DeviceDisconnected(address),
+                CentralEvent::DeviceExpired(address),
+            ]
+        );
+
+        // An expired device is discovered again when it next advertises.
+        assert!(adapter.advertise(&device, report("Battery")));
+        assert_eq!(
+            block_on(events.next()),
+            Some(CentralEvent::DeviceDiscovered(address))
+        );
+    }
+
+    #[test]
+    fn pairing_connects() {
+        let device = battery_device();
+        let (_adapter, peripheral) = discover(&device);
+        device.fail_next(Operation::Pair, Error::PermissionDenied("pair".into()));
+        assert!(matches!(
+            block_on(peripheral.pair()),
+            Err(Error::PermissionDenied(_))
+        ));
+        block_on(peripheral.pair()).unwrap();
+        assert!(device.is_connected());
+        assert!(block_on(peripheral.is_paired()).unwrap());
+        block_on(peripheral.unpair()).unwrap();
+        assert!(!device.is_paired());
+    }
+}
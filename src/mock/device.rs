@@ -0,0 +1,457 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{peripheral::Peripheral, Failures, Operation};
+use crate::{
+    api::{
+        AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor, Service, ValueNotification,
+        WriteType,
+    },
+    Error, Result,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// The contents of a single advertisement from a [`Device`], as passed to
+/// [`Adapter::advertise`](../adapter/struct.Adapter.html#method.advertise).
+///
+/// As with real advertisements, fields which are left empty don't clear what was received before.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct AdvertisingReport {
+    pub address_type: AddressType,
+    pub local_name: Option<String>,
+    pub tx_power_level: Option<i8>,
+    /// The signal strength at which the advertisement was received.
+    pub rssi: Option<i16>,
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    pub services: Vec<Uuid>,
+    /// Whether this is a scan response, which is only received by an active scan.
+    pub scan_response: bool,
+}
+
+#[derive(Debug)]
+enum Attribute {
+    Service {
+        uuid: Uuid,
+        primary: bool,
+    },
+    /// A characteristic, stored under its value handle. Its declaration is the handle before.
+    Characteristic {
+        service: u16,
+        uuid: Uuid,
+        properties: CharPropFlags,
+        value: Vec<u8>,
+    },
+    Descriptor {
+        characteristic: u16,
+        uuid: Uuid,
+        value: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+struct DeviceState {
+    attributes: BTreeMap<u16, Attribute>,
+    next_handle: u16,
+    connection: Option<Peripheral>,
+    subscriptions: BTreeSet<u16>,
+    paired: bool,
+}
+
+/// A remote device in the mock backend, which acts as a GATT server once connected.
+///
+/// Attributes are laid out in the order they are added, with handles assigned the same way as by a
+/// real device: characteristics belong to the service added most recently, and descriptors to the
+/// characteristic added most recently. Clones of a `Device` share the same state, so a test can
+/// keep one to inspect and drive the device while the backend uses another.
+#[derive(Clone, Debug)]
+pub struct Device {
+    address: BDAddr,
+    state: Arc<Mutex<DeviceState>>,
+    failures: Arc<Failures>,
+}
+
+impl Device {
+    pub fn new(address: BDAddr) -> Self {
+        Device {
+            address,
+            state: Arc::new(Mutex::new(DeviceState {
+                attributes: BTreeMap::new(),
+                next_handle: 1,
+                connection: None,
+                subscriptions: BTreeSet::new(),
+                paired: false,
+            })),
+            failures: Arc::new(Failures::default()),
+        }
+    }
+
+    pub fn address(&self) -> BDAddr {
+        self.address
+    }
+
+    /// Adds a service to the GATT table, returning its handle.
+    pub fn add_service(&self, uuid: Uuid, primary: bool) -> u16 {
+        let mut state = self.state.lock().unwrap();
+        let handle = state.allocate(1);
+        state
+            .attributes
+            .insert(handle, Attribute::Service { uuid, primary });
+        handle
+    }
+
+    /// Adds a characteristic with the initial value `value` to the service which was added most
+    /// recently, returning its value handle.
+    ///
+    /// Panics if no service has been added yet.
+    pub fn add_characteristic(&self, uuid: Uuid, properties: CharPropFlags, value: &[u8]) -> u16 {
+        let mut state = self.state.lock().unwrap();
+        let service = state
+            .last_handle(|attribute| matches!(attribute, Attribute::Service { .. }))
+            .expect("A characteristic must belong to a service.");
+        // One handle for the declaration, and one for the value.
+        let handle = state.allocate(2) + 1;
+        state.attributes.insert(
+            handle,
+            Attribute::Characteristic {
+                service,
+                uuid,
+                properties,
+                value: value.to_vec(),
+            },
+        );
+        handle
+    }
+
+    /// Adds a descriptor with the initial value `value` to the characteristic which was added most
+    /// recently, returning its handle.
+    ///
+    /// Panics if no characteristic has been added yet.
+    pub fn add_descriptor(&self, uuid: Uuid, value: &[u8]) -> u16 {
+        let mut state = self.state.lock().unwrap();
+        let characteristic = state
+            .last_handle(|attribute| matches!(attribute, Attribute::Characteristic { .. }))
+            .expect("A descriptor must belong to a characteristic.");
+        let handle = state.allocate(1);
+        state.attributes.insert(
+            handle,
+            Attribute::Descriptor {
+                characteristic,
+                uuid,
+                value: value.to_vec(),
+            },
+        );
+        handle
+    }
+
+    /// Returns the current value of the characteristic or descriptor with the given handle.
+    pub fn value(&self, handle: u16) -> Option<Vec<u8>> {
+        match self.state.lock().unwrap().attributes.get(&handle)? {
+            Attribute::Characteristic { value, .. } | Attribute::Descriptor { value, .. } => {
+                Some(value.clone())
+            }
+            Attribute::Service { .. } => None,
+        }
+    }
+
+    /// Changes the value of the characteristic or descriptor with the given handle, without
+    /// notifying anyone.
+    ///
+    /// Panics if there is no such characteristic or descriptor.
+    pub fn set_value(&self, handle: u16, new_value: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        match state.attributes.get_mut(&handle) {
+            Some(Attribute::Characteristic { value, .. })
+            | Some(Attribute::Descriptor { value, .. }) => *value = new_value.to_vec(),
+            _ => panic!("No characteristic or descriptor with handle {}.", handle),
+        }
+    }
+
+    /// Changes the value of the characteristic with the given value handle, and sends a
+    /// notification of the new value if the connected peripheral has subscribed to it. Returns
+    /// whether the notification was sent.
+    pub fn notify(&self, handle: u16, new_value: &[u8]) -> bool {
+        let (peripheral, uuid) = {
+            let mut state = self.state.lock().unwrap();
+            let subscribed = state.subscriptions.contains(&handle);
+            let uuid = match state.attributes.get_mut(&handle) {
+                Some(Attribute::Characteristic { uuid, value, .. }) => {
+                    *value = new_value.to_vec();
+                    *uuid
+                }
+                _ => panic!("No characteristic with handle {}.", handle),
+            };
+            match &state.connection {
+                Some(peripheral) if subscribed => (peripheral.clone(), uuid),
+                _ => return false,
+            }
+        };
+        peripheral.deliver(&ValueNotification {
+            uuid,
+            handle: Some(handle),
+            value: new_value.to_vec(),
+        });
+        true
+    }
+
+    /// Returns true if a peripheral is connected to the device.
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().connection.is_some()
+    }
+
+    /// Returns true if the connected peripheral has subscribed to the characteristic with the given
+    /// value handle.
+    pub fn is_subscribed(&self, handle: u16) -> bool {
+        self.state.lock().unwrap().subscriptions.contains(&handle)
+    }
+
+    /// Returns true if the device is paired.
+    pub fn is_paired(&self) -> bool {
+        self.state.lock().unwrap().paired
+    }
+
+    /// Pairs or unpairs the device, as if it had been done outside of the backend.
+    pub fn set_paired(&self, paired: bool) {
+        self.state.lock().unwrap().paired = paired;
+    }
+
+    /// Drops the connection from the device's side, as if it had gone out of range.
+    pub fn disconnect(&self) {
+        if let Some(peripheral) = self.take_connection() {
+            peripheral.connection_lost();
+        }
+    }
+
+    /// Makes the next call of `operation` on a peripheral for this device fail with `error`. Each
+    /// call to this method fails one more call of the operation.
+    pub fn fail_next(&self, operation: Operation, error: Error) {
+        self.failures.push(operation, error);
+    }
+
+    pub(super) fn check(&self, operation: Operation) -> Result<()> {
+        self.failures.check(operation)
+    }
+
+    /// Connects `peripheral` to the device, returning false if it was already connected.
+    pub(super) fn connect(&self, peripheral: &Peripheral) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.connection.is_some() {
+            return false;
+        }
+        state.connection = Some(peripheral.clone());
+        true
+    }
+
+    /// Disconnects the connected peripheral, if there is one, and returns it.
+    pub(super) fn take_connection(&self) -> Option<Peripheral> {
+        let mut state = self.state.lock().unwrap();
+        // Subscriptions don't outlive the connection.
+        state.subscriptions.clear();
+        state.connection.take()
+    }
+
+    /// Returns the GATT table as the services a peripheral would discover.
+    pub(super) fn services(&self) -> BTreeSet<Service> {
+        let state = self.state.lock().unwrap();
+        let last_handle = state.next_handle - 1;
+
+        // A characteristic's handle range ends just before the next service or characteristic.
+        let boundaries: Vec<u16> = state
+            .attributes
+            .iter()
+            .filter_map(|(&handle, attribute)| match attribute {
+                Attribute::Service { .. } => Some(handle),
+                Attribute::Characteristic { .. } => Some(handle - 1),
+                Attribute::Descriptor { .. } => None,
+            })
+            .collect();
+
+        let characteristics: Vec<(u16, Characteristic)> = state
+            .attributes
+            .iter()
+            .filter_map(|(&handle, attribute)| match attribute {
+                Attribute::Characteristic {
+                    service,
+                    uuid,
+                    properties,
+                    ..
+                } => Some((handle, *service, *uuid, *properties)),
+                _ => None,
+            })
+            .map(|(handle, service, uuid, properties)| {
+                let end_handle = boundaries
+                    .iter()
+                    .find(|&&boundary| boundary >= handle)
+                    .map_or(last_handle, |boundary| boundary - 1);
+                let descriptors = state
+                    .attributes
+                    .iter()
+                    .filter_map(|(&descriptor_handle, attribute)| match attribute {
+                        Attribute::Descriptor {
+                            characteristic,
+                            uuid,
+                            ..
+                        } if *characteristic == handle => Some(Descriptor {
+                            handle: descriptor_handle,
+                            uuid: *uuid,
+                        }),
+                        _ => None,
+                    })
+                    .collect();
+                let characteristic = Characteristic {
+                    start_handle: handle - 1,
+                    end_handle,
+                    value_handle: handle,
+                    uuid,
+                    service_uuid: match state.attributes.get(&service) {
+                        Some(Attribute::Service { uuid, .. }) => *uuid,
+                        _ => Uuid::nil(),
+                    },
+                    properties,
+                    descriptors,
+                };
+                (service, characteristic)
+            })
+            .collect();
+
+        state
+            .attributes
+            .iter()
+            .filter_map(|(&handle, attribute)| match attribute {
+                Attribute::Service { uuid, primary } => Some(Service {
+                    uuid: *uuid,
+                    primary: *primary,
+                    included_services: Vec::new(),
+                    characteristics: characteristics
+                        .iter()
+                        .filter(|(service, _c)| *service == handle)
+                        .map(|(_service, characteristic)| characteristic.clone())
+                        .collect(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(super) fn read_characteristic(&self, handle: u16) -> Result<Vec<u8>> {
+        match self.state.lock().unwrap().attributes.get(&handle) {
+            Some(Attribute::Characteristic {
+                properties, value, ..
+            }) => {
+                if properties.contains(CharPropFlags::READ) {
+                    Ok(value.clone())
+                } else {
+                    Err(Error::PermissionDenied("read".into()))
+                }
+            }
+            _ => Err(Error::NotSupported("read".into())),
+        }
+    }
+
+    pub(super) fn write_characteristic(
+        &self,
+        handle: u16,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let required = match write_type {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        };
+        match self.state.lock().unwrap().attributes.get_mut(&handle) {
+            Some(Attribute::Characteristic {
+                properties, value, ..
+            }) => {
+                if properties.contains(required) {
+                    *value = data.to_vec();
+                    Ok(())
+                } else {
+                    Err(Error::PermissionDenied("write".into()))
+                }
+            }
+            _ => Err(Error::NotSupported("write".into())),
+        }
+    }
+
+    /// Reads the value of the characteristic or descriptor of type `uuid` within the given range of
+    /// handles.
+    pub(super) fn read_by_type(&self, start: u16, end: u16, uuid: Uuid) -> Result<Vec<u8>> {
+        self.state
+            .lock()
+            .unwrap()
+            .attributes
+            .range(start..=end)
+            .find_map(|(_handle, attribute)| match attribute {
+                Attribute::Characteristic { uuid: u, value, .. }
+                | Attribute::Descriptor { uuid: u, value, .. }
+                    if *u == uuid =>
+                {
+                    Some(value.clone())
+                }
+                _ => None,
+            })
+            .ok_or_else(|| Error::NotSupported("read_by_type".into()))
+    }
+
+    pub(super) fn read_descriptor(&self, handle: u16) -> Result<Vec<u8>> {
+        match self.state.lock().unwrap().attributes.get(&handle) {
+            Some(Attribute::Descriptor { value, .. }) => Ok(value.clone()),
+            _ => Err(Error::NotSupported("read_descriptor".into())),
+        }
+    }
+
+    pub(super) fn write_descriptor(&self, handle: u16, data: &[u8]) -> Result<()> {
+        match self.state.lock().unwrap().attributes.get_mut(&handle) {
+            Some(Attribute::Descriptor { value, .. }) => {
+                *value = data.to_vec();
+                Ok(())
+            }
+            _ => Err(Error::NotSupported("write_descriptor".into())),
+        }
+    }
+
+    pub(super) fn subscribe(&self, handle: u16) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.attributes.get(&handle) {
+            Some(Attribute::Characteristic { properties, .. })
+                if properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) =>
+            {
+                state.subscriptions.insert(handle);
+                Ok(())
+            }
+            _ => Err(Error::NotSupported("subscribe".into())),
+        }
+    }
+
+    pub(super) fn unsubscribe(&self, handle: u16) {
+        self.state.lock().unwrap().subscriptions.remove(&handle);
+    }
+}
+
+impl DeviceState {
+    /// Reserves `count` consecutive handles, returning the first one.
+    fn allocate(&mut self, count: u16) -> u16 {
+        let handle = self.next_handle;
+        self.next_handle = handle
+            .checked_add(count)
+            .expect("The GATT table is out of handles.");
+        handle
+    }
+
+    /// Returns the handle of the last attribute matching `predicate`.
+    fn last_handle(&self, predicate: impl Fn(&Attribute) -> bool) -> Option<u16> {
+        self.attributes
+            .iter()
+            .rev()
+            .find(|(_handle, attribute)| predicate(attribute))
+            .map(|(&handle, _attribute)| handle)
+    }
+}
@@ -0,0 +1,36 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::adapter::Adapter;
+use crate::Result;
+use std::sync::Mutex;
+
+/// The entry point of the mock backend, which starts out with a single adapter.
+#[derive(Debug)]
+pub struct Manager {
+    adapters: Mutex<Vec<Adapter>>,
+}
+
+impl Manager {
+    pub fn new() -> Result<Self> {
+        Ok(Manager {
+            adapters: Mutex::new(vec![Adapter::new()]),
+        })
+    }
+
+    /// Returns the list of adapters available on the system.
+    pub fn adapters(&self) -> Result<Vec<Adapter>> {
+        Ok(self.adapters.lock().unwrap().clone())
+    }
+
+    /// Adds another adapter to the system, and returns it.
+    pub fn add_adapter(&self) -> Adapter {
+        let adapter = Adapter::new();
+        self.adapters.lock().unwrap().push(adapter.clone());
+        adapter
+    }
+}
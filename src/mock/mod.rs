@@ -0,0 +1,87 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! An in-memory backend, for testing code which uses btleplug without a Bluetooth radio.
+//!
+//! The mock [`Adapter`](adapter/struct.Adapter.html) and
+//! [`Peripheral`](peripheral/struct.Peripheral.html) implement the same traits as the real
+//! backends, and report events through the same
+//! [`AdapterManager`](../api/struct.AdapterManager.html), but the remote devices they talk to are
+//! scripted by the test:
+//!
+//! - A [`Device`](device/struct.Device.html) models a remote device, with a GATT table whose values
+//!   can be read and written from both sides, and which can push notifications or drop the
+//!   connection.
+//! - [`Adapter::advertise`](adapter/struct.Adapter.html#method.advertise) delivers an
+//!   [`AdvertisingReport`](device/struct.AdvertisingReport.html) from a device, as if it had been
+//!   received while scanning.
+//! - `fail_next` on either the adapter or a device makes the next call of a given
+//!   [`Operation`](enum.Operation.html) fail with a chosen error.
+//!
+//! Everything happens synchronously on the calling thread, so tests are deterministic.
+//!
+//! This module is only built when the `mock` feature is enabled.
+
+pub mod adapter;
+pub mod device;
+pub mod manager;
+pub mod peripheral;
+
+use crate::{Error, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// An operation which can be made to fail with `fail_next`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Operation {
+    StartScan,
+    StopScan,
+    Connect,
+    Disconnect,
+    Pair,
+    Unpair,
+    DiscoverCharacteristics,
+    Read,
+    Write,
+    ReadDescriptor,
+    WriteDescriptor,
+    Subscribe,
+    Unsubscribe,
+}
+
+/// The errors which have been queued up for operations by `fail_next`.
+#[derive(Debug, Default)]
+struct Failures {
+    errors: Mutex<HashMap<Operation, VecDeque<Error>>>,
+}
+
+impl Failures {
+    fn push(&self, operation: Operation, error: Error) {
+        self.errors
+            .lock()
+            .unwrap()
+            .entry(operation)
+            .or_default()
+            .push_back(error);
+    }
+
+    /// Returns the next error queued for `operation`, if there is one.
+    fn check(&self, operation: Operation) -> Result<()> {
+        match self
+            .errors
+            .lock()
+            .unwrap()
+            .get_mut(&operation)
+            .and_then(VecDeque::pop_front)
+        {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
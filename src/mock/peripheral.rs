@@ -0,0 +1,346 @@
+// btleplug Source Code File
+//
+// Copyright 2020 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+    device::{AdvertisingReport, Device},
+    Operation,
+};
+use crate::{
+    api::{
+        AdapterManager, AsyncPeripheral, BDAddr, CentralEvent, Characteristic, Descriptor,
+        NotificationHandler, NotificationStream, PeripheralProperties, Service, ValueNotification,
+        WriteType,
+    },
+    common::{notifications::NotificationStreams, util},
+    Error, Result,
+};
+use async_trait::async_trait;
+use futures::future;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Debug, Display, Formatter},
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// A peripheral in the mock backend, which talks to a scripted [`Device`].
+#[derive(Clone)]
+pub struct Peripheral {
+    adapter: AdapterManager<Self>,
+    device: Device,
+    properties: Arc<Mutex<PeripheralProperties>>,
+    characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    services: Arc<Mutex<BTreeSet<Service>>>,
+    notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    notification_streams: Arc<NotificationStreams<u16>>,
+}
+
+impl Peripheral {
+    pub(super) fn new(adapter: AdapterManager<Self>, device: Device) -> Self {
+        let properties = PeripheralProperties {
+            address: device.address(),
+            ..PeripheralProperties::default()
+        };
+        Peripheral {
+            adapter,
+            device,
+            properties: Arc::new(Mutex::new(properties)),
+            characteristics: Arc::new(Mutex::new(BTreeSet::new())),
+            services: Arc::new(Mutex::new(BTreeSet::new())),
+            notification_handlers: Arc::new(Mutex::new(Vec::new())),
+            notification_streams: Arc::new(NotificationStreams::new()),
+        }
+    }
+
+    /// Returns the scripted device which this peripheral talks to.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Updates the properties from an advertising report, and returns whether they changed. The
+    /// events which the report causes are added to `events`.
+    pub(super) fn update_properties(
+        &self,
+        report: &AdvertisingReport,
+        events: &mut Vec<CentralEvent>,
+    ) -> bool {
+        let mut properties = self.properties.lock().unwrap();
+        let address = self.device.address();
+        let mut updated = false;
+
+        properties.discovery_count += 1;
+        updated |= util::set_if_changed(&mut properties.address_type, report.address_type.clone());
+        let has_scan_response = properties.has_scan_response || report.scan_response;
+        updated |= util::set_if_changed(&mut properties.has_scan_response, has_scan_response);
+
+        // Advertisements are cumulative: set/replace data only if it's set
+        if report.local_name.is_some() {
+            updated |= util::set_if_changed(&mut properties.local_name, report.local_name.clone());
+        }
+        if report.tx_power_level.is_some() {
+            updated |= util::set_if_changed(&mut properties.tx_power_level, report.tx_power_level);
+        }
+        if let Some(rssi) = report.rssi {
+            updated |= util::set_if_changed(&mut properties.rssi, Some(rssi));
+            events.push(CentralEvent::RssiUpdate { address, rssi });
+        }
+        for (&manufacturer_id, data) in &report.manufacturer_data {
+            updated |= properties
+                .manufacturer_data
+                .insert(manufacturer_id, data.clone())
+                .as_ref()
+                != Some(data);
+            events.push(CentralEvent::ManufacturerDataAdvertisement {
+                address,
+                manufacturer_id,
+                data: data.clone(),
+            });
+        }
+        for (&service, data) in &report.service_data {
+            updated |= properties
+                .service_data
+                .insert(service, data.clone())
+                .as_ref()
+                != Some(data);
+            events.push(CentralEvent::ServiceDataAdvertisement {
+                address,
+                service,
+                data: data.clone(),
+            });
+        }
+        if !report.services.is_empty() {
+            updated |= util::set_if_changed(&mut properties.services, report.services.clone());
+            events.push(CentralEvent::ServicesAdvertisement {
+                address,
+                services: report.services.clone(),
+            });
+        }
+        updated
+    }
+
+    /// Passes a notification from the device on to the streams and handlers which want it.
+    pub(super) fn deliver(&self, notification: &ValueNotification) {
+        if let Some(handle) = notification.handle {
+            self.notification_streams.dispatch(&handle, notification);
+        }
+        util::invoke_handlers(&self.notification_handlers, notification);
+    }
+
+    /// Reports that the device dropped the connection.
+    pub(super) fn connection_lost(&self) {
+        self.adapter
+            .emit(CentralEvent::DeviceDisconnected(self.device.address()));
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.is_connected() {
+            Ok(())
+        } else {
+            Err(Error::NotConnected(self.device.address().to_string()))
+        }
+    }
+
+    /// Asks the device to start notifying, which is all that `subscribe` does once the
+    /// notification streams have had their say.
+    fn start_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        self.check_connected()?;
+        self.device.check(Operation::Subscribe)?;
+        self.device.subscribe(characteristic.value_handle)
+    }
+
+    fn stop_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        self.check_connected()?;
+        self.device.check(Operation::Unsubscribe)?;
+        self.device.unsubscribe(characteristic.value_handle);
+        Ok(())
+    }
+}
+
+impl Display for Peripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let connected = if self.is_connected() {
+            " connected"
+        } else {
+            ""
+        };
+        let properties = self.properties.lock().unwrap();
+        write!(
+            f,
+            "{} {}{}",
+            self.device.address(),
+            properties
+                .local_name
+                .clone()
+                .unwrap_or_else(|| "(unknown)".to_string()),
+            connected
+        )
+    }
+}
+
+impl Debug for Peripheral {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let connected = if self.is_connected() {
+            " connected"
+        } else {
+            ""
+        };
+        let properties = self.properties.lock().unwrap();
+        let characteristics = self.characteristics.lock().unwrap();
+        write!(
+            f,
+            "{} properties: {:?}, characteristics: {:?} {}",
+            self.device.address(),
+            *properties,
+            *characteristics,
+            connected
+        )
+    }
+}
+
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
+    fn address(&self) -> BDAddr {
+        self.device.address()
+    }
+
+    fn properties(&self) -> PeripheralProperties {
+        self.properties.lock().unwrap().clone()
+    }
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.characteristics.lock().unwrap().clone()
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.services.lock().unwrap().clone()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.device.is_connected()
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.device.check(Operation::Connect)?;
+        if self.device.connect(self) {
+            self.adapter
+                .emit(CentralEvent::DeviceConnected(self.device.address()));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.device.check(Operation::Disconnect)?;
+        if self.device.take_connection().is_some() {
+            self.adapter
+                .emit(CentralEvent::DeviceDisconnected(self.device.address()));
+        }
+        Ok(())
+    }
+
+    async fn pair(&self) -> Result<()> {
+        self.device.check(Operation::Pair)?;
+        // Pairing connects to the device if it isn't connected already.
+        self.connect().await?;
+        self.device.set_paired(true);
+        Ok(())
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        self.device.check(Operation::Unpair)?;
+        self.device.set_paired(false);
+        Ok(())
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        Ok(self.device.is_paired())
+    }
+
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+        self.check_connected()?;
+        self.device.check(Operation::DiscoverCharacteristics)?;
+        let services = self.device.services();
+        let characteristics: BTreeSet<Characteristic> = services
+            .iter()
+            .flat_map(|service| service.characteristics.iter().cloned())
+            .collect();
+        *self.services.lock().unwrap() = services;
+        *self.characteristics.lock().unwrap() = characteristics.clone();
+        Ok(characteristics.into_iter().collect())
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.check_connected()?;
+        self.device.check(Operation::Write)?;
+        self.device
+            .write_characteristic(characteristic.value_handle, data, write_type)
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        self.device.check(Operation::Read)?;
+        self.device.read_characteristic(characteristic.value_handle)
+    }
+
+    async fn read_by_type(&self, characteristic: &Characteristic, uuid: Uuid) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        self.device.check(Operation::Read)?;
+        self.device
+            .read_by_type(characteristic.start_handle, characteristic.end_handle, uuid)
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.check_connected()?;
+        self.device.check(Operation::ReadDescriptor)?;
+        self.device.read_descriptor(descriptor.handle)
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.check_connected()?;
+        self.device.check(Operation::WriteDescriptor)?;
+        self.device.write_descriptor(descriptor.handle, data)
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .subscribe(characteristic.value_handle, || {
+                future::ready(self.start_notify(characteristic))
+            })
+            .await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .unsubscribe(characteristic.value_handle, || {
+                future::ready(self.stop_notify(characteristic))
+            })
+            .await
+    }
+
+    fn on_notification(&self, handler: NotificationHandler) {
+        self.notification_handlers.lock().unwrap().push(handler);
+    }
+
+    async fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream> {
+        let handle = characteristic.value_handle;
+        let unsubscribe = {
+            let device = self.device.clone();
+            move || device.unsubscribe(handle)
+        };
+        self.notification_streams
+            .add(
+                handle,
+                || future::ready(self.start_notify(characteristic)),
+                unsubscribe,
+            )
+            .await
+    }
+}
@@ -13,10 +13,13 @@
 
 use super::{ble::watcher::BLEWatcher, peripheral::Peripheral, utils};
 use crate::{
-    api::{AdapterManager, BDAddr, Central, CentralEvent},
+    api::{
+        AdapterManager, AsyncCentral, BDAddr, CentralEvent, DeviceState, EventStream, ExpiryPolicy,
+    },
     Result,
 };
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
 
 #[derive(Clone)]
 pub struct Adapter {
@@ -32,32 +35,28 @@ impl Adapter {
     }
 }
 
-impl Central<Peripheral> for Adapter {
-    fn event_receiver(&self) -> Option<Receiver<CentralEvent>> {
-        self.manager.event_receiver()
+#[async_trait]
+impl AsyncCentral<Peripheral> for Adapter {
+    fn events_with_buffer(&self, buffer: usize) -> EventStream<CentralEvent> {
+        self.manager.events(buffer)
     }
 
-    fn start_scan(&self) -> Result<()> {
+    async fn start_scan(&self) -> Result<()> {
+        self.manager.reset_duplicates();
         let watcher = self.watcher.lock().unwrap();
         let manager = self.manager.clone();
         watcher.start(Box::new(move |args| {
             let bluetooth_address = args.bluetooth_address().unwrap();
             let address = utils::to_addr(bluetooth_address);
-            let peripheral = manager
-                .peripheral(address)
-                .unwrap_or_else(|| Peripheral::new(manager.clone(), address));
-            peripheral.update_properties(args);
-            if !manager.has_peripheral(&address) {
-                manager.add_peripheral(address, peripheral);
-                manager.emit(CentralEvent::DeviceDiscovered(address));
-            } else {
-                manager.update_peripheral(address, peripheral);
-                manager.emit(CentralEvent::DeviceUpdated(address));
-            }
+            manager.alter_peripheral(
+                address,
+                || Some(Peripheral::new(manager.clone(), address)),
+                |peripheral, events| peripheral.update_properties(args, events),
+            );
         }))
     }
 
-    fn stop_scan(&self) -> Result<()> {
+    async fn stop_scan(&self) -> Result<()> {
         let watcher = self.watcher.lock().unwrap();
         watcher.stop().unwrap();
         Ok(())
@@ -71,7 +70,17 @@ impl Central<Peripheral> for Adapter {
         self.manager.peripheral(address)
     }
 
+    fn peripheral_state(&self, address: BDAddr) -> Option<DeviceState> {
+        self.manager.peripheral_state(address)
+    }
+
+    fn expiry_policy(&self, policy: ExpiryPolicy) {
+        self.manager.set_expiry_policy(policy);
+    }
+
     fn active(&self, _enabled: bool) {}
 
-    fn filter_duplicates(&self, _enabled: bool) {}
+    fn filter_duplicates(&self, enabled: bool) {
+        self.manager.set_filter_duplicates(enabled);
+    }
 }
@@ -33,8 +33,10 @@ unsafe impl Sync for BLEDevice {}
 impl BLEDevice {
     pub fn new(address: BDAddr, connection_status_changed: ConnectedEventHandler) -> Result<Self> {
         let async_op = BluetoothLEDevice::from_bluetooth_address_async(utils::to_address(address))
-            .map_err(|_| Error::DeviceNotFound)?;
-        let device = async_op.get().map_err(|_| Error::DeviceNotFound)?;
+            .map_err(|e| Error::DeviceNotFound(format!("{:?}", e)))?;
+        let device = async_op
+            .get()
+            .map_err(|e| Error::DeviceNotFound(format!("{:?}", e)))?;
         let connection_status_handler = TypedEventHandler::new(
             move |sender: &Option<BluetoothLEDevice>, _: &Option<windows::Object>| {
                 if let Some(sender) = sender {
@@ -69,7 +71,9 @@ impl BLEDevice {
 
     pub fn connect(&self) -> Result<()> {
         let service_result = self.get_gatt_services()?;
-        let status = service_result.status().map_err(|_| Error::DeviceNotFound)?;
+        let status = service_result
+            .status()
+            .map_err(|e| Error::DeviceNotFound(format!("{:?}", e)))?;
         utils::to_error(status)
     }
 
@@ -18,15 +18,19 @@ use super::{
 use crate::{
     api::{
         bleuuid::{uuid_from_u16, uuid_from_u32},
-        AdapterManager, AddressType, BDAddr, CentralEvent, Characteristic, NotificationHandler,
-        Peripheral as ApiPeripheral, PeripheralProperties, ValueNotification, WriteType,
+        AdapterManager, AddressType, AsyncPeripheral, BDAddr, CentralEvent, Characteristic,
+        Descriptor, NotificationHandler, NotificationStream, PeripheralProperties, Service,
+        ValueNotification, WriteType,
     },
-    common::util,
+    common::{notifications::NotificationStreams, util},
     Error, Result,
 };
+use async_trait::async_trait;
 use dashmap::DashMap;
+use futures::future;
+use log::warn;
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     convert::TryInto,
     fmt::{self, Debug, Display, Formatter},
     sync::atomic::{AtomicBool, Ordering},
@@ -43,9 +47,13 @@ pub struct Peripheral {
     address: BDAddr,
     properties: Arc<Mutex<PeripheralProperties>>,
     characteristics: Arc<Mutex<BTreeSet<Characteristic>>>,
+    services: Arc<Mutex<BTreeSet<Service>>>,
     connected: Arc<AtomicBool>,
-    ble_characteristics: Arc<DashMap<Uuid, BLECharacteristic>>,
+    /// Keyed by service UUID and characteristic UUID, as a characteristic UUID may be reused by
+    /// several services.
+    ble_characteristics: Arc<DashMap<(Uuid, Uuid), BLECharacteristic>>,
     notification_handlers: Arc<Mutex<Vec<NotificationHandler>>>,
+    notification_streams: Arc<NotificationStreams<(Uuid, Uuid)>>,
 }
 
 impl Peripheral {
@@ -64,48 +72,57 @@ impl Peripheral {
             address,
             properties,
             characteristics,
+            services: Arc::new(Mutex::new(BTreeSet::new())),
             connected,
             ble_characteristics,
             notification_handlers,
+            notification_streams: Arc::new(NotificationStreams::new()),
         }
     }
 
-    pub fn update_properties(&self, args: &BluetoothLEAdvertisementReceivedEventArgs) {
+    /// Updates the properties from an advertisement, and returns whether they changed. The events
+    /// which the advertisement causes are added to `events`.
+    pub fn update_properties(
+        &self,
+        args: &BluetoothLEAdvertisementReceivedEventArgs,
+        events: &mut Vec<CentralEvent>,
+    ) -> bool {
         let mut properties = self.properties.lock().unwrap();
         let advertisement = args.advertisement().unwrap();
+        let mut updated = false;
 
         properties.discovery_count += 1;
 
         // Advertisements are cumulative: set/replace data only if it's set
         if let Ok(name) = advertisement.local_name() {
             if !name.is_empty() {
-                properties.local_name = Some(name.to_string());
+                updated |= util::set_if_changed(&mut properties.local_name, Some(name.to_string()));
             }
         }
         if let Ok(manufacturer_data) = advertisement.manufacturer_data() {
-            properties.manufacturer_data = manufacturer_data
+            let manufacturer_data = manufacturer_data
                 .into_iter()
                 .map(|d| {
                     let manufacturer_id = d.company_id().unwrap();
                     let data = utils::to_vec(&d.data().unwrap());
 
                     // Emit event of newly received advertisement
-                    self.adapter
-                        .emit(CentralEvent::ManufacturerDataAdvertisement {
-                            address: self.address,
-                            manufacturer_id,
-                            data: data.clone(),
-                        });
+                    events.push(CentralEvent::ManufacturerDataAdvertisement {
+                        address: self.address,
+                        manufacturer_id,
+                        data: data.clone(),
+                    });
 
                     (manufacturer_id, data)
                 })
                 .collect();
+            updated |= util::set_if_changed(&mut properties.manufacturer_data, manufacturer_data);
         }
 
         // The Windows Runtime API (as of 19041) does not directly expose Service Data as a friendly API (like Manufacturer Data above)
         // Instead they provide data sections for access to raw advertising data. That is processed here.
         if let Ok(data_sections) = advertisement.data_sections() {
-            properties.service_data = data_sections
+            let service_data = data_sections
                 .into_iter()
                 .filter_map(|d| {
                     let data = utils::to_vec(&d.data().unwrap());
@@ -131,7 +148,7 @@ impl Peripheral {
 
                     // Emit event of newly received advertisement
                     let (uuid, data) = service_data;
-                    self.adapter.emit(CentralEvent::ServiceDataAdvertisement {
+                    events.push(CentralEvent::ServiceDataAdvertisement {
                         address: self.address,
                         service: uuid,
                         data: data.clone(),
@@ -140,15 +157,17 @@ impl Peripheral {
                     Some((uuid, data))
                 })
                 .collect();
+            updated |= util::set_if_changed(&mut properties.service_data, service_data);
         }
 
         if let Ok(services) = advertisement.service_uuids() {
-            properties.services = services
+            let services = services
                 .into_iter()
                 .map(|uuid| utils::to_uuid(&uuid))
                 .collect();
+            updated |= util::set_if_changed(&mut properties.services, services);
 
-            self.adapter.emit(CentralEvent::ServicesAdvertisement {
+            events.push(CentralEvent::ServicesAdvertisement {
                 address: self.address,
                 services: properties.services.clone(),
             });
@@ -156,14 +175,55 @@ impl Peripheral {
 
         // windows does not provide the address type in the advertisement event args but only in the device object
         // https://social.msdn.microsoft.com/Forums/en-US/c71d51a2-56a1-425a-9063-de44fda48766/bluetooth-address-public-or-random?forum=wdk
-        properties.address_type = AddressType::default();
-        properties.has_scan_response =
+        updated |= util::set_if_changed(&mut properties.address_type, AddressType::default());
+        let has_scan_response =
             args.advertisement_type().unwrap() == BluetoothLEAdvertisementType::ScanResponse;
-        properties.tx_power_level = args
-            .raw_signal_strength_in_dbm()
-            .ok()
-            .map(|rssi| rssi as i8);
+        updated |= util::set_if_changed(&mut properties.has_scan_response, has_scan_response);
+        if let Ok(rssi) = args.raw_signal_strength_in_dbm() {
+            updated |= util::set_if_changed(&mut properties.rssi, Some(rssi));
+            events.push(CentralEvent::RssiUpdate {
+                address: self.address,
+                rssi,
+            });
+        }
+        updated
     }
+
+    /// Subscribes to the characteristic on the device, regardless of who else wants the
+    /// notifications.
+    fn start_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&key(characteristic))
+        {
+            let notification_handlers = self.notification_handlers.clone();
+            let notification_streams = self.notification_streams.clone();
+            let key = key(characteristic);
+            ble_characteristic.subscribe(Box::new(move |value| {
+                let notification = ValueNotification {
+                    uuid: key.1,
+                    handle: None,
+                    value,
+                };
+                notification_streams.dispatch(&key, &notification);
+                util::invoke_handlers(&notification_handlers, &notification);
+            }))
+        } else {
+            Err(Error::NotSupported("subscribe".into()))
+        }
+    }
+
+    fn stop_notify(&self, characteristic: &Characteristic) -> Result<()> {
+        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&key(characteristic))
+        {
+            ble_characteristic.unsubscribe()
+        } else {
+            Err(Error::NotSupported("unsubscribe".into()))
+        }
+    }
+}
+
+/// The key a characteristic is stored under in `ble_characteristics`.
+fn key(characteristic: &Characteristic) -> (Uuid, Uuid) {
+    (characteristic.service_uuid, characteristic.uuid)
 }
 
 impl Display for Peripheral {
@@ -204,7 +264,8 @@ impl Debug for Peripheral {
     }
 }
 
-impl ApiPeripheral for Peripheral {
+#[async_trait]
+impl AsyncPeripheral for Peripheral {
     /// Returns the address of the peripheral.
     fn address(&self) -> BDAddr {
         self.address.clone()
@@ -224,15 +285,21 @@ impl ApiPeripheral for Peripheral {
         l.clone()
     }
 
+    /// The set of services we've discovered for this device. This will be empty until
+    /// `discover_characteristics` is called.
+    fn services(&self) -> BTreeSet<Service> {
+        self.services.lock().unwrap().clone()
+    }
+
     /// Returns true iff we are currently connected to the device.
     fn is_connected(&self) -> bool {
         self.connected.load(Ordering::Relaxed)
     }
 
-    /// Creates a connection to the device. This is a synchronous operation; if this method returns
-    /// Ok there has been successful connection. Note that peripherals allow only one connection at
-    /// a time. Operations that attempt to communicate with a device will fail until it is connected.
-    fn connect(&self) -> Result<()> {
+    /// Creates a connection to the device. If the returned future resolves to Ok there has been a
+    /// successful connection. Note that peripherals allow only one connection at a time.
+    /// Operations that attempt to communicate with a device will fail until it is connected.
+    async fn connect(&self) -> Result<()> {
         let connected = self.connected.clone();
         let adapter_clone = self.adapter.clone();
         let address_clone = self.address.clone();
@@ -254,8 +321,8 @@ impl ApiPeripheral for Peripheral {
         Ok(())
     }
 
-    /// Terminates a connection to the device. This is a synchronous operation.
-    fn disconnect(&self) -> Result<()> {
+    /// Terminates a connection to the device.
+    async fn disconnect(&self) -> Result<()> {
         let winrt_error = |e| Error::Other(format!("{:?}", e));
         let mut device = self.device.lock().map_err(winrt_error)?;
         *device = None;
@@ -264,42 +331,73 @@ impl ApiPeripheral for Peripheral {
         Ok(())
     }
 
-    /// Discovers all characteristics for the device. This is a synchronous operation.
-    fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
+    async fn pair(&self) -> Result<()> {
+        Err(Error::NotSupported("pair".into()))
+    }
+
+    async fn unpair(&self) -> Result<()> {
+        Err(Error::NotSupported("unpair".into()))
+    }
+
+    async fn is_paired(&self) -> Result<bool> {
+        Err(Error::NotSupported("is_paired".into()))
+    }
+
+    /// Discovers all characteristics for the device.
+    async fn discover_characteristics(&self) -> Result<Vec<Characteristic>> {
         let device = self.device.lock().unwrap();
         if let Some(ref device) = *device {
             let mut characteristics_result = vec![];
+            let mut services = HashMap::<Uuid, Service>::new();
             let characteristics = device.discover_characteristics()?;
             for characteristic in characteristics {
                 let uuid = utils::to_uuid(&characteristic.uuid().unwrap());
+                let service_uuid =
+                    utils::to_uuid(&characteristic.service().unwrap().uuid().unwrap());
                 let properties =
                     utils::to_char_props(&characteristic.characteristic_properties().unwrap());
                 let chara = Characteristic {
                     uuid,
+                    service_uuid,
                     start_handle: 0,
                     end_handle: 0,
                     value_handle: 0,
                     properties,
+                    descriptors: BTreeSet::new(),
                 };
+                services
+                    .entry(service_uuid)
+                    .or_insert_with(|| Service {
+                        uuid: service_uuid,
+                        // Only primary services are returned by GetGattServicesAsync.
+                        primary: true,
+                        included_services: Vec::new(),
+                        characteristics: BTreeSet::new(),
+                    })
+                    .characteristics
+                    .insert(chara.clone());
                 characteristics_result.push(chara);
                 self.ble_characteristics
-                    .entry(uuid)
+                    .entry((service_uuid, uuid))
                     .or_insert_with(|| BLECharacteristic::new(characteristic));
             }
+            *self.characteristics.lock().unwrap() =
+                characteristics_result.iter().cloned().collect();
+            *self.services.lock().unwrap() = services.into_iter().map(|(_, s)| s).collect();
             return Ok(characteristics_result);
         }
-        Err(Error::NotConnected)
+        Err(Error::NotConnected("discover_characteristics".into()))
     }
 
     /// Write some data to the characteristic. Returns an error if the write couldn't be send or (in
     /// the case of a write-with-response) if the device returns an error.
-    fn write(
+    async fn write(
         &self,
         characteristic: &Characteristic,
         data: &[u8],
         write_type: WriteType,
     ) -> Result<()> {
-        if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
+        if let Some(ble_characteristic) = self.ble_characteristics.get(&key(characteristic)) {
             ble_characteristic.write_value(data, write_type)
         } else {
             Err(Error::NotSupported("write".into()))
@@ -309,44 +407,39 @@ impl ApiPeripheral for Peripheral {
     /// Sends a read-by-type request to device for the range of handles covered by the
     /// characteristic and for the specified declaration UUID. See
     /// [here](https://www.bluetooth.com/specifications/gatt/declarations) for valid UUIDs.
-    /// Synchronously returns either an error or the device response.
-    fn read_by_type(&self, characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
-        if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
+    /// Resolves to either an error or the device response.
+    async fn read_by_type(&self, characteristic: &Characteristic, _uuid: Uuid) -> Result<Vec<u8>> {
+        if let Some(ble_characteristic) = self.ble_characteristics.get(&key(characteristic)) {
             return ble_characteristic.read_value();
         } else {
             Err(Error::NotSupported("read_by_type".into()))
         }
     }
 
+    async fn read_descriptor(&self, _descriptor: &Descriptor) -> Result<Vec<u8>> {
+        Err(Error::NotSupported("read_descriptor".into()))
+    }
+
+    async fn write_descriptor(&self, _descriptor: &Descriptor, _data: &[u8]) -> Result<()> {
+        Err(Error::NotSupported("write_descriptor".into()))
+    }
+
     /// Enables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
-        {
-            let notification_handlers = self.notification_handlers.clone();
-            let uuid = characteristic.uuid;
-            ble_characteristic.subscribe(Box::new(move |value| {
-                let notification = ValueNotification {
-                    uuid: uuid,
-                    handle: None,
-                    value,
-                };
-                util::invoke_handlers(&notification_handlers, &notification);
-            }))
-        } else {
-            Err(Error::NotSupported("subscribe".into()))
-        }
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .subscribe(key(characteristic), || {
+                future::ready(self.start_notify(characteristic))
+            })
+            .await
     }
 
     /// Disables either notify or indicate (depending on support) for the specified characteristic.
-    /// This is a synchronous call.
-    fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
-        if let Some(mut ble_characteristic) = self.ble_characteristics.get_mut(&characteristic.uuid)
-        {
-            ble_characteristic.unsubscribe()
-        } else {
-            Err(Error::NotSupported("unsubscribe".into()))
-        }
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.notification_streams
+            .unsubscribe(key(characteristic), || {
+                future::ready(self.stop_notify(characteristic))
+            })
+            .await
     }
 
     /// Registers a handler that will be called when value notification messages are received from
@@ -357,8 +450,31 @@ impl ApiPeripheral for Peripheral {
         list.push(handler);
     }
 
-    fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
-        if let Some(ble_characteristic) = self.ble_characteristics.get(&characteristic.uuid) {
+    /// Returns a stream of value notifications from the specified characteristic, subscribing to
+    /// it if this is the first stream for that characteristic.
+    async fn notifications(&self, characteristic: &Characteristic) -> Result<NotificationStream> {
+        let unsubscribe = {
+            let ble_characteristics = self.ble_characteristics.clone();
+            let key = key(characteristic);
+            move || {
+                if let Some(mut ble_characteristic) = ble_characteristics.get_mut(&key) {
+                    if let Err(e) = ble_characteristic.unsubscribe() {
+                        warn!("Could not unsubscribe from {}: {:?}", key.1, e);
+                    }
+                }
+            }
+        };
+        self.notification_streams
+            .add(
+                key(characteristic),
+                || future::ready(self.start_notify(characteristic)),
+                unsubscribe,
+            )
+            .await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        if let Some(ble_characteristic) = self.ble_characteristics.get(&key(characteristic)) {
             return ble_characteristic.read_value();
         } else {
             Err(Error::NotSupported("read".into()))
@@ -28,9 +28,9 @@ use windows::Guid;
 
 pub fn to_error(status: GattCommunicationStatus) -> Result<()> {
     if status == GattCommunicationStatus::AccessDenied {
-        Err(Error::PermissionDenied)
+        Err(Error::PermissionDenied("AccessDenied".to_string()))
     } else if status == GattCommunicationStatus::Unreachable {
-        Err(Error::NotConnected)
+        Err(Error::NotConnected("Unreachable".to_string()))
     } else if status == GattCommunicationStatus::Success {
         Ok(())
     } else if status == GattCommunicationStatus::ProtocolError {